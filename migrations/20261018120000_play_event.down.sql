@@ -0,0 +1 @@
+DROP TABLE IF EXISTS "Play_Event" CASCADE;
@@ -0,0 +1,14 @@
+CREATE TABLE IF NOT EXISTS "Play_Event"
+(
+    id              bigserial PRIMARY KEY,
+    ---------------------------------------------
+    user_id         bigserial        NOT NULL,
+    audiobook_id    bigserial        NOT NULL,
+    created_at      timestamptz      NOT NULL DEFAULT now(),
+
+    FOREIGN KEY (user_id)       REFERENCES "User" (id) ON DELETE CASCADE,
+    FOREIGN KEY (audiobook_id)  REFERENCES "Audiobook" (id) ON DELETE CASCADE
+);
+
+CREATE INDEX IF NOT EXISTS "Play_Event_audiobook_id_idx" ON "Play_Event" (audiobook_id);
+CREATE INDEX IF NOT EXISTS "Play_Event_user_id_idx" ON "Play_Event" (user_id);
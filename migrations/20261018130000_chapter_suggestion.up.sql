@@ -0,0 +1,13 @@
+CREATE TABLE IF NOT EXISTS "Chapter_Suggestion"
+(
+    id              bigserial PRIMARY KEY,
+    ---------------------------------------------
+    audiobook_id    bigserial        NOT NULL,
+    position        float8           NOT NULL,
+    silence_length  float8           NOT NULL,
+    created_at      timestamptz      NOT NULL DEFAULT now(),
+
+    FOREIGN KEY (audiobook_id)  REFERENCES "Audiobook" (id) ON DELETE CASCADE
+);
+
+CREATE INDEX IF NOT EXISTS "Chapter_Suggestion_audiobook_id_idx" ON "Chapter_Suggestion" (audiobook_id);
@@ -0,0 +1,21 @@
+CREATE TABLE IF NOT EXISTS "Upload"
+(
+    id              uuid PRIMARY KEY,
+    ---------------------------------------------
+    user_id         bigserial        NOT NULL,
+    genre_id        bigserial        NOT NULL,
+    name            text             NOT NULL,
+    description     text             NOT NULL,
+    file_name       text,
+    content_type    text,
+    upload_length   bigint           NOT NULL,
+    upload_offset   bigint           NOT NULL DEFAULT 0,
+    created_at      timestamptz      NOT NULL DEFAULT now(),
+    edited_at       timestamptz      NOT NULL DEFAULT now(),
+    expires_at      timestamptz      NOT NULL,
+
+    FOREIGN KEY (user_id)   REFERENCES "User" (id) ON DELETE CASCADE,
+    FOREIGN KEY (genre_id)  REFERENCES "Genre" (id) ON DELETE CASCADE
+);
+
+CREATE INDEX IF NOT EXISTS "Upload_expires_at_idx" ON "Upload" (expires_at);
@@ -0,0 +1,12 @@
+DROP TRIGGER IF EXISTS "Audiobook_media_references" ON "Audiobook";
+DROP TRIGGER IF EXISTS "User_media_references" ON "User";
+DROP FUNCTION IF EXISTS audiobook_media_references();
+DROP FUNCTION IF EXISTS user_media_references();
+DROP FUNCTION IF EXISTS media_move_reference(text, text);
+
+ALTER TABLE "Audiobook"
+    DROP CONSTRAINT IF EXISTS "Audiobook_file_path_fkey",
+    DROP CONSTRAINT IF EXISTS "Audiobook_thumbnail_fkey";
+ALTER TABLE "User" DROP CONSTRAINT IF EXISTS "User_profile_picture_fkey";
+
+DROP TABLE IF EXISTS "Media" CASCADE;
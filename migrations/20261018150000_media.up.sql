@@ -0,0 +1,73 @@
+CREATE TABLE IF NOT EXISTS "Media"
+(
+    id              bigserial PRIMARY KEY,
+    ---------------------------------------------
+    path            text UNIQUE      NOT NULL,
+    -- SHA-256 of the uploaded contents, media stored before they were addressed by it
+    -- (and the bundled examples) have none
+    digest          text,
+    size            bigint           NOT NULL DEFAULT 0,
+    -- number of "Audiobook" and "User" rows pointing to the medium, kept by the triggers below
+    ref_count       bigint           NOT NULL DEFAULT 0,
+    created_at      timestamptz      NOT NULL DEFAULT now(),
+    -- the last time a file with these contents was uploaded
+    claimed_at      timestamptz      NOT NULL DEFAULT now()
+);
+
+CREATE INDEX IF NOT EXISTS "Media_unreferenced_idx" ON "Media" (claimed_at) WHERE ref_count = 0;
+
+INSERT INTO "Media" (path)
+SELECT file_path FROM "Audiobook"
+UNION SELECT thumbnail FROM "Audiobook" WHERE thumbnail IS NOT NULL
+UNION SELECT profile_picture FROM "User" WHERE profile_picture IS NOT NULL
+ON CONFLICT DO NOTHING;
+
+UPDATE "Media" AS M
+SET ref_count = (SELECT count(*) FROM "Audiobook" AS A WHERE A.file_path = M.path)
+              + (SELECT count(*) FROM "Audiobook" AS A WHERE A.thumbnail = M.path)
+              + (SELECT count(*) FROM "User" AS U WHERE U.profile_picture = M.path);
+
+ALTER TABLE "Audiobook"
+    DROP CONSTRAINT IF EXISTS "Audiobook_file_path_fkey",
+    DROP CONSTRAINT IF EXISTS "Audiobook_thumbnail_fkey",
+    ADD CONSTRAINT "Audiobook_file_path_fkey" FOREIGN KEY (file_path) REFERENCES "Media" (path),
+    ADD CONSTRAINT "Audiobook_thumbnail_fkey" FOREIGN KEY (thumbnail) REFERENCES "Media" (path);
+ALTER TABLE "User"
+    DROP CONSTRAINT IF EXISTS "User_profile_picture_fkey",
+    ADD CONSTRAINT "User_profile_picture_fkey" FOREIGN KEY (profile_picture) REFERENCES "Media" (path);
+
+-- moves a reference from the old path to the new one, either of them can be NULL
+CREATE OR REPLACE FUNCTION media_move_reference(old_path text, new_path text) RETURNS void AS
+$$
+BEGIN
+    IF old_path IS DISTINCT FROM new_path THEN
+        UPDATE "Media" SET ref_count = ref_count - 1 WHERE path = old_path;
+        UPDATE "Media" SET ref_count = ref_count + 1 WHERE path = new_path;
+    END IF;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE OR REPLACE FUNCTION audiobook_media_references() RETURNS trigger AS
+$$
+BEGIN
+    PERFORM media_move_reference(OLD.file_path, NEW.file_path);
+    PERFORM media_move_reference(OLD.thumbnail, NEW.thumbnail);
+    RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE OR REPLACE FUNCTION user_media_references() RETURNS trigger AS
+$$
+BEGIN
+    PERFORM media_move_reference(OLD.profile_picture, NEW.profile_picture);
+    RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE OR REPLACE TRIGGER "Audiobook_media_references"
+    AFTER INSERT OR DELETE OR UPDATE OF file_path, thumbnail ON "Audiobook"
+    FOR EACH ROW EXECUTE FUNCTION audiobook_media_references();
+
+CREATE OR REPLACE TRIGGER "User_media_references"
+    AFTER INSERT OR DELETE OR UPDATE OF profile_picture ON "User"
+    FOR EACH ROW EXECUTE FUNCTION user_media_references();
@@ -0,0 +1 @@
+DROP TABLE IF EXISTS "Recommender_Queue" CASCADE;
@@ -0,0 +1,16 @@
+-- books waiting to be registered in the recommender, the entry is created in the same
+-- transaction as the book and removed once the recommender has accepted it
+CREATE TABLE IF NOT EXISTS "Recommender_Queue"
+(
+    audiobook_id    bigint           PRIMARY KEY,
+    ---------------------------------------------
+    attempts        integer          NOT NULL DEFAULT 0,
+    last_error      text,
+    created_at      timestamptz      NOT NULL DEFAULT now(),
+    next_attempt_at timestamptz      NOT NULL DEFAULT now(),
+
+    FOREIGN KEY (audiobook_id) REFERENCES "Audiobook" (id) ON DELETE CASCADE
+);
+
+CREATE INDEX IF NOT EXISTS "Recommender_Queue_next_attempt_at_idx"
+    ON "Recommender_Queue" (next_attempt_at);
@@ -0,0 +1,5 @@
+DROP INDEX IF EXISTS "Audiobook_unprocessed_idx";
+ALTER TABLE "Audiobook" DROP CONSTRAINT IF EXISTS "Audiobook_processing_state_check";
+ALTER TABLE "Audiobook" DROP COLUMN IF EXISTS processing_started_at;
+ALTER TABLE "Audiobook" DROP COLUMN IF EXISTS processing_error;
+ALTER TABLE "Audiobook" DROP COLUMN IF EXISTS processing_state;
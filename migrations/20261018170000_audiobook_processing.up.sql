@@ -0,0 +1,12 @@
+-- uploaded books are checked and their derivatives created by a background worker,
+-- they are only shown to listeners once they are ready
+ALTER TABLE "Audiobook" ADD COLUMN IF NOT EXISTS processing_state text NOT NULL DEFAULT 'ready';
+ALTER TABLE "Audiobook" ADD COLUMN IF NOT EXISTS processing_error text;
+ALTER TABLE "Audiobook" ADD COLUMN IF NOT EXISTS processing_started_at timestamptz;
+
+ALTER TABLE "Audiobook" DROP CONSTRAINT IF EXISTS "Audiobook_processing_state_check";
+ALTER TABLE "Audiobook" ADD CONSTRAINT "Audiobook_processing_state_check"
+    CHECK (processing_state IN ('uploaded', 'processing', 'ready', 'failed'));
+
+CREATE INDEX IF NOT EXISTS "Audiobook_unprocessed_idx" ON "Audiobook" (created_at)
+    WHERE processing_state IN ('uploaded', 'processing');
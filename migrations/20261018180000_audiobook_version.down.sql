@@ -0,0 +1,3 @@
+DROP TRIGGER IF EXISTS "Audiobook_Version_media_references" ON "Audiobook_Version";
+DROP FUNCTION IF EXISTS audiobook_version_media_references();
+DROP TABLE IF EXISTS "Audiobook_Version";
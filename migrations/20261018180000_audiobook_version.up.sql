@@ -0,0 +1,28 @@
+-- audio files a book was replaced with are kept as its previous versions
+CREATE TABLE IF NOT EXISTS "Audiobook_Version"
+(
+    id              bigserial PRIMARY KEY,
+    ---------------------------------------------
+    audiobook_id    bigint           NOT NULL,
+    file_path       text             NOT NULL,
+    length          float8           NOT NULL,
+    -- when the file was replaced by a newer one
+    replaced_at     timestamptz      NOT NULL DEFAULT now(),
+
+    FOREIGN KEY (audiobook_id)      REFERENCES "Audiobook" (id) ON DELETE CASCADE,
+    FOREIGN KEY (file_path)         REFERENCES "Media" (path)
+);
+
+CREATE INDEX IF NOT EXISTS "Audiobook_Version_audiobook_id_idx" ON "Audiobook_Version" (audiobook_id);
+
+CREATE OR REPLACE FUNCTION audiobook_version_media_references() RETURNS trigger AS
+$$
+BEGIN
+    PERFORM media_move_reference(OLD.file_path, NEW.file_path);
+    RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE OR REPLACE TRIGGER "Audiobook_Version_media_references"
+    AFTER INSERT OR DELETE OR UPDATE OF file_path ON "Audiobook_Version"
+    FOR EACH ROW EXECUTE FUNCTION audiobook_version_media_references();
@@ -0,0 +1,2 @@
+DROP TABLE IF EXISTS "Audiobook_Export";
+ALTER TABLE "Audiobook" DROP COLUMN IF EXISTS downloadable;
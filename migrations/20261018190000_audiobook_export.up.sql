@@ -0,0 +1,25 @@
+-- books are only exported for listeners if their author allows it
+ALTER TABLE "Audiobook" ADD COLUMN IF NOT EXISTS downloadable boolean NOT NULL DEFAULT false;
+
+-- the latest M4B export of a book, created by a background worker and kept in the storage
+CREATE TABLE IF NOT EXISTS "Audiobook_Export"
+(
+    audiobook_id    bigint PRIMARY KEY,
+    ---------------------------------------------
+    -- SHA-256 of everything the export is made of, an export of an edited book is outdated
+    fingerprint     text             NOT NULL,
+    state           text             NOT NULL DEFAULT 'pending',
+    file_path       text,
+    size            bigint,
+    error           text,
+    requested_at    timestamptz      NOT NULL DEFAULT now(),
+    started_at      timestamptz,
+    finished_at     timestamptz,
+
+    FOREIGN KEY (audiobook_id)      REFERENCES "Audiobook" (id) ON DELETE CASCADE,
+    CONSTRAINT "Audiobook_Export_state_check"
+        CHECK (state IN ('pending', 'exporting', 'ready', 'failed'))
+);
+
+CREATE INDEX IF NOT EXISTS "Audiobook_Export_pending_idx" ON "Audiobook_Export" (requested_at)
+    WHERE state IN ('pending', 'exporting');
@@ -0,0 +1 @@
+DROP TABLE IF EXISTS "Audiobook_Download";
@@ -0,0 +1,18 @@
+-- offline downloads of books, the audio a listener downloaded is needed to match up the progress
+-- they report later, as the audio of the book can be replaced in the meantime
+CREATE TABLE IF NOT EXISTS "Audiobook_Download"
+(
+    id              bigserial PRIMARY KEY,
+    ---------------------------------------------
+    user_id         bigint           NOT NULL,
+    audiobook_id    bigint           NOT NULL,
+    -- not a reference, downloads do not keep the audio in the storage
+    file_path       text             NOT NULL,
+    length          float8           NOT NULL,
+    downloaded_at   timestamptz      NOT NULL DEFAULT now(),
+
+    FOREIGN KEY (user_id)       REFERENCES "User" (id) ON DELETE CASCADE,
+    FOREIGN KEY (audiobook_id)  REFERENCES "Audiobook" (id) ON DELETE CASCADE
+);
+
+CREATE INDEX IF NOT EXISTS "Audiobook_Download_user_id_idx" ON "Audiobook_Download" (user_id, audiobook_id);
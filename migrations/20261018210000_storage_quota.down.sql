@@ -0,0 +1,4 @@
+DROP TRIGGER IF EXISTS "User_default_role" ON "User";
+DROP FUNCTION IF EXISTS user_default_role();
+DROP TABLE IF EXISTS "User_Role";
+DROP TABLE IF EXISTS "Role";
@@ -0,0 +1,42 @@
+-- users can store as many bytes of media as the quota of their role allows, unless they have
+-- a quota of their own
+CREATE TABLE IF NOT EXISTS "Role"
+(
+    name            text PRIMARY KEY,
+    ---------------------------------------------
+    -- NULL for no limit
+    storage_quota   bigint
+);
+
+INSERT INTO "Role" (name, storage_quota)
+VALUES ('author', 10737418240),
+       ('unlimited', NULL)
+ON CONFLICT (name) DO NOTHING;
+
+CREATE TABLE IF NOT EXISTS "User_Role"
+(
+    user_id         bigint PRIMARY KEY,
+    ---------------------------------------------
+    role            text             NOT NULL DEFAULT 'author',
+    -- overrides the quota of the role
+    storage_quota   bigint,
+
+    FOREIGN KEY (user_id)   REFERENCES "User" (id) ON DELETE CASCADE,
+    FOREIGN KEY (role)      REFERENCES "Role" (name) ON UPDATE CASCADE
+);
+
+INSERT INTO "User_Role" (user_id)
+SELECT id FROM "User"
+ON CONFLICT (user_id) DO NOTHING;
+
+CREATE OR REPLACE FUNCTION user_default_role() RETURNS trigger AS
+$$
+BEGIN
+    INSERT INTO "User_Role" (user_id) VALUES (NEW.id) ON CONFLICT (user_id) DO NOTHING;
+    RETURN NULL;
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE OR REPLACE TRIGGER "User_default_role"
+    AFTER INSERT ON "User"
+    FOR EACH ROW EXECUTE FUNCTION user_default_role();
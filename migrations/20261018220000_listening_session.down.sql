@@ -0,0 +1 @@
+DROP TABLE IF EXISTS "Listening_Session";
@@ -0,0 +1,22 @@
+-- the listening history, sessions are only ever appended, the playback position in
+-- "Active_Audiobook" is the end of the latest session of the listener
+CREATE TABLE IF NOT EXISTS "Listening_Session"
+(
+    id              bigserial PRIMARY KEY,
+    ---------------------------------------------
+    user_id         bigint           NOT NULL,
+    audiobook_id    bigint           NOT NULL,
+    start_position  float8           NOT NULL,
+    end_position    float8           NOT NULL,
+    started_at      timestamptz      NOT NULL,
+    ended_at        timestamptz      NOT NULL DEFAULT now(),
+    -- the user agent of the player
+    client          text             NOT NULL DEFAULT '',
+
+    FOREIGN KEY (user_id)       REFERENCES "User" (id) ON DELETE CASCADE,
+    FOREIGN KEY (audiobook_id)  REFERENCES "Audiobook" (id) ON DELETE CASCADE,
+    CHECK (started_at <= ended_at)
+);
+
+CREATE INDEX IF NOT EXISTS "Listening_Session_user_id_idx" ON "Listening_Session" (user_id, ended_at);
+CREATE INDEX IF NOT EXISTS "Listening_Session_audiobook_id_idx" ON "Listening_Session" (audiobook_id);
@@ -0,0 +1,8 @@
+ALTER TABLE "Listening_Session"
+    DROP COLUMN IF EXISTS device_id;
+
+ALTER TABLE "Active_Audiobook"
+    DROP COLUMN IF EXISTS device_id,
+    DROP COLUMN IF EXISTS suggested_position,
+    DROP COLUMN IF EXISTS suggested_device_id,
+    DROP COLUMN IF EXISTS suggested_at;
@@ -0,0 +1,11 @@
+-- positions are synchronized across the devices of a listener, "edited_at" is the time
+-- the device reported the position at, the position that lost to a position of another
+-- device is kept as a suggestion to jump to
+ALTER TABLE "Active_Audiobook"
+    ADD COLUMN IF NOT EXISTS device_id              text             NOT NULL DEFAULT '',
+    ADD COLUMN IF NOT EXISTS suggested_position     float8           NULL,
+    ADD COLUMN IF NOT EXISTS suggested_device_id    text             NULL,
+    ADD COLUMN IF NOT EXISTS suggested_at           timestamptz      NULL;
+
+ALTER TABLE "Listening_Session"
+    ADD COLUMN IF NOT EXISTS device_id              text             NOT NULL DEFAULT '';
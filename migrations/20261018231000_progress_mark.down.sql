@@ -0,0 +1,2 @@
+ALTER TABLE "Active_Audiobook" DROP CONSTRAINT IF EXISTS "Active_Audiobook_progress_mark_check";
+ALTER TABLE "Active_Audiobook" DROP COLUMN IF EXISTS progress_mark;
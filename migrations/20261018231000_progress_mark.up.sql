@@ -0,0 +1,7 @@
+-- listeners mark books finished or unplayed, the mark overrides the state inferred
+-- from the position until the listener plays the book again
+ALTER TABLE "Active_Audiobook" ADD COLUMN IF NOT EXISTS progress_mark text NOT NULL DEFAULT 'unmarked';
+
+ALTER TABLE "Active_Audiobook" DROP CONSTRAINT IF EXISTS "Active_Audiobook_progress_mark_check";
+ALTER TABLE "Active_Audiobook" ADD CONSTRAINT "Active_Audiobook_progress_mark_check"
+    CHECK (progress_mark IN ('unmarked', 'finished', 'unplayed'));
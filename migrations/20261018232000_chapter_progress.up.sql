@@ -0,0 +1,18 @@
+-- how far each listener got in the chapters of the books they listen to, updated
+-- from the reported positions; `progress` is the part of the chapter that was reached
+CREATE TABLE IF NOT EXISTS "Chapter_Progress"
+(
+    user_id         bigint           NOT NULL,
+    chapter_id      bigint           NOT NULL,
+    ---------------------------------------------
+    progress        float8           NOT NULL DEFAULT 0,
+    completed_at    timestamptz,
+    edited_at       timestamptz      NOT NULL DEFAULT now(),
+
+    PRIMARY KEY (user_id, chapter_id),
+    FOREIGN KEY (user_id)       REFERENCES "User" (id) ON DELETE CASCADE,
+    FOREIGN KEY (chapter_id)    REFERENCES "Chapter" (id) ON DELETE CASCADE,
+    CHECK (progress >= 0 AND progress <= 1)
+);
+
+CREATE INDEX IF NOT EXISTS "Chapter_Progress_chapter_id_idx" ON "Chapter_Progress" (chapter_id);
@@ -0,0 +1,2 @@
+DROP TABLE IF EXISTS "Chapter_Play";
+DROP TABLE IF EXISTS "Play";
@@ -0,0 +1,38 @@
+-- plays counted from the listening sessions, a listener plays a book (or a chapter) at most
+-- once a day and only once they have heard enough of it
+CREATE TABLE IF NOT EXISTS "Play"
+(
+    id              bigserial PRIMARY KEY,
+    ---------------------------------------------
+    user_id         bigint           NOT NULL,
+    audiobook_id    bigint           NOT NULL,
+    played_on       date             NOT NULL,
+    created_at      timestamptz      NOT NULL DEFAULT now(),
+
+    FOREIGN KEY (user_id)       REFERENCES "User" (id) ON DELETE CASCADE,
+    FOREIGN KEY (audiobook_id)  REFERENCES "Audiobook" (id) ON DELETE CASCADE,
+    UNIQUE (user_id, audiobook_id, played_on)
+);
+
+CREATE INDEX IF NOT EXISTS "Play_audiobook_id_idx" ON "Play" (audiobook_id, played_on);
+
+CREATE TABLE IF NOT EXISTS "Chapter_Play"
+(
+    id              bigserial PRIMARY KEY,
+    ---------------------------------------------
+    user_id         bigint           NOT NULL,
+    chapter_id      bigint           NOT NULL,
+    played_on       date             NOT NULL,
+    created_at      timestamptz      NOT NULL DEFAULT now(),
+
+    FOREIGN KEY (user_id)       REFERENCES "User" (id) ON DELETE CASCADE,
+    FOREIGN KEY (chapter_id)    REFERENCES "Chapter" (id) ON DELETE CASCADE,
+    UNIQUE (user_id, chapter_id, played_on)
+);
+
+CREATE INDEX IF NOT EXISTS "Chapter_Play_chapter_id_idx" ON "Chapter_Play" (chapter_id);
+
+-- the stream count used to be incremented when a listener opened a book for the first time,
+-- it now counts the plays, the plays of the recorded sessions are counted by the application
+-- when it starts (see `AudiobookRepository::backfill_plays`)
+UPDATE "Audiobook" SET stream_count = 0;
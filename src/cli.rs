@@ -0,0 +1,139 @@
+use crate::database::common::DbReadOne;
+use crate::database::models::user::UserGetByUsername;
+use crate::database::repositories::media::repository::MediaRepository;
+use crate::database::repositories::user::repository::UserRepository;
+use crate::media::gc::{collect_garbage, GcOptions};
+use crate::media::storage::Storage;
+use crate::templates::utilities::format_size;
+use crate::MEDIA_GC_GRACE_PERIOD;
+
+/// `gc [--delete] [--grace-period <seconds>]` reports the garbage in the media storage,
+/// it is only removed with `--delete`
+pub async fn media_gc(
+    mut args: impl Iterator<Item = String>,
+    storage: &dyn Storage,
+    media_repo: &MediaRepository,
+) -> anyhow::Result<()> {
+    let mut options = GcOptions::new(true, MEDIA_GC_GRACE_PERIOD);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--delete" => options.dry_run = false,
+            "--grace-period" => {
+                let Some(seconds) = args.next() else {
+                    anyhow::bail!("--grace-period requires a number of seconds");
+                };
+                options.grace_period = seconds.parse()?;
+            }
+            other => anyhow::bail!("unknown option {other}, use --delete or --grace-period"),
+        }
+    }
+    print!("{}", collect_garbage(storage, media_repo, options).await?);
+    Ok(())
+}
+
+/// Parses a number of bytes, optionally with a K, M or G suffix (powers of 1024),
+/// `unlimited` is none
+fn parse_quota(value: &str) -> anyhow::Result<Option<i64>> {
+    if value == "unlimited" {
+        return Ok(None);
+    }
+    let (number, multiplier) = match value.char_indices().last() {
+        Some((index, 'K')) => (&value[..index], 1 << 10),
+        Some((index, 'M')) => (&value[..index], 1 << 20),
+        Some((index, 'G')) => (&value[..index], 1 << 30),
+        _ => (value, 1),
+    };
+    let bytes = number
+        .parse::<i64>()
+        .ok()
+        .filter(|bytes| *bytes >= 0)
+        .and_then(|bytes| bytes.checked_mul(multiplier))
+        .ok_or_else(|| {
+            anyhow::anyhow!("invalid quota {value}, use bytes, e.g. 500M, or unlimited")
+        })?;
+    Ok(Some(bytes))
+}
+
+/// `quota role <name> <size|unlimited>` sets the storage quota of a role,
+/// `quota user <username> <size|default> [--role <name>]` the quota and the role of a user
+pub async fn storage_quota(
+    mut args: impl Iterator<Item = String>,
+    user_repo: &UserRepository,
+) -> anyhow::Result<()> {
+    const USAGE: &str =
+        "use quota role <name> <size|unlimited> or quota user <username> <size|default> [--role <name>]";
+    match (args.next().as_deref(), args.next(), args.next()) {
+        (Some("role"), Some(role), Some(quota)) => {
+            let quota = parse_quota(&quota)?;
+            user_repo.set_role_quota(&role, quota).await?;
+            let quota = quota.map_or("unlimited".to_string(), |quota| format_size(&quota));
+            println!("the role {role} has a quota of {quota}");
+        }
+        (Some("user"), Some(username), Some(quota)) => {
+            let quota = match quota.as_str() {
+                "default" => None,
+                _ => match parse_quota(&quota)? {
+                    Some(bytes) => Some(bytes),
+                    None => anyhow::bail!("users without a limit have the role unlimited"),
+                },
+            };
+            let role = match (args.next().as_deref(), args.next()) {
+                (None, _) => None,
+                (Some("--role"), Some(role)) => Some(role),
+                _ => anyhow::bail!(USAGE),
+            };
+            let user = user_repo
+                .read_one(&UserGetByUsername::new(&username))
+                .await?;
+            user_repo
+                .set_user_quota(&user.id, role.as_deref(), quota)
+                .await?;
+            let usage = user_repo.read_storage_usage(&user.id).await?;
+            let quota = usage
+                .quota
+                .map_or("unlimited".to_string(), |quota| format_size(&quota));
+            println!(
+                "{username} ({}) uses {} of {quota}",
+                usage.role,
+                format_size(&usage.used())
+            );
+        }
+        _ => anyhow::bail!(USAGE),
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_quotas() {
+        assert_eq!(parse_quota("unlimited").ok(), Some(None));
+        assert_eq!(parse_quota("0").ok(), Some(Some(0)));
+        assert_eq!(parse_quota("1500").ok(), Some(Some(1500)));
+        assert_eq!(parse_quota("2K").ok(), Some(Some(2 << 10)));
+        assert_eq!(parse_quota("500M").ok(), Some(Some(500 << 20)));
+        assert_eq!(parse_quota("3G").ok(), Some(Some(3 << 30)));
+    }
+
+    #[test]
+    fn parse_invalid_quotas() {
+        for quota in [
+            "",
+            "-1",
+            "-5M",
+            "G",
+            "5T",
+            "5k",
+            "1.5G",
+            "ten",
+            "none",
+            " 5M",
+            "5 M",
+            "9223372036854775807K",
+        ] {
+            assert!(parse_quota(quota).is_err(), "{quota} should be invalid");
+        }
+    }
+}
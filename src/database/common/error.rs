@@ -28,11 +28,17 @@ pub enum BackendErrorKind {
     ChapterDoesNotExist,
     ChapterDeleted,
     ChapterUpdateParametersEmpty,
+    ChapterSuggestionDoesNotExist,
 
     GenreDeleted,
     GenreDoesNotExist,
     GenreUpdateParametersEmpty,
 
+    // --------------------------
+    // Upload errors
+    UploadDoesNotExist,
+    UploadOffsetMismatch,
+
     UnauthorizedOperation,
 }
 
@@ -75,6 +81,9 @@ impl Display for BackendErrorKind {
                     )
                 )
             }
+            ChapterSuggestionDoesNotExist => {
+                f.write_str(does_not_exist("chapter suggestion").as_str())
+            }
             AudiobookDoesNotExist => f.write_str(does_not_exist("audiobook").as_str()),
             AudiobookDeleted => f.write_str(deleted("audiobook").as_str()),
             AudiobookUpdateParametersEmpty => {
@@ -106,6 +115,13 @@ impl Display for BackendErrorKind {
                     )
                 )
             }
+            UploadDoesNotExist => f.write_str(does_not_exist("upload").as_str()),
+            UploadOffsetMismatch => {
+                write!(
+                    f,
+                    "The offset does not match the amount of data stored for the upload."
+                )
+            }
             UnauthorizedOperation => {
                 write!(
                     f,
@@ -14,6 +14,8 @@ pub struct DbQueryParams {
     pub offset: Option<i64>,
     pub book_state: Option<BookState>,
     pub fetch_deleted: bool,
+    /// Books that are not processed yet (or whose processing failed) are only shown to authors
+    pub fetch_unready: bool,
 }
 
 impl DbQueryParams {
@@ -31,6 +33,7 @@ impl DbQueryParams {
             offset,
             book_state,
             fetch_deleted,
+            fetch_unready: false,
         }
     }
 
@@ -42,6 +45,7 @@ impl DbQueryParams {
             offset: Some(offset),
             book_state,
             fetch_deleted: false,
+            fetch_unready: false,
         }
     }
 
@@ -52,6 +56,7 @@ impl DbQueryParams {
             offset: None,
             book_state,
             fetch_deleted: false,
+            fetch_unready: false,
         }
     }
     pub fn state(book_state: BookState) -> Self {
@@ -61,15 +66,18 @@ impl DbQueryParams {
             offset: None,
             book_state: Some(book_state),
             fetch_deleted: false,
+            fetch_unready: false,
         }
     }
-    pub fn deleted() -> Self {
+    /// Books of an author's studio, including the deleted and the unprocessed ones
+    pub fn studio() -> Self {
         Self {
             order: Some(DbOrderColumn::default()),
             limit: None,
             offset: None,
             book_state: None,
             fetch_deleted: true,
+            fetch_unready: true,
         }
     }
 }
@@ -82,6 +90,7 @@ impl Default for DbQueryParams {
             offset: None,
             book_state: None,
             fetch_deleted: false,
+            fetch_unready: false,
         }
     }
 }
@@ -9,20 +9,35 @@ pub fn generate_query_param_string(params: &DbQueryParams) -> String {
     if !params.fetch_deleted {
         qp_string.push_str("AND a.deleted_at IS NULL\n");
     }
+    if !params.fetch_unready {
+        qp_string.push_str("AND a.processing_state = 'ready'\n");
+    }
     if let Some(state) = &params.book_state {
         match state {
             BookState::Finished(val) => {
                 qp_string.push_str(
-                    format!("AND ((ab.playback_position / a.length > {ratio}) = {val})\n").as_str(),
+                    format!(
+                        "AND ((ab.progress_mark = 'finished' OR (ab.progress_mark = 'unmarked' \
+                        AND ab.playback_position / a.length > {ratio})) = {val})\n"
+                    )
+                    .as_str(),
                 );
             }
             BookState::Fresh(val) => {
-                qp_string.push_str(format!("AND (ab.audiobook_id IS NULL = {val})\n").as_str());
+                qp_string.push_str(
+                    format!(
+                        "AND ((ab.audiobook_id IS NULL OR ab.progress_mark = 'unplayed') = {val})\n"
+                    )
+                    .as_str(),
+                );
             }
             BookState::Active(val) => {
                 qp_string.push_str(
-                    format!("AND ((ab.playback_position / a.length <= {ratio}) = {val})\n")
-                        .as_str(),
+                    format!(
+                        "AND ((ab.progress_mark = 'unmarked' \
+                        AND ab.playback_position / a.length <= {ratio}) = {val})\n"
+                    )
+                    .as_str(),
                 );
             }
         }
@@ -1,14 +1,135 @@
 use crate::database::models::Id;
 use chrono::{DateTime, Utc};
+use serde::Deserialize;
+use std::fmt::{Display, Formatter};
 
 use crate::database::models::utilities::get_default_thumbnail;
+use crate::media::images::DisplayImage;
+use crate::{PROGRESS_BACKWARD_JUMP, PROGRESS_SUGGESTION_DISTANCE};
 
+/// State of a book the listener chose, it takes precedence over the state inferred from
+/// their position until they play the book again
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProgressMark {
+    Unmarked,
+    Finished,
+    Unplayed,
+}
+
+impl ProgressMark {
+    #[must_use]
+    pub const fn as_str(self) -> &'static str {
+        match self {
+            ProgressMark::Unmarked => "unmarked",
+            ProgressMark::Finished => "finished",
+            ProgressMark::Unplayed => "unplayed",
+        }
+    }
+}
+
+/// The column is constrained to the marks above
+impl From<String> for ProgressMark {
+    fn from(mark: String) -> Self {
+        match mark.as_str() {
+            "finished" => ProgressMark::Finished,
+            "unplayed" => ProgressMark::Unplayed,
+            _ => ProgressMark::Unmarked,
+        }
+    }
+}
+
+impl Display for ProgressMark {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Position of a listener in a book, `edited_at` is the time the device reported it at
 #[derive(sqlx::FromRow, Debug, PartialEq, Clone)]
 pub struct ActiveAudiobook {
     pub user_id: Id,
     pub audiobook_id: Id,
     pub playback_position: f64,
     pub edited_at: DateTime<Utc>,
+    pub device_id: String,
+    /// position that lost to a position reported by another device, the listener
+    /// is offered to jump back to it
+    pub suggested_position: Option<f64>,
+    pub suggested_device_id: Option<String>,
+    pub suggested_at: Option<DateTime<Utc>>,
+    #[sqlx(try_from = "String")]
+    pub progress_mark: ProgressMark,
+}
+
+/// What becomes of the suggested position of a listener
+#[derive(Debug, Clone, PartialEq)]
+pub enum PositionSuggestion {
+    Keep,
+    Clear,
+    Set {
+        position: f64,
+        device_id: String,
+        at: DateTime<Utc>,
+    },
+}
+
+/// Outcome of a reported position
+#[derive(Debug, Clone, PartialEq)]
+pub struct PositionSync {
+    pub applied: bool,
+    pub suggestion: PositionSuggestion,
+}
+
+impl ActiveAudiobook {
+    /// Decides whether the reported position replaces the current one: the later report
+    /// wins. A report that moves the position far back is applied only if it comes from
+    /// the same device, a jump back on another device (e.g. which was offline, or which
+    /// did not know about the latest position) keeps the current position as a suggestion.
+    /// The position that loses is suggested if it is far enough from the winning one.
+    /// Positions no device reported (the book was just opened) lose to any report, as their
+    /// time is the time of the server.
+    #[must_use]
+    pub fn sync(&self, update: &SetActiveAudiobook) -> PositionSync {
+        let is_far =
+            |position: f64, other: f64| (position - other).abs() > PROGRESS_SUGGESTION_DISTANCE;
+        if !self.device_id.is_empty() && update.reported_at < self.edited_at {
+            return PositionSync {
+                applied: false,
+                suggestion: if is_far(update.playback_position, self.playback_position) {
+                    PositionSuggestion::Set {
+                        position: update.playback_position,
+                        device_id: update.device_id.clone(),
+                        at: update.reported_at,
+                    }
+                } else {
+                    PositionSuggestion::Keep
+                },
+            };
+        }
+        if update.device_id != self.device_id
+            && self.playback_position - update.playback_position > PROGRESS_BACKWARD_JUMP
+        {
+            return PositionSync {
+                applied: true,
+                suggestion: PositionSuggestion::Set {
+                    position: self.playback_position,
+                    device_id: self.device_id.clone(),
+                    at: self.edited_at,
+                },
+            };
+        }
+        let reached_suggestion = self
+            .suggested_position
+            .is_some_and(|suggested| !is_far(suggested, update.playback_position));
+        PositionSync {
+            applied: true,
+            suggestion: if reached_suggestion {
+                PositionSuggestion::Clear
+            } else {
+                PositionSuggestion::Keep
+            },
+        }
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -18,7 +139,7 @@ pub struct RemoveActiveAudiobook {
 }
 
 impl RemoveActiveAudiobook {
-    #[allow(dead_code)]
+    #[must_use]
     #[inline]
     pub const fn new(user_id: Id, audiobook_id: Id) -> Self {
         Self {
@@ -28,11 +149,14 @@ impl RemoveActiveAudiobook {
     }
 }
 
+/// Position reported by a device of the listener
 #[derive(Debug, Clone)]
 pub struct SetActiveAudiobook {
     pub user_id: Id,
     pub audiobook_id: Id,
     pub playback_position: f64,
+    pub reported_at: DateTime<Utc>,
+    pub device_id: String,
 }
 
 #[derive(Debug, Clone)]
@@ -46,6 +170,8 @@ pub struct PlayedAudiobookDb {
     pub name: String,
     pub thumbnail: Option<String>,
     pub playback_position: f64,
+    pub suggested_position: Option<f64>,
+    pub suggested_at: Option<DateTime<Utc>>,
 }
 
 #[derive(Debug, Clone)]
@@ -57,8 +183,11 @@ pub struct PlayedAudiobook {
     pub is_liked: Option<bool>,
     pub path: String,
     pub name: String,
-    pub thumbnail: String,
+    pub thumbnail: DisplayImage,
     pub playback_position: f64,
+    /// position reported by another device that lost, see `ActiveAudiobook::suggested_position`
+    pub suggested_position: Option<f64>,
+    pub suggested_at: Option<DateTime<Utc>>,
 }
 
 impl From<PlayedAudiobookDb> for PlayedAudiobook {
@@ -73,6 +202,8 @@ impl From<PlayedAudiobookDb> for PlayedAudiobook {
             name: value.name,
             thumbnail: get_default_thumbnail(&value.thumbnail),
             playback_position: value.playback_position,
+            suggested_position: value.suggested_position,
+            suggested_at: value.suggested_at,
         }
     }
 }
@@ -80,11 +211,174 @@ impl From<PlayedAudiobookDb> for PlayedAudiobook {
 impl SetActiveAudiobook {
     #[must_use]
     #[inline]
-    pub const fn new(user_id: Id, audiobook_id: Id, playback_position: f64) -> Self {
+    pub fn new(
+        user_id: Id,
+        audiobook_id: Id,
+        playback_position: f64,
+        reported_at: DateTime<Utc>,
+        device_id: &str,
+    ) -> Self {
         Self {
             user_id,
             audiobook_id,
             playback_position,
+            reported_at,
+            device_id: device_id.to_owned(),
+        }
+    }
+}
+
+/// The position of the listener after a reported position
+#[derive(Debug, Clone, PartialEq)]
+pub struct ActiveAudiobookSynced {
+    pub applied: bool,
+    pub active: ActiveAudiobook,
+}
+
+/// What the listener does with their progress in a book: marks it finished, marks it
+/// unplayed (the position is reset and the book is not being listened to anymore),
+/// or starts it over
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ProgressChange {
+    Finished,
+    Unplayed,
+    Reset,
+}
+
+#[derive(Debug, Clone)]
+pub struct ProgressChangeSet {
+    pub user_id: Id,
+    pub audiobook_id: Id,
+    pub change: ProgressChange,
+    pub reported_at: DateTime<Utc>,
+    pub device_id: String,
+}
+
+impl ProgressChangeSet {
+    #[must_use]
+    #[inline]
+    pub fn new(
+        user_id: Id,
+        audiobook_id: Id,
+        change: ProgressChange,
+        reported_at: DateTime<Utc>,
+        device_id: &str,
+    ) -> Self {
+        Self {
+            user_id,
+            audiobook_id,
+            change,
+            reported_at,
+            device_id: device_id.to_owned(),
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Duration;
+
+    fn active(position: f64, device_id: &str, edited_at: DateTime<Utc>) -> ActiveAudiobook {
+        ActiveAudiobook {
+            user_id: 8,
+            audiobook_id: 1,
+            playback_position: position,
+            edited_at,
+            device_id: device_id.to_string(),
+            suggested_position: None,
+            suggested_device_id: None,
+            suggested_at: None,
+            progress_mark: ProgressMark::Unmarked,
+        }
+    }
+
+    fn report(position: f64, device_id: &str, reported_at: DateTime<Utc>) -> SetActiveAudiobook {
+        SetActiveAudiobook::new(8, 1, position, reported_at, device_id)
+    }
+
+    #[test]
+    fn sync_reports_out_of_order() {
+        let now = Utc::now();
+        let earlier = now - Duration::minutes(10);
+        let current = active(600.0, "phone", now);
+
+        // a report older than the current position loses, whichever device sent it
+        let far = report(100.0, "laptop", earlier);
+        assert_eq!(
+            current.sync(&far),
+            PositionSync {
+                applied: false,
+                suggestion: PositionSuggestion::Set {
+                    position: 100.0,
+                    device_id: "laptop".to_string(),
+                    at: earlier,
+                },
+            }
+        );
+        let near = report(590.0, "phone", earlier);
+        assert_eq!(
+            current.sync(&near),
+            PositionSync {
+                applied: false,
+                suggestion: PositionSuggestion::Keep,
+            }
+        );
+
+        // positions no device reported lose to any report
+        let opened = active(0.0, "", now);
+        assert!(opened.sync(&far).applied);
+    }
+
+    #[test]
+    fn sync_jumps_back() {
+        let now = Utc::now();
+        let later = now + Duration::minutes(10);
+        let current = active(600.0, "phone", now);
+
+        // another device moving far back keeps the current position as a suggestion
+        assert_eq!(
+            current.sync(&report(100.0, "laptop", later)),
+            PositionSync {
+                applied: true,
+                suggestion: PositionSuggestion::Set {
+                    position: 600.0,
+                    device_id: "phone".to_string(),
+                    at: now,
+                },
+            }
+        );
+        for update in [
+            report(100.0, "phone", later),
+            report(560.0, "laptop", later),
+        ] {
+            assert_eq!(
+                current.sync(&update),
+                PositionSync {
+                    applied: true,
+                    suggestion: PositionSuggestion::Keep,
+                }
+            );
+        }
+    }
+
+    #[test]
+    fn sync_reaches_the_suggestion() {
+        let now = Utc::now();
+        let later = now + Duration::minutes(10);
+        let mut current = active(100.0, "laptop", now);
+        current.suggested_position = Some(600.0);
+        current.suggested_device_id = Some("phone".to_string());
+        current.suggested_at = Some(now - Duration::minutes(5));
+
+        assert_eq!(
+            current.sync(&report(590.0, "laptop", later)).suggestion,
+            PositionSuggestion::Clear
+        );
+        assert_eq!(
+            current.sync(&report(200.0, "laptop", later)).suggestion,
+            PositionSuggestion::Keep
+        );
+    }
+}
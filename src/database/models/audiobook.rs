@@ -3,9 +3,58 @@ use crate::database::models::Id;
 use crate::CONSIDER_AUDIOBOOK_FINISHED_PERCENTAGE;
 use chrono::{DateTime, Utc};
 use serde::Deserialize;
+use std::fmt::{Display, Formatter};
 
 use crate::database::common::query_parameters::DbQueryParams;
+use crate::database::models::active_audiobook::ProgressMark;
+use crate::database::models::chapter::ChapterMoved;
 use crate::database::models::utilities::{get_default_profile_picture, get_default_thumbnail};
+use crate::media::images::DisplayImage;
+
+/// Uploaded books are checked and their derivatives (waveform, chapter suggestions and HLS
+/// renditions) are created in the background, listeners only see books that are `Ready`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProcessingState {
+    Uploaded,
+    Processing,
+    Ready,
+    Failed,
+}
+
+impl ProcessingState {
+    #[must_use]
+    pub const fn as_str(self) -> &'static str {
+        match self {
+            ProcessingState::Uploaded => "uploaded",
+            ProcessingState::Processing => "processing",
+            ProcessingState::Ready => "ready",
+            ProcessingState::Failed => "failed",
+        }
+    }
+
+    #[must_use]
+    pub const fn is_ready(self) -> bool {
+        matches!(self, ProcessingState::Ready)
+    }
+}
+
+/// The column is constrained to the states above
+impl From<String> for ProcessingState {
+    fn from(state: String) -> Self {
+        match state.as_str() {
+            "uploaded" => ProcessingState::Uploaded,
+            "processing" => ProcessingState::Processing,
+            "ready" => ProcessingState::Ready,
+            _ => ProcessingState::Failed,
+        }
+    }
+}
+
+impl Display for ProcessingState {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
 
 #[derive(sqlx::FromRow, Debug, Clone, PartialEq)]
 pub struct Audiobook {
@@ -24,6 +73,11 @@ pub struct Audiobook {
     pub created_at: DateTime<Utc>,
     pub edited_at: DateTime<Utc>,
     pub deleted_at: Option<DateTime<Utc>>,
+    #[sqlx(try_from = "String")]
+    pub processing_state: ProcessingState,
+    pub processing_error: Option<String>,
+    pub processing_started_at: Option<DateTime<Utc>>,
+    pub downloadable: bool,
 }
 
 impl HasDeletedAt for Audiobook {
@@ -49,6 +103,10 @@ pub struct AudiobookDetail {
     pub created_at: DateTime<Utc>,
     pub edited_at: DateTime<Utc>,
     pub deleted_at: Option<DateTime<Utc>>,
+    #[sqlx(try_from = "String")]
+    pub processing_state: ProcessingState,
+    pub processing_error: Option<String>,
+    pub downloadable: bool,
 
     pub username: String,
     pub email: String,
@@ -62,11 +120,18 @@ pub struct AudiobookDetail {
 
     pub playback_position: Option<f64>,
     pub active_audiobook_edited_at: Option<DateTime<Utc>>,
+    #[sqlx(try_from = "String")]
+    pub progress_mark: ProgressMark,
     pub is_liked: bool,
 }
 
 impl AudiobookDetail {
     pub fn is_finished(&self) -> bool {
+        match self.progress_mark {
+            ProgressMark::Finished => return true,
+            ProgressMark::Unplayed => return false,
+            ProgressMark::Unmarked => {}
+        }
         match self.playback_position {
             None => false,
             Some(pos) => {
@@ -78,11 +143,14 @@ impl AudiobookDetail {
         }
     }
     pub fn is_started(&self) -> bool {
-        self.playback_position.is_some()
+        self.playback_position.is_some() && self.progress_mark != ProgressMark::Unplayed
     }
 
     #[allow(dead_code)]
     pub fn is_active(&self) -> bool {
+        if self.progress_mark != ProgressMark::Unmarked {
+            return false;
+        }
         match self.playback_position {
             None => false,
             Some(pos) => {
@@ -112,18 +180,21 @@ pub struct AudiobookDisplay {
     pub stream_count: i64,
     pub like_count: i64,
     pub overall_rating: f64,
-    pub thumbnail: String,
+    pub thumbnail: DisplayImage,
     pub description: String,
     pub created_at: DateTime<Utc>,
     pub edited_at: DateTime<Utc>,
     pub deleted: bool,
+    pub processing_state: ProcessingState,
+    pub processing_error: Option<String>,
+    pub downloadable: bool,
 
     pub username: String,
     pub email: String,
     pub author_name: String,
     pub surname: String,
     pub bio: String,
-    pub profile_picture: String,
+    pub profile_picture: DisplayImage,
 
     pub genre_name: String,
     pub genre_color: String,
@@ -153,6 +224,9 @@ impl AudiobookDisplay {
             created_at: audiobook.created_at,
             edited_at: audiobook.edited_at,
             deleted: audiobook.deleted_at.map_or_else(|| false, |_v| true),
+            processing_state: audiobook.processing_state,
+            processing_error: audiobook.processing_error.clone(),
+            downloadable: audiobook.downloadable,
 
             username: audiobook.username.to_owned(),
             email: audiobook.email.to_owned(),
@@ -192,6 +266,9 @@ impl From<AudiobookDetail> for AudiobookDisplay {
             created_at: audiobook.created_at,
             edited_at: audiobook.edited_at,
             deleted: audiobook.deleted_at.map_or_else(|| false, |_v| true),
+            processing_state: audiobook.processing_state,
+            processing_error: audiobook.processing_error,
+            downloadable: audiobook.downloadable,
 
             username: audiobook.username,
             email: audiobook.email,
@@ -212,7 +289,7 @@ impl From<AudiobookDetail> for AudiobookDisplay {
 pub struct AudiobookRecommenderDisplay {
     pub id: Id,
     pub name: String,
-    pub thumbnail: String,
+    pub thumbnail: DisplayImage,
     pub author_name: String,
     pub genre_name: String,
     pub genre_color: String,
@@ -459,6 +536,7 @@ pub struct AudiobookUpdate {
     pub overall_rating: Option<f64>,
     pub thumbnail: Option<String>,
     pub description: Option<String>,
+    pub downloadable: Option<bool>,
 }
 
 impl AudiobookUpdate {
@@ -476,6 +554,7 @@ impl AudiobookUpdate {
         overall_rating: Option<&f64>,
         thumbnail: Option<String>,
         description: Option<&str>,
+        downloadable: Option<&bool>,
     ) -> Self {
         let change_to_owned = |value: &str| Some(value.to_owned());
         Self {
@@ -490,6 +569,7 @@ impl AudiobookUpdate {
             overall_rating: overall_rating.copied(),
             thumbnail,
             description: description.and_then(change_to_owned),
+            downloadable: downloadable.copied(),
         }
     }
 
@@ -505,6 +585,7 @@ impl AudiobookUpdate {
             && self.overall_rating.is_none()
             && self.description.is_none()
             && self.thumbnail.is_none()
+            && self.downloadable.is_none()
     }
 
     pub fn update_likes(id: Id, like_count: i64) -> Self {
@@ -520,6 +601,7 @@ impl AudiobookUpdate {
             overall_rating: None,
             thumbnail: None,
             description: None,
+            downloadable: None,
         }
     }
 }
@@ -602,3 +684,86 @@ pub struct AudiobookRecommenderCard {
     pub genre_name: String,
     pub genre_color: String,
 }
+
+/// At most `limit` books waiting for processing, books whose processing started more than
+/// `timeout` seconds ago are taken again (the worker processing them stopped)
+#[derive(Debug, Clone)]
+pub struct AudiobookProcessingTake {
+    pub limit: i64,
+    pub timeout: i64,
+}
+
+impl AudiobookProcessingTake {
+    #[must_use]
+    #[inline]
+    pub const fn new(limit: i64, timeout: i64) -> Self {
+        Self { limit, timeout }
+    }
+}
+
+/// Outcome of the processing, the book is ready without an `error` and failed with it
+#[derive(Debug, Clone)]
+pub struct AudiobookProcessed {
+    pub id: Id,
+    pub error: Option<String>,
+}
+
+impl AudiobookProcessed {
+    #[must_use]
+    #[inline]
+    pub const fn new(id: &Id, error: Option<String>) -> Self {
+        Self { id: *id, error }
+    }
+}
+
+/// How chapter beginnings and the positions of listeners move when the audio of a book is replaced
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PositionRemap {
+    /// Positions are scaled by the ratio of the lengths, for re-mastered books
+    Scale,
+    /// Positions are kept, those past the end of the new audio are moved to its end,
+    /// for small fixes
+    Clamp,
+}
+
+#[derive(Debug, Clone)]
+pub struct AudiobookAudioReplace {
+    pub id: Id,
+    pub file_path: String,
+    pub length: f64,
+    pub remap: PositionRemap,
+}
+
+impl AudiobookAudioReplace {
+    #[must_use]
+    #[inline]
+    pub fn new(id: &Id, file_path: &str, length: &f64, remap: PositionRemap) -> Self {
+        Self {
+            id: *id,
+            file_path: file_path.to_owned(),
+            length: *length,
+            remap,
+        }
+    }
+}
+
+#[derive(sqlx::FromRow, Debug, Clone, PartialEq)]
+pub struct AudiobookVersion {
+    pub id: Id,
+    pub audiobook_id: Id,
+    pub file_path: String,
+    pub length: f64,
+    pub replaced_at: DateTime<Utc>,
+}
+
+/// What changed when the audio of a book was replaced
+#[derive(Debug, Clone, PartialEq)]
+pub struct AudiobookAudioReplaced {
+    /// The replaced audio, kept as a previous version of the book
+    pub version: AudiobookVersion,
+    pub length: f64,
+    pub chapters_moved: Vec<ChapterMoved>,
+    /// Number of listeners whose playback position moved
+    pub listeners_moved: i64,
+}
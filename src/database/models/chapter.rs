@@ -68,6 +68,9 @@ pub struct ChapterDisplay {
     pub name: String,
     pub position: f64,
     pub order: usize,
+    /// part of the chapter the listener reached
+    pub progress: f64,
+    pub completed: bool,
 }
 #[derive(Debug, Clone)]
 pub struct ChapterCreate {
@@ -86,6 +89,24 @@ impl ChapterCreate {
     }
 }
 
+/// Chapter found in the files of an uploaded book, created together with the book
+#[derive(Debug, Clone)]
+pub struct ChapterEmbeddedCreate {
+    pub name: String,
+    pub position: f64,
+}
+
+impl ChapterEmbeddedCreate {
+    #[must_use]
+    #[inline]
+    pub fn new(name: &str, position: f64) -> Self {
+        Self {
+            name: name.to_owned(),
+            position,
+        }
+    }
+}
+
 impl ChapterSearch {
     #[allow(dead_code)]
     pub fn new(name: Option<&str>, audiobook_id: Option<&Id>) -> Self {
@@ -113,9 +134,143 @@ impl ChapterGetById {
     }
 }
 
+/// Possible chapter beginning in the middle of a long silence found in the book
+#[derive(sqlx::FromRow, Debug, PartialEq, Clone)]
+pub struct ChapterSuggestion {
+    pub id: Id,
+    pub audiobook_id: Id,
+    pub position: f64,
+    pub silence_length: f64,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone)]
+pub struct ChapterSuggestionCreate {
+    pub position: f64,
+    pub silence_length: f64,
+}
+
+impl ChapterSuggestionCreate {
+    #[must_use]
+    #[inline]
+    pub const fn new(position: f64, silence_length: f64) -> Self {
+        Self {
+            position,
+            silence_length,
+        }
+    }
+}
+
+/// Suggestions closer than `min_distance` seconds to an existing chapter are left out
+#[derive(Debug, Clone)]
+pub struct ChapterSuggestionsGetByBookId {
+    pub audiobook_id: Id,
+    pub min_distance: f64,
+}
+
+impl ChapterSuggestionsGetByBookId {
+    #[must_use]
+    #[inline]
+    pub const fn new(audiobook_id: Id, min_distance: f64) -> Self {
+        Self {
+            audiobook_id,
+            min_distance,
+        }
+    }
+}
+
+/// Accepts a single suggestion, or all suggestions of the book when `suggestion_id` is `None`
+#[derive(Debug, Clone)]
+pub struct ChapterSuggestionAccept {
+    pub audiobook_id: Id,
+    pub suggestion_id: Option<Id>,
+    pub min_distance: f64,
+}
+
+impl ChapterSuggestionAccept {
+    #[must_use]
+    #[inline]
+    pub const fn new(audiobook_id: Id, suggestion_id: Option<Id>, min_distance: f64) -> Self {
+        Self {
+            audiobook_id,
+            suggestion_id,
+            min_distance,
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct CreateChapterForm {
     pub book_id: Id,
     pub current_chapters: Vec<ChapterDisplay>,
     pub book_filepath: String,
 }
+
+/// Chapter whose beginning moved when the audio of its book was replaced
+#[derive(sqlx::FromRow, Debug, Clone, PartialEq)]
+pub struct ChapterMoved {
+    pub name: String,
+    pub previous_position: f64,
+    pub position: f64,
+}
+
+/// Part of the book a listener went through, from `start` to `end`; a reported position
+/// is a part of zero length
+#[derive(Debug, Clone)]
+pub struct ChapterProgressCreate {
+    pub user_id: Id,
+    pub audiobook_id: Id,
+    pub start: f64,
+    pub end: f64,
+}
+
+impl ChapterProgressCreate {
+    #[must_use]
+    #[inline]
+    pub const fn new(user_id: Id, audiobook_id: Id, start: f64, end: f64) -> Self {
+        Self {
+            user_id,
+            audiobook_id,
+            start,
+            end,
+        }
+    }
+}
+
+#[derive(sqlx::FromRow, Debug, PartialEq, Clone)]
+pub struct ChapterProgress {
+    pub chapter_id: Id,
+    pub progress: f64,
+    pub completed_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Debug, Clone)]
+pub struct ChapterProgressGet {
+    pub user_id: Id,
+    pub audiobook_id: Id,
+}
+
+impl ChapterProgressGet {
+    #[must_use]
+    #[inline]
+    pub const fn new(user_id: Id, audiobook_id: Id) -> Self {
+        Self {
+            user_id,
+            audiobook_id,
+        }
+    }
+}
+
+/// How many listeners started, completed and got no further than the chapter, listeners
+/// who are still listening to the chapter count as stopped there as well; `plays` are
+/// the plays of the chapter, see `AudiobookRepository::count_plays`
+#[derive(sqlx::FromRow, Debug, PartialEq, Clone)]
+pub struct ChapterRetention {
+    pub id: Id,
+    pub name: String,
+    pub position: f64,
+    pub listeners: i64,
+    pub completed: i64,
+    pub stopped: i64,
+    pub plays: i64,
+}
@@ -0,0 +1,34 @@
+use crate::database::models::Id;
+use chrono::{DateTime, Utc};
+
+/// Offline download of a book, `file_path` and `length` describe the audio that was downloaded
+#[derive(sqlx::FromRow, Debug, PartialEq, Clone)]
+pub struct AudiobookDownload {
+    pub id: Id,
+    pub user_id: Id,
+    pub audiobook_id: Id,
+    pub file_path: String,
+    pub length: f64,
+    pub downloaded_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone)]
+pub struct AudiobookDownloadCreate {
+    pub user_id: Id,
+    pub audiobook_id: Id,
+    pub file_path: String,
+    pub length: f64,
+}
+
+impl AudiobookDownloadCreate {
+    #[must_use]
+    #[inline]
+    pub fn new(user_id: &Id, audiobook_id: &Id, file_path: &str, length: &f64) -> Self {
+        Self {
+            user_id: *user_id,
+            audiobook_id: *audiobook_id,
+            file_path: file_path.to_owned(),
+            length: *length,
+        }
+    }
+}
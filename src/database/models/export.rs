@@ -0,0 +1,128 @@
+use crate::database::models::Id;
+use chrono::{DateTime, Utc};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExportState {
+    Pending,
+    Exporting,
+    Ready,
+    Failed,
+}
+
+/// The column is constrained to the states above
+impl From<String> for ExportState {
+    fn from(state: String) -> Self {
+        match state.as_str() {
+            "pending" => ExportState::Pending,
+            "exporting" => ExportState::Exporting,
+            "ready" => ExportState::Ready,
+            _ => ExportState::Failed,
+        }
+    }
+}
+
+/// The latest M4B export of a book
+#[derive(sqlx::FromRow, Debug, Clone, PartialEq)]
+pub struct AudiobookExport {
+    pub audiobook_id: Id,
+    /// Digest of everything the export is made of, see `export_fingerprint`
+    pub fingerprint: String,
+    #[sqlx(try_from = "String")]
+    pub state: ExportState,
+    pub file_path: Option<String>,
+    pub size: Option<i64>,
+    pub error: Option<String>,
+    pub requested_at: DateTime<Utc>,
+    pub started_at: Option<DateTime<Utc>>,
+    pub finished_at: Option<DateTime<Utc>>,
+}
+
+impl AudiobookExport {
+    /// The stored export, if it was made of the current state of the book
+    #[must_use]
+    pub fn current_file(&self, fingerprint: &str) -> Option<&str> {
+        match self.state {
+            ExportState::Ready if self.fingerprint == fingerprint => self.file_path.as_deref(),
+            _ => None,
+        }
+    }
+
+    #[must_use]
+    pub const fn is_running(&self) -> bool {
+        matches!(self.state, ExportState::Pending | ExportState::Exporting)
+    }
+}
+
+/// Export of the book in the state described by `fingerprint`
+#[derive(Debug, Clone)]
+pub struct AudiobookExportRequest {
+    pub audiobook_id: Id,
+    pub fingerprint: String,
+}
+
+impl AudiobookExportRequest {
+    #[must_use]
+    #[inline]
+    pub fn new(audiobook_id: &Id, fingerprint: &str) -> Self {
+        Self {
+            audiobook_id: *audiobook_id,
+            fingerprint: fingerprint.to_owned(),
+        }
+    }
+}
+
+/// At most `limit` requested exports, exports running for longer than `timeout` seconds
+/// are handed out again
+#[derive(Debug, Clone)]
+pub struct AudiobookExportsTake {
+    pub limit: i64,
+    pub timeout: i64,
+}
+
+impl AudiobookExportsTake {
+    #[must_use]
+    #[inline]
+    pub const fn new(limit: i64, timeout: i64) -> Self {
+        Self { limit, timeout }
+    }
+}
+
+/// Outcome of an export taken when it was requested at `requested_at`, the export is ready
+/// with a `file_path` and failed with an `error`
+#[derive(Debug, Clone)]
+pub struct AudiobookExportFinish {
+    pub audiobook_id: Id,
+    pub requested_at: DateTime<Utc>,
+    pub fingerprint: String,
+    pub file_path: Option<String>,
+    pub size: Option<i64>,
+    pub error: Option<String>,
+}
+
+impl AudiobookExportFinish {
+    #[must_use]
+    #[inline]
+    pub fn ready(export: &AudiobookExport, fingerprint: &str, file_path: &str, size: i64) -> Self {
+        Self {
+            audiobook_id: export.audiobook_id,
+            requested_at: export.requested_at,
+            fingerprint: fingerprint.to_owned(),
+            file_path: Some(file_path.to_owned()),
+            size: Some(size),
+            error: None,
+        }
+    }
+
+    #[must_use]
+    #[inline]
+    pub fn failed(export: &AudiobookExport, error: &str) -> Self {
+        Self {
+            audiobook_id: export.audiobook_id,
+            requested_at: export.requested_at,
+            fingerprint: export.fingerprint.clone(),
+            file_path: None,
+            size: None,
+            error: Some(error.to_owned()),
+        }
+    }
+}
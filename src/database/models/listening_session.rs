@@ -0,0 +1,93 @@
+use crate::database::models::Id;
+use chrono::{DateTime, Duration, Utc};
+
+/// Listening of a book from `start_position` to `end_position`, sessions end when
+/// the player pauses or seeks, and longer ones are cut into pieces by the player
+#[derive(sqlx::FromRow, Debug, PartialEq, Clone)]
+pub struct ListeningSession {
+    pub id: Id,
+    pub user_id: Id,
+    pub audiobook_id: Id,
+    pub start_position: f64,
+    pub end_position: f64,
+    pub started_at: DateTime<Utc>,
+    pub ended_at: DateTime<Utc>,
+    pub client: String,
+    pub device_id: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct ListeningSessionCreate {
+    pub user_id: Id,
+    pub audiobook_id: Id,
+    pub start_position: f64,
+    pub end_position: f64,
+    pub started_at: DateTime<Utc>,
+    pub ended_at: DateTime<Utc>,
+    pub device_id: String,
+    pub client: String,
+}
+
+impl ListeningSessionCreate {
+    /// Session which ended at `ended_at`, after `duration` seconds of listening
+    #[must_use]
+    #[inline]
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        user_id: &Id,
+        audiobook_id: &Id,
+        start_position: &f64,
+        end_position: &f64,
+        ended_at: &DateTime<Utc>,
+        duration: &f64,
+        device_id: &str,
+        client: &str,
+    ) -> Self {
+        let ended_at = *ended_at;
+        Self {
+            user_id: *user_id,
+            audiobook_id: *audiobook_id,
+            start_position: *start_position,
+            end_position: *end_position,
+            started_at: ended_at - Duration::milliseconds((duration * 1000.0) as i64),
+            ended_at,
+            device_id: device_id.to_owned(),
+            client: client.to_owned(),
+        }
+    }
+}
+
+/// Sessions of a listener, newest first, consecutive pieces of a session are joined
+#[derive(Debug, Clone)]
+pub struct ListeningHistoryGet {
+    pub user_id: Id,
+    pub audiobook_id: Option<Id>,
+    pub limit: i64,
+    pub offset: i64,
+}
+
+impl ListeningHistoryGet {
+    #[must_use]
+    #[inline]
+    pub const fn new(user_id: Id, audiobook_id: Option<Id>, limit: i64, offset: i64) -> Self {
+        Self {
+            user_id,
+            audiobook_id,
+            limit,
+            offset,
+        }
+    }
+}
+
+#[derive(sqlx::FromRow, Debug, PartialEq, Clone)]
+pub struct ListeningHistoryEntry {
+    pub audiobook_id: Id,
+    pub audiobook_name: String,
+    pub start_position: f64,
+    pub end_position: f64,
+    pub started_at: DateTime<Utc>,
+    pub ended_at: DateTime<Utc>,
+    /// seconds
+    pub listened: f64,
+    pub client: String,
+}
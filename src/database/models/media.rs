@@ -0,0 +1,62 @@
+use crate::database::models::Id;
+use chrono::{DateTime, Utc};
+
+/// Stored file (audio or image) that books and users point to, addressed by the SHA-256
+/// of its contents so that a file uploaded more than once is stored once
+#[derive(sqlx::FromRow, Debug, PartialEq, Eq, Clone)]
+pub struct Media {
+    pub id: Id,
+    pub path: String,
+    pub digest: Option<String>,
+    pub size: i64,
+    pub ref_count: i64,
+    pub created_at: DateTime<Utc>,
+    pub claimed_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone)]
+pub struct MediaClaim {
+    pub path: String,
+    pub digest: String,
+    pub size: i64,
+}
+
+impl MediaClaim {
+    #[must_use]
+    #[inline]
+    pub fn new(path: &str, digest: &str, size: i64) -> Self {
+        Self {
+            path: path.to_owned(),
+            digest: digest.to_owned(),
+            size,
+        }
+    }
+}
+
+/// Media in `paths` (all media if `None`) that lost their last reference, and were
+/// not claimed during the last `grace_period` seconds
+#[derive(Debug, Clone)]
+pub struct MediaUnreferenced {
+    pub paths: Option<Vec<String>>,
+    pub grace_period: i64,
+}
+
+impl MediaUnreferenced {
+    #[must_use]
+    #[inline]
+    pub const fn new(paths: Vec<String>, grace_period: i64) -> Self {
+        Self {
+            paths: Some(paths),
+            grace_period,
+        }
+    }
+
+    #[must_use]
+    #[inline]
+    pub const fn all(grace_period: i64) -> Self {
+        Self {
+            paths: None,
+            grace_period,
+        }
+    }
+}
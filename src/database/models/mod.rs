@@ -2,8 +2,16 @@ pub(crate) mod active_audiobook;
 pub(crate) mod audiobook;
 pub(crate) mod bookmark;
 pub(crate) mod chapter;
+pub(crate) mod download;
+pub(crate) mod export;
 pub(crate) mod genre;
+pub(crate) mod listening_session;
+pub(crate) mod media;
+pub(crate) mod play;
+pub(crate) mod play_event;
 pub(crate) mod rating;
+pub(crate) mod recommender_queue;
+pub(crate) mod upload;
 pub(crate) mod user;
 mod utilities;
 
@@ -0,0 +1,24 @@
+use crate::database::models::Id;
+use chrono::NaiveDate;
+
+/// Plays of a book on a day
+#[derive(sqlx::FromRow, Debug, PartialEq, Eq, Clone)]
+pub struct DailyPlays {
+    pub played_on: NaiveDate,
+    pub plays: i64,
+}
+
+#[derive(Debug, Clone)]
+pub struct DailyPlaysGet {
+    pub audiobook_id: Id,
+    /// number of the latest days
+    pub days: i32,
+}
+
+impl DailyPlaysGet {
+    #[must_use]
+    #[inline]
+    pub const fn new(audiobook_id: Id, days: i32) -> Self {
+        Self { audiobook_id, days }
+    }
+}
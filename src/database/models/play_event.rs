@@ -0,0 +1,27 @@
+use crate::database::models::Id;
+use chrono::{DateTime, Utc};
+
+#[derive(sqlx::FromRow, Debug, PartialEq, Eq, Clone)]
+pub struct PlayEvent {
+    pub id: Id,
+    pub user_id: Id,
+    pub audiobook_id: Id,
+    pub created_at: DateTime<Utc>,
+}
+
+#[derive(Debug, Clone)]
+pub struct PlayEventCreate {
+    pub user_id: Id,
+    pub audiobook_id: Id,
+}
+
+impl PlayEventCreate {
+    #[must_use]
+    #[inline]
+    pub const fn new(user_id: Id, audiobook_id: Id) -> Self {
+        Self {
+            user_id,
+            audiobook_id,
+        }
+    }
+}
@@ -0,0 +1,47 @@
+use crate::database::models::Id;
+use chrono::{DateTime, Utc};
+
+/// Book waiting to be registered in the recommender
+#[derive(sqlx::FromRow, Debug, PartialEq, Eq, Clone)]
+pub struct RecommenderTask {
+    pub audiobook_id: Id,
+    pub attempts: i32,
+    pub last_error: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub next_attempt_at: DateTime<Utc>,
+}
+
+/// At most `limit` tasks that are due, they are not handed out again for `lease` seconds
+#[derive(Debug, Clone)]
+pub struct RecommenderTasksTake {
+    pub limit: i64,
+    pub lease: i64,
+}
+
+impl RecommenderTasksTake {
+    #[must_use]
+    #[inline]
+    pub const fn new(limit: i64, lease: i64) -> Self {
+        Self { limit, lease }
+    }
+}
+
+/// Failed registration, retried after `delay` seconds
+#[derive(Debug, Clone)]
+pub struct RecommenderTaskPostpone {
+    pub audiobook_id: Id,
+    pub delay: i64,
+    pub error: String,
+}
+
+impl RecommenderTaskPostpone {
+    #[must_use]
+    #[inline]
+    pub fn new(audiobook_id: &Id, delay: i64, error: &str) -> Self {
+        Self {
+            audiobook_id: *audiobook_id,
+            delay,
+            error: error.to_owned(),
+        }
+    }
+}
@@ -0,0 +1,113 @@
+use crate::database::models::Id;
+use chrono::{DateTime, Utc};
+use uuid::Uuid;
+
+/// Resumable (tus) upload of a book, the metadata of the book are stored with it so that
+/// the book can be created once the last chunk arrives
+#[derive(sqlx::FromRow, Debug, PartialEq, Eq, Clone)]
+pub struct Upload {
+    pub id: Uuid,
+    pub user_id: Id,
+    pub genre_id: Id,
+    pub name: String,
+    pub description: String,
+    pub file_name: Option<String>,
+    pub content_type: Option<String>,
+    pub upload_length: i64,
+    pub upload_offset: i64,
+    pub created_at: DateTime<Utc>,
+    pub edited_at: DateTime<Utc>,
+    pub expires_at: DateTime<Utc>,
+}
+
+impl Upload {
+    #[must_use]
+    pub fn is_complete(&self) -> bool {
+        self.upload_offset == self.upload_length
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct UploadCreate {
+    pub id: Uuid,
+    pub user_id: Id,
+    pub genre_id: Id,
+    pub name: String,
+    pub description: String,
+    pub file_name: Option<String>,
+    pub content_type: Option<String>,
+    pub upload_length: i64,
+    pub expires_at: DateTime<Utc>,
+}
+
+impl UploadCreate {
+    #[must_use]
+    #[inline]
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        id: Uuid,
+        user_id: &Id,
+        genre_id: &Id,
+        name: &str,
+        description: &str,
+        file_name: Option<String>,
+        content_type: Option<String>,
+        upload_length: i64,
+        expires_at: DateTime<Utc>,
+    ) -> Self {
+        Self {
+            id,
+            user_id: *user_id,
+            genre_id: *genre_id,
+            name: name.to_owned(),
+            description: description.to_owned(),
+            file_name,
+            content_type,
+            upload_length,
+            expires_at,
+        }
+    }
+}
+
+/// Uploads are only visible to the user who created them
+#[derive(Debug, Clone)]
+pub struct UploadGetById {
+    pub id: Uuid,
+    pub user_id: Id,
+}
+
+impl UploadGetById {
+    #[must_use]
+    #[inline]
+    pub const fn new(id: Uuid, user_id: Id) -> Self {
+        Self { id, user_id }
+    }
+}
+
+/// Moves the offset of the upload after a chunk was written, `expected_offset` is the offset
+/// the chunk was appended at
+#[derive(Debug, Clone)]
+pub struct UploadOffsetUpdate {
+    pub id: Uuid,
+    pub expected_offset: i64,
+    pub upload_offset: i64,
+    pub expires_at: DateTime<Utc>,
+}
+
+impl UploadOffsetUpdate {
+    #[must_use]
+    #[inline]
+    pub const fn new(
+        id: Uuid,
+        expected_offset: i64,
+        upload_offset: i64,
+        expires_at: DateTime<Utc>,
+    ) -> Self {
+        Self {
+            id,
+            expected_offset,
+            upload_offset,
+            expires_at,
+        }
+    }
+}
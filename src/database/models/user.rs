@@ -1,6 +1,7 @@
 use crate::database::common::HasDeletedAt;
 use crate::database::models::utilities::get_default_profile_picture;
 use crate::database::models::Id;
+use crate::media::images::DisplayImage;
 use chrono::{DateTime, Utc};
 use serde::Deserialize;
 
@@ -35,7 +36,7 @@ pub struct UserDisplay {
     pub name: String,
     pub surname: String,
     pub bio: String,
-    pub profile_picture: String,
+    pub profile_picture: DisplayImage,
     pub password_hash: String,
     pub password_salt: String,
     pub created_at: DateTime<Utc>,
@@ -254,3 +255,31 @@ impl UserUpdatePassword {
         }
     }
 }
+
+/// Bytes of media stored by a user (the audio and covers of their books and their profile
+/// picture, each file counted once) and of their unfinished uploads, against the quota
+/// of the user or of their role
+#[derive(sqlx::FromRow, Debug, Clone, PartialEq, Eq)]
+pub struct StorageUsage {
+    pub role: String,
+    pub stored: i64,
+    pub uploading: i64,
+    /// `None` for no limit
+    pub quota: Option<i64>,
+}
+
+impl StorageUsage {
+    #[must_use]
+    pub const fn used(&self) -> i64 {
+        self.stored + self.uploading
+    }
+
+    /// Whether `size` more bytes fit into the quota
+    #[must_use]
+    pub const fn allows(&self, size: i64) -> bool {
+        match self.quota {
+            Some(quota) => self.used() + size <= quota,
+            None => true,
+        }
+    }
+}
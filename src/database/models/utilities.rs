@@ -1,11 +1,12 @@
-pub fn get_default_profile_picture(profile_picture: &Option<String>) -> String {
-    profile_picture
-        .clone()
-        .unwrap_or_else(|| "/static/images/profile_picture.png".to_string())
+use crate::media::images::DisplayImage;
+
+pub fn get_default_profile_picture(profile_picture: &Option<String>) -> DisplayImage {
+    DisplayImage::new(
+        profile_picture.as_deref(),
+        "/static/images/profile_picture.png",
+    )
 }
 
-pub fn get_default_thumbnail(thumbnail: &Option<String>) -> String {
-    thumbnail
-        .clone()
-        .unwrap_or_else(|| "/static/images/thumbnail.png".to_string())
+pub fn get_default_thumbnail(thumbnail: &Option<String>) -> DisplayImage {
+    DisplayImage::new(thumbnail.as_deref(), "/static/images/thumbnail.png")
 }
@@ -11,17 +11,33 @@ use async_trait::async_trait;
 
 use crate::database::common::utilities::generate_query_param_string;
 use crate::database::models::active_audiobook::{
-    ActiveAudiobook, PlayedAudiobook, PlayedAudiobookDb, RemoveActiveAudiobook, SetActiveAudiobook,
+    ActiveAudiobook, ActiveAudiobookSynced, PlayedAudiobook, PlayedAudiobookDb, PositionSuggestion,
+    ProgressChange, ProgressChangeSet, ProgressMark, RemoveActiveAudiobook, SetActiveAudiobook,
 };
+use chrono::{Duration, NaiveTime};
 use sqlx::{Postgres, Transaction};
 
 use crate::database::common::utilities::entity_is_correct;
 use crate::database::models::audiobook::{
-    Audiobook, AudiobookCreate, AudiobookDelete, AudiobookDetail, AudiobookDisplay,
-    AudiobookGetById, AudiobookGetByIdJoin, AudiobookRecommenderCard, AudiobookRecommenderForm,
-    AudiobookSearch, AudiobookUpdate, QuickSearch,
+    Audiobook, AudiobookAudioReplace, AudiobookAudioReplaced, AudiobookCreate, AudiobookDelete,
+    AudiobookDetail, AudiobookDisplay, AudiobookGetById, AudiobookGetByIdJoin, AudiobookProcessed,
+    AudiobookProcessingTake, AudiobookRecommenderCard, AudiobookRecommenderForm, AudiobookSearch,
+    AudiobookUpdate, AudiobookVersion, PositionRemap, QuickSearch,
 };
+use crate::database::models::chapter::{
+    ChapterEmbeddedCreate, ChapterMoved, ChapterProgressCreate,
+};
+use crate::database::models::download::{AudiobookDownload, AudiobookDownloadCreate};
+use crate::database::models::listening_session::{
+    ListeningHistoryEntry, ListeningHistoryGet, ListeningSession, ListeningSessionCreate,
+};
+use crate::database::models::play::{DailyPlays, DailyPlaysGet};
+use crate::database::models::play_event::{PlayEvent, PlayEventCreate};
 use crate::database::models::Id;
+use crate::{
+    CONSIDER_AUDIOBOOK_FINISHED_PERCENTAGE, MAX_PLAYBACK_RATE, PLAY_MIN_LISTENED_PERCENTAGE,
+    PLAY_MIN_LISTENED_SECONDS,
+};
 
 #[derive(Clone)]
 pub struct AudiobookRepository {
@@ -60,6 +76,7 @@ impl AudiobookRepository {
             r#"
                 SELECT A.id as book_id, A.file_path AS path, A.thumbnail as thumbnail,
                     A.name AS name, ACT.playback_position AS playback_position,
+                ACT.suggested_position AS suggested_position, ACT.suggested_at AS suggested_at,
                     B.edited_at IS NOT NULL AS is_liked, U.id as author_id,
                     U.name AS author_name, U.surname As author_surname
                 FROM "Active_Audiobook" ACT
@@ -93,6 +110,497 @@ impl AudiobookRepository {
         Ok(())
     }
 
+    /// Function which records that a listener started streaming the book. Every start
+    /// of the stream is recorded, unlike plays (see `count_plays`), which are counted
+    /// from what the listener heard.
+    ///
+    /// # Params
+    /// - `params`: structure containing the listener and the book
+    ///
+    /// # Returns
+    /// - `Ok(event)`: the recorded event
+    /// - `Err(_)`: otherwise
+    pub async fn record_play_event(&self, params: &PlayEventCreate) -> DbResultSingle<PlayEvent> {
+        let play_event = sqlx::query_as!(
+            PlayEvent,
+            r#"
+            INSERT INTO "Play_Event" (user_id, audiobook_id)
+            VALUES ($1, $2)
+            RETURNING *
+            "#,
+            params.user_id,
+            params.audiobook_id,
+        )
+        .fetch_one(&self.pool_handler.pool)
+        .await?;
+
+        Ok(play_event)
+    }
+
+    /// Function which records an offline download of a book
+    ///
+    /// # Params
+    /// - `params`: structure containing the listener, the book and its downloaded audio
+    ///
+    /// # Returns
+    /// - `Ok(download)`: the recorded download
+    /// - `Err(_)`: otherwise
+    pub async fn record_download(
+        &self,
+        params: &AudiobookDownloadCreate,
+    ) -> DbResultSingle<AudiobookDownload> {
+        let download = sqlx::query_as!(
+            AudiobookDownload,
+            r#"
+            INSERT INTO "Audiobook_Download" (user_id, audiobook_id, file_path, length)
+            VALUES ($1, $2, $3, $4)
+            RETURNING *
+            "#,
+            params.user_id,
+            params.audiobook_id,
+            params.file_path,
+            params.length,
+        )
+        .fetch_one(&self.pool_handler.pool)
+        .await?;
+
+        Ok(download)
+    }
+
+    /// Function which reads an offline download of a book
+    ///
+    /// # Params
+    /// - `download_id`: id of the download
+    ///
+    /// # Returns
+    /// - `Ok(Some(download))`: the download
+    /// - `Ok(None)`: if there is no such download
+    /// - `Err(_)`: otherwise
+    pub async fn read_download(
+        &self,
+        download_id: &Id,
+    ) -> DbResultSingle<Option<AudiobookDownload>> {
+        let download = sqlx::query_as!(
+            AudiobookDownload,
+            r#"
+            SELECT * FROM "Audiobook_Download"
+            WHERE id = $1
+            "#,
+            download_id,
+        )
+        .fetch_optional(&self.pool_handler.pool)
+        .await?;
+
+        Ok(download)
+    }
+
+    /// Function which appends a listening session to the history of the listener,
+    /// the end of the session is the position reported by the device.
+    ///
+    /// The position the listener resumes from is kept in `Active_Audiobook` rather than
+    /// derived from the sessions, as the position also changes without a session (positions
+    /// reported by the player or queued by offline devices, resetting the progress). The end
+    /// of the session is synced into it in the same transaction with the same rules as
+    /// a reported position (see `ActiveAudiobook::sync`), so a session reported late does not
+    /// move the position back, it is offered as a suggestion instead.
+    ///
+    /// # Params
+    /// - `params`: structure containing the listener, the book and the listened part
+    ///
+    /// # Returns
+    /// - `Ok(session)`: the recorded session
+    /// - `Err(_)`: otherwise
+    pub async fn record_listening_session(
+        &self,
+        params: &ListeningSessionCreate,
+    ) -> DbResultSingle<ListeningSession> {
+        let mut transaction = self.pool_handler.pool.begin().await?;
+
+        let session = sqlx::query_as!(
+            ListeningSession,
+            r#"
+            INSERT INTO "Listening_Session"
+                (user_id, audiobook_id, start_position, end_position, started_at, ended_at,
+                 device_id, client)
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8)
+            RETURNING *
+            "#,
+            params.user_id,
+            params.audiobook_id,
+            params.start_position,
+            params.end_position,
+            params.started_at,
+            params.ended_at,
+            params.device_id,
+            params.client,
+        )
+        .fetch_one(transaction.as_mut())
+        .await?;
+
+        // the resume position is denormalized, it has to agree with the recorded sessions
+        AudiobookRepository::sync_active_audiobook(
+            &SetActiveAudiobook::new(
+                session.user_id,
+                session.audiobook_id,
+                session.end_position,
+                session.ended_at,
+                &session.device_id,
+            ),
+            &mut transaction,
+        )
+        .await?;
+        AudiobookRepository::record_chapter_progress(
+            &ChapterProgressCreate::new(
+                session.user_id,
+                session.audiobook_id,
+                session.start_position,
+                session.end_position,
+            ),
+            &mut transaction,
+        )
+        .await?;
+        AudiobookRepository::count_plays(&session, &mut transaction).await?;
+
+        transaction.commit().await?;
+        Ok(session)
+    }
+
+    /// Function which counts the plays of the book and of its chapters the session completes.
+    /// The listener plays the book (or a chapter) once the sessions of the day the session
+    /// ended on cover `PLAY_MIN_LISTENED_SECONDS` of it, or `PLAY_MIN_LISTENED_PERCENTAGE`
+    /// of it if that is less, and plays it at most once a day. Parts of the book heard
+    /// repeatedly count once, and a session covers at most as much of the book as could be
+    /// played at `MAX_PLAYBACK_RATE` in its duration. Plays of the book are added to its
+    /// stream count.
+    ///
+    /// # Params
+    /// - `session`: the recorded session
+    /// - `transaction_handle` mutable reference to an ongoing transaction
+    ///
+    /// # Returns
+    /// - `Ok(())`: on success
+    /// - `Err(_)`: otherwise
+    pub async fn count_plays<'a>(
+        session: &ListeningSession,
+        transaction_handle: &mut Transaction<'a, Postgres>,
+    ) -> DbResultSingle<()> {
+        let played_on = session.ended_at.date_naive();
+        let day_start = played_on.and_time(NaiveTime::MIN).and_utc();
+        let day_end = day_start + Duration::days(1);
+        let ratio = PLAY_MIN_LISTENED_PERCENTAGE / 100f64;
+
+        // the sessions of the day merged into the distinct ranges of the book they cover
+        let covered = sqlx::query!(
+            r#"
+            WITH sessions AS (
+                SELECT
+                    start_position AS range_start,
+                    start_position + LEAST(
+                        end_position - start_position,
+                        EXTRACT(EPOCH FROM ended_at - started_at)::float8 * $5::float8
+                    ) AS range_end
+                FROM "Listening_Session"
+                WHERE
+                    user_id = $1
+                    AND audiobook_id = $2
+                    AND ended_at >= $3
+                    AND ended_at < $4
+                    AND end_position > start_position
+            ), starts AS (
+                -- a range starts with a session starting after all the previous ones ended
+                SELECT
+                    range_start,
+                    range_end,
+                    CASE WHEN range_start <= MAX(range_end) OVER (
+                        ORDER BY range_start, range_end
+                        ROWS BETWEEN UNBOUNDED PRECEDING AND 1 PRECEDING
+                    ) THEN 0 ELSE 1 END AS starts_range
+                FROM sessions
+            ), ranges AS (
+                SELECT
+                    range_start,
+                    range_end,
+                    SUM(starts_range) OVER (
+                        ORDER BY range_start, range_end ROWS UNBOUNDED PRECEDING
+                    ) AS range_number
+                FROM starts
+            )
+            SELECT MIN(range_start) AS "range_start!", MAX(range_end) AS "range_end!"
+            FROM ranges
+            GROUP BY range_number
+            "#,
+            session.user_id,
+            session.audiobook_id,
+            day_start,
+            day_end,
+            MAX_PLAYBACK_RATE,
+        )
+        .fetch_all(transaction_handle.as_mut())
+        .await?;
+        let (range_starts, range_ends): (Vec<f64>, Vec<f64>) = covered
+            .into_iter()
+            .map(|range| (range.range_start, range.range_end))
+            .unzip();
+        let listened: f64 = range_starts
+            .iter()
+            .zip(&range_ends)
+            .map(|(start, end)| end - start)
+            .sum();
+
+        let play = sqlx::query!(
+            r#"
+            INSERT INTO "Play" (user_id, audiobook_id, played_on)
+            SELECT $1, $2, $3
+            FROM "Audiobook" AS A
+            WHERE A.id = $2 AND $4::float8 >= LEAST($5, A.length * $6)
+            ON CONFLICT (user_id, audiobook_id, played_on) DO NOTHING
+            RETURNING id
+            "#,
+            session.user_id,
+            session.audiobook_id,
+            played_on,
+            listened,
+            PLAY_MIN_LISTENED_SECONDS,
+            ratio,
+        )
+        .fetch_optional(transaction_handle.as_mut())
+        .await?;
+        if play.is_some() {
+            AudiobookRepository::increment_stream_count(&session.audiobook_id, transaction_handle)
+                .await?;
+        }
+
+        sqlx::query!(
+            r#"
+            WITH bounds AS (
+                SELECT
+                    C.id,
+                    C.position AS chapter_start,
+                    COALESCE(LEAD(C.position) OVER (ORDER BY C.position), A.length) AS chapter_end
+                FROM "Chapter" AS C
+                    JOIN "Audiobook" AS A ON A.id = C.audiobook_id
+                WHERE C.audiobook_id = $2 AND C.deleted_at IS NULL
+            ), listened AS (
+                SELECT
+                    B.id,
+                    B.chapter_end - B.chapter_start AS length,
+                    SUM(GREATEST(
+                        LEAST(R.range_end, B.chapter_end)
+                            - GREATEST(R.range_start, B.chapter_start),
+                        0
+                    )) AS seconds
+                FROM bounds AS B
+                    CROSS JOIN UNNEST($6::float8[], $7::float8[]) AS R (range_start, range_end)
+                WHERE
+                    B.chapter_end > B.chapter_start
+                    AND B.chapter_end > $8
+                    AND B.chapter_start < $9
+                GROUP BY B.id, B.chapter_start, B.chapter_end
+            )
+            INSERT INTO "Chapter_Play" (user_id, chapter_id, played_on)
+            SELECT $1, id, $3
+            FROM listened
+            WHERE seconds >= LEAST($4, length * $5)
+            ON CONFLICT (user_id, chapter_id, played_on) DO NOTHING
+            "#,
+            session.user_id,
+            session.audiobook_id,
+            played_on,
+            PLAY_MIN_LISTENED_SECONDS,
+            ratio,
+            &range_starts,
+            &range_ends,
+            session.start_position,
+            session.end_position,
+        )
+        .execute(transaction_handle.as_mut())
+        .await?;
+        Ok(())
+    }
+
+    /// Function which counts the plays of the recorded listening sessions once, so that
+    /// the sessions recorded before plays were counted are counted too. Nothing is done
+    /// if any play was counted already.
+    ///
+    /// # Returns
+    /// - `Ok(sessions)`: the number of sessions whose plays were counted
+    /// - `Err(_)`: otherwise
+    pub async fn backfill_plays(&self) -> DbResultSingle<usize> {
+        let mut transaction = self.pool_handler.pool.begin().await?;
+
+        let counted = sqlx::query_scalar!(r#"SELECT EXISTS (SELECT 1 FROM "Play") AS "counted!""#)
+            .fetch_one(transaction.as_mut())
+            .await?;
+        if counted {
+            return Ok(0);
+        }
+
+        let sessions = sqlx::query_as!(
+            ListeningSession,
+            r#"
+            SELECT *
+            FROM "Listening_Session"
+            ORDER BY ended_at, id
+            "#
+        )
+        .fetch_all(transaction.as_mut())
+        .await?;
+        for session in &sessions {
+            AudiobookRepository::count_plays(session, &mut transaction).await?;
+        }
+
+        transaction.commit().await?;
+        Ok(sessions.len())
+    }
+
+    /// Function which counts the plays of the book on each of the latest days
+    ///
+    /// # Params
+    /// - `params`: structure containing the id of the book and the number of days
+    ///
+    /// # Returns
+    /// - `Ok(plays)`: the days the book was played on, newest first
+    /// - `Err(_)`: otherwise
+    pub async fn read_daily_plays(&self, params: &DailyPlaysGet) -> DbResultMultiple<DailyPlays> {
+        let plays = sqlx::query_as!(
+            DailyPlays,
+            r#"
+            SELECT played_on, COUNT(*) AS "plays!"
+            FROM "Play"
+            WHERE audiobook_id = $1 AND played_on > current_date - $2::int
+            GROUP BY played_on
+            ORDER BY played_on DESC
+            "#,
+            params.audiobook_id,
+            params.days,
+        )
+        .fetch_all(&self.pool_handler.pool)
+        .await?;
+        Ok(plays)
+    }
+
+    /// Function which records how far the listener got in the chapters the listened part
+    /// of the book reaches into, a chapter ends where the next one begins. Chapters are
+    /// completed once `CONSIDER_AUDIOBOOK_FINISHED_PERCENTAGE` of them is reached,
+    /// the progress in a chapter never goes back.
+    ///
+    /// # Params
+    /// - `params`: structure containing the listener, the book and the listened part
+    /// - `transaction_handle` mutable reference to an ongoing transaction
+    ///
+    /// # Returns
+    /// - `Ok(())`: on success
+    /// - `Err(_)`: otherwise
+    pub async fn record_chapter_progress<'a>(
+        params: &ChapterProgressCreate,
+        transaction_handle: &mut Transaction<'a, Postgres>,
+    ) -> DbResultSingle<()> {
+        sqlx::query!(
+            r#"
+            WITH bounds AS (
+                SELECT
+                    C.id,
+                    C.position AS chapter_start,
+                    COALESCE(LEAD(C.position) OVER (ORDER BY C.position), A.length) AS chapter_end
+                FROM "Chapter" AS C
+                    JOIN "Audiobook" AS A ON A.id = C.audiobook_id
+                WHERE C.audiobook_id = $2 AND C.deleted_at IS NULL
+            ), reached AS (
+                SELECT
+                    id,
+                    (LEAST(GREATEST($3::float8, $4::float8), chapter_end) - chapter_start)
+                        / (chapter_end - chapter_start) AS progress
+                FROM bounds
+                WHERE
+                    chapter_end > chapter_start
+                    AND LEAST($3::float8, $4::float8) <= chapter_end
+                    AND GREATEST($3::float8, $4::float8) > chapter_start
+            )
+            INSERT INTO "Chapter_Progress" (user_id, chapter_id, progress, completed_at)
+            SELECT $1, id, progress, CASE WHEN progress >= $5::float8 THEN now() END
+            FROM reached
+            ON CONFLICT (user_id, chapter_id) DO UPDATE
+            SET
+                progress = GREATEST("Chapter_Progress".progress, EXCLUDED.progress),
+                completed_at = COALESCE("Chapter_Progress".completed_at, EXCLUDED.completed_at),
+                edited_at = now()
+            "#,
+            params.user_id,
+            params.audiobook_id,
+            params.start,
+            params.end,
+            CONSIDER_AUDIOBOOK_FINISHED_PERCENTAGE / 100f64,
+        )
+        .execute(transaction_handle.as_mut())
+        .await?;
+
+        Ok(())
+    }
+
+    /// Function which reads the listening history of a user, newest first. The pieces
+    /// of a session, which continue where the previous piece ended, are joined together.
+    ///
+    /// # Params
+    /// - `params`: structure containing the listener, optionally a book, and the page
+    ///
+    /// # Returns
+    /// - `Ok(history)`: the sessions of the listener
+    /// - `Err(_)`: otherwise
+    pub async fn read_listening_history(
+        &self,
+        params: &ListeningHistoryGet,
+    ) -> DbResultMultiple<ListeningHistoryEntry> {
+        let history = sqlx::query_as!(
+            ListeningHistoryEntry,
+            r#"
+            WITH pieces AS (
+                SELECT
+                    s.*,
+                    CASE WHEN
+                        lag(s.device_id) OVER w = s.device_id
+                        AND lag(s.client) OVER w = s.client
+                        AND abs(s.start_position - lag(s.end_position) OVER w) < 1
+                        AND s.started_at - lag(s.ended_at) OVER w < interval '1 minute'
+                    THEN 0 ELSE 1 END AS starts_session
+                FROM "Listening_Session" s
+                WHERE s.user_id = $1
+                    AND ($2::bigint IS NULL OR s.audiobook_id = $2)
+                WINDOW w AS (PARTITION BY s.audiobook_id ORDER BY s.started_at, s.id)
+            ),
+            sessions AS (
+                SELECT
+                    p.*,
+                    sum(p.starts_session) OVER (
+                        PARTITION BY p.audiobook_id ORDER BY p.started_at, p.id
+                    ) AS session
+                FROM pieces p
+            )
+            SELECT
+                s.audiobook_id,
+                a.name AS audiobook_name,
+                (array_agg(s.start_position ORDER BY s.started_at, s.id))[1] AS "start_position!",
+                (array_agg(s.end_position ORDER BY s.started_at DESC, s.id DESC))[1] AS "end_position!",
+                min(s.started_at) AS "started_at!",
+                max(s.ended_at) AS "ended_at!",
+                sum(extract(epoch FROM s.ended_at - s.started_at))::float8 AS "listened!",
+                min(s.client) AS "client!"
+            FROM sessions s
+                JOIN "Audiobook" a ON a.id = s.audiobook_id
+            GROUP BY s.audiobook_id, a.name, s.session
+            ORDER BY max(s.ended_at) DESC
+            LIMIT $3 OFFSET $4
+            "#,
+            params.user_id,
+            params.audiobook_id,
+            params.limit,
+            params.offset,
+        )
+        .fetch_all(&self.pool_handler.pool)
+        .await?;
+
+        Ok(history)
+    }
+
     pub async fn quick_search(&self, query: &str) -> DbResultMultiple<QuickSearch> {
         let mut comparison_string: String = "%".to_owned();
         comparison_string.push_str(query);
@@ -102,7 +610,7 @@ impl AudiobookRepository {
             QuickSearch,
             r#"
             SELECT id, name FROM "Audiobook"
-            WHERE name ILIKE $1
+            WHERE name ILIKE $1 AND processing_state = 'ready'
             LIMIT 5
             "#,
             comparison_string
@@ -113,72 +621,266 @@ impl AudiobookRepository {
         Ok(results)
     }
 
-    #[allow(dead_code)]
-    pub async fn remove_active_audiobook(
+    /// Function which applies a position reported by a device of the listener,
+    /// see `ActiveAudiobook::sync` for which position wins
+    ///
+    /// # Params
+    /// - `params`: structure containing the listener, the book, the position and its device
+    ///
+    /// # Returns
+    /// - `Ok(synced)`: whether the position was applied, and the position of the listener
+    /// - `Err(_)`: otherwise
+    pub async fn set_active_audiobook(
         &self,
-        params: &RemoveActiveAudiobook,
-    ) -> DbResultSingle<ActiveAudiobook> {
-        let removed_active_audiobook = sqlx::query_as!(
+        params: &SetActiveAudiobook,
+    ) -> DbResultSingle<ActiveAudiobookSynced> {
+        let mut transaction = self.pool_handler.pool.begin().await?;
+        let synced = AudiobookRepository::sync_active_audiobook(params, &mut transaction).await?;
+        transaction.commit().await?;
+        Ok(synced)
+    }
+
+    /// Function which applies positions queued by a device while it was offline, in the order
+    /// they were reported in
+    ///
+    /// # Params
+    /// - `params`: the reported positions
+    ///
+    /// # Returns
+    /// - `Ok(synced)`: for each position, in the given order, whether it was applied
+    ///   and the position of the listener
+    /// - `Err(_)`: otherwise, no position is applied then
+    pub async fn set_active_audiobooks(
+        &self,
+        params: &[SetActiveAudiobook],
+    ) -> DbResultMultiple<ActiveAudiobookSynced> {
+        let mut order: Vec<usize> = (0..params.len()).collect();
+        order.sort_by_key(|index| params[*index].reported_at);
+
+        let mut transaction = self.pool_handler.pool.begin().await?;
+        let mut synced = vec![None; params.len()];
+        for index in order {
+            synced[index] = Some(
+                AudiobookRepository::sync_active_audiobook(&params[index], &mut transaction)
+                    .await?,
+            );
+        }
+        transaction.commit().await?;
+        Ok(synced.into_iter().flatten().collect())
+    }
+
+    pub async fn sync_active_audiobook<'a>(
+        params: &SetActiveAudiobook,
+        transaction_handle: &mut Transaction<'a, Postgres>,
+    ) -> DbResultSingle<ActiveAudiobookSynced> {
+        let progress = ChapterProgressCreate::new(
+            params.user_id,
+            params.audiobook_id,
+            params.playback_position,
+            params.playback_position,
+        );
+        let created = sqlx::query_as!(
             ActiveAudiobook,
             r#"
-            DELETE FROM "Active_Audiobook"
-            WHERE user_id = $1 AND audiobook_id = $2
+            INSERT INTO "Active_Audiobook"
+                (user_id, audiobook_id, playback_position, edited_at, device_id)
+            VALUES ($1, $2, $3, $4, $5)
+            ON CONFLICT (user_id, audiobook_id) DO NOTHING
             RETURNING *
             "#,
             params.user_id,
             params.audiobook_id,
+            params.playback_position,
+            params.reported_at,
+            params.device_id,
         )
-        .fetch_one(&self.pool_handler.pool)
+        .fetch_optional(transaction_handle.as_mut())
         .await?;
+        if let Some(active) = created {
+            AudiobookRepository::record_chapter_progress(&progress, transaction_handle).await?;
+            return Ok(ActiveAudiobookSynced {
+                applied: true,
+                active,
+            });
+        }
 
-        Ok(removed_active_audiobook)
-    }
-
-    pub async fn set_active_audiobook(
-        &self,
-        params: &SetActiveAudiobook,
-    ) -> DbResultSingle<ActiveAudiobook> {
-        let mut transaction = self.pool_handler.pool.begin().await?;
+        let current = sqlx::query_as!(
+            ActiveAudiobook,
+            r#"
+            SELECT * FROM "Active_Audiobook"
+            WHERE user_id = $1 AND audiobook_id = $2
+            FOR UPDATE
+            "#,
+            params.user_id,
+            params.audiobook_id,
+        )
+        .fetch_one(transaction_handle.as_mut())
+        .await?;
 
-        let updated_active_audiobook = sqlx::query_as!(
+        let sync = current.sync(params);
+        let (position, reported_at, device_id) = match sync.applied {
+            true => (
+                params.playback_position,
+                params.reported_at,
+                &params.device_id,
+            ),
+            false => (
+                current.playback_position,
+                current.edited_at,
+                &current.device_id,
+            ),
+        };
+        let (suggested_position, suggested_device_id, suggested_at) = match sync.suggestion {
+            PositionSuggestion::Keep => (
+                current.suggested_position,
+                current.suggested_device_id.clone(),
+                current.suggested_at,
+            ),
+            PositionSuggestion::Clear => (None, None, None),
+            PositionSuggestion::Set {
+                position,
+                device_id,
+                at,
+            } => (Some(position), Some(device_id), Some(at)),
+        };
+        let active = sqlx::query_as!(
             ActiveAudiobook,
             r#"
             UPDATE "Active_Audiobook"
             SET
-                playback_position = $1,
-                edited_at = current_timestamp
-            WHERE user_id = $2 AND audiobook_id = $3
+                playback_position = $3,
+                edited_at = $4,
+                device_id = $5,
+                suggested_position = $6,
+                suggested_device_id = $7,
+                suggested_at = $8,
+                progress_mark = CASE WHEN $9 THEN 'unmarked' ELSE progress_mark END
+            WHERE user_id = $1 AND audiobook_id = $2
             RETURNING *
             "#,
-            params.playback_position,
             params.user_id,
             params.audiobook_id,
+            position,
+            reported_at,
+            device_id,
+            suggested_position,
+            suggested_device_id,
+            suggested_at,
+            sync.applied,
         )
-        .fetch_all(transaction.as_mut())
+        .fetch_one(transaction_handle.as_mut())
         .await?;
 
-        if let Some(updated) = updated_active_audiobook.into_iter().nth(0) {
-            transaction.commit().await?;
-            return Ok(updated);
+        if sync.applied {
+            AudiobookRepository::record_chapter_progress(&progress, transaction_handle).await?;
         }
 
-        let new_active_audiobook = sqlx::query_as!(
+        Ok(ActiveAudiobookSynced {
+            applied: sync.applied,
+            active,
+        })
+    }
+
+    /// Function which marks the book finished or unplayed for the listener, or starts it over.
+    /// Marking the book unplayed and starting it over rewind it, marking it finished keeps
+    /// the position and the progress in the chapters. Positions reported before the change
+    /// are not applied anymore.
+    ///
+    /// # Params
+    /// - `params`: structure containing the listener, the book, the change and its device
+    ///
+    /// # Returns
+    /// - `Ok(active)`: the position of the listener after the change
+    /// - `Err(_)`: otherwise
+    pub async fn change_progress(
+        &self,
+        params: &ProgressChangeSet,
+    ) -> DbResultSingle<ActiveAudiobook> {
+        let mark = match params.change {
+            ProgressChange::Finished => ProgressMark::Finished,
+            ProgressChange::Unplayed => ProgressMark::Unplayed,
+            ProgressChange::Reset => ProgressMark::Unmarked,
+        };
+        let rewind = params.change != ProgressChange::Finished;
+        let mut transaction = self.pool_handler.pool.begin().await?;
+        let active = sqlx::query_as!(
             ActiveAudiobook,
             r#"
-            INSERT INTO "Active_Audiobook" (user_id, audiobook_id, playback_position)
-            VALUES ($1, $2, $3)
+            INSERT INTO "Active_Audiobook"
+                (user_id, audiobook_id, playback_position, edited_at, device_id, progress_mark)
+            VALUES ($1, $2, 0, $3, $4, $5)
+            ON CONFLICT (user_id, audiobook_id) DO UPDATE
+            SET
+                playback_position = CASE WHEN $6 THEN 0
+                    ELSE "Active_Audiobook".playback_position END,
+                edited_at = GREATEST("Active_Audiobook".edited_at, EXCLUDED.edited_at),
+                device_id = EXCLUDED.device_id,
+                progress_mark = EXCLUDED.progress_mark,
+                suggested_position = NULL,
+                suggested_device_id = NULL,
+                suggested_at = NULL
             RETURNING *
             "#,
             params.user_id,
             params.audiobook_id,
-            params.playback_position
+            params.reported_at,
+            params.device_id,
+            mark.as_str(),
+            rewind,
         )
         .fetch_one(transaction.as_mut())
         .await?;
 
+        if rewind {
+            sqlx::query!(
+                r#"
+                DELETE FROM "Chapter_Progress" AS CP
+                USING "Chapter" AS C
+                WHERE
+                    C.id = CP.chapter_id
+                    AND CP.user_id = $1
+                    AND C.audiobook_id = $2
+                "#,
+                params.user_id,
+                params.audiobook_id,
+            )
+            .execute(transaction.as_mut())
+            .await?;
+        }
+
         transaction.commit().await?;
 
-        Ok(new_active_audiobook)
+        Ok(active)
+    }
+
+    /// Function which dismisses the position the listener was offered to jump back to
+    ///
+    /// # Params
+    /// - `params`: structure containing the listener and the book
+    ///
+    /// # Returns
+    /// - `Ok(())`: on success
+    /// - `Err(_)`: otherwise
+    pub async fn dismiss_suggested_position(
+        &self,
+        params: &RemoveActiveAudiobook,
+    ) -> DbResultSingle<()> {
+        sqlx::query!(
+            r#"
+            UPDATE "Active_Audiobook"
+            SET
+                suggested_position = NULL,
+                suggested_device_id = NULL,
+                suggested_at = NULL
+            WHERE user_id = $1 AND audiobook_id = $2
+            "#,
+            params.user_id,
+            params.audiobook_id,
+        )
+        .execute(&self.pool_handler.pool)
+        .await?;
+
+        Ok(())
     }
 
     /// Returns most currently listened users book
@@ -191,6 +893,7 @@ impl AudiobookRepository {
             r#"
             SELECT A.id as book_id, A.file_path AS path, A.thumbnail as thumbnail,
                 A.name AS name, ACT.playback_position AS playback_position,
+                ACT.suggested_position AS suggested_position, ACT.suggested_at AS suggested_at,
                 B.edited_at IS NOT NULL AS is_liked, U.id as author_id,
                 U.name AS author_name, U.surname As author_surname
             FROM "Active_Audiobook" ACT
@@ -201,7 +904,7 @@ impl AudiobookRepository {
             LEFT JOIN "Bookmark" B ON
                 A.id = B.audiobook_id
             WHERE
-                ACT.user_id = $1
+                ACT.user_id = $1 AND ACT.progress_mark <> 'unplayed'
             ORDER BY ACT.edited_at DESC
             LIMIT 1
             "#,
@@ -241,7 +944,6 @@ impl AudiobookRepository {
         }
 
         // Audiobook was not played by user before -> create new ActiveAudiobook entry
-        AudiobookRepository::increment_stream_count(book_id, &mut transaction).await?;
         sqlx::query_as!(
             ActiveAudiobook,
             r#"
@@ -278,6 +980,9 @@ impl AudiobookRepository {
                 a.created_at,
                 a.edited_at,
                 a.deleted_at,
+                a.processing_state,
+                a.processing_error,
+                a.downloadable,
 
                 a.author_id,
                 u.name AS author_name,
@@ -293,6 +998,7 @@ impl AudiobookRepository {
 
                 ab.playback_position AS "playback_position?",
                 ab.edited_at AS "active_audiobook_edited_at?",
+                COALESCE(ab.progress_mark, 'unmarked') AS "progress_mark!",
                 b.audiobook_id IS NOT NULL AS "is_liked!"
             FROM
                 "Audiobook" AS a
@@ -392,6 +1098,287 @@ impl AudiobookRepository {
         .await?;
         Ok(books)
     }
+
+    /// Function which hands out the books waiting for their processing, and marks them
+    /// as being processed
+    ///
+    /// # Params
+    /// - `params`: structure containing the maximal number of books and the timeout after
+    ///   which books that are being processed are handed out again
+    ///
+    /// # Returns
+    /// - `Ok(books)`: the books to process, the oldest first
+    /// - `Err(_)`: otherwise
+    pub async fn take_unprocessed(
+        &self,
+        params: &AudiobookProcessingTake,
+    ) -> DbResultMultiple<Audiobook> {
+        let books = sqlx::query_as!(
+            Audiobook,
+            r#"
+            UPDATE "Audiobook"
+            SET
+                processing_state = 'processing',
+                processing_error = NULL,
+                processing_started_at = now()
+            WHERE id IN (
+                SELECT id FROM "Audiobook"
+                WHERE
+                    processing_state = 'uploaded'
+                    OR (
+                        processing_state = 'processing'
+                        AND processing_started_at < now() - make_interval(secs => $2)
+                    )
+                ORDER BY created_at
+                LIMIT $1
+                FOR UPDATE SKIP LOCKED
+            )
+            RETURNING *
+            "#,
+            params.limit,
+            params.timeout as f64
+        )
+        .fetch_all(&self.pool_handler.pool)
+        .await?;
+
+        Ok(books)
+    }
+
+    /// Function which records the outcome of the processing of a book
+    ///
+    /// # Params
+    /// - `params`: structure containing the id of the book and the error, if it failed
+    ///
+    /// # Returns
+    /// - `Ok(books)`: the updated book, none if it was not being processed anymore
+    /// - `Err(_)`: otherwise
+    pub async fn finish_processing(
+        &self,
+        params: &AudiobookProcessed,
+    ) -> DbResultMultiple<Audiobook> {
+        let books = sqlx::query_as!(
+            Audiobook,
+            r#"
+            UPDATE "Audiobook"
+            SET
+                processing_state = CASE WHEN $2::text IS NULL THEN 'ready' ELSE 'failed' END,
+                processing_error = $2
+            WHERE id = $1 AND processing_state = 'processing'
+            RETURNING *
+            "#,
+            params.id,
+            params.error
+        )
+        .fetch_all(&self.pool_handler.pool)
+        .await?;
+
+        Ok(books)
+    }
+
+    /// Function which creates an uploaded book together with the chapters found in its
+    /// files, and queues its registration in the recommender, all in one transaction.
+    /// The book waits for its processing then.
+    ///
+    /// # Params
+    /// - `params`: structure containing the book, its files have to be stored already
+    /// - `chapters`: chapters of the book
+    ///
+    /// # Returns
+    /// - `Ok(book)`: the created book
+    /// - `Err(_)`: otherwise, nothing is created then
+    pub async fn create_uploaded(
+        &self,
+        params: &AudiobookCreate,
+        chapters: &[ChapterEmbeddedCreate],
+    ) -> DbResultSingle<Audiobook> {
+        let names: Vec<String> = chapters.iter().map(|c| c.name.clone()).collect();
+        let positions: Vec<f64> = chapters.iter().map(|c| c.position).collect();
+
+        let mut transaction = self.pool_handler.pool.begin().await?;
+        let book = sqlx::query_as!(
+            Audiobook,
+            r#"
+            INSERT INTO "Audiobook" (
+                name, author_id, genre_id, file_path, length, thumbnail, description,
+                processing_state
+            )
+            VALUES ($1, $2, $3, $4, $5, $6, $7, 'uploaded')
+            RETURNING *
+            "#,
+            params.name,
+            params.author_id,
+            params.genre_id,
+            params.file_path,
+            params.length,
+            params.thumbnail,
+            params.description
+        )
+        .fetch_one(transaction.as_mut())
+        .await?;
+        sqlx::query!(
+            r#"
+            INSERT INTO "Chapter" (audiobook_id, name, position)
+            SELECT $1, * FROM UNNEST($2::text[], $3::float8[])
+            "#,
+            book.id,
+            &names,
+            &positions
+        )
+        .execute(transaction.as_mut())
+        .await?;
+        sqlx::query!(
+            r#"
+            INSERT INTO "Recommender_Queue" (audiobook_id)
+            VALUES ($1)
+            "#,
+            book.id
+        )
+        .execute(transaction.as_mut())
+        .await?;
+        transaction.commit().await?;
+        Ok(book)
+    }
+
+    /// Function which replaces the audio of a book, keeping the previous audio as a version
+    /// of the book, and moves the chapters and the positions of listeners to the new audio,
+    /// all in one transaction. The book waits for its processing then.
+    ///
+    /// # Params
+    /// - `params`: structure containing the new audio, stored already, its length and how
+    ///   the positions move
+    ///
+    /// # Returns
+    /// - `Ok(replaced)`: the previous version and what moved
+    /// - `Err(_)`: otherwise, nothing changes then
+    pub async fn replace_audio(
+        &self,
+        params: &AudiobookAudioReplace,
+    ) -> DbResultSingle<AudiobookAudioReplaced> {
+        let mut transaction = self.pool_handler.pool.begin().await?;
+        let book = sqlx::query_as!(
+            Audiobook,
+            r#"
+            SELECT * FROM "Audiobook"
+            WHERE id = $1
+            FOR UPDATE
+            "#,
+            params.id
+        )
+        .fetch_optional(transaction.as_mut())
+        .await?;
+        let book = entity_is_correct(
+            book,
+            EntityError::new(AudiobookDeleted, AudiobookDoesNotExist),
+            true,
+        )?;
+
+        let version = sqlx::query_as!(
+            AudiobookVersion,
+            r#"
+            INSERT INTO "Audiobook_Version" (audiobook_id, file_path, length)
+            VALUES ($1, $2, $3)
+            RETURNING *
+            "#,
+            book.id,
+            book.file_path,
+            book.length
+        )
+        .fetch_one(transaction.as_mut())
+        .await?;
+
+        let scale = match params.remap {
+            PositionRemap::Scale if book.length > 0.0 => params.length / book.length,
+            _ => 1.0,
+        };
+        let mut chapters_moved = sqlx::query_as!(
+            ChapterMoved,
+            r#"
+            WITH previous AS (
+                SELECT id, position FROM "Chapter"
+                WHERE audiobook_id = $1
+                FOR UPDATE
+            )
+            UPDATE "Chapter" AS c
+            SET
+                position = LEAST(c.position * $2, $3),
+                edited_at = now()
+            FROM previous
+            WHERE c.id = previous.id AND LEAST(c.position * $2, $3) <> c.position
+            RETURNING c.name, previous.position AS previous_position, c.position
+            "#,
+            book.id,
+            scale,
+            params.length
+        )
+        .fetch_all(transaction.as_mut())
+        .await?;
+        let listeners_moved = sqlx::query!(
+            r#"
+            UPDATE "Active_Audiobook"
+            SET
+                playback_position = LEAST(playback_position * $2, $3),
+                suggested_position = LEAST(suggested_position * $2, $3)
+            WHERE audiobook_id = $1 AND LEAST(playback_position * $2, $3) <> playback_position
+            "#,
+            book.id,
+            scale,
+            params.length
+        )
+        .execute(transaction.as_mut())
+        .await?
+        .rows_affected();
+
+        sqlx::query!(
+            r#"
+            UPDATE "Audiobook"
+            SET
+                file_path = $2,
+                length = $3,
+                processing_state = 'uploaded',
+                processing_error = NULL,
+                edited_at = now()
+            WHERE id = $1
+            "#,
+            book.id,
+            params.file_path,
+            params.length
+        )
+        .execute(transaction.as_mut())
+        .await?;
+        transaction.commit().await?;
+
+        chapters_moved.sort_by(|a, b| a.previous_position.total_cmp(&b.previous_position));
+        Ok(AudiobookAudioReplaced {
+            version,
+            length: params.length,
+            chapters_moved,
+            listeners_moved: listeners_moved as i64,
+        })
+    }
+
+    /// Function which reads the previous versions of the audio of a book
+    ///
+    /// # Params
+    /// - `audiobook_id`: the book
+    ///
+    /// # Returns
+    /// - `Ok(versions)`: the versions, the most recently replaced first
+    /// - `Err(_)`: otherwise
+    pub async fn read_versions(&self, audiobook_id: &Id) -> DbResultMultiple<AudiobookVersion> {
+        let versions = sqlx::query_as!(
+            AudiobookVersion,
+            r#"
+            SELECT * FROM "Audiobook_Version"
+            WHERE audiobook_id = $1
+            ORDER BY replaced_at DESC
+            "#,
+            audiobook_id
+        )
+        .fetch_all(&self.pool_handler.pool)
+        .await?;
+
+        Ok(versions)
+    }
 }
 
 #[async_trait]
@@ -434,6 +1421,9 @@ impl DbReadOne<AudiobookGetByIdJoin, AudiobookDetail> for AudiobookRepository {
                 a.created_at,
                 a.edited_at,
                 a.deleted_at,
+                a.processing_state,
+                a.processing_error,
+                a.downloadable,
 
                 a.author_id,
                 u.name AS author_name,
@@ -449,6 +1439,7 @@ impl DbReadOne<AudiobookGetByIdJoin, AudiobookDetail> for AudiobookRepository {
 
                 ab.playback_position AS "playback_position?",
                 ab.edited_at AS "active_audiobook_edited_at?",
+                COALESCE(ab.progress_mark, 'unmarked') AS "progress_mark!",
                 b.audiobook_id IS NOT NULL AS "is_liked!"
             FROM
                 "Audiobook" AS a
@@ -495,6 +1486,9 @@ impl DbReadMany<AudiobookSearch, AudiobookDisplay> for AudiobookRepository {
                 a.created_at,
                 a.edited_at,
                 a.deleted_at,
+                a.processing_state,
+                a.processing_error,
+                a.downloadable,
 
                 a.author_id,
                 u.name AS author_name,
@@ -510,6 +1504,7 @@ impl DbReadMany<AudiobookSearch, AudiobookDisplay> for AudiobookRepository {
 
                 ab.playback_position,
                 ab.edited_at AS active_audiobook_edited_at,
+                COALESCE(ab.progress_mark, 'unmarked') AS progress_mark,
                 b.audiobook_id IS NOT NULL AS is_liked
             FROM
                 "Audiobook" AS a
@@ -618,8 +1613,9 @@ impl DbUpdate<AudiobookUpdate, Audiobook> for AudiobookRepository {
                 overall_rating = COALESCE($8, overall_rating),
                 thumbnail = COALESCE($9, thumbnail),
                 description = COALESCE($10, description),
+                downloadable = COALESCE($11, downloadable),
                 edited_at = current_timestamp
-            WHERE id = $11
+            WHERE id = $12
             RETURNING *
             "#,
             params.name,
@@ -632,6 +1628,7 @@ impl DbUpdate<AudiobookUpdate, Audiobook> for AudiobookRepository {
             params.overall_rating,
             params.thumbnail,
             params.description,
+            params.downloadable,
             audiobook.id
         )
         .fetch_all(transaction.as_mut())
@@ -1,5 +1,6 @@
 use crate::database::common::error::BackendErrorKind::{
-    ChapterDeleted, ChapterDoesNotExist, ChapterUpdateParametersEmpty,
+    ChapterDeleted, ChapterDoesNotExist, ChapterSuggestionDoesNotExist,
+    ChapterUpdateParametersEmpty,
 };
 use crate::database::common::error::{
     BackendError, DbError, DbResultMultiple, DbResultSingle, EntityError,
@@ -10,9 +11,12 @@ use crate::database::common::{
 
 use crate::database::common::utilities::entity_is_correct;
 use crate::database::models::chapter::{
-    Chapter, ChapterCreate, ChapterDetail, ChapterGetById, ChapterSearch, ChapterUpdate,
-    ChaptersGetByBookId, ChaptersGetByBookIdJoin,
+    Chapter, ChapterCreate, ChapterDetail, ChapterGetById, ChapterProgress, ChapterProgressGet,
+    ChapterRetention, ChapterSearch, ChapterSuggestion, ChapterSuggestionAccept,
+    ChapterSuggestionCreate, ChapterSuggestionsGetByBookId, ChapterUpdate, ChaptersGetByBookId,
+    ChaptersGetByBookIdJoin,
 };
+use crate::database::models::Id;
 use async_trait::async_trait;
 use sqlx::{Postgres, Transaction};
 
@@ -103,6 +107,232 @@ impl ChapterRepository {
         return Ok(chapter);
     }
 
+    /// Function which replaces the chapter suggestions of the book with new ones
+    ///
+    /// # Params
+    /// - `audiobook_id`: id of the book the suggestions were found in
+    /// - `suggestions`: new suggestions, the previous ones are removed
+    ///
+    /// # Returns
+    /// - `Ok(suggestions)`: on successful connection and insertion
+    /// - `Err(_)`: otherwise
+    pub async fn replace_suggestions(
+        &self,
+        audiobook_id: Id,
+        suggestions: &[ChapterSuggestionCreate],
+    ) -> DbResultMultiple<ChapterSuggestion> {
+        let positions: Vec<f64> = suggestions.iter().map(|s| s.position).collect();
+        let lengths: Vec<f64> = suggestions.iter().map(|s| s.silence_length).collect();
+
+        let mut transaction = self.pool_handler.pool.begin().await?;
+        sqlx::query!(
+            r#"
+            DELETE FROM "Chapter_Suggestion"
+            WHERE audiobook_id = $1
+            "#,
+            audiobook_id
+        )
+        .execute(transaction.as_mut())
+        .await?;
+        let suggestions = sqlx::query_as!(
+            ChapterSuggestion,
+            r#"
+            INSERT INTO "Chapter_Suggestion" (audiobook_id, position, silence_length)
+            SELECT $1, * FROM UNNEST($2::float8[], $3::float8[])
+            RETURNING *
+            "#,
+            audiobook_id,
+            &positions,
+            &lengths
+        )
+        .fetch_all(transaction.as_mut())
+        .await?;
+        transaction.commit().await?;
+        Ok(suggestions)
+    }
+
+    /// Function which retrieves the chapter suggestions of the book, which are not too close
+    /// to any of its chapters
+    ///
+    /// # Params
+    /// - `params`: structure containing the id of the book and the minimal distance to chapters
+    ///
+    /// # Returns
+    /// - `Ok(suggestions)`: ordered by their position
+    /// - `Err(_)`: otherwise
+    pub async fn read_suggestions(
+        &self,
+        params: &ChapterSuggestionsGetByBookId,
+    ) -> DbResultMultiple<ChapterSuggestion> {
+        let suggestions = sqlx::query_as!(
+            ChapterSuggestion,
+            r#"
+            SELECT S.* FROM "Chapter_Suggestion" AS S
+            WHERE
+                S.audiobook_id = $1
+                AND NOT EXISTS (
+                    SELECT 1 FROM "Chapter" AS C
+                    WHERE C.audiobook_id = S.audiobook_id
+                        AND C.deleted_at IS NULL
+                        AND abs(C.position - S.position) < $2
+                )
+            ORDER BY S.position
+            "#,
+            params.audiobook_id,
+            params.min_distance
+        )
+        .fetch_all(&self.pool_handler.pool)
+        .await?;
+        Ok(suggestions)
+    }
+
+    /// Function which turns chapter suggestions into chapters, they are named
+    /// "Chapter N" by their position in the book
+    ///
+    /// # Params
+    /// - `params`: structure containing the id of the book and optionally of the suggestion,
+    ///   all visible suggestions of the book are accepted without it
+    ///
+    /// # Returns
+    /// - `Ok(chapters)`: the newly created chapters
+    /// - `Err(DbError)`: when the suggestion does not exist, or on a database error
+    pub async fn accept_suggestions(
+        &self,
+        params: &ChapterSuggestionAccept,
+    ) -> DbResultMultiple<Chapter> {
+        let chapters = sqlx::query_as!(
+            Chapter,
+            r#"
+            WITH accepted AS (
+                DELETE FROM "Chapter_Suggestion" AS S
+                WHERE
+                    S.audiobook_id = $1
+                    AND (S.id = $2 OR $2 IS NULL)
+                    AND NOT EXISTS (
+                        SELECT 1 FROM "Chapter" AS C
+                        WHERE C.audiobook_id = S.audiobook_id
+                            AND C.deleted_at IS NULL
+                            AND abs(C.position - S.position) < $3
+                    )
+                RETURNING S.position
+            )
+            INSERT INTO "Chapter" (name, audiobook_id, position)
+            SELECT
+                -- named by their order among the chapters of the book
+                'Chapter ' || (
+                    ROW_NUMBER() OVER (ORDER BY A.position)
+                    + (
+                        SELECT COUNT(*) FROM "Chapter" AS C
+                        WHERE C.audiobook_id = $1
+                            AND C.deleted_at IS NULL
+                            AND C.position < A.position
+                    )
+                ),
+                $1,
+                A.position
+            FROM accepted AS A
+            RETURNING *
+            "#,
+            params.audiobook_id,
+            params.suggestion_id,
+            params.min_distance
+        )
+        .fetch_all(&self.pool_handler.pool)
+        .await?;
+
+        if params.suggestion_id.is_some() && chapters.is_empty() {
+            return Err(DbError::from(BackendError::new(
+                ChapterSuggestionDoesNotExist,
+            )));
+        }
+        Ok(chapters)
+    }
+
+    /// Function which retrieves how far the listener got in the chapters of the book
+    ///
+    /// # Params
+    /// - `params`: structure containing the listener and the book
+    ///
+    /// # Returns
+    /// - `Ok(progress)`: the chapters the listener reached
+    /// - `Err(_)`: otherwise
+    pub async fn read_progress(
+        &self,
+        params: &ChapterProgressGet,
+    ) -> DbResultMultiple<ChapterProgress> {
+        let progress = sqlx::query_as!(
+            ChapterProgress,
+            r#"
+            SELECT CP.chapter_id, CP.progress, CP.completed_at
+            FROM "Chapter_Progress" AS CP
+                JOIN "Chapter" AS C ON C.id = CP.chapter_id
+            WHERE
+                CP.user_id = $1
+                AND C.audiobook_id = $2
+                AND C.deleted_at IS NULL
+            "#,
+            params.user_id,
+            params.audiobook_id,
+        )
+        .fetch_all(&self.pool_handler.pool)
+        .await?;
+        Ok(progress)
+    }
+
+    /// Function which counts for each chapter of the book the listeners who started it,
+    /// completed it, and got no further than it, and its plays. The furthest chapter of a listener is
+    /// the last chapter they reached.
+    ///
+    /// # Params
+    /// - `params`: structure containing the id of the book
+    ///
+    /// # Returns
+    /// - `Ok(retention)`: ordered by the position of the chapters
+    /// - `Err(_)`: otherwise
+    pub async fn read_retention(
+        &self,
+        params: &ChaptersGetByBookId,
+    ) -> DbResultMultiple<ChapterRetention> {
+        let retention = sqlx::query_as!(
+            ChapterRetention,
+            r#"
+            WITH progress AS (
+                SELECT CP.chapter_id, CP.user_id, CP.completed_at, C.position
+                FROM "Chapter_Progress" AS CP
+                    JOIN "Chapter" AS C ON C.id = CP.chapter_id
+                WHERE C.audiobook_id = $1 AND C.deleted_at IS NULL
+            ), furthest AS (
+                SELECT DISTINCT ON (user_id) user_id, chapter_id, completed_at
+                FROM progress
+                ORDER BY user_id, position DESC
+            )
+            SELECT
+                C.id,
+                C.name,
+                C.position,
+                COUNT(P.user_id) AS "listeners!",
+                COUNT(P.completed_at) AS "completed!",
+                (
+                    SELECT COUNT(*) FROM furthest AS F
+                    WHERE F.chapter_id = C.id AND F.completed_at IS NULL
+                ) AS "stopped!",
+                (
+                    SELECT COUNT(*) FROM "Chapter_Play" AS CP
+                    WHERE CP.chapter_id = C.id
+                ) AS "plays!"
+            FROM "Chapter" AS C
+                LEFT JOIN progress AS P ON P.chapter_id = C.id
+            WHERE C.audiobook_id = $1 AND C.deleted_at IS NULL
+            GROUP BY C.id
+            ORDER BY C.position
+            "#,
+            params.audiobook_id,
+        )
+        .fetch_all(&self.pool_handler.pool)
+        .await?;
+        Ok(retention)
+    }
+
     /// Function which checks if the chapter is correct (existing and not deleted)
     ///
     /// # Params
@@ -0,0 +1,209 @@
+use crate::database::common::error::{DbResultMultiple, DbResultSingle};
+use crate::database::common::{DbPoolHandler, DbRepository, PoolHandler};
+use crate::database::models::export::{
+    AudiobookExport, AudiobookExportFinish, AudiobookExportRequest, AudiobookExportsTake,
+};
+use crate::database::models::Id;
+use async_trait::async_trait;
+
+#[derive(Clone)]
+pub struct AudiobookExportRepository {
+    pool_handler: PoolHandler,
+}
+
+impl AudiobookExportRepository {
+    /// Function which reads the latest export of a book
+    ///
+    /// # Params
+    /// - `audiobook_id`: id of the book
+    ///
+    /// # Returns
+    /// - `Ok(export)`: the export, none if the book was never exported
+    /// - `Err(_)`: otherwise
+    pub async fn read(&self, audiobook_id: &Id) -> DbResultSingle<Option<AudiobookExport>> {
+        let export = sqlx::query_as!(
+            AudiobookExport,
+            r#"
+            SELECT * FROM "Audiobook_Export"
+            WHERE audiobook_id = $1
+            "#,
+            audiobook_id
+        )
+        .fetch_optional(&self.pool_handler.pool)
+        .await?;
+
+        Ok(export)
+    }
+
+    /// Function which requests an export of a book. Nothing changes if the book is exported
+    /// in this state already, or is being exported, unless the export failed.
+    ///
+    /// # Params
+    /// - `params`: structure containing the id of the book and the fingerprint of its state
+    ///
+    /// # Returns
+    /// - `Ok(export)`: the requested export
+    /// - `Err(_)`: otherwise
+    pub async fn request(
+        &self,
+        params: &AudiobookExportRequest,
+    ) -> DbResultSingle<AudiobookExport> {
+        let mut transaction = self.pool_handler.pool.begin().await?;
+        let requested = sqlx::query_as!(
+            AudiobookExport,
+            r#"
+            INSERT INTO "Audiobook_Export" (audiobook_id, fingerprint)
+            VALUES ($1, $2)
+            ON CONFLICT (audiobook_id) DO UPDATE
+            SET
+                fingerprint = EXCLUDED.fingerprint,
+                state = 'pending',
+                error = NULL,
+                requested_at = now(),
+                started_at = NULL,
+                finished_at = NULL
+            WHERE
+                "Audiobook_Export".state = 'failed'
+                OR "Audiobook_Export".fingerprint <> EXCLUDED.fingerprint
+            RETURNING *
+            "#,
+            params.audiobook_id,
+            params.fingerprint
+        )
+        .fetch_optional(transaction.as_mut())
+        .await?;
+        let export = match requested {
+            Some(export) => export,
+            None => {
+                sqlx::query_as!(
+                    AudiobookExport,
+                    r#"
+                    SELECT * FROM "Audiobook_Export"
+                    WHERE audiobook_id = $1
+                    "#,
+                    params.audiobook_id
+                )
+                .fetch_one(transaction.as_mut())
+                .await?
+            }
+        };
+        transaction.commit().await?;
+
+        Ok(export)
+    }
+
+    /// Function which hands out the requested exports, and marks them as running
+    ///
+    /// # Params
+    /// - `params`: structure containing the maximal number of exports and the timeout after
+    ///   which running exports are handed out again
+    ///
+    /// # Returns
+    /// - `Ok(exports)`: the exports to run, the oldest requests first
+    /// - `Err(_)`: otherwise
+    pub async fn take_pending(
+        &self,
+        params: &AudiobookExportsTake,
+    ) -> DbResultMultiple<AudiobookExport> {
+        let exports = sqlx::query_as!(
+            AudiobookExport,
+            r#"
+            UPDATE "Audiobook_Export"
+            SET
+                state = 'exporting',
+                started_at = now()
+            WHERE audiobook_id IN (
+                SELECT audiobook_id FROM "Audiobook_Export"
+                WHERE
+                    state = 'pending'
+                    OR (state = 'exporting' AND started_at < now() - make_interval(secs => $2))
+                ORDER BY requested_at
+                LIMIT $1
+                FOR UPDATE SKIP LOCKED
+            )
+            RETURNING *
+            "#,
+            params.limit,
+            params.timeout as f64
+        )
+        .fetch_all(&self.pool_handler.pool)
+        .await?;
+
+        Ok(exports)
+    }
+
+    /// Function which records the outcome of an export. An export that was requested again
+    /// while it was running is not recorded, the new request is run instead.
+    ///
+    /// # Params
+    /// - `params`: structure containing the export, its file or the error
+    ///
+    /// # Returns
+    /// - `Ok(paths)`: stored exports that are not used anymore, the previous export of the book
+    ///   or the file of an export that was not recorded
+    /// - `Err(_)`: otherwise
+    pub async fn finish(&self, params: &AudiobookExportFinish) -> DbResultMultiple<String> {
+        let finished = sqlx::query!(
+            r#"
+            WITH previous AS (
+                SELECT audiobook_id, file_path FROM "Audiobook_Export"
+                WHERE audiobook_id = $1
+                FOR UPDATE
+            )
+            UPDATE "Audiobook_Export" AS e
+            SET
+                state = CASE WHEN $4::text IS NULL THEN 'failed' ELSE 'ready' END,
+                fingerprint = $3,
+                file_path = COALESCE($4, e.file_path),
+                size = COALESCE($5, e.size),
+                error = $6,
+                finished_at = now()
+            FROM previous
+            WHERE
+                e.audiobook_id = previous.audiobook_id
+                AND e.state = 'exporting'
+                AND e.requested_at = $2
+            RETURNING previous.file_path AS previous_file_path
+            "#,
+            params.audiobook_id,
+            params.requested_at,
+            params.fingerprint,
+            params.file_path,
+            params.size,
+            params.error
+        )
+        .fetch_optional(&self.pool_handler.pool)
+        .await?;
+
+        let unused = match finished {
+            // a failed export keeps the previous one, the same export is stored at the same path
+            Some(finished) => finished.previous_file_path.filter(|previous| {
+                params.file_path.is_some() && Some(previous) != params.file_path.as_ref()
+            }),
+            // an export that was handed out again may have stored the same file
+            None => match &params.file_path {
+                Some(file_path) => {
+                    let recorded = self.read(&params.audiobook_id).await?;
+                    let in_use =
+                        recorded.is_some_and(|export| export.file_path.as_ref() == Some(file_path));
+                    (!in_use).then(|| file_path.clone())
+                }
+                None => None,
+            },
+        };
+        Ok(unused.into_iter().collect())
+    }
+}
+
+#[async_trait]
+impl DbRepository for AudiobookExportRepository {
+    #[inline]
+    fn new(pool_handler: PoolHandler) -> Self {
+        Self { pool_handler }
+    }
+
+    #[inline]
+    async fn disconnect(&self) -> () {
+        self.pool_handler.disconnect().await;
+    }
+}
@@ -0,0 +1,181 @@
+use crate::database::common::error::{DbResultMultiple, DbResultSingle};
+use crate::database::common::{DbPoolHandler, DbRepository, PoolHandler};
+use crate::database::models::media::{Media, MediaClaim, MediaUnreferenced};
+use async_trait::async_trait;
+use std::future::Future;
+
+#[derive(Clone)]
+pub struct MediaRepository {
+    pool_handler: PoolHandler,
+}
+
+impl MediaRepository {
+    /// Function which registers a stored file, or marks an already registered one as used
+    /// again. Claimed media are kept for a while even without any references, so that
+    /// the book or user being created can point to them.
+    ///
+    /// # Params
+    /// - `params`: structure containing the path, digest and size of the file
+    ///
+    /// # Returns
+    /// - `Ok(media)`: the registered medium
+    /// - `Err(_)`: otherwise
+    pub async fn claim(&self, params: &MediaClaim) -> DbResultSingle<Media> {
+        let media = sqlx::query_as!(
+            Media,
+            r#"
+            INSERT INTO "Media" (path, digest, size)
+            VALUES ($1, $2, $3)
+            ON CONFLICT (path) DO UPDATE
+            SET claimed_at = current_timestamp
+            RETURNING *
+            "#,
+            params.path,
+            params.digest,
+            params.size
+        )
+        .fetch_one(&self.pool_handler.pool)
+        .await?;
+
+        Ok(media)
+    }
+
+    /// Function which lists the media that are not referenced anymore, without removing them
+    ///
+    /// # Params
+    /// - `params`: structure containing the candidate paths and the grace period
+    ///
+    /// # Returns
+    /// - `Ok(media)`: the media that `remove_unreferenced` would remove
+    /// - `Err(_)`: otherwise
+    pub async fn read_unreferenced(&self, params: &MediaUnreferenced) -> DbResultMultiple<Media> {
+        let media = sqlx::query_as!(
+            Media,
+            r#"
+            SELECT * FROM "Media"
+            WHERE
+                ($1::text[] IS NULL OR path = ANY($1))
+                AND ref_count = 0
+                AND claimed_at < now() - make_interval(secs => $2)
+            ORDER BY path
+            "#,
+            params.paths.as_deref(),
+            params.grace_period as f64
+        )
+        .fetch_all(&self.pool_handler.pool)
+        .await?;
+
+        Ok(media)
+    }
+
+    /// Function which lists the paths of all registered media
+    ///
+    /// # Returns
+    /// - `Ok(paths)`: paths of the media, referenced or not
+    /// - `Err(_)`: otherwise
+    pub async fn read_paths(&self) -> DbResultMultiple<String> {
+        let paths = sqlx::query_scalar!(
+            r#"
+            SELECT path FROM "Media"
+            "#
+        )
+        .fetch_all(&self.pool_handler.pool)
+        .await?;
+
+        Ok(paths)
+    }
+
+    /// Function which lists the paths books and users point to, including deleted books
+    ///
+    /// # Returns
+    /// - `Ok(paths)`: distinct paths of the audio files (previous versions included), thumbnails
+    ///   and profile pictures
+    /// - `Err(_)`: otherwise
+    pub async fn read_references(&self) -> DbResultMultiple<String> {
+        let paths = sqlx::query_scalar!(
+            r#"
+            SELECT file_path AS "path!" FROM "Audiobook"
+            UNION
+            SELECT file_path FROM "Audiobook_Version"
+            UNION
+            SELECT thumbnail FROM "Audiobook" WHERE thumbnail IS NOT NULL
+            UNION
+            SELECT profile_picture FROM "User" WHERE profile_picture IS NOT NULL
+            ORDER BY 1
+            "#
+        )
+        .fetch_all(&self.pool_handler.pool)
+        .await?;
+
+        Ok(paths)
+    }
+
+    /// Function which removes the media that are not referenced anymore
+    ///
+    /// # Params
+    /// - `params`: structure containing the candidate paths and the grace period
+    /// - `remove`: removes the stored files of the medium and returns whether it succeeded,
+    ///   it is called while the medium is locked, so that it can not be claimed meanwhile
+    ///
+    /// # Returns
+    /// - `Ok(media)`: the removed media, those whose files could not be removed are kept
+    /// - `Err(_)`: otherwise
+    pub async fn remove_unreferenced<F, Fut>(
+        &self,
+        params: &MediaUnreferenced,
+        remove: F,
+    ) -> DbResultMultiple<Media>
+    where
+        F: Fn(Media) -> Fut + Send,
+        Fut: Future<Output = bool> + Send,
+    {
+        let mut transaction = self.pool_handler.pool.begin().await?;
+        let candidates = sqlx::query_as!(
+            Media,
+            r#"
+            SELECT * FROM "Media"
+            WHERE
+                ($1::text[] IS NULL OR path = ANY($1))
+                AND ref_count = 0
+                AND claimed_at < now() - make_interval(secs => $2)
+            FOR UPDATE SKIP LOCKED
+            "#,
+            params.paths.as_deref(),
+            params.grace_period as f64
+        )
+        .fetch_all(transaction.as_mut())
+        .await?;
+
+        let mut removed = Vec::with_capacity(candidates.len());
+        for media in candidates {
+            if remove(media.clone()).await {
+                removed.push(media);
+            }
+        }
+        let ids: Vec<i64> = removed.iter().map(|media| media.id).collect();
+        sqlx::query!(
+            r#"
+            DELETE FROM "Media"
+            WHERE id = ANY($1)
+            "#,
+            &ids
+        )
+        .execute(transaction.as_mut())
+        .await?;
+        transaction.commit().await?;
+        Ok(removed)
+    }
+}
+
+#[async_trait]
+impl DbRepository for MediaRepository {
+    #[inline]
+    fn new(pool_handler: PoolHandler) -> Self {
+        Self { pool_handler }
+    }
+
+    #[inline]
+    async fn disconnect(&self) -> () {
+        self.pool_handler.disconnect().await;
+    }
+}
@@ -1,5 +1,9 @@
 pub mod audiobook;
 pub mod chapter;
+pub mod export;
 pub mod genre;
+pub mod media;
 pub mod rating;
+pub mod recommender_queue;
+pub mod upload;
 pub mod user;
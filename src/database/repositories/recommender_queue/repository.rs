@@ -0,0 +1,114 @@
+use crate::database::common::error::{DbResultMultiple, DbResultSingle};
+use crate::database::common::{DbPoolHandler, DbRepository, PoolHandler};
+use crate::database::models::recommender_queue::{
+    RecommenderTask, RecommenderTaskPostpone, RecommenderTasksTake,
+};
+use crate::database::models::Id;
+use async_trait::async_trait;
+
+#[derive(Clone)]
+pub struct RecommenderQueueRepository {
+    pool_handler: PoolHandler,
+}
+
+impl RecommenderQueueRepository {
+    /// Function which hands out the registrations that are due. The tasks are postponed by
+    /// the lease, so that they are not handed out twice, and retried after it if the one
+    /// who took them does not finish or postpone them.
+    ///
+    /// # Params
+    /// - `params`: structure containing the maximal number of tasks and the lease
+    ///
+    /// # Returns
+    /// - `Ok(tasks)`: the tasks to process, the oldest first
+    /// - `Err(_)`: otherwise
+    pub async fn take_due(
+        &self,
+        params: &RecommenderTasksTake,
+    ) -> DbResultMultiple<RecommenderTask> {
+        let tasks = sqlx::query_as!(
+            RecommenderTask,
+            r#"
+            UPDATE "Recommender_Queue"
+            SET next_attempt_at = now() + make_interval(secs => $2)
+            WHERE audiobook_id IN (
+                SELECT audiobook_id FROM "Recommender_Queue"
+                WHERE next_attempt_at <= now()
+                ORDER BY created_at
+                LIMIT $1
+                FOR UPDATE SKIP LOCKED
+            )
+            RETURNING *
+            "#,
+            params.limit,
+            params.lease as f64
+        )
+        .fetch_all(&self.pool_handler.pool)
+        .await?;
+
+        Ok(tasks)
+    }
+
+    /// Function which removes the task once the book was registered (or does not need to be)
+    ///
+    /// # Params
+    /// - `audiobook_id`: id of the registered book
+    ///
+    /// # Returns
+    /// - `Ok(())`: on success, also if the task was removed already
+    /// - `Err(_)`: otherwise
+    pub async fn finish(&self, audiobook_id: &Id) -> DbResultSingle<()> {
+        sqlx::query!(
+            r#"
+            DELETE FROM "Recommender_Queue"
+            WHERE audiobook_id = $1
+            "#,
+            audiobook_id
+        )
+        .execute(&self.pool_handler.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Function which records a failed registration and schedules the next attempt
+    ///
+    /// # Params
+    /// - `params`: structure containing the id of the book, the delay and the error
+    ///
+    /// # Returns
+    /// - `Ok(())`: on success, also if the task was removed meanwhile
+    /// - `Err(_)`: otherwise
+    pub async fn postpone(&self, params: &RecommenderTaskPostpone) -> DbResultSingle<()> {
+        sqlx::query!(
+            r#"
+            UPDATE "Recommender_Queue"
+            SET
+                attempts = attempts + 1,
+                last_error = $2,
+                next_attempt_at = now() + make_interval(secs => $3)
+            WHERE audiobook_id = $1
+            "#,
+            params.audiobook_id,
+            params.error,
+            params.delay as f64
+        )
+        .execute(&self.pool_handler.pool)
+        .await?;
+
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl DbRepository for RecommenderQueueRepository {
+    #[inline]
+    fn new(pool_handler: PoolHandler) -> Self {
+        Self { pool_handler }
+    }
+
+    #[inline]
+    async fn disconnect(&self) -> () {
+        self.pool_handler.disconnect().await;
+    }
+}
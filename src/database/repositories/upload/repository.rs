@@ -0,0 +1,218 @@
+use crate::database::common::error::BackendErrorKind::{UploadDoesNotExist, UploadOffsetMismatch};
+use crate::database::common::error::{BackendError, DbError, DbResultMultiple, DbResultSingle};
+use crate::database::common::{DbDelete, DbPoolHandler, DbReadOne, DbRepository, PoolHandler};
+use crate::database::models::upload::{Upload, UploadCreate, UploadGetById, UploadOffsetUpdate};
+use crate::database::models::user::StorageUsage;
+use crate::database::repositories::user::repository::UserRepository;
+use async_trait::async_trait;
+use sqlx::{Postgres, Transaction};
+use uuid::Uuid;
+
+#[derive(Clone)]
+pub struct UploadRepository {
+    pool_handler: PoolHandler,
+}
+
+/// Exclusive access to a resumable upload, held by an advisory lock of the transaction,
+/// so that it is exclusive across all instances of the application. The upload is unlocked
+/// when the lock is dropped.
+pub struct UploadLock {
+    _transaction: Transaction<'static, Postgres>,
+}
+
+impl UploadRepository {
+    /// Function which creates the upload if it fits into the storage quota of the user.
+    /// The whole upload is accounted for from its creation, parallel uploads of the user
+    /// are created one after another, so that they can not exceed the quota together.
+    ///
+    /// # Params
+    /// - `params`: structure containing the upload
+    ///
+    /// # Returns
+    /// - `Ok(Ok(upload))`: the created upload
+    /// - `Ok(Err(usage))`: the storage usage of the user the upload does not fit into
+    /// - `Err(_)`: otherwise
+    pub async fn create_within_quota(
+        &self,
+        params: &UploadCreate,
+    ) -> DbResultSingle<Result<Upload, StorageUsage>> {
+        let mut transaction = self.pool_handler.pool.begin().await?;
+        let usage = UserRepository::lock_storage_usage(&params.user_id, &mut transaction).await?;
+        if !usage.allows(params.upload_length) {
+            return Ok(Err(usage));
+        }
+        let upload = sqlx::query_as!(
+            Upload,
+            r#"
+            INSERT INTO "Upload" (
+                id, user_id, genre_id, name, description, file_name, content_type,
+                upload_length, expires_at
+            )
+            VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)
+            RETURNING *
+            "#,
+            params.id,
+            params.user_id,
+            params.genre_id,
+            params.name,
+            params.description,
+            params.file_name,
+            params.content_type,
+            params.upload_length,
+            params.expires_at
+        )
+        .fetch_one(transaction.as_mut())
+        .await?;
+        transaction.commit().await?;
+
+        Ok(Ok(upload))
+    }
+
+    /// Function which locks the upload, chunks of an upload must not be written by two
+    /// requests at once
+    ///
+    /// # Params
+    /// - `id`: id of the upload
+    ///
+    /// # Returns
+    /// - `Ok(Some(lock))`: the lock, the upload is locked until it is dropped
+    /// - `Ok(None)`: when another request holds the lock
+    /// - `Err(_)`: otherwise
+    pub async fn lock(&self, id: &Uuid) -> DbResultSingle<Option<UploadLock>> {
+        let mut transaction = self.pool_handler.pool.begin().await?;
+        let locked = sqlx::query_scalar!(
+            r#"SELECT pg_try_advisory_xact_lock(hashtextextended($1::text, 0)) AS "locked!""#,
+            id.to_string()
+        )
+        .fetch_one(transaction.as_mut())
+        .await?;
+
+        Ok(locked.then_some(UploadLock {
+            _transaction: transaction,
+        }))
+    }
+
+    /// Function which moves the offset of an upload after a chunk of it was stored
+    ///
+    /// # Params
+    /// - `params`: structure containing the id of the upload, the offset the chunk was
+    ///   written at and the new offset
+    ///
+    /// # Returns
+    /// - `Ok(upload)`: the updated upload
+    /// - `Err(DbError)`: when the upload does not exist, has expired, or its offset was
+    ///   moved by a concurrent request in the meantime
+    pub async fn advance_offset(&self, params: &UploadOffsetUpdate) -> DbResultSingle<Upload> {
+        let mut transaction = self.pool_handler.pool.begin().await?;
+        let upload = sqlx::query_as!(
+            Upload,
+            r#"
+            SELECT * FROM "Upload"
+            WHERE id = $1 AND expires_at > now()
+            FOR UPDATE
+            "#,
+            params.id
+        )
+        .fetch_optional(transaction.as_mut())
+        .await?
+        .ok_or(DbError::from(BackendError::new(UploadDoesNotExist)))?;
+        if upload.upload_offset != params.expected_offset {
+            return Err(DbError::from(BackendError::new(UploadOffsetMismatch)));
+        }
+
+        let upload = sqlx::query_as!(
+            Upload,
+            r#"
+            UPDATE "Upload"
+            SET
+                upload_offset = $2,
+                expires_at = $3,
+                edited_at = current_timestamp
+            WHERE id = $1
+            RETURNING *
+            "#,
+            params.id,
+            params.upload_offset,
+            params.expires_at
+        )
+        .fetch_one(transaction.as_mut())
+        .await?;
+        transaction.commit().await?;
+        Ok(upload)
+    }
+
+    /// Function which removes all uploads that were not finished in time
+    ///
+    /// # Returns
+    /// - `Ok(uploads)`: the removed uploads, their partial files have to be removed as well
+    /// - `Err(_)`: otherwise
+    pub async fn delete_expired(&self) -> DbResultMultiple<Upload> {
+        let uploads = sqlx::query_as!(
+            Upload,
+            r#"
+            DELETE FROM "Upload"
+            WHERE expires_at <= now()
+            RETURNING *
+            "#
+        )
+        .fetch_all(&self.pool_handler.pool)
+        .await?;
+        Ok(uploads)
+    }
+}
+
+#[async_trait]
+impl DbRepository for UploadRepository {
+    #[inline]
+    fn new(pool_handler: PoolHandler) -> Self {
+        Self { pool_handler }
+    }
+
+    #[inline]
+    async fn disconnect(&self) -> () {
+        self.pool_handler.disconnect().await;
+    }
+}
+
+#[async_trait]
+impl DbReadOne<UploadGetById, Upload> for UploadRepository {
+    /// Expired uploads do not exist anymore, even before they are removed
+    async fn read_one(&self, params: &UploadGetById) -> DbResultSingle<Upload> {
+        let upload = sqlx::query_as!(
+            Upload,
+            r#"
+            SELECT * FROM "Upload"
+            WHERE id = $1 AND user_id = $2 AND expires_at > now()
+            "#,
+            params.id,
+            params.user_id
+        )
+        .fetch_optional(&self.pool_handler.pool)
+        .await?;
+
+        upload.ok_or(DbError::from(BackendError::new(UploadDoesNotExist)))
+    }
+}
+
+#[async_trait]
+impl DbDelete<UploadGetById, Upload> for UploadRepository {
+    async fn delete(&self, params: &UploadGetById) -> DbResultMultiple<Upload> {
+        let uploads = sqlx::query_as!(
+            Upload,
+            r#"
+            DELETE FROM "Upload"
+            WHERE id = $1 AND user_id = $2
+            RETURNING *
+            "#,
+            params.id,
+            params.user_id
+        )
+        .fetch_all(&self.pool_handler.pool)
+        .await?;
+
+        if uploads.is_empty() {
+            return Err(DbError::from(BackendError::new(UploadDoesNotExist)));
+        }
+        Ok(uploads)
+    }
+}
@@ -19,9 +19,10 @@ use crate::database::models::audiobook::QuickSearch;
 
 use crate::database::models::bookmark::{Bookmark, BookmarkOperation};
 use crate::database::models::user::{
-    User, UserCreate, UserDelete, UserGetById, UserGetByUsername, UserLogin, UserSearch,
-    UserUpdate, UserUpdatePassword,
+    StorageUsage, User, UserCreate, UserDelete, UserGetById, UserGetByUsername, UserLogin,
+    UserSearch, UserUpdate, UserUpdatePassword,
 };
+use crate::database::models::Id;
 
 fn generate_salt() -> SaltString {
     SaltString::generate(&mut OsRng)
@@ -166,6 +167,152 @@ impl UserRepository {
         Ok(users)
     }
 
+    /// Function which computes the storage used by a user and reads their quota
+    ///
+    /// # Params
+    /// - `user_id`: id of the user
+    ///
+    /// # Returns
+    /// - `Ok(usage)`: the stored and uploading bytes, and the quota of the user or their role
+    /// - `Err(_)`: otherwise
+    pub async fn read_storage_usage(&self, user_id: &Id) -> DbResultSingle<StorageUsage> {
+        let mut transaction = self.pool_handler.pool.begin().await?;
+        let usage = UserRepository::lock_storage_usage(user_id, &mut transaction).await?;
+        transaction.commit().await?;
+        Ok(usage)
+    }
+
+    /// Function which locks the role of the user until the end of the transaction and computes
+    /// the storage used by them. Storage the user takes up in the same transaction can not
+    /// be taken up by another transaction meanwhile.
+    ///
+    /// # Params
+    /// - `user_id`: id of the user
+    /// - `transaction_handle` mutable reference to an ongoing transaction
+    ///
+    /// # Returns
+    /// - `Ok(usage)`: the stored and uploading bytes, and the quota of the user or their role
+    /// - `Err(_)`: otherwise
+    pub async fn lock_storage_usage<'a>(
+        user_id: &Id,
+        transaction_handle: &mut Transaction<'a, Postgres>,
+    ) -> DbResultSingle<StorageUsage> {
+        // the usage is computed by a separate statement, so that it includes everything
+        // the transaction holding the lock before stored
+        sqlx::query!(
+            r#"
+            SELECT user_id FROM "User_Role"
+            WHERE user_id = $1
+            FOR UPDATE
+            "#,
+            user_id
+        )
+        .fetch_optional(transaction_handle.as_mut())
+        .await?;
+        let usage = sqlx::query_as!(
+            StorageUsage,
+            r#"
+            WITH stored AS (
+                SELECT file_path AS path FROM "Audiobook"
+                WHERE author_id = $1
+                UNION
+                SELECT thumbnail FROM "Audiobook"
+                WHERE author_id = $1 AND thumbnail IS NOT NULL
+                UNION
+                SELECT v.file_path FROM "Audiobook_Version" AS v
+                    JOIN "Audiobook" AS a ON a.id = v.audiobook_id
+                WHERE a.author_id = $1
+                UNION
+                SELECT profile_picture FROM "User"
+                WHERE id = $1 AND profile_picture IS NOT NULL
+            )
+            SELECT
+                ur.role,
+                (
+                    SELECT COALESCE(SUM(m.size), 0) FROM stored
+                        JOIN "Media" AS m ON m.path = stored.path
+                )::bigint AS "stored!",
+                (
+                    SELECT COALESCE(SUM(upload_length), 0) FROM "Upload"
+                    WHERE user_id = $1
+                )::bigint AS "uploading!",
+                COALESCE(ur.storage_quota, r.storage_quota) AS quota
+            FROM "User_Role" AS ur
+                JOIN "Role" AS r ON r.name = ur.role
+            WHERE ur.user_id = $1
+            "#,
+            user_id
+        )
+        .fetch_optional(transaction_handle.as_mut())
+        .await?;
+
+        usage.ok_or(DbError::from(BackendError::new(UserDoesNotExist)))
+    }
+
+    /// Function which sets the storage quota of a role, the role is created if it does not exist
+    ///
+    /// # Params
+    /// - `role`: name of the role
+    /// - `storage_quota`: bytes the users with the role can store, none for no limit
+    ///
+    /// # Returns
+    /// - `Ok(())`: on success
+    /// - `Err(_)`: otherwise
+    pub async fn set_role_quota(
+        &self,
+        role: &str,
+        storage_quota: Option<i64>,
+    ) -> DbResultSingle<()> {
+        sqlx::query!(
+            r#"
+            INSERT INTO "Role" (name, storage_quota)
+            VALUES ($1, $2)
+            ON CONFLICT (name) DO UPDATE
+            SET storage_quota = EXCLUDED.storage_quota
+            "#,
+            role,
+            storage_quota
+        )
+        .execute(&self.pool_handler.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Function which sets the role of a user and their own storage quota
+    ///
+    /// # Params
+    /// - `user_id`: id of the user
+    /// - `role`: name of an existing role, the role does not change if none
+    /// - `storage_quota`: quota overriding the quota of the role, none to use the quota of the role
+    ///
+    /// # Returns
+    /// - `Ok(())`: on success
+    /// - `Err(_)`: otherwise, also if the role does not exist
+    pub async fn set_user_quota(
+        &self,
+        user_id: &Id,
+        role: Option<&str>,
+        storage_quota: Option<i64>,
+    ) -> DbResultSingle<()> {
+        sqlx::query!(
+            r#"
+            UPDATE "User_Role"
+            SET
+                role = COALESCE($2, role),
+                storage_quota = $3
+            WHERE user_id = $1
+            "#,
+            user_id,
+            role,
+            storage_quota
+        )
+        .execute(&self.pool_handler.pool)
+        .await?;
+
+        Ok(())
+    }
+
     pub async fn quick_search(&self, query: &str) -> DbResultMultiple<QuickSearch> {
         let mut comparison_string: String = "%".to_owned();
         comparison_string.push_str(query);
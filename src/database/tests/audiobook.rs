@@ -0,0 +1,772 @@
+#[cfg(test)]
+pub mod audiobook_repo_tests {
+
+    use actix_web::web;
+    use chrono::{Duration, Utc};
+    use sqlx::PgPool;
+
+    use crate::database::common::query_parameters::{BookState, DbQueryParams};
+    use crate::database::common::{
+        DbPoolHandler, DbReadMany, DbReadOne, DbRepository, PoolHandler,
+    };
+    use crate::database::models::active_audiobook::{
+        ProgressChange, ProgressChangeSet, ProgressMark, RemoveActiveAudiobook, SetActiveAudiobook,
+    };
+    use crate::database::models::audiobook::{
+        Audiobook, AudiobookAudioReplace, AudiobookCreate, AudiobookGetById, AudiobookProcessed,
+        AudiobookProcessingTake, AudiobookSearch, PositionRemap, ProcessingState,
+    };
+    use crate::database::models::chapter::{
+        ChapterEmbeddedCreate, ChapterProgressGet, ChapterSuggestionAccept,
+        ChapterSuggestionCreate, ChaptersGetByBookId,
+    };
+    use crate::database::models::download::AudiobookDownloadCreate;
+    use crate::database::models::listening_session::{ListeningHistoryGet, ListeningSessionCreate};
+    use crate::database::models::media::MediaClaim;
+    use crate::database::models::play::DailyPlaysGet;
+    use crate::database::models::Id;
+    use crate::database::repositories::audiobook::repository::AudiobookRepository;
+    use crate::database::repositories::chapter::repository::ChapterRepository;
+    use crate::database::repositories::media::repository::MediaRepository;
+    use crate::handlers::utilities::authorized_to_stream;
+
+    const BOOK_FILE: &str = "/media/abc_audio.mp3";
+    const REPLACED_FILE: &str = "/media/def_audio.mp3";
+
+    /// Claims `BOOK_FILE` and creates an uploaded book of it by user 9
+    async fn create_uploaded_book(
+        pool: &PgPool,
+        length: f64,
+        chapters: &[ChapterEmbeddedCreate],
+    ) -> Audiobook {
+        MediaRepository::new(PoolHandler::new(pool.clone()))
+            .claim(&MediaClaim::new(BOOK_FILE, "abc", 3))
+            .await
+            .expect("Claim media should succeed");
+        AudiobookRepository::new(PoolHandler::new(pool.clone()))
+            .create_uploaded(
+                &AudiobookCreate::new("book", &9, &29, BOOK_FILE, &length, None, "bio"),
+                chapters,
+            )
+            .await
+            .expect("Create uploaded book should succeed")
+    }
+
+    #[sqlx::test(fixtures("users", "genres"))]
+    async fn process_uploaded_book(pool: PgPool) {
+        let book = create_uploaded_book(&pool, 60.0, &[]).await;
+        let audiobook_repository = AudiobookRepository::new(PoolHandler::new(pool));
+        assert_eq!(book.processing_state, ProcessingState::Uploaded);
+
+        let mut search = AudiobookSearch::default(9);
+        search.author_id = Some(9);
+        let books = audiobook_repository
+            .read_many(&search)
+            .await
+            .expect("Read books should succeed");
+        assert!(books.is_empty());
+
+        let take = AudiobookProcessingTake::new(10, 60);
+        let books = audiobook_repository
+            .take_unprocessed(&take)
+            .await
+            .expect("Take unprocessed books should succeed");
+        assert_eq!(books.len(), 1);
+        assert_eq!(books[0].id, book.id);
+        assert_eq!(books[0].processing_state, ProcessingState::Processing);
+
+        // books are handed out again only once the timeout passes
+        let books = audiobook_repository
+            .take_unprocessed(&take)
+            .await
+            .expect("Take unprocessed books should succeed");
+        assert!(books.is_empty());
+
+        let books = audiobook_repository
+            .finish_processing(&AudiobookProcessed::new(
+                &book.id,
+                Some("no audio stream".to_string()),
+            ))
+            .await
+            .expect("Finish processing should succeed");
+        assert_eq!(books.len(), 1);
+        assert_eq!(books[0].processing_state, ProcessingState::Failed);
+        assert_eq!(
+            books[0].processing_error.as_deref(),
+            Some("no audio stream")
+        );
+
+        // only books that are being processed can finish
+        let books = audiobook_repository
+            .finish_processing(&AudiobookProcessed::new(&book.id, None))
+            .await
+            .expect("Finish processing should succeed");
+        assert!(books.is_empty());
+
+        search.query_params = DbQueryParams::studio();
+        let books = audiobook_repository
+            .read_many(&search)
+            .await
+            .expect("Read books should succeed");
+        assert_eq!(books.len(), 1);
+        assert_eq!(books[0].processing_state, ProcessingState::Failed);
+        audiobook_repository.disconnect().await;
+    }
+
+    #[sqlx::test(fixtures("users", "genres"))]
+    async fn stream_pending_book(pool: PgPool) {
+        let book = create_uploaded_book(&pool, 60.0, &[]).await;
+        let audiobook_repository = web::Data::new(AudiobookRepository::new(PoolHandler::new(pool)));
+
+        // only the author can listen to the book before it is processed
+        assert!(authorized_to_stream(&audiobook_repository, 9, book.id)
+            .await
+            .is_ok());
+        assert!(authorized_to_stream(&audiobook_repository, 8, book.id)
+            .await
+            .is_err());
+
+        audiobook_repository
+            .take_unprocessed(&AudiobookProcessingTake::new(10, 60))
+            .await
+            .expect("Take unprocessed books should succeed");
+        assert!(authorized_to_stream(&audiobook_repository, 8, book.id)
+            .await
+            .is_err());
+
+        audiobook_repository
+            .finish_processing(&AudiobookProcessed::new(&book.id, None))
+            .await
+            .expect("Finish processing should succeed");
+        assert!(authorized_to_stream(&audiobook_repository, 8, book.id)
+            .await
+            .is_ok());
+        audiobook_repository.disconnect().await;
+    }
+
+    #[sqlx::test(fixtures("users", "genres"))]
+    async fn replace_book_audio(pool: PgPool) {
+        let book = create_uploaded_book(
+            &pool,
+            60.0,
+            &[
+                ChapterEmbeddedCreate::new("first", 0.0),
+                ChapterEmbeddedCreate::new("second", 30.0),
+                ChapterEmbeddedCreate::new("third", 55.0),
+            ],
+        )
+        .await;
+        let media_repository = MediaRepository::new(PoolHandler::new(pool.clone()));
+        let audiobook_repository = AudiobookRepository::new(PoolHandler::new(pool));
+        media_repository
+            .claim(&MediaClaim::new(REPLACED_FILE, "def", 3))
+            .await
+            .expect("Claim media should succeed");
+        audiobook_repository
+            .set_active_audiobook(&SetActiveAudiobook::new(
+                8,
+                book.id,
+                50.0,
+                Utc::now(),
+                "laptop",
+            ))
+            .await
+            .expect("Set active book should succeed");
+
+        let replaced = audiobook_repository
+            .replace_audio(&AudiobookAudioReplace::new(
+                &book.id,
+                REPLACED_FILE,
+                &40.0,
+                PositionRemap::Clamp,
+            ))
+            .await
+            .expect("Replace audio should succeed");
+        assert_eq!(replaced.version.file_path, BOOK_FILE);
+        assert_eq!(replaced.version.length, 60.0);
+        assert_eq!(replaced.chapters_moved.len(), 1);
+        assert_eq!(replaced.chapters_moved[0].name, "third");
+        assert_eq!(replaced.chapters_moved[0].previous_position, 55.0);
+        assert_eq!(replaced.chapters_moved[0].position, 40.0);
+        assert_eq!(replaced.listeners_moved, 1);
+
+        let replaced = audiobook_repository
+            .replace_audio(&AudiobookAudioReplace::new(
+                &book.id,
+                BOOK_FILE,
+                &80.0,
+                PositionRemap::Scale,
+            ))
+            .await
+            .expect("Replace audio should succeed");
+        assert_eq!(replaced.version.file_path, REPLACED_FILE);
+        let positions: Vec<f64> = replaced
+            .chapters_moved
+            .iter()
+            .map(|chapter| chapter.position)
+            .collect();
+        assert_eq!(positions, vec![60.0, 80.0]);
+
+        let book = audiobook_repository
+            .read_one(&AudiobookGetById::new(&book.id, false))
+            .await
+            .expect("Read book should succeed");
+        assert_eq!(book.file_path, BOOK_FILE);
+        assert_eq!(book.length, 80.0);
+        assert_eq!(book.processing_state, ProcessingState::Uploaded);
+
+        // previous versions keep their media referenced
+        let versions = audiobook_repository
+            .read_versions(&book.id)
+            .await
+            .expect("Read versions should succeed");
+        assert_eq!(versions.len(), 2);
+        let references = media_repository
+            .read_references()
+            .await
+            .expect("Read references should succeed");
+        assert!(references.contains(&REPLACED_FILE.to_string()));
+        audiobook_repository.disconnect().await;
+    }
+
+    #[sqlx::test(fixtures("users", "genres"))]
+    async fn record_book_download(pool: PgPool) {
+        let book = create_uploaded_book(&pool, 60.0, &[]).await;
+        let audiobook_repository = AudiobookRepository::new(PoolHandler::new(pool));
+
+        let download = audiobook_repository
+            .record_download(&AudiobookDownloadCreate::new(
+                &8,
+                &book.id,
+                &book.file_path,
+                &book.length,
+            ))
+            .await
+            .expect("Record download should succeed");
+        assert_eq!(download.user_id, 8);
+        assert_eq!(download.audiobook_id, book.id);
+        assert_eq!(download.file_path, BOOK_FILE);
+        assert_eq!(download.length, 60.0);
+        audiobook_repository.disconnect().await;
+    }
+
+    #[sqlx::test(fixtures("users", "genres"))]
+    async fn record_listening_sessions(pool: PgPool) {
+        let book = create_uploaded_book(&pool, 600.0, &[]).await;
+        let audiobook_repository = AudiobookRepository::new(PoolHandler::new(pool));
+
+        let now = Utc::now();
+        // two pieces of a session, a later re-listen, and a session from another device
+        // which is reported late
+        for (start, end, started_at, device) in [
+            (0.0, 30.0, -3600, "laptop"),
+            (30.0, 60.0, -3570, "laptop"),
+            (10.0, 20.0, -10, "laptop"),
+            (300.0, 305.0, -7200, "phone"),
+        ] {
+            let ended_at =
+                now + Duration::seconds(started_at) + Duration::seconds(end as i64 - start as i64);
+            let session = ListeningSessionCreate::new(
+                &8,
+                &book.id,
+                &start,
+                &end,
+                &ended_at,
+                &(end - start),
+                device,
+                device,
+            );
+            audiobook_repository
+                .record_listening_session(&session)
+                .await
+                .expect("Record listening session should succeed");
+        }
+
+        // the position is where the latest session ended
+        let played = audiobook_repository
+            .get_latest_active_audiobook(&8)
+            .await
+            .expect("Read latest active book should succeed")
+            .expect("The book should be active");
+        assert_eq!(played.book_id, book.id);
+        assert_eq!(played.playback_position, 20.0);
+        // the late session lost, it can be jumped to
+        assert_eq!(played.suggested_position, Some(305.0));
+
+        let history = audiobook_repository
+            .read_listening_history(&ListeningHistoryGet::new(8, None, 10, 0))
+            .await
+            .expect("Read listening history should succeed");
+        let sessions: Vec<(f64, f64, f64, &str)> = history
+            .iter()
+            .map(|entry| {
+                (
+                    entry.start_position,
+                    entry.end_position,
+                    entry.listened,
+                    entry.client.as_str(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            sessions,
+            vec![
+                (10.0, 20.0, 10.0, "laptop"),
+                (0.0, 60.0, 60.0, "laptop"),
+                (300.0, 305.0, 5.0, "phone"),
+            ]
+        );
+
+        let history = audiobook_repository
+            .read_listening_history(&ListeningHistoryGet::new(8, Some(book.id), 1, 1))
+            .await
+            .expect("Read listening history should succeed");
+        assert_eq!(history.len(), 1);
+        assert_eq!(history[0].end_position, 60.0);
+
+        let history = audiobook_repository
+            .read_listening_history(&ListeningHistoryGet::new(9, None, 10, 0))
+            .await
+            .expect("Read listening history should succeed");
+        assert!(history.is_empty());
+        audiobook_repository.disconnect().await;
+    }
+
+    #[sqlx::test(fixtures("users", "genres"))]
+    async fn sync_positions_across_devices(pool: PgPool) {
+        let book = create_uploaded_book(&pool, 7200.0, &[]).await;
+        let audiobook_repository = AudiobookRepository::new(PoolHandler::new(pool));
+
+        let now = Utc::now();
+        let report = |position: f64, seconds: i64, device: &str| {
+            SetActiveAudiobook::new(
+                8,
+                book.id,
+                position,
+                now + Duration::seconds(seconds),
+                device,
+            )
+        };
+        audiobook_repository
+            .get_or_create_active_audiobook(&8, &book.id)
+            .await
+            .expect("Open the book should succeed");
+        // the position of the opened book was not reported by a device, any report wins,
+        // then the phone was offline, its position is older than the one of the laptop
+        let steps = [
+            (report(100.0, -60, "laptop"), true, 100.0, None),
+            (report(3000.0, -600, "phone"), false, 100.0, Some(3000.0)),
+            // the laptop got close to the suggested position
+            (report(3010.0, 10, "laptop"), true, 3010.0, None),
+            // the phone did not know about the position of the laptop
+            (report(200.0, 20, "phone"), true, 200.0, Some(3010.0)),
+            // jumps back on the same device are intended
+            (report(150.0, 30, "phone"), true, 150.0, Some(3010.0)),
+        ];
+        for (update, applied, position, suggested_position) in steps {
+            let synced = audiobook_repository
+                .set_active_audiobook(&update)
+                .await
+                .expect("Set active book should succeed");
+            assert_eq!(synced.applied, applied);
+            assert_eq!(synced.active.playback_position, position);
+            assert_eq!(synced.active.suggested_position, suggested_position);
+        }
+
+        // queued positions are applied in the order they were reported in
+        let synced = audiobook_repository
+            .set_active_audiobooks(&[report(400.0, 50, "phone"), report(300.0, 40, "phone")])
+            .await
+            .expect("Set active books should succeed");
+        assert_eq!(synced.len(), 2);
+        assert_eq!(synced[0].active.playback_position, 400.0);
+        assert_eq!(synced[1].active.playback_position, 300.0);
+        let played = audiobook_repository
+            .get_latest_active_audiobook(&8)
+            .await
+            .expect("Read latest active book should succeed")
+            .expect("The book should be active");
+        assert_eq!(played.playback_position, 400.0);
+
+        audiobook_repository
+            .dismiss_suggested_position(&RemoveActiveAudiobook::new(8, book.id))
+            .await
+            .expect("Dismiss suggested position should succeed");
+        let played = audiobook_repository
+            .get_latest_active_audiobook(&8)
+            .await
+            .expect("Read latest active book should succeed")
+            .expect("The book should be active");
+        assert_eq!(played.suggested_position, None);
+        audiobook_repository.disconnect().await;
+    }
+
+    async fn books_in_state(
+        audiobook_repository: &AudiobookRepository,
+        state: BookState,
+    ) -> Vec<Id> {
+        audiobook_repository
+            .read_many(&AudiobookSearch::with_params(
+                DbQueryParams::state(state),
+                8,
+            ))
+            .await
+            .expect("Read books should succeed")
+            .into_iter()
+            .map(|book| book.id)
+            .collect()
+    }
+
+    #[sqlx::test(fixtures("users", "genres"))]
+    async fn mark_book_progress(pool: PgPool) {
+        let book = create_uploaded_book(&pool, 7200.0, &[]).await;
+        let audiobook_repository = AudiobookRepository::new(PoolHandler::new(pool));
+        audiobook_repository
+            .take_unprocessed(&AudiobookProcessingTake::new(10, 60))
+            .await
+            .expect("Take unprocessed books should succeed");
+        audiobook_repository
+            .finish_processing(&AudiobookProcessed::new(&book.id, None))
+            .await
+            .expect("Finish processing should succeed");
+
+        let now = Utc::now();
+        let change = |change: ProgressChange, seconds: i64| {
+            ProgressChangeSet::new(
+                8,
+                book.id,
+                change,
+                now + Duration::seconds(seconds),
+                "laptop",
+            )
+        };
+        audiobook_repository
+            .set_active_audiobook(&SetActiveAudiobook::new(8, book.id, 100.0, now, "laptop"))
+            .await
+            .expect("Set active book should succeed");
+        assert_eq!(
+            books_in_state(&audiobook_repository, BookState::Active(true)).await,
+            vec![book.id]
+        );
+
+        // marking the book finished keeps the position
+        let active = audiobook_repository
+            .change_progress(&change(ProgressChange::Finished, 10))
+            .await
+            .expect("Mark the book finished should succeed");
+        assert_eq!(active.progress_mark, ProgressMark::Finished);
+        assert_eq!(active.playback_position, 100.0);
+        assert_eq!(
+            books_in_state(&audiobook_repository, BookState::Finished(true)).await,
+            vec![book.id]
+        );
+        assert!(
+            books_in_state(&audiobook_repository, BookState::Active(true))
+                .await
+                .is_empty()
+        );
+
+        // positions reported before the mark do not undo it
+        let synced = audiobook_repository
+            .set_active_audiobook(&SetActiveAudiobook::new(8, book.id, 150.0, now, "phone"))
+            .await
+            .expect("Set active book should succeed");
+        assert!(!synced.applied);
+        assert_eq!(synced.active.progress_mark, ProgressMark::Finished);
+
+        let active = audiobook_repository
+            .change_progress(&change(ProgressChange::Unplayed, 20))
+            .await
+            .expect("Mark the book unplayed should succeed");
+        assert_eq!(active.progress_mark, ProgressMark::Unplayed);
+        assert_eq!(active.playback_position, 0.0);
+        assert!(
+            books_in_state(&audiobook_repository, BookState::Fresh(true))
+                .await
+                .contains(&book.id)
+        );
+        assert!(audiobook_repository
+            .get_latest_active_audiobook(&8)
+            .await
+            .expect("Read latest active book should succeed")
+            .is_none());
+
+        // playing the book again clears the mark
+        let synced = audiobook_repository
+            .set_active_audiobook(&SetActiveAudiobook::new(
+                8,
+                book.id,
+                30.0,
+                now + Duration::seconds(30),
+                "laptop",
+            ))
+            .await
+            .expect("Set active book should succeed");
+        assert!(synced.applied);
+        assert_eq!(synced.active.progress_mark, ProgressMark::Unmarked);
+        assert_eq!(
+            books_in_state(&audiobook_repository, BookState::Active(true)).await,
+            vec![book.id]
+        );
+
+        let active = audiobook_repository
+            .change_progress(&change(ProgressChange::Reset, 40))
+            .await
+            .expect("Start the book over should succeed");
+        assert_eq!(active.progress_mark, ProgressMark::Unmarked);
+        assert_eq!(active.playback_position, 0.0);
+        audiobook_repository.disconnect().await;
+    }
+
+    #[sqlx::test(fixtures("users", "genres"))]
+    async fn track_chapter_progress(pool: PgPool) {
+        let book = create_uploaded_book(
+            &pool,
+            300.0,
+            &[
+                ChapterEmbeddedCreate::new("first", 0.0),
+                ChapterEmbeddedCreate::new("second", 100.0),
+                ChapterEmbeddedCreate::new("third", 200.0),
+            ],
+        )
+        .await;
+        let chapter_repository = ChapterRepository::new(PoolHandler::new(pool.clone()));
+        let audiobook_repository = AudiobookRepository::new(PoolHandler::new(pool));
+
+        let now = Utc::now();
+        audiobook_repository
+            .record_listening_session(&ListeningSessionCreate::new(
+                &8, &book.id, &0.0, &150.0, &now, &150.0, "laptop", "laptop",
+            ))
+            .await
+            .expect("Record listening session should succeed");
+        // the phone skipped to the last chapter
+        audiobook_repository
+            .set_active_audiobook(&SetActiveAudiobook::new(
+                8,
+                book.id,
+                250.0,
+                now + Duration::seconds(10),
+                "phone",
+            ))
+            .await
+            .expect("Set active book should succeed");
+
+        let mut progress = chapter_repository
+            .read_progress(&ChapterProgressGet::new(8, book.id))
+            .await
+            .expect("Read chapter progress should succeed");
+        progress.sort_by(|a, b| a.progress.total_cmp(&b.progress));
+        let progress: Vec<(f64, bool)> = progress
+            .iter()
+            .map(|chapter| (chapter.progress, chapter.completed_at.is_some()))
+            .collect();
+        assert_eq!(progress, vec![(0.5, false), (0.5, false), (1.0, true)]);
+
+        let retention = chapter_repository
+            .read_retention(&ChaptersGetByBookId::new(book.id))
+            .await
+            .expect("Read chapter retention should succeed");
+        let retention: Vec<(i64, i64, i64)> = retention
+            .iter()
+            .map(|chapter| (chapter.listeners, chapter.completed, chapter.stopped))
+            .collect();
+        assert_eq!(retention, vec![(1, 1, 0), (1, 0, 0), (1, 0, 1)]);
+
+        // starting the book over forgets the progress in its chapters
+        audiobook_repository
+            .change_progress(&ProgressChangeSet::new(
+                8,
+                book.id,
+                ProgressChange::Reset,
+                now + Duration::seconds(20),
+                "phone",
+            ))
+            .await
+            .expect("Start the book over should succeed");
+        assert!(chapter_repository
+            .read_progress(&ChapterProgressGet::new(8, book.id))
+            .await
+            .expect("Read chapter progress should succeed")
+            .is_empty());
+        audiobook_repository.disconnect().await;
+    }
+
+    #[sqlx::test(fixtures("users", "genres"))]
+    async fn count_plays(pool: PgPool) {
+        let book = create_uploaded_book(
+            &pool,
+            300.0,
+            &[
+                ChapterEmbeddedCreate::new("first", 0.0),
+                ChapterEmbeddedCreate::new("second", 100.0),
+            ],
+        )
+        .await;
+        let chapter_repository = ChapterRepository::new(PoolHandler::new(pool.clone()));
+        let audiobook_repository = AudiobookRepository::new(PoolHandler::new(pool.clone()));
+        audiobook_repository
+            .get_or_create_active_audiobook(&8, &book.id)
+            .await
+            .expect("Open the book should succeed");
+
+        let noon = Utc::now()
+            .date_naive()
+            .and_hms_opt(12, 0, 0)
+            .expect("Noon should be a valid time")
+            .and_utc();
+        // sessions and the number of plays of the book after each of them; opening the book
+        // is not a play, neither is hearing less than PLAY_MIN_LISTENED_SECONDS of it,
+        // and the listener plays the book at most once a day
+        let third_day = noon + Duration::days(2);
+        let steps = [
+            (0.0, 20.0, 20.0, noon, 0),
+            (20.0, 40.0, 20.0, noon + Duration::minutes(1), 1),
+            (40.0, 80.0, 40.0, noon + Duration::minutes(2), 1),
+            (80.0, 120.0, 40.0, noon + Duration::days(1), 2),
+            // hearing the same part again does not add to what was heard
+            (0.0, 20.0, 20.0, third_day, 2),
+            (0.0, 20.0, 20.0, third_day + Duration::minutes(1), 2),
+            // the session could not have covered more than 8 seconds in 2 seconds
+            (20.0, 300.0, 2.0, third_day + Duration::minutes(2), 2),
+            (28.0, 40.0, 12.0, third_day + Duration::minutes(3), 3),
+        ];
+        for (start, end, duration, ended_at, plays) in steps {
+            audiobook_repository
+                .record_listening_session(&ListeningSessionCreate::new(
+                    &8, &book.id, &start, &end, &ended_at, &duration, "laptop", "laptop",
+                ))
+                .await
+                .expect("Record listening session should succeed");
+            let stored = audiobook_repository
+                .read_one(&AudiobookGetById::new(&book.id, true))
+                .await
+                .expect("Read book should succeed");
+            assert_eq!(stored.stream_count, plays);
+        }
+
+        let days = audiobook_repository
+            .read_daily_plays(&DailyPlaysGet::new(book.id, 30))
+            .await
+            .expect("Read daily plays should succeed");
+        let days: Vec<_> = days.iter().map(|day| (day.played_on, day.plays)).collect();
+        assert_eq!(
+            days,
+            vec![
+                (third_day.date_naive(), 1),
+                ((noon + Duration::days(1)).date_naive(), 1),
+                (noon.date_naive(), 1)
+            ]
+        );
+
+        // the second chapter was heard for only 20 seconds, the rest of the last but one
+        // session does not count
+        let chapters = chapter_repository
+            .read_retention(&ChaptersGetByBookId::new(book.id))
+            .await
+            .expect("Read chapter retention should succeed");
+        let plays: Vec<i64> = chapters.iter().map(|chapter| chapter.plays).collect();
+        assert_eq!(plays, vec![2, 0]);
+
+        // plays counted already are not counted again
+        let sessions = audiobook_repository
+            .backfill_plays()
+            .await
+            .expect("Backfill plays should succeed");
+        assert_eq!(sessions, 0);
+
+        // the sessions recorded before plays were counted count the same plays
+        for statement in [
+            r#"DELETE FROM "Play""#,
+            r#"DELETE FROM "Chapter_Play""#,
+            r#"UPDATE "Audiobook" SET stream_count = 0"#,
+        ] {
+            sqlx::query(statement)
+                .execute(&pool)
+                .await
+                .expect("Reset plays should succeed");
+        }
+        let sessions = audiobook_repository
+            .backfill_plays()
+            .await
+            .expect("Backfill plays should succeed");
+        assert_eq!(sessions, steps.len());
+        let stored = audiobook_repository
+            .read_one(&AudiobookGetById::new(&book.id, true))
+            .await
+            .expect("Read book should succeed");
+        assert_eq!(stored.stream_count, 3);
+        let chapters = chapter_repository
+            .read_retention(&ChaptersGetByBookId::new(book.id))
+            .await
+            .expect("Read chapter retention should succeed");
+        let plays: Vec<i64> = chapters.iter().map(|chapter| chapter.plays).collect();
+        assert_eq!(plays, vec![2, 0]);
+        audiobook_repository.disconnect().await;
+    }
+
+    #[sqlx::test(fixtures("users", "genres"))]
+    async fn accept_chapter_suggestions(pool: PgPool) {
+        let book = create_uploaded_book(
+            &pool,
+            300.0,
+            &[
+                ChapterEmbeddedCreate::new("first", 0.0),
+                ChapterEmbeddedCreate::new("second", 100.0),
+            ],
+        )
+        .await;
+        let chapter_repository = ChapterRepository::new(PoolHandler::new(pool.clone()));
+        let audiobook_repository = AudiobookRepository::new(PoolHandler::new(pool));
+        let suggestions = chapter_repository
+            .replace_suggestions(
+                book.id,
+                &[
+                    ChapterSuggestionCreate::new(50.0, 2.0),
+                    ChapterSuggestionCreate::new(102.0, 2.0),
+                    ChapterSuggestionCreate::new(200.0, 2.0),
+                    ChapterSuggestionCreate::new(250.0, 2.0),
+                ],
+            )
+            .await
+            .expect("Replace suggestions should succeed");
+
+        // chapters are named by their order in the book at the time they are accepted
+        let accepted = chapter_repository
+            .accept_suggestions(&ChapterSuggestionAccept::new(
+                book.id,
+                Some(suggestions[2].id),
+                5.0,
+            ))
+            .await
+            .expect("Accept suggestion should succeed");
+        assert_eq!(accepted.len(), 1);
+        assert_eq!(accepted[0].name, "Chapter 3");
+
+        // the suggestion too close to an existing chapter is not accepted
+        chapter_repository
+            .accept_suggestions(&ChapterSuggestionAccept::new(book.id, None, 5.0))
+            .await
+            .expect("Accept suggestions should succeed");
+        let chapters = chapter_repository
+            .read_many(&ChaptersGetByBookId::new(book.id))
+            .await
+            .expect("Read chapters should succeed");
+        let mut chapters: Vec<_> = chapters
+            .iter()
+            .map(|chapter| (chapter.position, chapter.name.as_str()))
+            .collect();
+        chapters.sort_by(|a, b| a.0.total_cmp(&b.0));
+        assert_eq!(
+            chapters,
+            vec![
+                (0.0, "first"),
+                (50.0, "Chapter 2"),
+                (100.0, "second"),
+                (200.0, "Chapter 3"),
+                (250.0, "Chapter 5"),
+            ]
+        );
+        audiobook_repository.disconnect().await;
+    }
+}
@@ -0,0 +1,147 @@
+#[cfg(test)]
+pub mod export_repo_tests {
+
+    use sqlx::PgPool;
+
+    use crate::database::common::{DbPoolHandler, DbRepository, PoolHandler};
+    use crate::database::models::audiobook::AudiobookCreate;
+    use crate::database::models::export::{
+        AudiobookExportFinish, AudiobookExportRequest, AudiobookExportsTake, ExportState,
+    };
+    use crate::database::models::media::MediaClaim;
+    use crate::database::repositories::audiobook::repository::AudiobookRepository;
+    use crate::database::repositories::export::repository::AudiobookExportRepository;
+    use crate::database::repositories::media::repository::MediaRepository;
+
+    const BOOK_FILE: &str = "/media/abc_audio.mp3";
+    const EXPORT_FILE: &str = "/exports/audiobook_1_abc.m4b";
+    const EDITED_EXPORT_FILE: &str = "/exports/audiobook_1_def.m4b";
+
+    #[sqlx::test(fixtures("users", "genres"))]
+    async fn export_book(pool: PgPool) {
+        let media_repository = MediaRepository::new(PoolHandler::new(pool.clone()));
+        let audiobook_repository = AudiobookRepository::new(PoolHandler::new(pool.clone()));
+        let export_repository = AudiobookExportRepository::new(PoolHandler::new(pool));
+        media_repository
+            .claim(&MediaClaim::new(BOOK_FILE, "abc", 3))
+            .await
+            .expect("Claim media should succeed");
+        let book = audiobook_repository
+            .create_uploaded(
+                &AudiobookCreate::new("book", &9, &29, BOOK_FILE, &60.0, None, "bio"),
+                &[],
+            )
+            .await
+            .expect("Create uploaded book should succeed");
+
+        let export = export_repository
+            .request(&AudiobookExportRequest::new(&book.id, "abc"))
+            .await
+            .expect("Request export should succeed");
+        assert_eq!(export.state, ExportState::Pending);
+
+        let take = AudiobookExportsTake::new(10, 60);
+        let exports = export_repository
+            .take_pending(&take)
+            .await
+            .expect("Take pending exports should succeed");
+        assert_eq!(exports.len(), 1);
+        assert_eq!(exports[0].state, ExportState::Exporting);
+        let running = exports[0].clone();
+
+        // a running export of the same state is not requested again
+        let export = export_repository
+            .request(&AudiobookExportRequest::new(&book.id, "abc"))
+            .await
+            .expect("Request export should succeed");
+        assert_eq!(export.state, ExportState::Exporting);
+        assert!(export_repository
+            .take_pending(&take)
+            .await
+            .expect("Take pending exports should succeed")
+            .is_empty());
+
+        let unused = export_repository
+            .finish(&AudiobookExportFinish::ready(
+                &running,
+                "abc",
+                EXPORT_FILE,
+                42,
+            ))
+            .await
+            .expect("Finish export should succeed");
+        assert!(unused.is_empty());
+        let export = export_repository
+            .read(&book.id)
+            .await
+            .expect("Read export should succeed")
+            .expect("The export should exist");
+        assert_eq!(export.current_file("abc"), Some(EXPORT_FILE));
+        assert_eq!(export.current_file("def"), None);
+        assert_eq!(export.size, Some(42));
+
+        // the book was edited while its export ran, the export is not recorded
+        export_repository
+            .request(&AudiobookExportRequest::new(&book.id, "def"))
+            .await
+            .expect("Request export should succeed");
+        let edited = export_repository
+            .take_pending(&take)
+            .await
+            .expect("Take pending exports should succeed")
+            .remove(0);
+        export_repository
+            .request(&AudiobookExportRequest::new(&book.id, "ghi"))
+            .await
+            .expect("Request export should succeed");
+        let unused = export_repository
+            .finish(&AudiobookExportFinish::ready(
+                &edited,
+                "def",
+                EDITED_EXPORT_FILE,
+                42,
+            ))
+            .await
+            .expect("Finish export should succeed");
+        assert_eq!(unused, vec![EDITED_EXPORT_FILE.to_string()]);
+
+        // the replaced export is not used anymore
+        let latest = export_repository
+            .take_pending(&take)
+            .await
+            .expect("Take pending exports should succeed")
+            .remove(0);
+        let unused = export_repository
+            .finish(&AudiobookExportFinish::ready(
+                &latest,
+                "ghi",
+                EDITED_EXPORT_FILE,
+                42,
+            ))
+            .await
+            .expect("Finish export should succeed");
+        assert_eq!(unused, vec![EXPORT_FILE.to_string()]);
+
+        // a failed export can be requested again
+        export_repository
+            .request(&AudiobookExportRequest::new(&book.id, "jkl"))
+            .await
+            .expect("Request export should succeed");
+        let failed = export_repository
+            .take_pending(&take)
+            .await
+            .expect("Take pending exports should succeed")
+            .remove(0);
+        let unused = export_repository
+            .finish(&AudiobookExportFinish::failed(&failed, "no audio stream"))
+            .await
+            .expect("Finish export should succeed");
+        assert!(unused.is_empty());
+        let export = export_repository
+            .request(&AudiobookExportRequest::new(&book.id, "jkl"))
+            .await
+            .expect("Request export should succeed");
+        assert_eq!(export.state, ExportState::Pending);
+        assert_eq!(export.error, None);
+    }
+}
@@ -1,3 +1,5 @@
+INSERT INTO "Media" (path) VALUES ('/home/hafo/profile_pic');
+
 INSERT INTO "User" (id, username, email, name, surname, bio, profile_picture, password_hash, password_salt, created_at, edited_at)
 
 VALUES
@@ -0,0 +1,113 @@
+#[cfg(test)]
+pub mod media_repo_tests {
+
+    use sqlx::PgPool;
+
+    use crate::database::common::{DbPoolHandler, DbRepository, DbUpdate, PoolHandler};
+    use crate::database::models::media::{MediaClaim, MediaUnreferenced};
+    use crate::database::models::user::UserUpdate;
+    use crate::database::repositories::media::repository::MediaRepository;
+    use crate::database::repositories::user::repository::UserRepository;
+
+    const OLD_PICTURE: &str = "/home/hafo/profile_pic";
+    const NEW_PICTURE: &str = "/media/abc_image.jpg";
+
+    fn set_picture(id: i64, picture: &str) -> UserUpdate {
+        UserUpdate::new(&id, None, None, None, None, None, Some(picture), None)
+    }
+
+    #[sqlx::test(fixtures("users"))]
+    async fn count_references(pool: PgPool) {
+        let media_repository = MediaRepository::new(PoolHandler::new(pool.clone()));
+        let user_repository = UserRepository::new(PoolHandler::new(pool));
+        let media = media_repository
+            .claim(&MediaClaim::new(NEW_PICTURE, "abc", 3))
+            .await
+            .expect("Claim media should succeed");
+        assert_eq!(media.ref_count, 0);
+
+        user_repository
+            .update(&set_picture(9, NEW_PICTURE))
+            .await
+            .expect("Update user should succeed");
+        let media = media_repository
+            .claim(&MediaClaim::new(NEW_PICTURE, "abc", 3))
+            .await
+            .expect("Claim media should succeed");
+        assert_eq!(media.ref_count, 1);
+        user_repository.disconnect().await;
+    }
+
+    #[sqlx::test(fixtures("users"))]
+    async fn remove_unreferenced_media(pool: PgPool) {
+        let media_repository = MediaRepository::new(PoolHandler::new(pool.clone()));
+        let user_repository = UserRepository::new(PoolHandler::new(pool));
+        let unreferenced =
+            MediaUnreferenced::new(vec![OLD_PICTURE.to_string(), NEW_PICTURE.to_string()], 0);
+        media_repository
+            .claim(&MediaClaim::new(NEW_PICTURE, "abc", 3))
+            .await
+            .expect("Claim media should succeed");
+
+        // the old picture is still used by the other user
+        user_repository
+            .update(&set_picture(9, NEW_PICTURE))
+            .await
+            .expect("Update user should succeed");
+        let removed = media_repository
+            .remove_unreferenced(&unreferenced, |_| async { true })
+            .await
+            .expect("Remove media should succeed");
+        assert!(removed.is_empty());
+
+        user_repository
+            .update(&set_picture(8, NEW_PICTURE))
+            .await
+            .expect("Update user should succeed");
+        let removed = media_repository
+            .remove_unreferenced(&unreferenced, |_| async { true })
+            .await
+            .expect("Remove media should succeed");
+        assert_eq!(removed.len(), 1);
+        assert_eq!(removed[0].path, OLD_PICTURE);
+        media_repository.disconnect().await;
+    }
+
+    #[sqlx::test(fixtures("users"))]
+    async fn read_garbage_candidates(pool: PgPool) {
+        let media_repository = MediaRepository::new(PoolHandler::new(pool));
+        media_repository
+            .claim(&MediaClaim::new(NEW_PICTURE, "abc", 3))
+            .await
+            .expect("Claim media should succeed");
+
+        let references = media_repository
+            .read_references()
+            .await
+            .expect("Read references should succeed");
+        assert!(references.contains(&OLD_PICTURE.to_string()));
+        assert!(!references.contains(&NEW_PICTURE.to_string()));
+
+        let unreferenced = media_repository
+            .read_unreferenced(&MediaUnreferenced::all(0))
+            .await
+            .expect("Read unreferenced media should succeed");
+        assert_eq!(unreferenced.len(), 1);
+        assert_eq!(unreferenced[0].path, NEW_PICTURE);
+
+        // recently claimed media are kept
+        let unreferenced = media_repository
+            .read_unreferenced(&MediaUnreferenced::all(3600))
+            .await
+            .expect("Read unreferenced media should succeed");
+        assert!(unreferenced.is_empty());
+
+        let paths = media_repository
+            .read_paths()
+            .await
+            .expect("Read paths should succeed");
+        assert!(paths.contains(&NEW_PICTURE.to_string()));
+        assert_eq!(paths.len(), references.len() + 1);
+        media_repository.disconnect().await;
+    }
+}
@@ -1,2 +1,6 @@
+pub mod audiobook;
+pub mod export;
 pub mod genre;
+pub mod media;
+pub mod recommender_queue;
 pub mod user;
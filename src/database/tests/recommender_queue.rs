@@ -0,0 +1,82 @@
+#[cfg(test)]
+pub mod recommender_queue_repo_tests {
+
+    use sqlx::PgPool;
+
+    use crate::database::common::{DbPoolHandler, DbRepository, PoolHandler};
+    use crate::database::models::audiobook::AudiobookCreate;
+    use crate::database::models::chapter::ChapterEmbeddedCreate;
+    use crate::database::models::media::MediaClaim;
+    use crate::database::models::recommender_queue::{
+        RecommenderTaskPostpone, RecommenderTasksTake,
+    };
+    use crate::database::repositories::audiobook::repository::AudiobookRepository;
+    use crate::database::repositories::media::repository::MediaRepository;
+    use crate::database::repositories::recommender_queue::repository::RecommenderQueueRepository;
+
+    const BOOK_FILE: &str = "/media/abc_audio.mp3";
+
+    #[sqlx::test(fixtures("users", "genres"))]
+    async fn queue_uploaded_book(pool: PgPool) {
+        let media_repository = MediaRepository::new(PoolHandler::new(pool.clone()));
+        let audiobook_repository = AudiobookRepository::new(PoolHandler::new(pool.clone()));
+        let queue_repository = RecommenderQueueRepository::new(PoolHandler::new(pool));
+        media_repository
+            .claim(&MediaClaim::new(BOOK_FILE, "abc", 3))
+            .await
+            .expect("Claim media should succeed");
+
+        let book = audiobook_repository
+            .create_uploaded(
+                &AudiobookCreate::new("book", &9, &29, BOOK_FILE, &60.0, None, "bio"),
+                &[
+                    ChapterEmbeddedCreate::new("first", 0.0),
+                    ChapterEmbeddedCreate::new("second", 30.0),
+                ],
+            )
+            .await
+            .expect("Create uploaded book should succeed");
+
+        let take = RecommenderTasksTake::new(10, 60);
+        let tasks = queue_repository
+            .take_due(&take)
+            .await
+            .expect("Take tasks should succeed");
+        assert_eq!(tasks.len(), 1);
+        assert_eq!(tasks[0].audiobook_id, book.id);
+
+        // taken tasks are leased
+        let tasks = queue_repository
+            .take_due(&take)
+            .await
+            .expect("Take tasks should succeed");
+        assert!(tasks.is_empty());
+
+        queue_repository
+            .postpone(&RecommenderTaskPostpone::new(&book.id, 0, "unavailable"))
+            .await
+            .expect("Postpone task should succeed");
+        let tasks = queue_repository
+            .take_due(&take)
+            .await
+            .expect("Take tasks should succeed");
+        assert_eq!(tasks.len(), 1);
+        assert_eq!(tasks[0].attempts, 1);
+        assert_eq!(tasks[0].last_error.as_deref(), Some("unavailable"));
+
+        queue_repository
+            .finish(&book.id)
+            .await
+            .expect("Finish task should succeed");
+        queue_repository
+            .postpone(&RecommenderTaskPostpone::new(&book.id, 0, "unavailable"))
+            .await
+            .expect("Postpone task should succeed");
+        let tasks = queue_repository
+            .take_due(&take)
+            .await
+            .expect("Take tasks should succeed");
+        assert!(tasks.is_empty());
+        queue_repository.disconnect().await;
+    }
+}
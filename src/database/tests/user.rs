@@ -1,12 +1,20 @@
 #[cfg(test)]
 pub mod user_repo_tests {
 
+    use chrono::{Duration, Utc};
     use sqlx::PgPool;
+    use uuid::Uuid;
 
     use crate::database::common::{
         DbCreate, DbPoolHandler, DbReadMany, DbRepository, DbUpdate, PoolHandler,
     };
+    use crate::database::models::audiobook::AudiobookCreate;
+    use crate::database::models::media::MediaClaim;
+    use crate::database::models::upload::UploadCreate;
     use crate::database::models::user::{UserCreate, UserSearch, UserUpdate};
+    use crate::database::repositories::audiobook::repository::AudiobookRepository;
+    use crate::database::repositories::media::repository::MediaRepository;
+    use crate::database::repositories::upload::repository::UploadRepository;
     use crate::database::repositories::user::repository::UserRepository;
 
     #[sqlx::test(fixtures("users"))]
@@ -70,4 +78,157 @@ pub mod user_repo_tests {
         assert_eq!(u.email, "pe@pe.com");
         user_repository.disconnect().await;
     }
+
+    #[sqlx::test(fixtures("users", "genres"))]
+    async fn storage_usage(pool: PgPool) {
+        let media_repository = MediaRepository::new(PoolHandler::new(pool.clone()));
+        let audiobook_repository = AudiobookRepository::new(PoolHandler::new(pool.clone()));
+        let upload_repository = UploadRepository::new(PoolHandler::new(pool.clone()));
+        let user_repository = UserRepository::new(PoolHandler::new(pool));
+        for (path, digest, size) in [
+            ("/media/abc_audio.mp3", "abc", 300),
+            ("/media/def_cover.png", "def", 20),
+        ] {
+            media_repository
+                .claim(&MediaClaim::new(path, digest, size))
+                .await
+                .expect("Claim media should succeed");
+        }
+        // both books share the audio, it is stored once
+        for name in ["book", "another book"] {
+            audiobook_repository
+                .create_uploaded(
+                    &AudiobookCreate::new(
+                        name,
+                        &9,
+                        &29,
+                        "/media/abc_audio.mp3",
+                        &60.0,
+                        Some("/media/def_cover.png".to_string()),
+                        "",
+                    ),
+                    &[],
+                )
+                .await
+                .expect("Create uploaded book should succeed");
+        }
+
+        let usage = user_repository
+            .read_storage_usage(&9)
+            .await
+            .expect("Read storage usage should succeed");
+        assert_eq!(usage.role, "author");
+        assert_eq!(usage.stored, 320);
+        assert_eq!(usage.uploading, 0);
+        assert!(usage.quota.is_some_and(|quota| quota > 320));
+
+        user_repository
+            .set_user_quota(&9, None, Some(500))
+            .await
+            .expect("Set user quota should succeed");
+        let usage = user_repository
+            .read_storage_usage(&9)
+            .await
+            .expect("Read storage usage should succeed");
+        assert_eq!(usage.quota, Some(500));
+        assert!(usage.allows(180));
+        assert!(!usage.allows(181));
+
+        // parallel uploads are accounted for one after another, only one of them fits
+        let upload = |length| {
+            UploadCreate::new(
+                Uuid::new_v4(),
+                &9,
+                &29,
+                "book",
+                "",
+                None,
+                None,
+                length,
+                Utc::now() + Duration::hours(1),
+            )
+        };
+        let (first, second) = (upload(100), upload(100));
+        let (first, second) = tokio::join!(
+            upload_repository.create_within_quota(&first),
+            upload_repository.create_within_quota(&second),
+        );
+        let first = first.expect("Create upload should succeed");
+        let second = second.expect("Create upload should succeed");
+        assert!(first.is_ok() != second.is_ok());
+        let rejected = first
+            .err()
+            .or(second.err())
+            .expect("An upload should not fit");
+        assert_eq!(rejected.uploading, 100);
+        let usage = user_repository
+            .read_storage_usage(&9)
+            .await
+            .expect("Read storage usage should succeed");
+        assert_eq!(usage.uploading, 100);
+        assert!(upload_repository
+            .create_within_quota(&upload(80))
+            .await
+            .expect("Create upload should succeed")
+            .is_ok());
+
+        // the quota of the user overrides the quota of the role
+        user_repository
+            .set_role_quota("listener", Some(100))
+            .await
+            .expect("Set role quota should succeed");
+        user_repository
+            .set_user_quota(&9, Some("listener"), None)
+            .await
+            .expect("Set user role should succeed");
+        let usage = user_repository
+            .read_storage_usage(&9)
+            .await
+            .expect("Read storage usage should succeed");
+        assert_eq!(usage.role, "listener");
+        assert_eq!(usage.quota, Some(100));
+        assert!(!usage.allows(0));
+
+        user_repository
+            .set_user_quota(&9, Some("unlimited"), None)
+            .await
+            .expect("Set user role should succeed");
+        let usage = user_repository
+            .read_storage_usage(&9)
+            .await
+            .expect("Read storage usage should succeed");
+        assert_eq!(usage.quota, None);
+        assert!(usage.allows(i64::MAX / 2));
+
+        assert!(user_repository
+            .set_user_quota(&9, Some("nonexistent"), None)
+            .await
+            .is_err());
+        user_repository.disconnect().await;
+    }
+
+    #[sqlx::test(fixtures("users"))]
+    async fn lock_upload(pool: PgPool) {
+        let upload_repository = UploadRepository::new(PoolHandler::new(pool));
+        let (id, other_id) = (Uuid::new_v4(), Uuid::new_v4());
+
+        // an upload is written by one request at a time, whichever connection it uses
+        let lock = upload_repository
+            .lock(&id)
+            .await
+            .expect("Lock upload should succeed");
+        assert!(lock.is_some());
+        assert!(upload_repository
+            .lock(&id)
+            .await
+            .expect("Lock upload should succeed")
+            .is_none());
+        assert!(upload_repository
+            .lock(&other_id)
+            .await
+            .expect("Lock upload should succeed")
+            .is_some());
+        drop(lock);
+        upload_repository.disconnect().await;
+    }
 }
@@ -33,6 +33,10 @@ pub enum AppErrorKind {
     FileError,
     #[error("unauthorized")]
     Unauthorized,
+    #[error("forbidden")]
+    Forbidden,
+    #[error("payload too large")]
+    PayloadTooLarge,
 }
 
 impl From<askama::Error> for AppError {
@@ -77,11 +81,17 @@ impl From<BackendError> for AppError {
             BackendErrorKind::UserDoesNotExist
             | BackendErrorKind::AudiobookDoesNotExist
             | BackendErrorKind::ChapterDoesNotExist
+            | BackendErrorKind::ChapterSuggestionDoesNotExist
             | BackendErrorKind::GenreDoesNotExist
-            | BackendErrorKind::RatingDoesNotExist => {
+            | BackendErrorKind::RatingDoesNotExist
+            | BackendErrorKind::UploadDoesNotExist => {
                 Self::new(AppErrorKind::NotFound, value.to_string().as_str())
             }
 
+            BackendErrorKind::UploadOffsetMismatch => {
+                Self::new(AppErrorKind::Conflict, value.to_string().as_str())
+            }
+
             BackendErrorKind::UserPasswordDoesNotMatch
             | BackendErrorKind::UnauthorizedOperation
             | BackendErrorKind::UserPasswordVerificationFailed => {
@@ -130,6 +140,12 @@ impl From<std::io::Error> for AppError {
     }
 }
 
+impl From<image::ImageError> for AppError {
+    fn from(value: image::ImageError) -> Self {
+        Self::new(AppErrorKind::FileError, value.to_string().as_str())
+    }
+}
+
 impl From<lofty::LoftyError> for AppError {
     fn from(value: lofty::LoftyError) -> Self {
         Self::new(AppErrorKind::FileError, value.to_string().as_str())
@@ -165,6 +181,8 @@ impl ResponseError for AppError {
             AppErrorKind::NotFound => StatusCode::NOT_FOUND,
             AppErrorKind::Conflict => StatusCode::CONFLICT,
             AppErrorKind::Unauthorized => StatusCode::UNAUTHORIZED,
+            AppErrorKind::Forbidden => StatusCode::FORBIDDEN,
+            AppErrorKind::PayloadTooLarge => StatusCode::PAYLOAD_TOO_LARGE,
             AppErrorKind::TemplatingError
             | AppErrorKind::InternalServerError
             | AppErrorKind::IdentityError
@@ -1,3 +1,5 @@
+use crate::database::models::active_audiobook::ProgressChange;
+use crate::database::models::audiobook::PositionRemap;
 use crate::database::models::Id;
 use actix_multipart::form::tempfile::TempFile;
 use actix_multipart::form::text::Text;
@@ -15,7 +17,7 @@ pub struct AudiobookUploadForm {
     #[multipart(rename = "thumbnail")]
     pub thumbnail: Option<TempFile>,
     #[multipart(rename = "file")]
-    pub audio_file: TempFile,
+    pub audio_files: Vec<TempFile>,
 }
 
 #[derive(Debug, MultipartForm)]
@@ -25,12 +27,23 @@ pub struct AudiobookThumbnailEditForm {
     pub audiobook_id: Text<Id>,
 }
 
+#[derive(Debug, MultipartForm)]
+pub struct AudiobookAudioReplaceForm {
+    #[multipart(rename = "file")]
+    pub audio_files: Vec<TempFile>,
+    pub audiobook_id: Text<Id>,
+    pub remap: Text<PositionRemap>,
+}
+
 #[derive(Debug, Clone, Deserialize)]
 pub struct AudiobookEditForm {
     pub audiobook_id: Id,
     pub name: String,
     pub genre_id: Id,
     pub description: String,
+    /// unchecked checkboxes are not sent
+    #[serde(default)]
+    pub downloadable: bool,
 }
 
 #[derive(Deserialize)]
@@ -38,3 +51,53 @@ pub struct AudiobookQuickSearchQuery {
     pub query: String,
     pub search_type: String,
 }
+
+/// Part of the book the player played, positions are in seconds of the book,
+/// `duration` is how long the listening took, `ended_at` is in milliseconds since the epoch
+#[derive(Debug, Clone, Deserialize)]
+pub struct ListeningSessionForm {
+    pub start_position: f64,
+    pub end_position: f64,
+    pub duration: f64,
+    pub ended_at: Option<i64>,
+    pub device: Option<String>,
+}
+
+/// Position reported by a player, `timestamp` is in milliseconds since the epoch
+#[derive(Deserialize)]
+pub struct ReportedPositionQuery {
+    pub position: f64,
+    pub timestamp: Option<i64>,
+    pub device: Option<String>,
+}
+
+/// Change of the listening state the listener asked for, `timestamp` is in milliseconds
+/// since the epoch
+#[derive(Deserialize)]
+pub struct ListeningStateForm {
+    pub change: ProgressChange,
+    pub timestamp: Option<i64>,
+    pub device: Option<String>,
+}
+
+/// Positions queued by a device while it was offline
+#[derive(Debug, Deserialize)]
+pub struct PositionSyncForm {
+    pub device: Option<String>,
+    pub updates: Vec<PositionUpdate>,
+}
+
+/// Position in a book, `download` is the offline download the position is in, if the audio
+/// of the book was replaced since, the position is moved to the new audio
+#[derive(Debug, Deserialize)]
+pub struct PositionUpdate {
+    pub audiobook_id: Id,
+    pub position: f64,
+    pub timestamp: i64,
+    pub download: Option<Id>,
+}
+
+#[derive(Deserialize)]
+pub struct ListeningHistoryQuery {
+    pub offset: Option<i64>,
+}
@@ -8,6 +8,13 @@ pub struct ChapterCreateForm {
     pub position: f64,
 }
 
+#[derive(Debug, Clone, Deserialize)]
+pub struct ChapterSuggestionAcceptForm {
+    pub audiobook_id: Id,
+    /// all suggestions of the book are accepted when missing
+    pub suggestion_id: Option<Id>,
+}
+
 #[derive(Debug, Clone, Deserialize)]
 pub struct ChapterDeleteForm {
     pub chapter_id: Id,
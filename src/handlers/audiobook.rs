@@ -1,6 +1,6 @@
-use crate::database::common::{DbCreate, DbDelete, DbReadMany, DbReadOne, DbUpdate};
+use crate::database::common::{DbDelete, DbReadMany, DbReadOne, DbUpdate};
 use crate::database::models::audiobook::{
-    AudiobookCreate, AudiobookDelete, AudiobookDisplay, AudiobookGetById, AudiobookGetByIdJoin,
+    Audiobook, AudiobookDelete, AudiobookDisplay, AudiobookGetById, AudiobookGetByIdJoin,
     AudiobookRecommenderDisplay, AudiobookUpdate,
 };
 use crate::database::models::genre::{GenreGetById, GenreSearch};
@@ -8,24 +8,31 @@ use crate::database::models::genre::{GenreGetById, GenreSearch};
 use crate::database::models::Id;
 use crate::database::repositories::audiobook::repository::AudiobookRepository;
 use crate::database::repositories::chapter::repository::ChapterRepository;
+use crate::database::repositories::export::repository::AudiobookExportRepository;
 use crate::database::repositories::genre::repository::GenreRepository;
+use crate::database::repositories::media::repository::MediaRepository;
 use crate::database::repositories::user::repository::UserRepository;
 
 use crate::error::{AppError, AppErrorKind};
 use crate::forms::audiobook::{
-    AudiobookCreateForm, AudiobookEditForm, AudiobookQuickSearchQuery, AudiobookThumbnailEditForm,
-    AudiobookUploadForm,
+    AudiobookAudioReplaceForm, AudiobookCreateForm, AudiobookEditForm, AudiobookQuickSearchQuery,
+    AudiobookThumbnailEditForm, AudiobookUploadForm, ListeningHistoryQuery, ListeningSessionForm,
+    ListeningStateForm, PositionSyncForm, PositionUpdate, ReportedPositionQuery,
 };
 use crate::handlers::utilities::{
-    authorized_to_modify, authorized_to_modify_join, get_metadata_from_session,
-    get_user_from_identity, parse_user_id, remove_file, save_file, validate_file,
-    AudiobookCreateSessionKeys,
+    authorized_to_modify, authorized_to_modify_join, authorized_to_stream, check_storage_quota,
+    get_metadata_from_session, get_user_from_identity, is_playback_start, parse_user_id,
+    release_media, reported_at, signed_hls_url, signed_stream_url, store_uploaded_image,
+    validate_device_id, validate_file, AudiobookCreateSessionKeys,
 };
 use crate::templates::audiobook::{
-    AudiobookCoverUpload, AudiobookCreateContentTemplate, AudiobookCreatePageTemplate,
-    AudiobookDetailContentTemplate, AudiobookDetailPageTemplate, AudiobookEditContentTemplate,
-    AudiobookEditPageTemplate, AudiobookRecommendationTemplate, AudiobookUploadFormTemplate,
-    NewReleasesContentTemplate, NewReleasesPageTemplate, PlayerTemplate, QuickSearchResults,
+    AudiobookAudioReplaceContentTemplate, AudiobookAudioReplacePageTemplate,
+    AudiobookAudioReplacedTemplate, AudiobookCoverUpload, AudiobookCreateContentTemplate,
+    AudiobookCreatePageTemplate, AudiobookDetailContentTemplate, AudiobookDetailPageTemplate,
+    AudiobookEditContentTemplate, AudiobookEditPageTemplate, AudiobookRecommendationTemplate,
+    AudiobookUploadFormTemplate, DailyPlaysTemplate, ListeningHistoryTemplate,
+    ListeningStateTemplate, NewReleasesContentTemplate, NewReleasesPageTemplate, PlayerTemplate,
+    QuickSearchResults,
 };
 use crate::templates::audiobook::{
     AudiobookDetailAuthorContentTemplate, AudiobookDetailAuthorPageTemplate, DetailLikesTemplate,
@@ -34,25 +41,40 @@ use actix_identity::Identity;
 use actix_multipart::form::MultipartForm;
 
 use actix_session::Session;
-use actix_web::http::header::LOCATION;
+use actix_web::http::header::{LOCATION, USER_AGENT};
+use actix_web::middleware::from_fn;
 use actix_web::{delete, get, patch, post, put, web, HttpRequest, HttpResponse, Responder};
 
 use askama::Template;
-use lofty::AudioFile;
 use log::{info, warn};
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
 
-use crate::database::models::active_audiobook::SetActiveAudiobook;
+use crate::database::models::active_audiobook::{
+    ActiveAudiobookSynced, ProgressChangeSet, RemoveActiveAudiobook, SetActiveAudiobook,
+};
 use crate::database::models::bookmark::BookmarkOperation;
-use crate::{authorized, RECOMMEND_BOOKS_CNT};
+use crate::database::models::listening_session::{ListeningHistoryGet, ListeningSessionCreate};
+use crate::database::models::play::DailyPlaysGet;
+use crate::database::models::play_event::PlayEventCreate;
+use crate::{
+    authorized, LISTENING_HISTORY_PAGE_SIZE, MAX_LISTENING_SESSION, MEDIA_URL_VALIDITY,
+    PLAY_STATS_DAYS, PROGRESS_SYNC_BATCH_LIMIT, RECOMMEND_BOOKS_CNT,
+};
 
 use crate::handlers::helpers::{
-    get_audiobook_detail_base, get_audiobook_edit, get_chapters_by_book, get_releases,
+    create_uploaded_audiobook, get_audio_replace, get_audiobook_detail_base, get_audiobook_edit,
+    get_chapters_by_book, get_releases, replace_audiobook_audio, AudioReplacement, BookUpload,
 };
-use uuid::Uuid;
+use std::path::{Component, Path};
+use std::time::Duration;
 
+use crate::media::hls::{hls_directory, rewrite_playlist, PlaylistChapter};
+use crate::media::signing::{require_signed_url, sign_url};
+use crate::media::storage::Storage;
+use crate::media::stream::stream_object;
 use crate::recommender::recommandation_system::{delete_book_from_recommendation, recommend_books};
-use crate::recommender::recommender::add_book_recommender;
 #[get("/create")]
 pub async fn create_audiobook_page(
     request: HttpRequest,
@@ -117,14 +139,21 @@ pub async fn upload_book_cover_post(
     request: HttpRequest,
     identity: Option<Identity>,
     audiobook_repo: web::Data<AudiobookRepository>,
+    media_repo: web::Data<MediaRepository>,
+    user_repo: web::Data<UserRepository>,
+    storage: web::Data<dyn Storage>,
     MultipartForm(form): MultipartForm<AudiobookThumbnailEditForm>,
 ) -> Result<HttpResponse, AppError> {
-    let uuid = Uuid::new_v4();
     let u = authorized!(identity, request.path());
+    let user_id = parse_user_id(u)?;
     let audiobook_id = form.audiobook_id.into_inner();
-    authorized_to_modify(&audiobook_repo, parse_user_id(u)?, audiobook_id).await?;
+    let audiobook = authorized_to_modify(&audiobook_repo, user_id, audiobook_id).await?;
 
-    let thumbnail_path = validate_file(&form.thumbnail, uuid, "image", "audiobook")?;
+    validate_file(&form.thumbnail, "image")?;
+    check_storage_quota(&user_repo, user_id, form.thumbnail.size as u64).await?;
+    let thumbnail_path = store_uploaded_image(storage.get_ref(), &media_repo, form.thumbnail)
+        .await?
+        .path;
     let book_update = AudiobookUpdate::new(
         &audiobook_id,
         None,
@@ -137,9 +166,12 @@ pub async fn upload_book_cover_post(
         None,
         Some(thumbnail_path.clone()),
         None,
+        None,
     );
     audiobook_repo.update(&book_update).await?;
-    save_file(form.thumbnail, &thumbnail_path)?;
+    if let Some(previous) = audiobook.thumbnail {
+        release_media(storage.get_ref(), &media_repo, vec![previous]).await?;
+    }
 
     let handler = format!("/audiobook/{}/manage-content", audiobook_id);
     return Ok(HttpResponse::SeeOther()
@@ -172,76 +204,64 @@ pub async fn create_audiobook(
 }
 
 #[post("/upload")]
+#[allow(clippy::too_many_arguments)]
 pub async fn upload_audiobook(
     request: HttpRequest,
     identity: Option<Identity>,
     session: Session,
     user_repo: web::Data<UserRepository>,
-    genre_repo: web::Data<GenreRepository>,
     audiobook_repo: web::Data<AudiobookRepository>,
-    MultipartForm(mut form): MultipartForm<AudiobookUploadForm>,
+    media_repo: web::Data<MediaRepository>,
+    storage: web::Data<dyn Storage>,
+    MultipartForm(form): MultipartForm<AudiobookUploadForm>,
 ) -> Result<HttpResponse, AppError> {
-    let uuid = Uuid::new_v4();
     let u = authorized!(identity, request.path());
     let user = get_user_from_identity(u, &user_repo).await?;
     let session_keys = AudiobookCreateSessionKeys::new(user.id);
-
-    let audiobook_path = validate_file(&form.audio_file, uuid, "audio", "audiobook")?;
-    let thumbnail_path = match &form.thumbnail {
-        None => None,
-        Some(thumb) => Some(validate_file(thumb, uuid, "image", "audiobook")?),
-    };
     let metadata = get_metadata_from_session(&session, &session_keys)?;
 
-    let audio_file = form.audio_file.file.as_file_mut();
-    let lofty_audio_file = match lofty::read_from(audio_file) {
-        Ok(f) => f,
-        Err(e) => {
-            let template = AudiobookUploadFormTemplate {
-                message: e.to_string(),
-            }
-            .render()?;
-            return Ok(HttpResponse::Ok().content_type("text/html").body(template));
-        }
-    };
-    let properties = lofty_audio_file.properties();
-    let length = properties.duration().as_secs_f64();
-    if let (Some(thumb_path), Some(thumbnail)) = (&thumbnail_path, form.thumbnail) {
-        save_file(thumbnail, thumb_path)?;
-    }
-    let book_crate = AudiobookCreate::new(
-        &metadata.name,
-        &user.id,
-        &metadata.genre_id,
-        &audiobook_path,
-        &length,
-        thumbnail_path.clone(),
-        &metadata.description,
-    );
-    let book = audiobook_repo.create(&book_crate).await?;
-
-    let genre = genre_repo
-        .read_one(&GenreGetById::new(&book.genre_id))
-        .await?;
-
-    if let Err(err) = add_book_recommender(&book, &*genre.name).await {
-        warn!("failed add book too grpc recommender system, check if server is running: {err}");
-    } else {
-        info!("book added to the grpc repository!");
+    // browsers send an empty file when no thumbnail was selected
+    let thumbnail = form.thumbnail.filter(|thumb| thumb.size > 0);
+    let size = form
+        .audio_files
+        .iter()
+        .chain(&thumbnail)
+        .map(|file| file.size as u64)
+        .sum();
+    check_storage_quota(&user_repo, user.id, size).await?;
+    let book_id = match create_uploaded_audiobook(
+        user.id,
+        &metadata,
+        form.audio_files,
+        thumbnail,
+        audiobook_repo,
+        media_repo,
+        storage,
+    )
+    .await?
+    {
+        BookUpload::Created(book_id) => book_id,
+        BookUpload::Rejected(message) => return upload_form_with_message(&message),
     };
 
-    save_file(form.audio_file, &audiobook_path)?;
-
     session.remove(session_keys.name.as_str());
     session.remove(session_keys.description.as_str());
     session.remove(session_keys.genre_id.as_str());
 
-    let handler = format!("/audiobook/{}/manage-content", book.id);
+    let handler = format!("/audiobook/{}/manage-content", book_id);
     Ok(HttpResponse::SeeOther()
         .insert_header((LOCATION, handler))
         .finish())
 }
 
+fn upload_form_with_message(message: &str) -> Result<HttpResponse, AppError> {
+    let template = AudiobookUploadFormTemplate {
+        message: message.to_string(),
+    }
+    .render()?;
+    Ok(HttpResponse::Ok().content_type("text/html").body(template))
+}
+
 #[get("/{id}/manage")]
 pub async fn manage_audiobook(
     request: HttpRequest,
@@ -335,6 +355,7 @@ pub async fn edit_audiobook(
         None,
         None,
         Some(&form.description),
+        Some(&form.downloadable),
     );
     audiobook_repo.update(&book_update).await?;
 
@@ -344,6 +365,79 @@ pub async fn edit_audiobook(
         .finish())
 }
 
+#[get("/{id}/replace-audio")]
+pub async fn replace_audio_page(
+    request: HttpRequest,
+    identity: Option<Identity>,
+    audiobook_repo: web::Data<AudiobookRepository>,
+    path: web::Path<(Id,)>,
+) -> Result<HttpResponse, AppError> {
+    let u = authorized!(identity, request.path());
+    let base = get_audio_replace(u, audiobook_repo, path.into_inner().0).await?;
+    let template = AudiobookAudioReplacePageTemplate::from(base);
+    let body = template.render()?;
+    Ok(HttpResponse::Ok().content_type("text/html").body(body))
+}
+
+#[get("/{id}/replace-audio-content")]
+pub async fn replace_audio_content(
+    request: HttpRequest,
+    identity: Option<Identity>,
+    audiobook_repo: web::Data<AudiobookRepository>,
+    path: web::Path<(Id,)>,
+) -> Result<HttpResponse, AppError> {
+    let u = authorized!(identity, request.path());
+    let base = get_audio_replace(u, audiobook_repo, path.into_inner().0).await?;
+    let template = AudiobookAudioReplaceContentTemplate::from(base);
+    let body = template.render()?;
+    Ok(HttpResponse::Ok().content_type("text/html").body(body))
+}
+
+#[post("/replace-audio")]
+pub async fn replace_audio(
+    request: HttpRequest,
+    identity: Option<Identity>,
+    audiobook_repo: web::Data<AudiobookRepository>,
+    user_repo: web::Data<UserRepository>,
+    media_repo: web::Data<MediaRepository>,
+    storage: web::Data<dyn Storage>,
+    MultipartForm(form): MultipartForm<AudiobookAudioReplaceForm>,
+) -> Result<HttpResponse, AppError> {
+    let u = authorized!(identity, request.path());
+    let user_id = parse_user_id(u)?;
+    let audiobook =
+        authorized_to_modify(&audiobook_repo, user_id, form.audiobook_id.into_inner()).await?;
+    let size = form.audio_files.iter().map(|file| file.size as u64).sum();
+    check_storage_quota(&user_repo, user_id, size).await?;
+    let replacement = replace_audiobook_audio(
+        &audiobook,
+        form.audio_files,
+        form.remap.into_inner(),
+        &audiobook_repo,
+        &media_repo,
+        storage.get_ref(),
+    )
+    .await?;
+
+    let audiobook = audiobook_repo
+        .read_one(&AudiobookGetByIdJoin::new(user_id, audiobook.id, true))
+        .await?;
+    let body = match replacement {
+        AudioReplacement::Replaced(replaced) => AudiobookAudioReplacedTemplate {
+            audiobook: AudiobookDisplay::from(audiobook),
+            replaced,
+        }
+        .render()?,
+        AudioReplacement::Rejected(message) => AudiobookAudioReplaceContentTemplate {
+            message,
+            versions: audiobook_repo.read_versions(&audiobook.id).await?,
+            audiobook: AudiobookDisplay::from(audiobook),
+        }
+        .render()?,
+    };
+    Ok(HttpResponse::Ok().content_type("text/html").body(body))
+}
+
 #[get("/{id}/detail")]
 pub async fn get_audiobook(
     request: HttpRequest,
@@ -486,18 +580,29 @@ pub async fn hard_remove_audiobook(
     request: HttpRequest,
     identity: Option<Identity>,
     audiobook_repo: web::Data<AudiobookRepository>,
+    media_repo: web::Data<MediaRepository>,
+    export_repo: web::Data<AudiobookExportRepository>,
+    storage: web::Data<dyn Storage>,
     path: web::Path<(Id,)>,
 ) -> Result<HttpResponse, AppError> {
     let u = authorized!(identity, request.path());
     let audiobook =
         authorized_to_modify(&audiobook_repo, parse_user_id(u)?, path.into_inner().0).await?;
-    remove_file(&audiobook.file_path)?;
-    if let Some(thumbnail) = &audiobook.thumbnail {
-        remove_file(thumbnail)?;
-    }
+    let versions = audiobook_repo.read_versions(&audiobook.id).await?;
+    let export = export_repo.read(&audiobook.id).await?;
     audiobook_repo
         .hard_delete(&AudiobookDelete::new(&audiobook.id))
         .await?;
+    // exports are not media, nothing else refers to them
+    if let Some(file_path) = export.and_then(|export| export.file_path) {
+        storage.delete(&file_path).await?;
+    }
+    // the files can be shared with other books
+    let media = std::iter::once(audiobook.file_path)
+        .chain(audiobook.thumbnail)
+        .chain(versions.into_iter().map(|version| version.file_path))
+        .collect();
+    release_media(storage.get_ref(), &media_repo, media).await?;
 
     Ok(HttpResponse::SeeOther()
         .insert_header((LOCATION, "/studio-content"))
@@ -607,30 +712,346 @@ pub async fn search(
         .body(template.render()?))
 }
 
-#[derive(Deserialize)]
-struct Position {
-    position: f64,
+/// The position of the listener after a reported position, `error` tells why a queued
+/// position was not applied at all
+#[derive(Serialize)]
+struct PositionSynced {
+    audiobook_id: Id,
+    applied: bool,
+    position: Option<f64>,
+    suggested_position: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+impl From<ActiveAudiobookSynced> for PositionSynced {
+    fn from(value: ActiveAudiobookSynced) -> Self {
+        Self {
+            audiobook_id: value.active.audiobook_id,
+            applied: value.applied,
+            position: Some(value.active.playback_position),
+            suggested_position: value.active.suggested_position,
+            error: None,
+        }
+    }
+}
+
+fn validate_position(position: f64) -> Result<(), AppError> {
+    match position.is_finite() && position >= 0.0 {
+        true => Ok(()),
+        false => Err(AppError::new(AppErrorKind::BadRequest, "Invalid position")),
+    }
 }
 
+/// Sets the position of the listener in the book, unless another device reported a later
+/// position, see `ActiveAudiobook::sync`
 #[put("/{id}/active")]
 pub async fn set_active_audiobook(
     request: HttpRequest,
     identity: Option<Identity>,
     audiobook_repo: web::Data<AudiobookRepository>,
-    query: web::Query<Position>,
+    query: web::Query<ReportedPositionQuery>,
     path: web::Path<(Id,)>,
 ) -> Result<HttpResponse, AppError> {
     let identity = authorized!(identity, request.path());
+    validate_position(query.position)?;
 
-    audiobook_repo
+    let synced = audiobook_repo
         .set_active_audiobook(&SetActiveAudiobook::new(
             parse_user_id(identity)?,
             path.into_inner().0,
             query.position,
+            reported_at(query.timestamp)?,
+            validate_device_id(query.device.as_deref())?,
         ))
         .await?;
 
-    Ok(HttpResponse::Ok().finish())
+    Ok(HttpResponse::Ok().json(PositionSynced::from(synced)))
+}
+
+/// Applies the positions a device queued while it was offline, the response tells for each
+/// of them whether it was applied and where the listener is
+#[post("/progress")]
+pub async fn sync_positions(
+    request: HttpRequest,
+    identity: Option<Identity>,
+    audiobook_repo: web::Data<AudiobookRepository>,
+    form: web::Json<PositionSyncForm>,
+) -> Result<HttpResponse, AppError> {
+    let identity = authorized!(identity, request.path());
+    let user_id = parse_user_id(identity)?;
+    let device_id = validate_device_id(form.device.as_deref())?;
+    if form.updates.len() > PROGRESS_SYNC_BATCH_LIMIT {
+        return Err(AppError::new(
+            AppErrorKind::PayloadTooLarge,
+            &format!("At most {PROGRESS_SYNC_BATCH_LIMIT} positions can be sent at once"),
+        ));
+    }
+
+    let mut books = HashMap::new();
+    let mut results = Vec::with_capacity(form.updates.len());
+    let mut updates = Vec::new();
+    for update in &form.updates {
+        let audiobook = match books.entry(update.audiobook_id) {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => entry.insert(
+                authorized_to_stream(&audiobook_repo, user_id, update.audiobook_id)
+                    .await
+                    .map_err(|err| err.message),
+            ),
+        };
+        let position = match audiobook {
+            Ok(audiobook) => download_position(&audiobook_repo, user_id, audiobook, update).await,
+            Err(err) => Err(err.clone()),
+        };
+        let reported = position.and_then(|position| {
+            Ok(SetActiveAudiobook::new(
+                user_id,
+                update.audiobook_id,
+                position,
+                reported_at(Some(update.timestamp)).map_err(|err| err.message)?,
+                device_id,
+            ))
+        });
+        match reported {
+            Ok(reported) => {
+                results.push(None);
+                updates.push(reported);
+            }
+            Err(error) => results.push(Some(PositionSynced {
+                audiobook_id: update.audiobook_id,
+                applied: false,
+                position: None,
+                suggested_position: None,
+                error: Some(error),
+            })),
+        }
+    }
+
+    let mut synced = audiobook_repo
+        .set_active_audiobooks(&updates)
+        .await?
+        .into_iter()
+        .map(PositionSynced::from);
+    let results: Vec<PositionSynced> = results
+        .into_iter()
+        .filter_map(|result| result.or_else(|| synced.next()))
+        .collect();
+    Ok(HttpResponse::Ok().json(results))
+}
+
+/// Position in the current audio of the book, positions in the audio of an offline download
+/// which was replaced since are scaled to the new audio
+async fn download_position(
+    audiobook_repo: &web::Data<AudiobookRepository>,
+    user_id: Id,
+    audiobook: &Audiobook,
+    update: &PositionUpdate,
+) -> Result<f64, String> {
+    validate_position(update.position).map_err(|err| err.message)?;
+    let Some(download_id) = update.download else {
+        return Ok(update.position.min(audiobook.length));
+    };
+    let download = audiobook_repo
+        .read_download(&download_id)
+        .await
+        .map_err(|err| AppError::from(err).message)?
+        .filter(|download| download.user_id == user_id && download.audiobook_id == audiobook.id)
+        .ok_or_else(|| "Unknown download".to_string())?;
+    let position = match download.file_path != audiobook.file_path && download.length > 0.0 {
+        true => update.position * audiobook.length / download.length,
+        false => update.position,
+    };
+    Ok(position.min(audiobook.length))
+}
+
+/// Dismisses the position the listener was offered to jump back to
+#[delete("/{id}/active/suggestion")]
+pub async fn dismiss_suggested_position(
+    request: HttpRequest,
+    identity: Option<Identity>,
+    audiobook_repo: web::Data<AudiobookRepository>,
+    path: web::Path<(Id,)>,
+) -> Result<HttpResponse, AppError> {
+    let identity = authorized!(identity, request.path());
+    audiobook_repo
+        .dismiss_suggested_position(&RemoveActiveAudiobook::new(
+            parse_user_id(identity)?,
+            path.into_inner().0,
+        ))
+        .await?;
+    Ok(HttpResponse::Ok().body(""))
+}
+
+async fn listening_state(
+    audiobook_repo: &AudiobookRepository,
+    user_id: Id,
+    audiobook_id: Id,
+) -> Result<HttpResponse, AppError> {
+    let audiobook = audiobook_repo
+        .read_one(&AudiobookGetByIdJoin::new(user_id, audiobook_id, false))
+        .await?;
+    let body = ListeningStateTemplate {
+        audiobook_id,
+        finished: audiobook.is_finished(),
+        started: audiobook.is_started(),
+    }
+    .render()?;
+    Ok(HttpResponse::Ok().content_type("text/html").body(body))
+}
+
+/// Shows whether the listener finished the book or is listening to it
+#[get("/{id}/listening-state")]
+pub async fn get_listening_state(
+    request: HttpRequest,
+    identity: Option<Identity>,
+    audiobook_repo: web::Data<AudiobookRepository>,
+    path: web::Path<(Id,)>,
+) -> Result<HttpResponse, AppError> {
+    let identity = authorized!(identity, request.path());
+    listening_state(
+        &audiobook_repo,
+        parse_user_id(identity)?,
+        path.into_inner().0,
+    )
+    .await
+}
+
+/// Marks the book finished or unplayed for the listener, or starts it over
+#[put("/{id}/listening-state")]
+pub async fn change_listening_state(
+    request: HttpRequest,
+    identity: Option<Identity>,
+    audiobook_repo: web::Data<AudiobookRepository>,
+    form: web::Form<ListeningStateForm>,
+    path: web::Path<(Id,)>,
+) -> Result<HttpResponse, AppError> {
+    let identity = authorized!(identity, request.path());
+    let user_id = parse_user_id(identity)?;
+    let audiobook = authorized_to_stream(&audiobook_repo, user_id, path.into_inner().0).await?;
+
+    audiobook_repo
+        .change_progress(&ProgressChangeSet::new(
+            user_id,
+            audiobook.id,
+            form.change,
+            reported_at(form.timestamp)?,
+            validate_device_id(form.device.as_deref())?,
+        ))
+        .await?;
+    listening_state(&audiobook_repo, user_id, audiobook.id).await
+}
+
+/// Appends a listening session reported by the player to the history of the listener,
+/// the player sends it when the playback pauses or seeks and every few minutes meanwhile
+#[post("/{id}/session")]
+pub async fn record_listening_session(
+    request: HttpRequest,
+    identity: Option<Identity>,
+    audiobook_repo: web::Data<AudiobookRepository>,
+    form: web::Form<ListeningSessionForm>,
+    path: web::Path<(Id,)>,
+) -> Result<HttpResponse, AppError> {
+    let identity = authorized!(identity, request.path());
+    let user_id = parse_user_id(identity)?;
+    let audiobook = authorized_to_stream(&audiobook_repo, user_id, path.into_inner().0).await?;
+
+    validate_position(form.start_position)?;
+    validate_position(form.end_position)?;
+    if form.end_position < form.start_position
+        || !(0.0..=MAX_LISTENING_SESSION).contains(&form.duration)
+    {
+        return Err(AppError::new(
+            AppErrorKind::BadRequest,
+            "Invalid listening session",
+        ));
+    }
+    let client: String = request
+        .headers()
+        .get(USER_AGENT)
+        .and_then(|agent| agent.to_str().ok())
+        .unwrap_or_default()
+        .chars()
+        .take(255)
+        .collect();
+
+    audiobook_repo
+        .record_listening_session(&ListeningSessionCreate::new(
+            &user_id,
+            &audiobook.id,
+            &form.start_position.min(audiobook.length),
+            &form.end_position.min(audiobook.length),
+            &reported_at(form.ended_at)?,
+            &form.duration,
+            validate_device_id(form.device.as_deref())?,
+            &client,
+        ))
+        .await?;
+
+    Ok(HttpResponse::NoContent().finish())
+}
+
+/// Returns a page of the listening history of the user, the page is followed by a button
+/// loading the next one
+#[get("/history")]
+pub async fn get_listening_history(
+    request: HttpRequest,
+    identity: Option<Identity>,
+    audiobook_repo: web::Data<AudiobookRepository>,
+    query: web::Query<ListeningHistoryQuery>,
+) -> Result<HttpResponse, AppError> {
+    let identity = authorized!(identity, request.path());
+    let offset = query.offset.unwrap_or_default().max(0);
+    let mut history = audiobook_repo
+        .read_listening_history(&ListeningHistoryGet::new(
+            parse_user_id(identity)?,
+            None,
+            LISTENING_HISTORY_PAGE_SIZE + 1,
+            offset,
+        ))
+        .await?;
+    let next_offset = (history.len() as i64 > LISTENING_HISTORY_PAGE_SIZE).then(|| {
+        history.truncate(LISTENING_HISTORY_PAGE_SIZE as usize);
+        offset + LISTENING_HISTORY_PAGE_SIZE
+    });
+
+    let template = ListeningHistoryTemplate {
+        history,
+        next_offset,
+    };
+    Ok(HttpResponse::Ok()
+        .content_type("text/html")
+        .body(template.render()?))
+}
+
+/// Shows the author how many times the book was played on each of the latest days
+#[get("/{id}/plays")]
+pub async fn get_daily_plays(
+    request: HttpRequest,
+    identity: Option<Identity>,
+    audiobook_repo: web::Data<AudiobookRepository>,
+    path: web::Path<(Id,)>,
+) -> Result<HttpResponse, AppError> {
+    let identity = authorized!(identity, request.path());
+    let audiobook = authorized_to_modify(
+        &audiobook_repo,
+        parse_user_id(identity)?,
+        path.into_inner().0,
+    )
+    .await?;
+    let days = audiobook_repo
+        .read_daily_plays(&DailyPlaysGet::new(audiobook.id, PLAY_STATS_DAYS))
+        .await?;
+
+    let template = DailyPlaysTemplate {
+        total: audiobook.stream_count,
+        most: days.iter().map(|day| day.plays).max().unwrap_or_default(),
+        days,
+        period: PLAY_STATS_DAYS,
+    };
+    Ok(HttpResponse::Ok()
+        .content_type("text/html")
+        .body(template.render()?))
 }
 
 #[get("/last-played")]
@@ -638,6 +1059,7 @@ pub async fn get_last_active_audiobook(
     request: HttpRequest,
     identity: Option<Identity>,
     audiobook_repo: web::Data<AudiobookRepository>,
+    storage: web::Data<dyn Storage>,
 ) -> Result<HttpResponse, AppError> {
     let identity = authorized!(identity, request.path());
     let id = parse_user_id(identity)?;
@@ -645,7 +1067,11 @@ pub async fn get_last_active_audiobook(
 
     return match latest {
         Some(book) => {
-            let template = PlayerTemplate { played_book: book };
+            let template = PlayerTemplate {
+                stream_url: signed_stream_url(book.book_id),
+                hls_url: signed_hls_url(storage.get_ref(), book.book_id, &book.path).await?,
+                played_book: book,
+            };
             Ok(HttpResponse::Ok()
                 .content_type("text/html")
                 .body(template.render()?))
@@ -659,6 +1085,97 @@ pub async fn get_last_active_audiobook(
     };
 }
 
+/// Streams the audio file of the book, seeking is supported through range requests.
+/// Players are redirected to the storage if it can serve the file to them directly.
+#[get("/{id}/stream", wrap = "from_fn(require_signed_url)")]
+pub async fn stream_audiobook(
+    request: HttpRequest,
+    identity: Option<Identity>,
+    audiobook_repo: web::Data<AudiobookRepository>,
+    storage: web::Data<dyn Storage>,
+    path: web::Path<(Id,)>,
+) -> Result<HttpResponse, AppError> {
+    let identity = authorized!(identity, request.path());
+    let user_id = parse_user_id(identity)?;
+    let audiobook = authorized_to_stream(&audiobook_repo, user_id, path.into_inner().0).await?;
+    let presigned = storage
+        .presign(
+            &audiobook.file_path,
+            Duration::from_secs(MEDIA_URL_VALIDITY),
+        )
+        .await?;
+    let response = match presigned {
+        Some(url) => HttpResponse::TemporaryRedirect()
+            .insert_header((LOCATION, url))
+            .finish(),
+        None => stream_object(&request, storage.get_ref(), &audiobook.file_path).await?,
+    };
+
+    if is_playback_start(&request) {
+        audiobook_repo
+            .record_play_event(&PlayEventCreate::new(user_id, audiobook.id))
+            .await?;
+    }
+    Ok(response)
+}
+
+/// Serves the HLS playlists and segments of the book. URIs in the playlists are replaced
+/// with signed ones and chapters are added to the media playlists.
+#[get("/{id}/hls/{file:.*}", wrap = "from_fn(require_signed_url)")]
+pub async fn stream_audiobook_hls(
+    request: HttpRequest,
+    identity: Option<Identity>,
+    audiobook_repo: web::Data<AudiobookRepository>,
+    chapter_repo: web::Data<ChapterRepository>,
+    storage: web::Data<dyn Storage>,
+    path: web::Path<(Id, String)>,
+) -> Result<HttpResponse, AppError> {
+    let identity = authorized!(identity, request.path());
+    let (audiobook_id, file) = path.into_inner();
+    let audiobook =
+        authorized_to_stream(&audiobook_repo, parse_user_id(identity)?, audiobook_id).await?;
+
+    let file = Path::new(&file);
+    if !file
+        .components()
+        .all(|component| matches!(component, Component::Normal(_)))
+    {
+        return Err(AppError::new(AppErrorKind::BadRequest, "Invalid HLS file"));
+    }
+    let stored_path = format!("{}/{}", hls_directory(&audiobook.file_path), file.display());
+    if file.extension().and_then(|ext| ext.to_str()) != Some("m3u8") {
+        return stream_object(&request, storage.get_ref(), &stored_path).await;
+    }
+
+    let playlist = String::from_utf8_lossy(&storage.get(&stored_path).await?).to_string();
+    let chapters = get_chapters_by_book(chapter_repo, audiobook.id).await?;
+    let chapters: Vec<PlaylistChapter> = chapters
+        .iter()
+        .map(|chapter| PlaylistChapter {
+            id: chapter.id,
+            name: &chapter.name,
+            position: chapter.position,
+        })
+        .collect();
+    let directory = file.parent().unwrap_or(Path::new(""));
+    let playlist = rewrite_playlist(
+        &playlist,
+        |uri| {
+            let uri = directory.join(uri);
+            sign_url(&format!(
+                "/audiobook/{}/hls/{}",
+                audiobook.id,
+                uri.display()
+            ))
+        },
+        &chapters,
+        audiobook.created_at,
+    );
+    Ok(HttpResponse::Ok()
+        .content_type("application/vnd.apple.mpegurl")
+        .body(playlist))
+}
+
 #[derive(Deserialize)]
 pub struct PositionQuery {
     position: Option<f64>,
@@ -670,6 +1187,7 @@ pub async fn get_audiobook_player(
     request: HttpRequest,
     identity: Option<Identity>,
     audiobook_repo: web::Data<AudiobookRepository>,
+    storage: web::Data<dyn Storage>,
     position_query: web::Query<PositionQuery>,
     path: web::Path<(Id,)>,
 ) -> Result<HttpResponse, AppError> {
@@ -684,6 +1202,8 @@ pub async fn get_audiobook_player(
     };
 
     let template = PlayerTemplate {
+        stream_url: signed_stream_url(played.book_id),
+        hls_url: signed_hls_url(storage.get_ref(), played.book_id, &played.path).await?,
         played_book: played,
     };
     Ok(HttpResponse::Ok()
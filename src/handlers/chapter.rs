@@ -1,17 +1,24 @@
-use crate::authorized;
 use crate::database::common::{DbCreate, DbDelete, DbReadOne};
-use crate::database::models::chapter::{ChapterCreate, ChapterGetById};
+use crate::database::models::chapter::{
+    ChapterCreate, ChapterGetById, ChapterSuggestionAccept, ChapterSuggestionsGetByBookId,
+    ChaptersGetByBookId,
+};
+use crate::{authorized, CHAPTER_SUGGESTION_MIN_DISTANCE};
 
 use crate::database::models::audiobook::AudiobookGetById;
 use crate::database::models::Id;
 use crate::database::repositories::audiobook::repository::AudiobookRepository;
 use crate::database::repositories::chapter::repository::ChapterRepository;
 use crate::error::{AppError, AppErrorKind};
-use crate::forms::chapter::{ChapterCreateForm, ChapterDeleteForm};
+use crate::forms::chapter::{ChapterCreateForm, ChapterDeleteForm, ChapterSuggestionAcceptForm};
 use crate::handlers::helpers::get_displayable_chapters;
-use crate::handlers::utilities::{authorized_to_modify, parse_user_id};
+use crate::handlers::utilities::{authorized_to_modify, parse_user_id, signed_stream_url};
+use crate::media::storage::Storage;
+use crate::media::stream::stream_object;
+use crate::media::waveform::waveform_path;
 use crate::templates::chapter::{
-    ChapterCreatorPlayerTemplate, ChapterListTemplate, ChapterTimelineTemplate,
+    ChapterCreatorPlayerTemplate, ChapterListTemplate, ChapterRetentionTemplate,
+    ChapterTimelineTemplate,
 };
 use actix_identity::Identity;
 use actix_web::http::header::LOCATION;
@@ -69,13 +76,37 @@ pub async fn audio_selection_for_chapter(
         .await?;
 
     let template = ChapterCreatorPlayerTemplate {
-        source: book.file_path,
+        source: signed_stream_url(book.id),
+        audiobook_id: book.id,
     };
     Ok(HttpResponse::Ok()
         .content_type("text/html")
         .body(template.render()?))
 }
 
+/// Peaks of the book in the audiowaveform `.dat` format, used to draw the waveform
+/// in the chapter creator
+#[get("/audiobook/{id}/waveform")]
+pub async fn get_waveform(
+    request: HttpRequest,
+    identity: Option<Identity>,
+    audiobook_repo: web::Data<AudiobookRepository>,
+    storage: web::Data<dyn Storage>,
+    path: web::Path<Id>,
+) -> Result<HttpResponse, AppError> {
+    let u = authorized!(identity, request.path());
+    let audiobook =
+        authorized_to_modify(&audiobook_repo, parse_user_id(u)?, path.into_inner()).await?;
+    let waveform = waveform_path(&audiobook.file_path);
+    if !storage.exists(&waveform).await? {
+        return Err(AppError::new(
+            AppErrorKind::NotFound,
+            "The waveform of the audiobook has not been created yet",
+        ));
+    }
+    stream_object(&request, storage.get_ref(), &waveform).await
+}
+
 #[get("/audiobook/{id}/chapter-timeline")]
 pub async fn get_chapter_timeline(
     request: HttpRequest,
@@ -93,7 +124,7 @@ pub async fn get_chapter_timeline(
             fetch_deleted: true,
         })
         .await?;
-    let displayable_chapters = get_displayable_chapters(chapter_repo, audiobook_id).await?;
+    let displayable_chapters = get_displayable_chapters(chapter_repo, audiobook_id, None).await?;
     let template = ChapterTimelineTemplate {
         audiobook_id,
         chapters: displayable_chapters,
@@ -111,11 +142,13 @@ pub async fn get_chapter_list(
     chapter_repo: web::Data<ChapterRepository>,
     path: web::Path<Id>,
 ) -> Result<HttpResponse, AppError> {
-    authorized!(identity, request.path());
+    let u = authorized!(identity, request.path());
     let audiobook_id = path.into_inner();
     let template = ChapterListTemplate {
         audiobook_id,
-        chapters: get_displayable_chapters(chapter_repo, audiobook_id).await?,
+        chapters: get_displayable_chapters(chapter_repo, audiobook_id, Some(parse_user_id(u)?))
+            .await?,
+        suggestions: Vec::new(),
         show_delete: false,
     };
     Ok(HttpResponse::Ok()
@@ -131,17 +164,59 @@ pub async fn get_manage_chapter_list(
     path: web::Path<Id>,
 ) -> Result<HttpResponse, AppError> {
     authorized!(identity, request.path());
-    let audiobook_id = path.into_inner();
-    let template = ChapterListTemplate {
-        audiobook_id,
-        chapters: get_displayable_chapters(chapter_repo, audiobook_id).await?,
-        show_delete: true,
+    manage_chapter_list(chapter_repo, path.into_inner()).await
+}
+
+/// Shows the author where the listeners of the book stop listening
+#[get("/audiobook/{id}/retention")]
+pub async fn get_chapter_retention(
+    request: HttpRequest,
+    identity: Option<Identity>,
+    chapter_repo: web::Data<ChapterRepository>,
+    audiobook_repo: web::Data<AudiobookRepository>,
+    path: web::Path<Id>,
+) -> Result<HttpResponse, AppError> {
+    let u = authorized!(identity, request.path());
+    let audiobook =
+        authorized_to_modify(&audiobook_repo, parse_user_id(u)?, path.into_inner()).await?;
+    let chapters = chapter_repo
+        .read_retention(&ChaptersGetByBookId::new(audiobook.id))
+        .await?;
+    let template = ChapterRetentionTemplate {
+        listeners: chapters
+            .iter()
+            .map(|chapter| chapter.listeners)
+            .max()
+            .unwrap_or_default(),
+        chapters,
     };
     Ok(HttpResponse::Ok()
         .content_type("text/html")
         .body(template.render()?))
 }
 
+/// Creates chapters from the silences found in the book
+#[post("/suggestion/accept")]
+pub async fn accept_chapter_suggestion(
+    request: HttpRequest,
+    identity: Option<Identity>,
+    chapter_repo: web::Data<ChapterRepository>,
+    audiobook_repo: web::Data<AudiobookRepository>,
+    form: web::Form<ChapterSuggestionAcceptForm>,
+) -> Result<HttpResponse, AppError> {
+    let u = authorized!(identity, request.path());
+    let audiobook =
+        authorized_to_modify(&audiobook_repo, parse_user_id(u)?, form.audiobook_id).await?;
+    chapter_repo
+        .accept_suggestions(&ChapterSuggestionAccept::new(
+            audiobook.id,
+            form.suggestion_id,
+            CHAPTER_SUGGESTION_MIN_DISTANCE,
+        ))
+        .await?;
+    manage_chapter_list(chapter_repo, audiobook.id).await
+}
+
 #[delete("/delete")]
 pub async fn remove_chapter(
     request: HttpRequest,
@@ -156,10 +231,23 @@ pub async fn remove_chapter(
     chapter_repo
         .delete(&ChapterGetById::new(form.chapter_id))
         .await?;
-    let displayable_chapters = get_displayable_chapters(chapter_repo, audiobook.id).await?;
+    manage_chapter_list(chapter_repo, audiobook.id).await
+}
+
+async fn manage_chapter_list(
+    chapter_repo: web::Data<ChapterRepository>,
+    audiobook_id: Id,
+) -> Result<HttpResponse, AppError> {
+    let suggestions = chapter_repo
+        .read_suggestions(&ChapterSuggestionsGetByBookId::new(
+            audiobook_id,
+            CHAPTER_SUGGESTION_MIN_DISTANCE,
+        ))
+        .await?;
     let template = ChapterListTemplate {
-        audiobook_id: audiobook.id,
-        chapters: displayable_chapters,
+        audiobook_id,
+        chapters: get_displayable_chapters(chapter_repo, audiobook_id, None).await?,
+        suggestions,
         show_delete: true,
     };
     Ok(HttpResponse::Ok()
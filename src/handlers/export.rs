@@ -0,0 +1,193 @@
+use crate::authorized;
+use crate::database::common::error::{BackendError, BackendErrorKind};
+use crate::database::common::DbReadMany;
+use crate::database::models::audiobook::AudiobookDetail;
+use crate::database::models::chapter::ChaptersGetByBookId;
+use crate::database::models::download::AudiobookDownloadCreate;
+use crate::database::models::export::{AudiobookExport, AudiobookExportRequest, ExportState};
+use crate::database::models::Id;
+use crate::database::repositories::audiobook::repository::AudiobookRepository;
+use crate::database::repositories::chapter::repository::ChapterRepository;
+use crate::database::repositories::export::repository::AudiobookExportRepository;
+use crate::error::{AppError, AppErrorKind};
+use crate::handlers::utilities::{attachment_disposition, authorized_to_download, parse_user_id};
+use crate::media::bundle::download_bundle;
+use crate::media::export::{export_fingerprint, notify_book_export};
+use crate::media::storage::Storage;
+use crate::media::stream::stream_object;
+use crate::templates::audiobook::AudiobookExportTemplate;
+use actix_identity::Identity;
+use actix_web::body::SizedStream;
+use actix_web::http::header::{TryIntoHeaderValue, CONTENT_DISPOSITION, LOCATION};
+use actix_web::{get, post, web, HttpRequest, HttpResponse};
+use askama::Template;
+
+/// Fingerprint of the current state of the book, see `export_fingerprint`
+async fn current_fingerprint(
+    chapter_repo: &ChapterRepository,
+    audiobook: &AudiobookDetail,
+) -> Result<String, AppError> {
+    let chapters = chapter_repo
+        .read_many(&ChaptersGetByBookId::new(audiobook.id))
+        .await?;
+    Ok(export_fingerprint(audiobook, &chapters))
+}
+
+fn export_template(
+    audiobook_id: Id,
+    export: Option<AudiobookExport>,
+    fingerprint: &str,
+) -> AudiobookExportTemplate {
+    let Some(export) = export else {
+        return AudiobookExportTemplate {
+            audiobook_id,
+            ready: false,
+            running: false,
+            size: None,
+            error: None,
+        };
+    };
+    AudiobookExportTemplate {
+        audiobook_id,
+        ready: export.current_file(fingerprint).is_some(),
+        running: export.is_running(),
+        size: export.size,
+        error: match export.state {
+            ExportState::Failed => export.error,
+            _ => None,
+        },
+    }
+}
+
+/// Shows the state of the M4B export of the book, running exports are polled
+#[get("/{id}/export")]
+pub async fn get_audiobook_export(
+    request: HttpRequest,
+    identity: Option<Identity>,
+    audiobook_repo: web::Data<AudiobookRepository>,
+    chapter_repo: web::Data<ChapterRepository>,
+    export_repo: web::Data<AudiobookExportRepository>,
+    path: web::Path<(Id,)>,
+) -> Result<HttpResponse, AppError> {
+    let identity = authorized!(identity, request.path());
+    let audiobook = authorized_to_download(
+        &audiobook_repo,
+        parse_user_id(identity)?,
+        path.into_inner().0,
+    )
+    .await?;
+    let fingerprint = current_fingerprint(&chapter_repo, &audiobook).await?;
+    let export = export_repo.read(&audiobook.id).await?;
+
+    let body = export_template(audiobook.id, export, &fingerprint).render()?;
+    Ok(HttpResponse::Ok().content_type("text/html").body(body))
+}
+
+/// Requests an M4B export of the book in its current state, the book is exported
+/// in the background
+#[post("/{id}/export")]
+pub async fn request_audiobook_export(
+    request: HttpRequest,
+    identity: Option<Identity>,
+    audiobook_repo: web::Data<AudiobookRepository>,
+    chapter_repo: web::Data<ChapterRepository>,
+    export_repo: web::Data<AudiobookExportRepository>,
+    path: web::Path<(Id,)>,
+) -> Result<HttpResponse, AppError> {
+    let identity = authorized!(identity, request.path());
+    let audiobook = authorized_to_download(
+        &audiobook_repo,
+        parse_user_id(identity)?,
+        path.into_inner().0,
+    )
+    .await?;
+    let fingerprint = current_fingerprint(&chapter_repo, &audiobook).await?;
+    let export = export_repo
+        .request(&AudiobookExportRequest::new(&audiobook.id, &fingerprint))
+        .await?;
+    if export.is_running() {
+        notify_book_export();
+    }
+
+    let body = export_template(audiobook.id, Some(export), &fingerprint).render()?;
+    Ok(HttpResponse::Ok().content_type("text/html").body(body))
+}
+
+/// Downloads the M4B export of the book, once it is made of the current state of the book
+#[get("/{id}/export/download")]
+pub async fn download_audiobook_export(
+    request: HttpRequest,
+    identity: Option<Identity>,
+    audiobook_repo: web::Data<AudiobookRepository>,
+    chapter_repo: web::Data<ChapterRepository>,
+    export_repo: web::Data<AudiobookExportRepository>,
+    storage: web::Data<dyn Storage>,
+    path: web::Path<(Id,)>,
+) -> Result<HttpResponse, AppError> {
+    let identity = authorized!(identity, request.path());
+    let audiobook = authorized_to_download(
+        &audiobook_repo,
+        parse_user_id(identity)?,
+        path.into_inner().0,
+    )
+    .await?;
+    let fingerprint = current_fingerprint(&chapter_repo, &audiobook).await?;
+    let export = export_repo.read(&audiobook.id).await?;
+    let Some(file_path) = export
+        .as_ref()
+        .and_then(|export| export.current_file(&fingerprint))
+    else {
+        return Err(AppError::new(
+            AppErrorKind::NotFound,
+            "The export of the audiobook is not ready",
+        ));
+    };
+
+    let mut response = stream_object(&request, storage.get_ref(), file_path).await?;
+    let disposition = attachment_disposition(&audiobook.name, "m4b");
+    if let Ok(value) = disposition.try_into_value() {
+        response.headers_mut().insert(CONTENT_DISPOSITION, value);
+    }
+    Ok(response)
+}
+
+/// Downloads the book for offline listening as a ZIP archive with the audio, the cover,
+/// the chapters and the details of the book. The download is recorded, so that the progress
+/// the listener reports later can be matched to the audio they downloaded.
+#[get("/{id}/download")]
+pub async fn download_audiobook(
+    request: HttpRequest,
+    identity: Option<Identity>,
+    audiobook_repo: web::Data<AudiobookRepository>,
+    chapter_repo: web::Data<ChapterRepository>,
+    storage: web::Data<dyn Storage>,
+    path: web::Path<(Id,)>,
+) -> Result<HttpResponse, AppError> {
+    let identity = authorized!(identity, request.path());
+    let user_id = parse_user_id(identity)?;
+    let audiobook = authorized_to_download(&audiobook_repo, user_id, path.into_inner().0).await?;
+    if audiobook.deleted_at.is_some() {
+        return Err(AppError::from(BackendError::new(
+            BackendErrorKind::AudiobookDeleted,
+        )));
+    }
+    let chapters = chapter_repo
+        .read_many(&ChaptersGetByBookId::new(audiobook.id))
+        .await?;
+
+    let download = audiobook_repo
+        .record_download(&AudiobookDownloadCreate::new(
+            &user_id,
+            &audiobook.id,
+            &audiobook.file_path,
+            &audiobook.length,
+        ))
+        .await?;
+    let (bundle, size) =
+        download_bundle(storage.get_ref(), &audiobook, &chapters, &download).await?;
+
+    Ok(HttpResponse::Ok()
+        .content_type("application/zip")
+        .insert_header(attachment_disposition(&audiobook.name, "zip"))
+        .body(SizedStream::new(size, bundle)))
+}
@@ -1,23 +1,41 @@
 use crate::database::common::{DbReadMany, DbReadOne};
 use actix_identity::Identity;
+use actix_multipart::form::tempfile::TempFile;
 use actix_web::web;
 
 use crate::database::common::query_parameters::{
     BookState, DbColumn, DbOrder, DbOrderColumn, DbQueryParams, DbTable,
 };
-use crate::database::models::audiobook::{AudiobookDisplay, AudiobookGetByIdJoin, AudiobookSearch};
-use crate::database::models::chapter::{ChapterDisplay, ChaptersGetByBookId};
+use crate::database::models::audiobook::{
+    Audiobook, AudiobookAudioReplace, AudiobookAudioReplaced, AudiobookCreate, AudiobookDisplay,
+    AudiobookGetByIdJoin, AudiobookMetadataForm, AudiobookSearch, PositionRemap,
+};
+use crate::database::models::chapter::{
+    ChapterDisplay, ChapterEmbeddedCreate, ChapterProgress, ChapterProgressGet, ChaptersGetByBookId,
+};
 use crate::database::models::genre::{GenreGetById, GenreSearch};
 use crate::database::models::user::UserGetById;
 use crate::database::models::Id;
 use crate::database::repositories::audiobook::repository::AudiobookRepository;
 use crate::database::repositories::chapter::repository::ChapterRepository;
 use crate::database::repositories::genre::repository::GenreRepository;
+use crate::database::repositories::media::repository::MediaRepository;
 use crate::database::repositories::user::repository::UserRepository;
 use crate::error::AppError;
-use crate::handlers::utilities::{authorized_to_modify_join, parse_user_id};
-use crate::templates::audiobook::{AudiobookDetailBase, AudiobookEditBase, AudiobooksByGenreBase};
+use crate::handlers::utilities::{
+    authorized_to_modify_join, parse_user_id, validate_file, MediaStaging,
+};
+use crate::media::formats::MediaFormat;
+use crate::media::processing::notify_book_processing;
+use crate::media::storage::Storage;
+use crate::media::tracks::{book_chapters, can_concatenate, sort_tracks, TrackInfo};
+use crate::recommender::registration::notify_recommender_queue;
+use crate::templates::audiobook::{
+    AudiobookAudioReplaceBase, AudiobookDetailBase, AudiobookEditBase, AudiobooksByGenreBase,
+};
 use crate::templates::index::IndexBase;
+use crate::templates::studio::StudioBase;
+use std::collections::HashMap;
 
 pub async fn get_releases(
     u: Identity,
@@ -35,7 +53,7 @@ pub async fn get_chapters_by_book(
     chapter_repo: web::Data<ChapterRepository>,
     audiobook_id: Id,
 ) -> Result<Vec<ChapterDisplay>, AppError> {
-    let displayed_chapters = get_displayable_chapters(chapter_repo, audiobook_id).await?;
+    let displayed_chapters = get_displayable_chapters(chapter_repo, audiobook_id, None).await?;
     Ok(displayed_chapters)
 }
 
@@ -49,7 +67,8 @@ pub async fn get_audiobook_detail_base(
         .read_one(&AudiobookGetByIdJoin::new(user_id, audiobook_id, false))
         .await?;
 
-    let displayed_chapters = get_displayable_chapters(chapter_repo, audiobook_id).await?;
+    let displayed_chapters =
+        get_displayable_chapters(chapter_repo, audiobook_id, Some(user_id)).await?;
 
     Ok(AudiobookDetailBase {
         is_liked: audiobook.is_liked,
@@ -58,21 +77,38 @@ pub async fn get_audiobook_detail_base(
     })
 }
 
+/// Chapters of the book in the order they are played in, with the progress of the listener
+/// in them when there is one
 pub async fn get_displayable_chapters(
     chapter_repo: web::Data<ChapterRepository>,
     audiobook_id: Id,
+    user_id: Option<Id>,
 ) -> Result<Vec<ChapterDisplay>, AppError> {
     let chapters = chapter_repo
         .read_many(&ChaptersGetByBookId::new(audiobook_id))
         .await?;
+    let progress: HashMap<Id, ChapterProgress> = match user_id {
+        Some(user_id) => chapter_repo
+            .read_progress(&ChapterProgressGet::new(user_id, audiobook_id))
+            .await?
+            .into_iter()
+            .map(|progress| (progress.chapter_id, progress))
+            .collect(),
+        None => HashMap::new(),
+    };
     Ok(chapters
         .into_iter()
         .enumerate()
-        .map(|(order, ch)| ChapterDisplay {
-            id: ch.id,
-            name: ch.name,
-            order: order + 1,
-            position: ch.position,
+        .map(|(order, ch)| {
+            let progress = progress.get(&ch.id);
+            ChapterDisplay {
+                id: ch.id,
+                name: ch.name,
+                order: order + 1,
+                position: ch.position,
+                progress: progress.map_or(0.0, |progress| progress.progress),
+                completed: progress.is_some_and(|progress| progress.completed_at.is_some()),
+            }
         })
         .collect())
 }
@@ -153,15 +189,20 @@ pub async fn get_library(
 pub async fn get_studio(
     u: Identity,
     book_repo: web::Data<AudiobookRepository>,
-) -> Result<Vec<AudiobookDisplay>, AppError> {
+    user_repo: web::Data<UserRepository>,
+) -> Result<StudioBase, AppError> {
     let user_id = parse_user_id(u)?;
-    Ok(book_repo
+    let audiobooks = book_repo
         .read_many(&AudiobookSearch::search_by_author_id(
             user_id,
             user_id,
-            DbQueryParams::deleted(),
+            DbQueryParams::studio(),
         ))
-        .await?)
+        .await?;
+    Ok(StudioBase {
+        audiobooks,
+        storage: user_repo.read_storage_usage(&user_id).await?,
+    })
 }
 
 pub async fn get_author_profile(
@@ -191,3 +232,183 @@ pub async fn get_audiobook_edit(
         audiobook: AudiobookDisplay::from(audiobook),
     })
 }
+
+pub async fn get_audio_replace(
+    u: Identity,
+    audiobook_repo: web::Data<AudiobookRepository>,
+    audiobook_id: Id,
+) -> Result<AudiobookAudioReplaceBase, AppError> {
+    let audiobook =
+        authorized_to_modify_join(&audiobook_repo, parse_user_id(u)?, audiobook_id).await?;
+    let versions = audiobook_repo.read_versions(&audiobook.id).await?;
+    Ok(AudiobookAudioReplaceBase {
+        message: String::new(),
+        audiobook: AudiobookDisplay::from(audiobook),
+        versions,
+    })
+}
+
+/// Outcome of creating a book from uploaded files
+pub enum BookUpload {
+    Created(Id),
+    /// The files are valid, but no book can be made of them, the message is shown to the user
+    Rejected(String),
+}
+
+/// Uploaded audio files, checked and sorted in the order they are joined in
+pub enum UploadedTracks {
+    Valid(MediaFormat, Vec<TrackInfo>),
+    /// The files are valid, but they can not be joined, the message is shown to the user
+    Rejected(String),
+}
+
+/// Validates the uploaded audio files, sorts them and reads their details
+pub fn read_uploaded_tracks(audio_files: &mut [TempFile]) -> Result<UploadedTracks, AppError> {
+    let Some(first_track) = audio_files.first() else {
+        return Ok(UploadedTracks::Rejected(
+            "No audio file was uploaded".to_string(),
+        ));
+    };
+    let format = validate_file(first_track, "audio")?;
+    for track in audio_files.iter().skip(1) {
+        validate_file(track, "audio")?;
+    }
+
+    sort_tracks(audio_files);
+    let mut tracks = Vec::with_capacity(audio_files.len());
+    for audio_file in audio_files.iter_mut() {
+        match TrackInfo::read(audio_file) {
+            Ok(track) => tracks.push(track),
+            Err(e) => return Ok(UploadedTracks::Rejected(e.to_string())),
+        }
+    }
+    if !can_concatenate(&tracks) {
+        return Ok(UploadedTracks::Rejected(
+            "Books consisting of multiple files must be uploaded as MP3 tracks \
+            with the same sample rate and channels"
+                .to_string(),
+        ));
+    }
+    Ok(UploadedTracks::Valid(format, tracks))
+}
+
+/// Creates the book from the uploaded tracks, shared by the upload form and resumable uploads.
+/// The book gets the embedded cover of the tracks if no thumbnail was uploaded.
+///
+/// The files are stored and verified first, then the book is created with its chapters in
+/// one transaction, which also queues its registration in the recommender. If anything fails,
+/// the files stored by this upload are removed again. The book is hidden from listeners until
+/// its processing finishes.
+#[allow(clippy::too_many_arguments)]
+pub async fn create_uploaded_audiobook(
+    user_id: Id,
+    metadata: &AudiobookMetadataForm,
+    mut audio_files: Vec<TempFile>,
+    thumbnail: Option<TempFile>,
+    audiobook_repo: web::Data<AudiobookRepository>,
+    media_repo: web::Data<MediaRepository>,
+    storage: web::Data<dyn Storage>,
+) -> Result<BookUpload, AppError> {
+    if let Some(thumb) = &thumbnail {
+        validate_file(thumb, "image")?;
+    }
+    let (format, mut tracks) = match read_uploaded_tracks(&mut audio_files)? {
+        UploadedTracks::Valid(format, tracks) => (format, tracks),
+        UploadedTracks::Rejected(message) => return Ok(BookUpload::Rejected(message)),
+    };
+    let length = tracks.iter().map(|track| track.length).sum();
+    let chapters: Vec<ChapterEmbeddedCreate> = book_chapters(&tracks)
+        .iter()
+        .map(|chapter| ChapterEmbeddedCreate::new(&chapter.name, chapter.position))
+        .collect();
+
+    let mut staging = MediaStaging::new(storage.get_ref(), &media_repo);
+    let created = async {
+        let thumbnail_path = if let Some(thumbnail) = thumbnail {
+            Some(staging.uploaded_image(thumbnail).await?)
+        } else if let Some(cover) = tracks.iter_mut().find_map(|track| track.cover.take()) {
+            Some(staging.image(cover.data).await?)
+        } else {
+            None
+        };
+        let audiobook_path = staging.tracks(audio_files, format).await?;
+        let book_create = AudiobookCreate::new(
+            &metadata.name,
+            &user_id,
+            &metadata.genre_id,
+            &audiobook_path,
+            &length,
+            thumbnail_path,
+            &metadata.description,
+        );
+        Ok::<_, AppError>(
+            audiobook_repo
+                .create_uploaded(&book_create, &chapters)
+                .await?,
+        )
+    }
+    .await;
+    let book = match created {
+        Ok(book) => book,
+        Err(err) => {
+            staging.discard().await;
+            return Err(err);
+        }
+    };
+
+    notify_recommender_queue();
+    notify_book_processing();
+    Ok(BookUpload::Created(book.id))
+}
+
+/// Outcome of replacing the audio of a book
+pub enum AudioReplacement {
+    Replaced(AudiobookAudioReplaced),
+    /// The files are valid, but they can not replace the audio, the message is shown to the user
+    Rejected(String),
+}
+
+/// Replaces the audio of the book with the uploaded tracks. The previous audio is kept as
+/// a version of the book, and the chapters and the positions of listeners are moved as
+/// `remap` says. If anything fails, the files stored by this upload are removed again.
+/// The book is hidden from listeners until the new audio is processed.
+pub async fn replace_audiobook_audio(
+    audiobook: &Audiobook,
+    mut audio_files: Vec<TempFile>,
+    remap: PositionRemap,
+    audiobook_repo: &AudiobookRepository,
+    media_repo: &MediaRepository,
+    storage: &dyn Storage,
+) -> Result<AudioReplacement, AppError> {
+    let (format, tracks) = match read_uploaded_tracks(&mut audio_files)? {
+        UploadedTracks::Valid(format, tracks) => (format, tracks),
+        UploadedTracks::Rejected(message) => return Ok(AudioReplacement::Rejected(message)),
+    };
+    let length: f64 = tracks.iter().map(|track| track.length).sum();
+
+    let mut staging = MediaStaging::new(storage, media_repo);
+    let replaced = async {
+        let audiobook_path = staging.tracks(audio_files, format).await?;
+        if audiobook_path == audiobook.file_path {
+            return Ok(AudioReplacement::Rejected(
+                "The uploaded audio is the same as the current one".to_string(),
+            ));
+        }
+        let replace = AudiobookAudioReplace::new(&audiobook.id, &audiobook_path, &length, remap);
+        Ok::<_, AppError>(AudioReplacement::Replaced(
+            audiobook_repo.replace_audio(&replace).await?,
+        ))
+    }
+    .await;
+    match replaced {
+        Ok(AudioReplacement::Replaced(replaced)) => {
+            notify_book_processing();
+            Ok(AudioReplacement::Replaced(replaced))
+        }
+        result => {
+            // the same audio is still used by the book, so it is not removed
+            staging.discard().await;
+            result
+        }
+    }
+}
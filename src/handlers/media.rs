@@ -0,0 +1,31 @@
+use crate::error::{AppError, AppErrorKind};
+use crate::handlers::utilities::is_public_media;
+use crate::media::images::derivative_source;
+use crate::media::signing::require_signed_url;
+use crate::media::storage::Storage;
+use crate::media::stream::stream_object;
+use actix_web::middleware::from_fn;
+use actix_web::{get, web, HttpRequest, HttpResponse};
+use std::path::Path;
+
+/// Serves images from the media storage, audio files have to be requested
+/// through the stream handler.
+#[get("/media/{file}", wrap = "from_fn(require_signed_url)")]
+pub async fn get_media(
+    request: HttpRequest,
+    storage: web::Data<dyn Storage>,
+    file: web::Path<String>,
+) -> Result<HttpResponse, AppError> {
+    let path = format!("/media/{}", file.into_inner());
+    if !is_public_media(Path::new(&path)) {
+        return Err(AppError::new(
+            AppErrorKind::NotFound,
+            "The file does not exist",
+        ));
+    }
+    let path = match derivative_source(&path) {
+        Some(source) if !storage.exists(&path).await? => source,
+        _ => path,
+    };
+    stream_object(&request, storage.get_ref(), &path).await
+}
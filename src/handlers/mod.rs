@@ -1,11 +1,14 @@
 pub mod audiobook;
 pub mod chapter;
+pub mod export;
 pub mod genre;
 pub mod helpers;
 pub mod homepage;
 pub mod library;
+pub mod media;
 pub mod rating;
 pub mod studio;
+pub mod upload;
 pub mod user;
 pub mod utilities;
 
@@ -26,6 +29,8 @@ pub use crate::handlers::user::user_manage_picture_form;
 
 pub use crate::handlers::audiobook::*;
 pub use crate::handlers::chapter::*;
+pub use crate::handlers::export::*;
 pub use crate::handlers::genre::*;
 pub use crate::handlers::homepage::*;
+pub use crate::handlers::media::*;
 pub use crate::handlers::rating::*;
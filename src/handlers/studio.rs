@@ -13,13 +13,11 @@ use askama::Template;
 pub async fn studio_index(
     request: HttpRequest,
     identity: Option<Identity>,
-    _user_repo: web::Data<UserRepository>,
+    user_repo: web::Data<UserRepository>,
     book_repo: web::Data<AudiobookRepository>,
 ) -> Result<HttpResponse, AppError> {
     let u = authorized!(identity, request.path());
-    let template = StudioPageTemplate {
-        audiobooks: get_studio(u, book_repo).await?,
-    };
+    let template = StudioPageTemplate::from(get_studio(u, book_repo, user_repo).await?);
     let body = template.render()?;
     Ok(HttpResponse::Ok().content_type("text/html").body(body))
 }
@@ -28,12 +26,11 @@ pub async fn studio_index(
 pub async fn studio_get_content(
     request: HttpRequest,
     identity: Option<Identity>,
+    user_repo: web::Data<UserRepository>,
     book_repo: web::Data<AudiobookRepository>,
 ) -> Result<HttpResponse, AppError> {
     let u = authorized!(identity, request.path());
-    let template = StudioContentTemplate {
-        audiobooks: get_studio(u, book_repo).await?,
-    };
+    let template = StudioContentTemplate::from(get_studio(u, book_repo, user_repo).await?);
     let body = template.render()?;
     Ok(HttpResponse::Ok().content_type("text/html").body(body))
 }
@@ -0,0 +1,368 @@
+use crate::authorized;
+use crate::database::common::{DbDelete, DbReadOne};
+use crate::database::models::audiobook::AudiobookMetadataForm;
+use crate::database::models::upload::{Upload, UploadCreate, UploadGetById, UploadOffsetUpdate};
+use crate::database::models::Id;
+use crate::database::repositories::audiobook::repository::AudiobookRepository;
+use crate::database::repositories::media::repository::MediaRepository;
+use crate::database::repositories::upload::repository::UploadRepository;
+use crate::error::{AppError, AppErrorKind};
+use crate::handlers::helpers::{create_uploaded_audiobook, BookUpload};
+use crate::handlers::utilities::{
+    get_metadata_from_session, parse_user_id, storage_quota_exceeded, AudiobookCreateSessionKeys,
+};
+use crate::media::storage::Storage;
+use crate::media::uploads::{parse_upload_metadata, partial_upload_path, remove_partial_upload};
+use crate::{PAYLOAD_LIMIT, UPLOAD_EXPIRATION};
+use actix_identity::Identity;
+use actix_multipart::form::tempfile::TempFile;
+use actix_session::Session;
+use actix_web::http::header::{HttpDate, CACHE_CONTROL, CONTENT_TYPE, LOCATION};
+use actix_web::{delete, head, options, patch, post, web, HttpRequest, HttpResponse};
+use chrono::{DateTime, Utc};
+use futures_util::StreamExt;
+use std::time::SystemTime;
+use tempfile::{NamedTempFile, TempPath};
+use tokio::io::{AsyncSeekExt, AsyncWriteExt};
+use uuid::Uuid;
+
+/// Version of the tus protocol (https://tus.io/protocols/resumable-upload) that is supported
+pub const TUS_VERSION: &str = "1.0.0";
+const TUS_EXTENSIONS: &str = "creation,termination,expiration";
+const OFFSET_CONTENT_TYPE: &str = "application/offset+octet-stream";
+
+/// Clients announce the version of the protocol they speak in every request except OPTIONS
+fn unsupported_version(request: &HttpRequest) -> Option<HttpResponse> {
+    if request
+        .headers()
+        .get("Tus-Resumable")
+        .is_some_and(|version| version == TUS_VERSION)
+    {
+        return None;
+    }
+    Some(
+        HttpResponse::PreconditionFailed()
+            .insert_header(("Tus-Version", TUS_VERSION))
+            .finish(),
+    )
+}
+
+fn parse_header<T: std::str::FromStr>(request: &HttpRequest, name: &str) -> Option<T> {
+    request
+        .headers()
+        .get(name)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse().ok())
+}
+
+fn upload_expires(expires_at: DateTime<Utc>) -> String {
+    HttpDate::from(SystemTime::from(expires_at)).to_string()
+}
+
+fn expiration() -> DateTime<Utc> {
+    Utc::now() + chrono::Duration::seconds(UPLOAD_EXPIRATION)
+}
+
+#[options("")]
+pub async fn tus_options() -> HttpResponse {
+    HttpResponse::NoContent()
+        .insert_header(("Tus-Version", TUS_VERSION))
+        .insert_header(("Tus-Extension", TUS_EXTENSIONS))
+        .insert_header(("Tus-Max-Size", PAYLOAD_LIMIT.to_string()))
+        .finish()
+}
+
+/// Starts a resumable upload of the book whose details were filled in before (the same
+/// as for the upload form). The client can send the `filename` and `filetype` of the audio
+/// file in `Upload-Metadata`.
+#[post("")]
+pub async fn tus_create(
+    request: HttpRequest,
+    identity: Option<Identity>,
+    session: Session,
+    upload_repo: web::Data<UploadRepository>,
+    storage: web::Data<dyn Storage>,
+) -> Result<HttpResponse, AppError> {
+    let u = authorized!(identity, request.path());
+    if let Some(response) = unsupported_version(&request) {
+        return Ok(response);
+    }
+    let user_id = parse_user_id(u)?;
+    let Some(length) = parse_header::<i64>(&request, "Upload-Length").filter(|len| *len >= 0)
+    else {
+        return Err(AppError::new(
+            AppErrorKind::BadRequest,
+            "Upload-Length is missing or invalid",
+        ));
+    };
+    if length == 0 {
+        return Err(AppError::new(
+            AppErrorKind::BadRequest,
+            "An empty file can not be uploaded",
+        ));
+    }
+    if length as u64 > PAYLOAD_LIMIT as u64 {
+        return Ok(HttpResponse::PayloadTooLarge().finish());
+    }
+    let metadata = match request
+        .headers()
+        .get("Upload-Metadata")
+        .map(|header| header.to_str().ok().and_then(parse_upload_metadata))
+    {
+        None => Default::default(),
+        Some(Some(metadata)) => metadata,
+        Some(None) => {
+            return Err(AppError::new(
+                AppErrorKind::BadRequest,
+                "Upload-Metadata is malformed",
+            ))
+        }
+    };
+
+    // the details of the book move from the session to the upload, so that the upload
+    // can be resumed from another session
+    let session_keys = AudiobookCreateSessionKeys::new(user_id);
+    let book = get_metadata_from_session(&session, &session_keys)?;
+    let id = Uuid::new_v4();
+    tokio::fs::File::create(partial_upload_path(storage.get_ref(), id)).await?;
+    let upload = upload_repo
+        .create_within_quota(&UploadCreate::new(
+            id,
+            &user_id,
+            &book.genre_id,
+            &book.name,
+            &book.description,
+            metadata.get("filename").cloned(),
+            metadata.get("filetype").cloned(),
+            length,
+            expiration(),
+        ))
+        .await;
+    let upload = match upload {
+        Ok(Ok(upload)) => upload,
+        Ok(Err(usage)) => {
+            remove_partial_upload(storage.get_ref(), id).await?;
+            return Err(storage_quota_exceeded(&usage, length));
+        }
+        Err(err) => {
+            remove_partial_upload(storage.get_ref(), id).await?;
+            return Err(err.into());
+        }
+    };
+    session.remove(session_keys.name.as_str());
+    session.remove(session_keys.description.as_str());
+    session.remove(session_keys.genre_id.as_str());
+
+    Ok(HttpResponse::Created()
+        .insert_header((LOCATION, format!("/audiobook/tus/{}", upload.id)))
+        .insert_header(("Upload-Expires", upload_expires(upload.expires_at)))
+        .finish())
+}
+
+#[head("/{id}")]
+pub async fn tus_offset(
+    request: HttpRequest,
+    identity: Option<Identity>,
+    upload_repo: web::Data<UploadRepository>,
+    path: web::Path<(Uuid,)>,
+) -> Result<HttpResponse, AppError> {
+    let u = authorized!(identity, request.path());
+    if let Some(response) = unsupported_version(&request) {
+        return Ok(response);
+    }
+    let upload = upload_repo
+        .read_one(&UploadGetById::new(path.into_inner().0, parse_user_id(u)?))
+        .await?;
+
+    Ok(HttpResponse::Ok()
+        .insert_header(("Upload-Offset", upload.upload_offset.to_string()))
+        .insert_header(("Upload-Length", upload.upload_length.to_string()))
+        .insert_header(("Upload-Expires", upload_expires(upload.expires_at)))
+        .insert_header((CACHE_CONTROL, "no-store"))
+        .finish())
+}
+
+/// Appends a chunk to the upload, the book is created once the whole file has arrived.
+///
+/// The offset is moved by what was stored even if the client disconnects in the middle
+/// of the chunk, so that it can continue from there.
+#[patch("/{id}")]
+#[allow(clippy::too_many_arguments)]
+pub async fn tus_append(
+    request: HttpRequest,
+    identity: Option<Identity>,
+    upload_repo: web::Data<UploadRepository>,
+    audiobook_repo: web::Data<AudiobookRepository>,
+    media_repo: web::Data<MediaRepository>,
+    storage: web::Data<dyn Storage>,
+    path: web::Path<(Uuid,)>,
+    mut payload: web::Payload,
+) -> Result<HttpResponse, AppError> {
+    let u = authorized!(identity, request.path());
+    if let Some(response) = unsupported_version(&request) {
+        return Ok(response);
+    }
+    if request
+        .headers()
+        .get(CONTENT_TYPE)
+        .is_none_or(|content_type| content_type != OFFSET_CONTENT_TYPE)
+    {
+        return Ok(HttpResponse::UnsupportedMediaType().finish());
+    }
+    let Some(offset) = parse_header::<i64>(&request, "Upload-Offset") else {
+        return Err(AppError::new(
+            AppErrorKind::BadRequest,
+            "Upload-Offset is missing or invalid",
+        ));
+    };
+    let id = path.into_inner().0;
+    let Some(_lock) = upload_repo.lock(&id).await? else {
+        return Err(AppError::new(
+            AppErrorKind::Conflict,
+            "The upload is being written by another request",
+        ));
+    };
+    let upload = upload_repo
+        .read_one(&UploadGetById::new(id, parse_user_id(u)?))
+        .await?;
+    if offset != upload.upload_offset {
+        return Err(AppError::new(
+            AppErrorKind::Conflict,
+            "Upload-Offset does not match the offset of the upload",
+        ));
+    }
+
+    let partial_path = partial_upload_path(storage.get_ref(), id);
+    let mut file = tokio::fs::OpenOptions::new()
+        .write(true)
+        .open(&partial_path)
+        .await?;
+    // data past the offset are left over from a chunk that was not recorded
+    file.set_len(offset as u64).await?;
+    file.seek(std::io::SeekFrom::End(0)).await?;
+    let mut written = 0_i64;
+    let mut failure = None;
+    while let Some(chunk) = payload.next().await {
+        let chunk = match chunk {
+            Ok(chunk) => chunk,
+            Err(err) => {
+                failure = Some(AppError::new(AppErrorKind::BadRequest, &err.to_string()));
+                break;
+            }
+        };
+        if offset + written + chunk.len() as i64 > upload.upload_length {
+            failure = Some(AppError::new(
+                AppErrorKind::BadRequest,
+                "The chunk exceeds the length of the upload",
+            ));
+            break;
+        }
+        if let Err(err) = file.write_all(&chunk).await {
+            failure = Some(AppError::from(err));
+            break;
+        }
+        written += chunk.len() as i64;
+    }
+    file.flush().await?;
+    drop(file);
+
+    let upload = upload_repo
+        .advance_offset(&UploadOffsetUpdate::new(
+            id,
+            offset,
+            offset + written,
+            expiration(),
+        ))
+        .await?;
+    if let Some(err) = failure {
+        return Err(err);
+    }
+
+    let mut response = HttpResponse::NoContent();
+    response
+        .insert_header(("Upload-Offset", upload.upload_offset.to_string()))
+        .insert_header(("Upload-Expires", upload_expires(upload.expires_at)));
+    if upload.is_complete() {
+        let book_id =
+            finish_upload(upload, upload_repo, audiobook_repo, media_repo, storage).await?;
+        response.insert_header((
+            "Audiobook-Location",
+            format!("/audiobook/{book_id}/manage-content"),
+        ));
+    }
+    Ok(response.finish())
+}
+
+/// Creates the book from the completed upload, the upload is removed whether that succeeds
+/// or not. It is removed only after the book is created, so that a completed upload is not
+/// lost if creating the book is interrupted.
+async fn finish_upload(
+    upload: Upload,
+    upload_repo: web::Data<UploadRepository>,
+    audiobook_repo: web::Data<AudiobookRepository>,
+    media_repo: web::Data<MediaRepository>,
+    storage: web::Data<dyn Storage>,
+) -> Result<Id, AppError> {
+    let partial_path = partial_upload_path(storage.get_ref(), upload.id);
+    let file = std::fs::File::open(&partial_path)?;
+    let audio_file = TempFile {
+        file: NamedTempFile::from_parts(file, TempPath::try_from_path(partial_path)?),
+        content_type: upload
+            .content_type
+            .as_deref()
+            .and_then(|content_type| content_type.parse().ok()),
+        file_name: upload.file_name,
+        size: upload.upload_length as usize,
+    };
+    let metadata = AudiobookMetadataForm {
+        name: upload.name,
+        description: upload.description,
+        genre_id: upload.genre_id,
+    };
+
+    // the partial file is consumed even if the book is not created
+    let created = create_uploaded_audiobook(
+        upload.user_id,
+        &metadata,
+        vec![audio_file],
+        None,
+        audiobook_repo,
+        media_repo,
+        storage,
+    )
+    .await;
+    upload_repo
+        .delete(&UploadGetById::new(upload.id, upload.user_id))
+        .await?;
+    match created? {
+        BookUpload::Created(book_id) => Ok(book_id),
+        BookUpload::Rejected(message) => Err(AppError::new(AppErrorKind::BadRequest, &message)),
+    }
+}
+
+/// Cancels the upload (termination extension of tus)
+#[delete("/{id}")]
+pub async fn tus_terminate(
+    request: HttpRequest,
+    identity: Option<Identity>,
+    upload_repo: web::Data<UploadRepository>,
+    storage: web::Data<dyn Storage>,
+    path: web::Path<(Uuid,)>,
+) -> Result<HttpResponse, AppError> {
+    let u = authorized!(identity, request.path());
+    if let Some(response) = unsupported_version(&request) {
+        return Ok(response);
+    }
+    let id = path.into_inner().0;
+    let Some(_lock) = upload_repo.lock(&id).await? else {
+        return Err(AppError::new(
+            AppErrorKind::Conflict,
+            "The upload is being written by another request",
+        ));
+    };
+    upload_repo
+        .delete(&UploadGetById::new(id, parse_user_id(u)?))
+        .await?;
+    remove_partial_upload(storage.get_ref(), id).await?;
+    Ok(HttpResponse::NoContent().finish())
+}
@@ -14,7 +14,6 @@ use actix_web::http::StatusCode;
 use actix_web::web::Redirect;
 use actix_web::{get, post, web, HttpMessage, HttpRequest, HttpResponse, Responder};
 use askama::Template;
-use uuid::Uuid;
 
 use crate::database::common::error::{BackendError, BackendErrorKind};
 use crate::database::common::{DbCreate, DbReadOne, DbUpdate};
@@ -24,14 +23,17 @@ use crate::database::models::user::{
     UserCreate, UserDisplay, UserGetById, UserLogin, UserUpdate, UserUpdatePassword,
 };
 use crate::database::repositories::audiobook::repository::AudiobookRepository;
+use crate::database::repositories::media::repository::MediaRepository;
 use crate::forms::user::{
     ProfilePictureUploadForm, UserCreateForm, UserLoginForm, UserLoginReturnURL, UserUpdateForm,
     UserUpdatePasswordForm,
 };
 use crate::handlers::helpers::get_author_profile;
+use crate::media::storage::Storage;
 
 use crate::handlers::utilities::{
-    get_user_from_identity, parse_user_id, remove_file, save_file, validate_file, validate_password,
+    check_storage_quota, get_user_from_identity, parse_user_id, release_media,
+    store_uploaded_image, validate_file, validate_password,
 };
 
 #[get("/register")]
@@ -307,14 +309,18 @@ pub async fn user_manage_picture(
     request: HttpRequest,
     identity: Option<Identity>,
     user_repo: web::Data<UserRepository>,
+    media_repo: web::Data<MediaRepository>,
+    storage: web::Data<dyn Storage>,
     MultipartForm(form): MultipartForm<ProfilePictureUploadForm>,
 ) -> Result<impl Responder, AppError> {
     let u = authorized!(identity, request.path());
-    let path = validate_file(&form.picture, Uuid::new_v4(), "image", "user")?;
     let user = get_user_from_identity(u, &user_repo).await?;
-    if let Some(pic) = &user.profile_picture {
-        remove_file(pic)?;
-    }
+    validate_file(&form.picture, "image")?;
+    check_storage_quota(&user_repo, user.id, form.picture.size as u64).await?;
+    let path = store_uploaded_image(storage.get_ref(), &media_repo, form.picture)
+        .await?
+        .path;
+    let previous_picture = user.profile_picture;
     let user_update = UserUpdate::new(
         &user.id,
         None,
@@ -327,7 +333,6 @@ pub async fn user_manage_picture(
     );
 
     let users = user_repo.update(&user_update).await?;
-    save_file(form.picture, &path)?;
 
     let Some(user) = users.into_iter().next() else {
         return Err(AppError::new(
@@ -335,6 +340,9 @@ pub async fn user_manage_picture(
             "Update of user profile failed",
         ));
     };
+    if let Some(previous) = previous_picture {
+        release_media(storage.get_ref(), &media_repo, vec![previous]).await?;
+    }
 
     let template = UserManageProfilePictureTemplate {
         user: UserDisplay::from(user),
@@ -2,19 +2,31 @@ use crate::database::common::DbReadOne;
 use crate::database::models::audiobook::{
     Audiobook, AudiobookDetail, AudiobookGetById, AudiobookGetByIdJoin, AudiobookMetadataForm,
 };
-use crate::database::models::user::{User, UserGetById};
+use crate::database::models::media::{Media, MediaClaim, MediaUnreferenced};
+use crate::database::models::user::{StorageUsage, User, UserGetById};
 use crate::database::models::Id;
+use crate::database::repositories::media::repository::MediaRepository;
 use crate::database::repositories::user::repository::UserRepository;
 use crate::error::{AppError, AppErrorKind};
 use actix_identity::Identity;
 use actix_multipart::form::tempfile::TempFile;
 use actix_session::Session;
-use actix_web::web;
+use actix_web::http::header::{ContentDisposition, DispositionParam, DispositionType, RANGE};
+use actix_web::{web, HttpRequest};
 
 use crate::database::common::error::{BackendError, BackendErrorKind};
 use crate::database::repositories::audiobook::repository::AudiobookRepository;
-use crate::MIN_PASS_LEN;
-use uuid::Uuid;
+use crate::media::content::{content_path, data_digest, file_digest, remove_media_files};
+use crate::media::formats::{detect_format, MediaFormat};
+use crate::media::hls::{hls_available, HLS_MASTER_PLAYLIST};
+use crate::media::images::{encode_image_derivatives, image_files, IMAGE_EXTENSION};
+use crate::media::signing::sign_url;
+use crate::media::storage::Storage;
+use crate::media::tracks::concatenate_mp3;
+use crate::templates::utilities::format_size;
+use crate::{MAX_DEVICE_ID_LEN, MIN_PASS_LEN, UNREFERENCED_MEDIA_GRACE_PERIOD};
+use chrono::{DateTime, LocalResult, TimeZone, Utc};
+use std::path::Path;
 
 pub struct AudiobookCreateSessionKeys {
     pub name: String,
@@ -77,60 +89,265 @@ pub async fn get_user_from_identity(
         .await?)
 }
 
-pub fn validate_file(
-    file: &TempFile,
-    uuid: Uuid,
-    mime: &str,
-    handler: &str,
-) -> Result<String, AppError> {
-    let extension = match file.file_name.clone() {
-        None => "".to_owned(),
-        Some(name) => {
-            let split_res = name.split('.');
-            let vector = split_res.collect::<Vec<&str>>();
-            match vector.last() {
-                None => "".to_owned(),
-                Some(ext) => ext.to_string(),
-            }
-        }
-    };
-    let file_path = format!("/media/{handler}_{uuid}_{mime}.{extension}");
-
-    let Some(file_mime) = &file.content_type else {
-        return Err(AppError::new(
+/// Checks that the uploaded file is of an allowed `mime` format (`audio` or `image`)
+/// and returns the detected format. The format is detected from the contents,
+/// the file name and the MIME type sent by the client are not trusted.
+pub fn validate_file(file: &TempFile, mime: &str) -> Result<MediaFormat, AppError> {
+    let file_name = file.file_name.as_deref().unwrap_or("The file");
+    let rejected = |reason: String| {
+        AppError::new(
             AppErrorKind::FileError,
-            format!("No MIME type found for {file_path}").as_str(),
-        ));
+            format!("{file_name} was rejected: {reason}").as_str(),
+        )
     };
 
-    if !file_mime
-        .to_string()
-        .starts_with(format!("{mime}/").as_str())
-    {
-        return Err(AppError::new(
-            AppErrorKind::FileError,
-            format!("Invalid content type for {file_path}").as_str(),
-        ));
+    let format = detect_format(file.file.path()).map_err(|err| rejected(err.to_string()))?;
+    if format.mime() != mime {
+        return Err(rejected(format!("{format} is not an {mime} format")));
     }
-    Ok(file_path)
+    // a type contradicting the contents points to a renamed or forged file
+    if let Some(content_type) = &file.content_type {
+        let declared = content_type.essence_str();
+        if matches!(content_type.type_().as_str(), "audio" | "image")
+            && !format.content_types().contains(&declared)
+        {
+            return Err(rejected(format!(
+                "it was sent as {declared}, but it contains {format}"
+            )));
+        }
+    }
+    Ok(format)
 }
 
-pub fn save_file(file: TempFile, path: &str) -> Result<(), AppError> {
-    log::info!("saving file to .{path}");
-    let path = format!(".{path}");
-    if let Err(e) = file.file.persist(path) {
-        return Err(AppError::new(
-            AppErrorKind::FileError,
-            e.to_string().as_str(),
-        ));
-    };
-    Ok(())
+/// Stores the file under the digest of its contents and returns the claimed medium,
+/// nothing is stored if a file with the same contents exists already
+pub async fn store_file(
+    storage: &dyn Storage,
+    media_repo: &MediaRepository,
+    file: &Path,
+    format: MediaFormat,
+) -> Result<Media, AppError> {
+    let digest = file_digest(file).await?;
+    let size = tokio::fs::metadata(file).await?.len();
+    let path = content_path(&digest, format.mime(), format.extension());
+    let media = media_repo
+        .claim(&MediaClaim::new(&path, &digest, size as i64))
+        .await?;
+    // a file whose copying was interrupted is incomplete, it is stored again
+    if !is_stored(storage, &path, size).await? {
+        log::info!("saving file to {path}");
+        storage.put_file(&path, file).await?;
+        if !is_stored(storage, &path, size).await? {
+            return Err(not_verified(&path));
+        }
+    }
+    Ok(media)
+}
+
+async fn is_stored(storage: &dyn Storage, path: &str, size: u64) -> std::io::Result<bool> {
+    Ok(storage
+        .head(path)
+        .await?
+        .is_some_and(|meta| meta.size == size))
+}
+
+async fn are_stored(storage: &dyn Storage, paths: &[String]) -> std::io::Result<bool> {
+    for path in paths {
+        if !storage.exists(path).await? {
+            return Ok(false);
+        }
+    }
+    Ok(true)
 }
 
-pub fn remove_file(path: &str) -> Result<(), AppError> {
-    let fs_path = format!(".{path}");
-    if !path.is_empty() && std::path::Path::new(&fs_path).exists() {
-        std::fs::remove_file(&fs_path)?;
+fn not_verified(path: &str) -> AppError {
+    AppError::new(
+        AppErrorKind::FileError,
+        format!("{path} could not be verified after it was stored").as_str(),
+    )
+}
+
+/// Stores the image together with its derivatives in all sizes and returns the claimed
+/// medium, the image is addressed by the digest of the original
+pub async fn store_image(
+    storage: &dyn Storage,
+    media_repo: &MediaRepository,
+    data: Vec<u8>,
+) -> Result<Media, AppError> {
+    let digest = data_digest(&data);
+    let path = content_path(&digest, "image", IMAGE_EXTENSION);
+    let media = media_repo
+        .claim(&MediaClaim::new(&path, &digest, data.len() as i64))
+        .await?;
+    let files = image_files(&path);
+    if are_stored(storage, &files).await? {
+        return Ok(media);
+    }
+    log::info!("saving image to {path}");
+    let image_path = path.clone();
+    let encoded = web::block(move || encode_image_derivatives(&data, &image_path))
+        .await
+        .map_err(|e| AppError::new(AppErrorKind::FileError, e.to_string().as_str()))??;
+    for (file_path, data) in encoded {
+        storage.put(&file_path, data).await?;
+    }
+    if !are_stored(storage, &files).await? {
+        return Err(not_verified(&path));
+    }
+    Ok(media)
+}
+
+pub async fn store_uploaded_image(
+    storage: &dyn Storage,
+    media_repo: &MediaRepository,
+    file: TempFile,
+) -> Result<Media, AppError> {
+    let data = tokio::fs::read(file.file.path()).await?;
+    store_image(storage, media_repo, data).await
+}
+
+/// Stores uploaded tracks of the `format` and returns the claimed medium,
+/// multiple tracks are joined into a single file
+pub async fn store_tracks(
+    storage: &dyn Storage,
+    media_repo: &MediaRepository,
+    tracks: Vec<TempFile>,
+    format: MediaFormat,
+) -> Result<Media, AppError> {
+    if tracks.len() == 1 {
+        let track = tracks.into_iter().next().expect("exactly one track");
+        return store_file(storage, media_repo, track.file.path(), format).await;
+    }
+    log::info!("joining {} tracks", tracks.len());
+    let joined = match storage.upload_directory() {
+        Some(directory) => tempfile::NamedTempFile::new_in(directory)?,
+        None => tempfile::NamedTempFile::new()?,
+    }
+    .into_temp_path();
+    let joined_path = joined.to_path_buf();
+    web::block(move || {
+        let sources = tracks
+            .iter()
+            .map(|track| track.file.path())
+            .collect::<Vec<_>>();
+        concatenate_mp3(&sources, &joined_path)
+    })
+    .await
+    .map_err(|e| AppError::new(AppErrorKind::FileError, e.to_string().as_str()))??;
+    store_file(storage, media_repo, &joined, format).await
+}
+
+/// Media stored for a book that is being created. If the book can not be created,
+/// `discard` removes the media stored by this upload, media that were stored before
+/// (or claimed by another upload since) are left alone.
+pub struct MediaStaging<'a> {
+    storage: &'a dyn Storage,
+    media_repo: &'a MediaRepository,
+    staged: Vec<Media>,
+}
+
+impl<'a> MediaStaging<'a> {
+    #[must_use]
+    #[inline]
+    pub const fn new(storage: &'a dyn Storage, media_repo: &'a MediaRepository) -> Self {
+        Self {
+            storage,
+            media_repo,
+            staged: Vec::new(),
+        }
+    }
+
+    fn stage(&mut self, media: Media) -> String {
+        let path = media.path.clone();
+        self.staged.push(media);
+        path
+    }
+
+    pub async fn image(&mut self, data: Vec<u8>) -> Result<String, AppError> {
+        let media = store_image(self.storage, self.media_repo, data).await?;
+        Ok(self.stage(media))
+    }
+
+    pub async fn uploaded_image(&mut self, file: TempFile) -> Result<String, AppError> {
+        let media = store_uploaded_image(self.storage, self.media_repo, file).await?;
+        Ok(self.stage(media))
+    }
+
+    pub async fn tracks(
+        &mut self,
+        tracks: Vec<TempFile>,
+        format: MediaFormat,
+    ) -> Result<String, AppError> {
+        let media = store_tracks(self.storage, self.media_repo, tracks, format).await?;
+        Ok(self.stage(media))
+    }
+
+    pub async fn discard(self) {
+        // a medium claimed for the first time was created by this upload, its claim
+        // changes when another upload claims it
+        let staged: Vec<Media> = self
+            .staged
+            .into_iter()
+            .filter(|media| media.created_at == media.claimed_at)
+            .collect();
+        if staged.is_empty() {
+            return;
+        }
+        let storage = self.storage;
+        let params =
+            MediaUnreferenced::new(staged.iter().map(|media| media.path.clone()).collect(), 0);
+        let removed = self
+            .media_repo
+            .remove_unreferenced(&params, |media| {
+                let staged = &staged;
+                async move {
+                    let unchanged = staged.iter().any(|staged| {
+                        staged.id == media.id && staged.claimed_at == media.claimed_at
+                    });
+                    if !unchanged {
+                        return false;
+                    }
+                    match remove_media_files(storage, &media.path).await {
+                        Ok(()) => true,
+                        Err(err) => {
+                            log::warn!("failed to remove staged {}: {err}", media.path);
+                            false
+                        }
+                    }
+                }
+            })
+            .await;
+        match removed {
+            Ok(removed) => {
+                for media in removed {
+                    log::info!("removed staged {}", media.path);
+                }
+            }
+            Err(err) => log::warn!("failed to remove staged media: {err}"),
+        }
+    }
+}
+
+/// Removes the media in `paths` that are not referenced anymore, with their files
+pub async fn release_media(
+    storage: &dyn Storage,
+    media_repo: &MediaRepository,
+    paths: Vec<String>,
+) -> Result<(), AppError> {
+    let params = MediaUnreferenced::new(paths, UNREFERENCED_MEDIA_GRACE_PERIOD);
+    let removed = media_repo
+        .remove_unreferenced(&params, |media| async move {
+            match remove_media_files(storage, &media.path).await {
+                Ok(()) => true,
+                Err(err) => {
+                    log::warn!("failed to remove {}: {err}", media.path);
+                    false
+                }
+            }
+        })
+        .await?;
+    for media in removed {
+        log::info!("removed {}, it is not used anymore", media.path);
     }
     Ok(())
 }
@@ -174,6 +391,167 @@ pub async fn authorized_to_modify_join(
     Ok(audiobook)
 }
 
+/// Books can be streamed by their author, e.g. when editing their chapters, other users
+/// can only stream processed books that are not deleted
+pub async fn authorized_to_stream(
+    audiobook_repo: &web::Data<AudiobookRepository>,
+    user_id: Id,
+    audiobook_id: Id,
+) -> Result<Audiobook, AppError> {
+    let audiobook = audiobook_repo
+        .read_one(&AudiobookGetById::new(&audiobook_id, true))
+        .await?;
+    if audiobook.author_id == user_id {
+        return Ok(audiobook);
+    }
+    if audiobook.deleted_at.is_some() {
+        return Err(AppError::from(BackendError::new(
+            BackendErrorKind::AudiobookDeleted,
+        )));
+    }
+    if !audiobook.processing_state.is_ready() {
+        return Err(AppError::new(
+            AppErrorKind::Forbidden,
+            "The audiobook can not be streamed yet",
+        ));
+    }
+    Ok(audiobook)
+}
+
+/// Books can be downloaded by their author, other users can only download processed books
+/// that are not deleted and that their author made downloadable
+pub async fn authorized_to_download(
+    audiobook_repo: &web::Data<AudiobookRepository>,
+    user_id: Id,
+    audiobook_id: Id,
+) -> Result<AudiobookDetail, AppError> {
+    let audiobook = audiobook_repo
+        .read_one(&AudiobookGetByIdJoin::new(user_id, audiobook_id, true))
+        .await?;
+    if audiobook.author_id == user_id {
+        return Ok(audiobook);
+    }
+    if audiobook.deleted_at.is_some() {
+        return Err(AppError::from(BackendError::new(
+            BackendErrorKind::AudiobookDeleted,
+        )));
+    }
+    if !audiobook.downloadable || !audiobook.processing_state.is_ready() {
+        return Err(AppError::new(
+            AppErrorKind::Forbidden,
+            "The audiobook can not be downloaded",
+        ));
+    }
+    Ok(audiobook)
+}
+
+/// Uploads of `size` bytes are only accepted if they fit into the storage quota of the user
+pub async fn check_storage_quota(
+    user_repo: &UserRepository,
+    user_id: Id,
+    size: u64,
+) -> Result<(), AppError> {
+    let usage = user_repo.read_storage_usage(&user_id).await?;
+    let size = i64::try_from(size).unwrap_or(i64::MAX);
+    if !usage.allows(size) {
+        return Err(storage_quota_exceeded(&usage, size));
+    }
+    Ok(())
+}
+
+/// Error for an upload of `size` bytes that does not fit into the storage quota
+pub fn storage_quota_exceeded(usage: &StorageUsage, size: i64) -> AppError {
+    AppError::new(
+        AppErrorKind::PayloadTooLarge,
+        format!(
+            "The upload of {} does not fit into your storage quota, {} of {} is used",
+            format_size(&size),
+            format_size(&usage.used()),
+            format_size(&usage.quota.unwrap_or_default())
+        )
+        .as_str(),
+    )
+}
+
+/// Downloads are named after the book, characters that are not safe in file names
+/// are replaced
+pub fn attachment_disposition(book_name: &str, extension: &str) -> ContentDisposition {
+    let name: String = book_name
+        .chars()
+        .map(|c| match c {
+            c if c.is_alphanumeric() || c == ' ' || c == '-' => c,
+            _ => '_',
+        })
+        .collect();
+    ContentDisposition {
+        disposition: DispositionType::Attachment,
+        parameters: vec![DispositionParam::Filename(format!(
+            "{}.{extension}",
+            name.trim()
+        ))],
+    }
+}
+
+pub fn signed_stream_url(audiobook_id: Id) -> String {
+    sign_url(&format!("/audiobook/{audiobook_id}/stream"))
+}
+
+/// Master playlist of the book, `None` until its HLS renditions have been created
+pub async fn signed_hls_url(
+    storage: &dyn Storage,
+    audiobook_id: Id,
+    file_path: &str,
+) -> Result<Option<String>, AppError> {
+    let available = hls_available(storage, file_path).await?;
+    Ok(available.then(|| {
+        sign_url(&format!(
+            "/audiobook/{audiobook_id}/hls/{HLS_MASTER_PLAYLIST}"
+        ))
+    }))
+}
+
+/// Players request the whole file from its beginning when the playback starts. Bounded
+/// ranges, even those starting at zero, are probes or buffering (Safari asks for `bytes=0-1`
+/// before every playback), any other range is requested only when seeking.
+pub fn is_playback_start(request: &HttpRequest) -> bool {
+    match request.headers().get(RANGE) {
+        None => true,
+        Some(range) => range.to_str().is_ok_and(|range| range.trim() == "bytes=0-"),
+    }
+}
+
+/// Time a device reported something at, in milliseconds since the epoch. The clock of
+/// the device may be off, times in the future are moved to now.
+pub fn reported_at(timestamp: Option<i64>) -> Result<DateTime<Utc>, AppError> {
+    let now = Utc::now();
+    let Some(timestamp) = timestamp else {
+        return Ok(now);
+    };
+    match Utc.timestamp_millis_opt(timestamp) {
+        LocalResult::Single(reported_at) => Ok(reported_at.min(now)),
+        _ => Err(AppError::new(AppErrorKind::BadRequest, "Invalid timestamp")),
+    }
+}
+
+/// Devices identify themselves by an id they generated, players which do not are
+/// all the same device
+pub fn validate_device_id(device_id: Option<&str>) -> Result<&str, AppError> {
+    let device_id = device_id.unwrap_or_default();
+    match device_id.len() <= MAX_DEVICE_ID_LEN {
+        true => Ok(device_id),
+        false => Err(AppError::new(AppErrorKind::BadRequest, "Invalid device id")),
+    }
+}
+
+/// Only images (thumbnails and profile pictures) and their derivatives are served
+/// from `/media`, audio files have to be requested through the stream handler.
+pub fn is_public_media(path: &Path) -> bool {
+    path.file_name()
+        .and_then(|name| name.to_str())
+        .and_then(|name| name.split('.').next())
+        .is_some_and(|stem| stem.ends_with("_image"))
+}
+
 pub fn is_authorized(user_id: Id, author_id: Id) -> Result<(), AppError> {
     match user_id == author_id {
         true => Ok(()),
@@ -217,3 +595,65 @@ pub fn validate_password(password: &str) -> bool {
             });
     lower && upper && numeric && special && password.len() >= MIN_PASS_LEN
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{ImageBuffer, ImageFormat, Rgb};
+    use std::io::{Cursor, Write};
+    use tempfile::NamedTempFile;
+
+    fn uploaded_png(file_name: &str, content_type: &str) -> TempFile {
+        let mut data = Cursor::new(Vec::new());
+        ImageBuffer::from_pixel(4, 4, Rgb([200u8, 100, 0]))
+            .write_to(&mut data, ImageFormat::Png)
+            .expect("Encode image should succeed");
+        let mut file = NamedTempFile::new().expect("Create temporary file should succeed");
+        file.write_all(data.get_ref())
+            .expect("Write temporary file should succeed");
+        TempFile {
+            file,
+            content_type: Some(
+                content_type
+                    .parse()
+                    .expect("Parse MIME type should succeed"),
+            ),
+            file_name: Some(file_name.to_string()),
+            size: data.get_ref().len(),
+        }
+    }
+
+    #[test]
+    fn validate_file_detects_the_format_from_the_contents() {
+        let file = uploaded_png("cover.png", "image/png");
+        assert_eq!(
+            validate_file(&file, "image").expect("Validate file should succeed"),
+            MediaFormat::Png
+        );
+        // the extension is not trusted, the file is stored as PNG
+        let file = uploaded_png("cover.jpg", "image/x-png");
+        assert_eq!(
+            validate_file(&file, "image").expect("Validate file should succeed"),
+            MediaFormat::Png
+        );
+        // types that do not say anything about the contents are ignored
+        let file = uploaded_png("cover", "application/octet-stream");
+        assert_eq!(
+            validate_file(&file, "image").expect("Validate file should succeed"),
+            MediaFormat::Png
+        );
+    }
+
+    #[test]
+    fn validate_file_rejects_mismatched_files() {
+        let file = uploaded_png("cover.jpg", "image/jpeg");
+        let err = validate_file(&file, "image").expect_err("Validate file should fail");
+        assert!(err
+            .message
+            .contains("it was sent as image/jpeg, but it contains PNG"));
+
+        let file = uploaded_png("book.mp3", "audio/mpeg");
+        let err = validate_file(&file, "audio").expect_err("Validate file should fail");
+        assert!(err.message.contains("PNG is not an audio format"));
+    }
+}
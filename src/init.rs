@@ -2,8 +2,11 @@ use crate::database::common::PoolHandler;
 use crate::database::common::{DbPoolHandler, DbRepository};
 use crate::database::repositories::audiobook::repository::AudiobookRepository;
 use crate::database::repositories::chapter::repository::ChapterRepository;
+use crate::database::repositories::export::repository::AudiobookExportRepository;
 use crate::database::repositories::genre::repository::GenreRepository;
+use crate::database::repositories::media::repository::MediaRepository;
 use crate::database::repositories::rating::repository::RatingRepository;
+use crate::database::repositories::upload::repository::UploadRepository;
 use crate::database::repositories::user::repository::UserRepository;
 use crate::handlers::audiobook::{
     change_like, create_audiobook_content, get_audiobook_detail_content, get_audiobook_player,
@@ -13,19 +16,31 @@ use crate::handlers::genre::get_genres_content;
 use crate::handlers::rating::{
     create_rating, get_ratings_by_audiobook, remove_rating_for_audiobook,
 };
+use crate::handlers::upload::{
+    tus_append, tus_create, tus_offset, tus_options, tus_terminate, TUS_VERSION,
+};
 use crate::handlers::user::{user_manage_form_content, user_manage_profile_form};
 use crate::handlers::*;
+use crate::media::storage::Storage;
 use actix_files::Files as ActixFiles;
+use actix_web::middleware::DefaultHeaders;
 use actix_web::web;
 use actix_web::web::ServiceConfig;
 use sqlx::PgPool;
+use std::sync::Arc;
 
-pub fn configure_webapp(pool: &PgPool) -> Box<dyn FnOnce(&mut ServiceConfig)> {
+pub fn configure_webapp(
+    pool: &PgPool,
+    storage: Arc<dyn Storage>,
+) -> Box<dyn FnOnce(&mut ServiceConfig)> {
     let user_repository = UserRepository::new(PoolHandler::new(pool.clone()));
     let audiobook_repository = AudiobookRepository::new(PoolHandler::new(pool.clone()));
     let chapter_repository = ChapterRepository::new(PoolHandler::new(pool.clone()));
     let genre_repository = GenreRepository::new(PoolHandler::new(pool.clone()));
     let rating_repository = RatingRepository::new(PoolHandler::new(pool.clone()));
+    let upload_repository = UploadRepository::new(PoolHandler::new(pool.clone()));
+    let media_repository = MediaRepository::new(PoolHandler::new(pool.clone()));
+    let export_repository = AudiobookExportRepository::new(PoolHandler::new(pool.clone()));
     let user_scope = web::scope("user")
         .service(user_login_page)
         .service(user_login)
@@ -43,9 +58,20 @@ pub fn configure_webapp(pool: &PgPool) -> Box<dyn FnOnce(&mut ServiceConfig)> {
         .service(author_content)
         .service(author_index);
 
+    // resumable uploads (tus protocol), every response carries the protocol version
+    let tus_scope = web::scope("tus")
+        .wrap(DefaultHeaders::new().add(("Tus-Resumable", TUS_VERSION)))
+        .app_data(web::Data::new(upload_repository.clone()))
+        .service(tus_options)
+        .service(tus_create)
+        .service(tus_offset)
+        .service(tus_append)
+        .service(tus_terminate);
+
     let audiobook_scope = web::scope("audiobook")
         .app_data(web::Data::new(genre_repository.clone()))
         .app_data(web::Data::new(chapter_repository.clone()))
+        .app_data(web::Data::new(export_repository))
         .service(create_audiobook)
         .service(upload_audiobook)
         .service(create_audiobook_page)
@@ -53,6 +79,9 @@ pub fn configure_webapp(pool: &PgPool) -> Box<dyn FnOnce(&mut ServiceConfig)> {
         .service(edit_audiobook_page)
         .service(edit_audiobook_content)
         .service(edit_audiobook)
+        .service(replace_audio_page)
+        .service(replace_audio_content)
+        .service(replace_audio)
         .service(upload_audiobook_form)
         .service(get_audiobook)
         .service(manage_audiobook)
@@ -63,22 +92,39 @@ pub fn configure_webapp(pool: &PgPool) -> Box<dyn FnOnce(&mut ServiceConfig)> {
         .service(change_like)
         .service(search)
         .service(set_active_audiobook)
+        .service(sync_positions)
+        .service(dismiss_suggested_position)
+        .service(get_listening_state)
+        .service(change_listening_state)
+        .service(record_listening_session)
+        .service(get_listening_history)
+        .service(get_daily_plays)
         .service(get_last_active_audiobook)
         .service(get_audiobook_detail_content)
         .service(get_audiobook_player)
+        .service(stream_audiobook)
+        .service(stream_audiobook_hls)
         .service(upload_book_cover)
         .service(upload_book_cover_post)
         .service(recommend_audiobooks)
         .service(restore_audiobook)
-        .service(hard_remove_audiobook);
+        .service(hard_remove_audiobook)
+        .service(get_audiobook_export)
+        .service(request_audiobook_export)
+        .service(download_audiobook_export)
+        .service(download_audiobook)
+        .service(tus_scope);
 
     let chapter_scope = web::scope("chapter")
         .app_data(web::Data::new(chapter_repository.clone()))
         .service(audio_selection_for_chapter)
         .service(get_chapter_timeline)
+        .service(get_chapter_retention)
+        .service(get_waveform)
         .service(get_chapter_list)
         .service(create_chapter)
         .service(remove_chapter)
+        .service(accept_chapter_suggestion)
         .service(get_manage_chapter_list);
 
     let genre_scope = web::scope("genre")
@@ -101,6 +147,8 @@ pub fn configure_webapp(pool: &PgPool) -> Box<dyn FnOnce(&mut ServiceConfig)> {
     Box::new(move |cfg: &mut ServiceConfig| {
         cfg.app_data(web::Data::new(user_repository.clone()))
             .app_data(web::Data::new(audiobook_repository.clone()))
+            .app_data(web::Data::new(media_repository.clone()))
+            .app_data(web::Data::from(storage))
             .service(index)
             .service(index_content)
             .service(user_scope)
@@ -110,7 +158,7 @@ pub fn configure_webapp(pool: &PgPool) -> Box<dyn FnOnce(&mut ServiceConfig)> {
             .service(rating_scope)
             .service(library::index)
             .service(library::get_content)
-            .service(ActixFiles::new("/media", "./media").prefer_utf8(true))
+            .service(get_media)
             .service(ActixFiles::new("/static", "./static").prefer_utf8(true))
             .service(studio::studio_index)
             .service(studio::studio_get_content);
@@ -1,8 +1,26 @@
 use crate::database::common::setup_pool;
+use crate::database::common::{DbPoolHandler, DbRepository, PoolHandler};
+use crate::database::repositories::audiobook::repository::AudiobookRepository;
+use crate::database::repositories::chapter::repository::ChapterRepository;
+use crate::database::repositories::export::repository::AudiobookExportRepository;
+use crate::database::repositories::genre::repository::GenreRepository;
+use crate::database::repositories::media::repository::MediaRepository;
+use crate::database::repositories::recommender_queue::repository::RecommenderQueueRepository;
+use crate::database::repositories::upload::repository::UploadRepository;
+use crate::database::repositories::user::repository::UserRepository;
 use crate::init::configure_webapp;
+use crate::media::export::spawn_book_export;
+use crate::media::gc::spawn_media_gc;
+use crate::media::processing::spawn_book_processing;
+use crate::media::signing::MEDIA_SIGNING_KEY;
+use crate::media::silence::SILENCE_DETECTION;
+use crate::media::storage::storage_from_env;
+use crate::media::uploads::spawn_upload_cleanup;
 use crate::recommender::recommender::init_recommender;
+use crate::recommender::registration::spawn_recommender_registration;
 use actix_cors::Cors;
 use actix_identity::IdentityMiddleware;
+use actix_multipart::form::tempfile::TempFileConfig;
 use actix_multipart::form::MultipartFormConfig;
 use actix_session::config::PersistentSession;
 use actix_session::{storage::CookieSessionStore, SessionMiddleware};
@@ -14,11 +32,13 @@ use env_logger::Env;
 use log::{info, warn};
 use std::env;
 
+mod cli;
 mod database;
 mod error;
 mod forms;
 mod handlers;
 mod init;
+mod media;
 mod recommender;
 mod templates;
 const DEFAULT_HOSTNAME: &str = "localhost";
@@ -29,6 +49,59 @@ const CONSIDER_AUDIOBOOK_FINISHED_PERCENTAGE: f64 = 98.0;
 const RECOMMEND_BOOKS_CNT: i32 = 3;
 
 const MIN_PASS_LEN: usize = 6;
+/// Signed media links are valid for at least this many seconds (and at most twice as long)
+const MEDIA_URL_VALIDITY: u64 = 6 * 60 * 60;
+/// Silences quieter than this (in dBFS) and at least this many seconds long are suggested
+/// as chapter beginnings, `SILENCE_THRESHOLD_DB` and `SILENCE_MIN_DURATION` override them
+const DEFAULT_SILENCE_THRESHOLD_DB: f64 = -40.0;
+const DEFAULT_SILENCE_MIN_DURATION: f64 = 2.0;
+/// Media without references are kept for this many seconds after they were last uploaded,
+/// so that books and users that are being created can still point to them
+const UNREFERENCED_MEDIA_GRACE_PERIOD: i64 = 60 * 60;
+/// Resumable uploads expire this many seconds after their last chunk arrived
+const UPLOAD_EXPIRATION: i64 = 60 * 60 * 24;
+/// Expired uploads are removed every this many seconds
+const UPLOAD_CLEANUP_INTERVAL: u64 = 60 * 60;
+/// Stored files and media without references are removed by the garbage collection once
+/// they are this many seconds old, `--grace-period` of the `gc` command overrides it
+const MEDIA_GC_GRACE_PERIOD: i64 = 60 * 60 * 24;
+/// The garbage collection of the media storage runs every this many seconds
+const MEDIA_GC_INTERVAL: u64 = 60 * 60 * 24;
+/// Books waiting to be added to the recommender are looked for every this many seconds,
+/// failed attempts are retried after at most `RECOMMENDER_RETRY_MAX_DELAY` seconds
+const RECOMMENDER_QUEUE_INTERVAL: u64 = 60;
+const RECOMMENDER_RETRY_MAX_DELAY: i64 = 60 * 60 * 6;
+/// Uploaded books are looked for every this many seconds, a book whose processing takes
+/// longer than `BOOK_PROCESSING_TIMEOUT` seconds fails, or is processed again if it was
+/// interrupted
+const BOOK_PROCESSING_INTERVAL: u64 = 60;
+const BOOK_PROCESSING_TIMEOUT: i64 = 60 * 60 * 2;
+/// Requested exports are looked for every this many seconds, an export that takes longer
+/// than `BOOK_EXPORT_TIMEOUT` seconds fails, or is run again if it was interrupted
+const BOOK_EXPORT_INTERVAL: u64 = 60;
+const BOOK_EXPORT_TIMEOUT: i64 = 60 * 60;
+/// Suggestions closer than this many seconds to an existing chapter are not offered
+const CHAPTER_SUGGESTION_MIN_DISTANCE: f64 = 5.0;
+/// Players report listening sessions no longer than this many seconds
+const MAX_LISTENING_SESSION: f64 = 60.0 * 60.0 * 24.0;
+const LISTENING_HISTORY_PAGE_SIZE: i64 = 20;
+/// Another device moving the position back by more than this many seconds does not lose
+/// the position, it is offered to jump back to
+const PROGRESS_BACKWARD_JUMP: f64 = 60.0;
+/// Positions that lose are offered to jump back to if they are this many seconds away
+const PROGRESS_SUGGESTION_DISTANCE: f64 = 30.0;
+/// Positions queued by an offline device are sent in batches of at most this many
+const PROGRESS_SYNC_BATCH_LIMIT: usize = 1000;
+const MAX_DEVICE_ID_LEN: usize = 64;
+/// A book (or a chapter) is played once a listener hears this many seconds of it in a day,
+/// or this percentage of it if that is less; every listener plays it at most once a day
+const PLAY_MIN_LISTENED_SECONDS: f64 = 30.0;
+const PLAY_MIN_LISTENED_PERCENTAGE: f64 = 50.0;
+/// Players play books at most this many times faster, a listening session does not count
+/// more of the book than could be heard at this rate in its duration
+const MAX_PLAYBACK_RATE: f64 = 4.0;
+/// Authors see the plays of their books per day for this many days
+const PLAY_STATS_DAYS: i32 = 30;
 
 pub mod recommender_grpc_api {
     tonic::include_proto!("recommender");
@@ -36,26 +109,10 @@ pub mod recommender_grpc_api {
 
 #[actix_web::main]
 async fn main() -> anyhow::Result<()> {
-    // We are forced to change the TMP dir because TmpFile that is used in Actix multipart stores uploaded files in the /tmp dir.
-    // by default, and I was not able to alter the default configuration,
-    // then, after calling function persist, it uses the rename(2) syscall to unlink the file from /tmp and link
-    // it to another folder. However, this syscall fails on an attempt to move the file across file system boundaries.
-    // On many distros /tmp uses tmpfs and is mounted separately. Also, we are deploying in Kubernetes, and while the /tmp
-    // dir is not mounted separately, we use persistent volume claims to take advantage of the large NFS storage,
-    // so the target file path is on a different FS as well.
-    env::set_var("TMPDIR", "./media");
-    let _dir = env::temp_dir();
-
     let pool = setup_pool(10_u32).await?;
     let host = parse_host();
     let host2 = host.clone();
 
-    let key = Key::from(
-        &env::var("COOKIE_SESSION_KEY")
-            .unwrap_or_default()
-            .bytes()
-            .collect::<Vec<u8>>(),
-    );
     env_logger::init_from_env(Env::default().default_filter_or("info"));
 
     let use_secure_cookie = env::var("USE_SECURE_COOKIE")
@@ -66,6 +123,58 @@ async fn main() -> anyhow::Result<()> {
     if let Err(e) = dotenvy::dotenv() {
         warn!("failed loading .env file: {e}");
     };
+    lazy_static::initialize(&MEDIA_SIGNING_KEY);
+    lazy_static::initialize(&SILENCE_DETECTION);
+
+    let storage = storage_from_env()?;
+    let media_repo = MediaRepository::new(PoolHandler::new(pool.clone()));
+    let mut args = env::args().skip(1);
+    match args.next().as_deref() {
+        None => {}
+        Some("gc") => return cli::media_gc(args, storage.as_ref(), &media_repo).await,
+        Some("quota") => {
+            return cli::storage_quota(args, &UserRepository::new(PoolHandler::new(pool))).await
+        }
+        Some(other) => anyhow::bail!("unknown command {other}, use gc, quota or no command"),
+    }
+
+    let key = Key::from(
+        &env::var("COOKIE_SESSION_KEY")
+            .unwrap_or_default()
+            .bytes()
+            .collect::<Vec<u8>>(),
+    );
+
+    // Uploads are buffered next to the media if they are stored locally, so that they can be
+    // moved in place, as rename(2) fails across file system boundaries (/tmp is often tmpfs,
+    // and in Kubernetes the media are on an NFS-backed persistent volume claim).
+    let mut upload_config = TempFileConfig::default();
+    if let Some(directory) = storage.upload_directory() {
+        std::fs::create_dir_all(&directory)?;
+        upload_config = upload_config.directory(directory);
+    }
+    let sessions = AudiobookRepository::new(PoolHandler::new(pool.clone()))
+        .backfill_plays()
+        .await?;
+    if sessions > 0 {
+        info!("counted the plays of {sessions} listening sessions");
+    }
+    spawn_upload_cleanup(
+        UploadRepository::new(PoolHandler::new(pool.clone())),
+        storage.clone(),
+    );
+    spawn_media_gc(media_repo, storage.clone(), MEDIA_GC_GRACE_PERIOD)?;
+    spawn_book_processing(
+        storage.clone(),
+        AudiobookRepository::new(PoolHandler::new(pool.clone())),
+        ChapterRepository::new(PoolHandler::new(pool.clone())),
+    );
+    spawn_book_export(
+        storage.clone(),
+        AudiobookExportRepository::new(PoolHandler::new(pool.clone())),
+        AudiobookRepository::new(PoolHandler::new(pool.clone())),
+        ChapterRepository::new(PoolHandler::new(pool.clone())),
+    );
     info!("starting server on {host}");
 
     if let Err(err) = init_recommender(&pool).await {
@@ -73,6 +182,12 @@ async fn main() -> anyhow::Result<()> {
     } else {
         info!("initialization of grpc server was successful")
     };
+    // the queue is processed after the initialization, so that it does not overwrite additions
+    spawn_recommender_registration(
+        RecommenderQueueRepository::new(PoolHandler::new(pool.clone())),
+        AudiobookRepository::new(PoolHandler::new(pool.clone())),
+        GenreRepository::new(PoolHandler::new(pool.clone())),
+    );
 
     HttpServer::new(move || {
         App::new()
@@ -81,6 +196,7 @@ async fn main() -> anyhow::Result<()> {
                     .total_limit(PAYLOAD_LIMIT)
                     .memory_limit(PAYLOAD_LIMIT),
             )
+            .app_data(upload_config.clone())
             .app_data(PayloadConfig::new(PAYLOAD_LIMIT))
             .wrap(IdentityMiddleware::default())
             .wrap(
@@ -102,7 +218,7 @@ async fn main() -> anyhow::Result<()> {
                     .max_age(3600),
             )
             .wrap(Logger::default())
-            .configure(configure_webapp(&pool))
+            .configure(configure_webapp(&pool, storage.clone()))
     })
     .bind(host2)?
     .run()
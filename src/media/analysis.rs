@@ -0,0 +1,38 @@
+use crate::database::models::chapter::ChapterSuggestionCreate;
+use crate::database::models::Id;
+use crate::database::repositories::chapter::repository::ChapterRepository;
+use crate::media::silence::SILENCE_DETECTION;
+use crate::media::storage::Storage;
+use crate::media::waveform::{generate_waveform, waveform_path, Waveform};
+
+/// Decodes the uploaded book, stores its waveform and suggests chapter beginnings
+/// in the long silences found in it. Returns the number of suggestions.
+pub async fn analyze_audio(
+    storage: &dyn Storage,
+    chapter_repo: &ChapterRepository,
+    audiobook_id: Id,
+    file_path: &str,
+) -> anyhow::Result<usize> {
+    let waveform = store_waveform(storage, file_path)
+        .await
+        .map_err(|err| anyhow::anyhow!("could not create the waveform: {err}"))?;
+    let suggestions: Vec<ChapterSuggestionCreate> = SILENCE_DETECTION
+        .detect(&waveform)
+        .iter()
+        .map(|silence| ChapterSuggestionCreate::new(silence.middle(), silence.length()))
+        .collect();
+    let suggestions = chapter_repo
+        .replace_suggestions(audiobook_id, &suggestions)
+        .await
+        .map_err(|err| anyhow::anyhow!("could not store the chapter suggestions: {err}"))?;
+    Ok(suggestions.len())
+}
+
+async fn store_waveform(storage: &dyn Storage, file_path: &str) -> std::io::Result<Waveform> {
+    let audio = storage.local_copy(file_path).await?;
+    let waveform = generate_waveform(audio.path()).await?;
+    storage
+        .put(&waveform_path(file_path), waveform.to_dat())
+        .await?;
+    Ok(waveform)
+}
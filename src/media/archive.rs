@@ -0,0 +1,409 @@
+use crate::media::storage::ByteStream;
+use bytes::{BufMut, Bytes, BytesMut};
+use chrono::{DateTime, Datelike, Timelike, Utc};
+use futures_util::stream::{self, StreamExt};
+use std::io::{Error, ErrorKind};
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::Arc;
+
+const LOCAL_HEADER_SIGNATURE: u32 = 0x0403_4b50;
+const DATA_DESCRIPTOR_SIGNATURE: u32 = 0x0807_4b50;
+const CENTRAL_HEADER_SIGNATURE: u32 = 0x0201_4b50;
+const ZIP64_END_SIGNATURE: u32 = 0x0606_4b50;
+const ZIP64_LOCATOR_SIGNATURE: u32 = 0x0706_4b50;
+const END_SIGNATURE: u32 = 0x0605_4b50;
+const ZIP64_EXTRA_ID: u16 = 0x0001;
+const VERSION: u16 = 20;
+const ZIP64_VERSION: u16 = 45;
+/// The checksum and the sizes follow the data of the entry in a data descriptor
+const DATA_DESCRIPTOR_FLAG: u16 = 1 << 3;
+const UTF8_NAME_FLAG: u16 = 1 << 11;
+/// Sizes and offsets from this value on are stored in the ZIP64 extra field
+const ZIP64_LIMIT: u64 = u32::MAX as u64;
+
+struct ZipEntry {
+    name: String,
+    size: u64,
+    offset: u64,
+    flags: u16,
+    /// known once the data of the entry were streamed
+    crc32: Arc<AtomicU32>,
+}
+
+impl ZipEntry {
+    fn is_zip64(&self) -> bool {
+        self.size >= ZIP64_LIMIT
+    }
+}
+
+/// ZIP archive which is streamed as it is written, without being stored anywhere. Entries
+/// are stored as they are, so the size of the archive is known before it is written.
+///
+/// The checksums of streamed entries are only known once their data were streamed, they
+/// are written in data descriptors after the data and in the central directory.
+pub struct ZipStream {
+    parts: Vec<ByteStream>,
+    entries: Vec<ZipEntry>,
+    size: u64,
+    modified: (u16, u16),
+}
+
+impl ZipStream {
+    /// Entries are marked as modified at `modified`
+    #[must_use]
+    pub fn new(modified: &DateTime<Utc>) -> Self {
+        Self {
+            parts: Vec::new(),
+            entries: Vec::new(),
+            size: 0,
+            modified: dos_date_time(modified),
+        }
+    }
+
+    /// Adds an entry whose contents are known
+    pub fn add_bytes(&mut self, name: &str, contents: Vec<u8>) {
+        let crc32 = crc32fast::hash(&contents);
+        let size = contents.len() as u64;
+        let header = self.start_entry(name, size, 0, crc32);
+        self.push(header);
+        self.push(contents);
+    }
+
+    /// Adds an entry of `size` bytes streamed from `data`, the archive fails if `data`
+    /// does not have that size
+    pub fn add_stream(&mut self, name: &str, size: u64, data: ByteStream) {
+        let header = self.start_entry(name, size, DATA_DESCRIPTOR_FLAG, 0);
+        self.push(header);
+        let checksum = self.entries[self.entries.len() - 1].crc32.clone();
+        let zip64 = size >= ZIP64_LIMIT;
+        self.size += size + data_descriptor_len(zip64);
+
+        let state = Some((data, crc32fast::Hasher::new(), 0_u64));
+        let entry = stream::unfold(state, move |state| {
+            let checksum = checksum.clone();
+            async move {
+                let (mut data, mut hasher, streamed) = state?;
+                match data.next().await {
+                    Some(Ok(chunk)) if streamed + chunk.len() as u64 <= size => {
+                        hasher.update(&chunk);
+                        let streamed = streamed + chunk.len() as u64;
+                        Some((Ok(chunk), Some((data, hasher, streamed))))
+                    }
+                    Some(Err(err)) => Some((Err(err), None)),
+                    Some(Ok(_)) => Some((Err(size_mismatch()), None)),
+                    None if streamed != size => Some((Err(size_mismatch()), None)),
+                    None => {
+                        let crc32 = hasher.finalize();
+                        checksum.store(crc32, Ordering::Release);
+                        Some((Ok(data_descriptor(crc32, size, zip64)), None))
+                    }
+                }
+            }
+        });
+        self.parts.push(entry.boxed());
+    }
+
+    /// Finishes the archive with the central directory, returns the stream of the archive
+    /// and its size
+    #[must_use]
+    pub fn finish(self) -> (ByteStream, u64) {
+        let Self {
+            mut parts,
+            entries,
+            size,
+            modified,
+        } = self;
+        let directory_len: u64 = entries.iter().map(central_header_len).sum();
+        let zip64 = size >= ZIP64_LIMIT || directory_len >= ZIP64_LIMIT || entries.len() >= 0xffff;
+        let end_len = if zip64 { 56 + 20 + 22 } else { 22 };
+        let total = size + directory_len + end_len;
+
+        // the central directory is written once the checksums of all entries are known
+        parts.push(
+            stream::once(async move { Ok(central_directory(&entries, size, modified, zip64)) })
+                .boxed(),
+        );
+        (stream::iter(parts).flatten().boxed(), total)
+    }
+
+    fn start_entry(&mut self, name: &str, size: u64, flags: u16, crc32: u32) -> BytesMut {
+        let entry = ZipEntry {
+            name: name.to_string(),
+            size,
+            offset: self.size,
+            flags: flags | UTF8_NAME_FLAG,
+            crc32: Arc::new(AtomicU32::new(crc32)),
+        };
+        let header = local_header(&entry, self.modified);
+        self.entries.push(entry);
+        header
+    }
+
+    fn push(&mut self, part: impl Into<Bytes>) {
+        let part = part.into();
+        self.size += part.len() as u64;
+        self.parts
+            .push(stream::once(async move { Ok(part) }).boxed());
+    }
+}
+
+fn size_mismatch() -> Error {
+    Error::new(
+        ErrorKind::InvalidData,
+        "the entry does not have the announced size",
+    )
+}
+
+/// Date and time in the MS-DOS format, `(time, date)`
+fn dos_date_time(timestamp: &DateTime<Utc>) -> (u16, u16) {
+    if timestamp.year() < 1980 {
+        return (0, (1 << 5) | 1);
+    }
+    let time = (timestamp.hour() << 11) | (timestamp.minute() << 5) | (timestamp.second() / 2);
+    let date = (((timestamp.year() - 1980).min(127) as u32) << 9)
+        | (timestamp.month() << 5)
+        | timestamp.day();
+    (time as u16, date as u16)
+}
+
+fn local_header(entry: &ZipEntry, (time, date): (u16, u16)) -> BytesMut {
+    let zip64 = entry.is_zip64();
+    let streamed = entry.flags & DATA_DESCRIPTOR_FLAG != 0;
+    let mut header = BytesMut::new();
+    header.put_u32_le(LOCAL_HEADER_SIGNATURE);
+    header.put_u16_le(if zip64 { ZIP64_VERSION } else { VERSION });
+    header.put_u16_le(entry.flags);
+    // stored
+    header.put_u16_le(0);
+    header.put_u16_le(time);
+    header.put_u16_le(date);
+    header.put_u32_le(entry.crc32.load(Ordering::Acquire));
+    let size = match (zip64, streamed) {
+        (true, _) => u32::MAX,
+        (false, true) => 0,
+        (false, false) => entry.size as u32,
+    };
+    header.put_u32_le(size);
+    header.put_u32_le(size);
+    header.put_u16_le(entry.name.len() as u16);
+    header.put_u16_le(if zip64 { 20 } else { 0 });
+    header.put_slice(entry.name.as_bytes());
+    if zip64 {
+        // the sizes of streamed entries are in the data descriptor
+        let size = if streamed { 0 } else { entry.size };
+        header.put_u16_le(ZIP64_EXTRA_ID);
+        header.put_u16_le(16);
+        header.put_u64_le(size);
+        header.put_u64_le(size);
+    }
+    header
+}
+
+const fn data_descriptor_len(zip64: bool) -> u64 {
+    if zip64 {
+        24
+    } else {
+        16
+    }
+}
+
+fn data_descriptor(crc32: u32, size: u64, zip64: bool) -> Bytes {
+    let mut descriptor = BytesMut::new();
+    descriptor.put_u32_le(DATA_DESCRIPTOR_SIGNATURE);
+    descriptor.put_u32_le(crc32);
+    if zip64 {
+        descriptor.put_u64_le(size);
+        descriptor.put_u64_le(size);
+    } else {
+        descriptor.put_u32_le(size as u32);
+        descriptor.put_u32_le(size as u32);
+    }
+    descriptor.freeze()
+}
+
+/// Sizes and offsets that do not fit are in the ZIP64 extra field, in this order
+fn zip64_fields(entry: &ZipEntry) -> Vec<u64> {
+    let mut fields = Vec::new();
+    if entry.is_zip64() {
+        fields.extend([entry.size, entry.size]);
+    }
+    if entry.offset >= ZIP64_LIMIT {
+        fields.push(entry.offset);
+    }
+    fields
+}
+
+fn central_header_len(entry: &ZipEntry) -> u64 {
+    let extra = match zip64_fields(entry).len() {
+        0 => 0,
+        fields => 4 + 8 * fields,
+    };
+    (46 + entry.name.len() + extra) as u64
+}
+
+fn central_directory(
+    entries: &[ZipEntry],
+    offset: u64,
+    (time, date): (u16, u16),
+    zip64: bool,
+) -> Bytes {
+    let mut directory = BytesMut::new();
+    for entry in entries {
+        let fields = zip64_fields(entry);
+        let version = if fields.is_empty() {
+            VERSION
+        } else {
+            ZIP64_VERSION
+        };
+        directory.put_u32_le(CENTRAL_HEADER_SIGNATURE);
+        directory.put_u16_le(version);
+        directory.put_u16_le(version);
+        directory.put_u16_le(entry.flags);
+        directory.put_u16_le(0);
+        directory.put_u16_le(time);
+        directory.put_u16_le(date);
+        directory.put_u32_le(entry.crc32.load(Ordering::Acquire));
+        let size = entry.size.min(ZIP64_LIMIT) as u32;
+        directory.put_u32_le(size);
+        directory.put_u32_le(size);
+        directory.put_u16_le(entry.name.len() as u16);
+        directory.put_u16_le(match fields.len() {
+            0 => 0,
+            fields => 4 + 8 * fields as u16,
+        });
+        // comment, disk, internal and external attributes
+        directory.put_u16_le(0);
+        directory.put_u16_le(0);
+        directory.put_u16_le(0);
+        directory.put_u32_le(0);
+        directory.put_u32_le(entry.offset.min(ZIP64_LIMIT) as u32);
+        directory.put_slice(entry.name.as_bytes());
+        if !fields.is_empty() {
+            directory.put_u16_le(ZIP64_EXTRA_ID);
+            directory.put_u16_le(8 * fields.len() as u16);
+            for field in fields {
+                directory.put_u64_le(field);
+            }
+        }
+    }
+
+    let directory_len = directory.len() as u64;
+    let count = entries.len() as u64;
+    if zip64 {
+        let end_offset = offset + directory_len;
+        directory.put_u32_le(ZIP64_END_SIGNATURE);
+        // size of the rest of the record
+        directory.put_u64_le(44);
+        directory.put_u16_le(ZIP64_VERSION);
+        directory.put_u16_le(ZIP64_VERSION);
+        directory.put_u32_le(0);
+        directory.put_u32_le(0);
+        directory.put_u64_le(count);
+        directory.put_u64_le(count);
+        directory.put_u64_le(directory_len);
+        directory.put_u64_le(offset);
+
+        directory.put_u32_le(ZIP64_LOCATOR_SIGNATURE);
+        directory.put_u32_le(0);
+        directory.put_u64_le(end_offset);
+        directory.put_u32_le(1);
+    }
+    directory.put_u32_le(END_SIGNATURE);
+    directory.put_u16_le(0);
+    directory.put_u16_le(0);
+    directory.put_u16_le(count.min(0xffff) as u16);
+    directory.put_u16_le(count.min(0xffff) as u16);
+    directory.put_u32_le(directory_len.min(ZIP64_LIMIT) as u32);
+    directory.put_u32_le(offset.min(ZIP64_LIMIT) as u32);
+    // comment
+    directory.put_u16_le(0);
+    directory.freeze()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+    use futures_util::TryStreamExt;
+    use std::io::{Cursor, Read};
+    use zip::ZipArchive;
+
+    fn chunks(chunks: &[&'static [u8]]) -> ByteStream {
+        stream::iter(
+            chunks
+                .iter()
+                .map(|chunk| Ok(Bytes::from_static(chunk)))
+                .collect::<Vec<_>>(),
+        )
+        .boxed()
+    }
+
+    async fn collect(archive: ZipStream) -> std::io::Result<(Vec<u8>, u64)> {
+        let (stream, size) = archive.finish();
+        let chunks: Vec<Bytes> = stream.try_collect().await?;
+        Ok((chunks.concat(), size))
+    }
+
+    #[tokio::test]
+    async fn streamed_archive() {
+        let modified = Utc
+            .with_ymd_and_hms(2024, 5, 17, 13, 45, 30)
+            .single()
+            .expect("The time should be valid");
+        let mut archive = ZipStream::new(&modified);
+        archive.add_stream("audio.mp3", 11, chunks(&[b"first", b"", b"second"]));
+        archive.add_bytes("chapters.json", b"{\"chapters\":[]}".to_vec());
+        archive.add_stream("cover.png", 0, chunks(&[]));
+        archive.add_bytes("kapitoly/první.txt", "žluťoučký kůň".as_bytes().to_vec());
+        let (data, size) = collect(archive)
+            .await
+            .expect("Stream the archive should succeed");
+        assert_eq!(data.len() as u64, size);
+
+        // the checksums are verified once the entries are read to the end
+        let mut archive = ZipArchive::new(Cursor::new(data)).expect("Open the archive");
+        let expected: [(&str, &[u8]); 4] = [
+            ("audio.mp3", b"firstsecond"),
+            ("chapters.json", b"{\"chapters\":[]}"),
+            ("cover.png", b""),
+            ("kapitoly/první.txt", "žluťoučký kůň".as_bytes()),
+        ];
+        assert_eq!(archive.len(), expected.len());
+        for (index, (name, contents)) in expected.into_iter().enumerate() {
+            let mut entry = archive.by_index(index).expect("Read the entry");
+            assert_eq!(entry.name(), name);
+            assert_eq!(entry.size(), contents.len() as u64);
+            let mut read = Vec::new();
+            entry
+                .read_to_end(&mut read)
+                .expect("Read the entry to the end");
+            assert_eq!(read, contents);
+            let modified = entry.last_modified();
+            assert_eq!(
+                (modified.year(), modified.month(), modified.day()),
+                (2024, 5, 17)
+            );
+            assert_eq!(
+                (modified.hour(), modified.minute(), modified.second()),
+                (13, 45, 30)
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn streamed_entries_of_the_wrong_size() {
+        for data in [&[b"short" as &[u8]][..], &[b"too long", b"!"]] {
+            let mut archive = ZipStream::new(&Utc::now());
+            archive.add_stream("audio.mp3", 8, chunks(data));
+            assert!(collect(archive).await.is_err());
+        }
+
+        let mut archive = ZipStream::new(&Utc::now());
+        archive.add_stream(
+            "audio.mp3",
+            8,
+            stream::iter([Err(Error::other("storage failed"))]).boxed(),
+        );
+        assert!(collect(archive).await.is_err());
+    }
+}
@@ -0,0 +1,180 @@
+use crate::database::models::audiobook::AudiobookDetail;
+use crate::database::models::chapter::Chapter;
+use crate::database::models::download::AudiobookDownload;
+use crate::database::models::Id;
+use crate::media::archive::ZipStream;
+use crate::media::storage::{not_found, ByteStream, Storage};
+use chrono::{DateTime, SecondsFormat, Utc};
+use serde::Serialize;
+use std::path::Path;
+
+const SIMPLE_CHAPTERS_VERSION: &str = "1.2";
+
+/// Chapters in the JSON representation of Podlove Simple Chapters
+#[derive(Serialize)]
+struct SimpleChapters<'a> {
+    version: &'static str,
+    chapters: Vec<SimpleChapter<'a>>,
+}
+
+#[derive(Serialize)]
+struct SimpleChapter<'a> {
+    /// normal play time, `HH:MM:SS.mmm`
+    start: String,
+    title: &'a str,
+}
+
+/// Contents of `metadata.json`, the download identifies the audio the listener reports
+/// their progress in
+#[derive(Serialize)]
+struct BundleMetadata<'a> {
+    audiobook: BookMetadata<'a>,
+    download: DownloadMetadata,
+}
+
+/// The details of the book, files are referred to by their names in the bundle and the email
+/// of the author is left out
+#[derive(Serialize)]
+struct BookMetadata<'a> {
+    id: Id,
+    name: &'a str,
+    author_id: Id,
+    genre_id: Id,
+    audio: &'a str,
+    cover: Option<&'a str>,
+    length: f64,
+    stream_count: i64,
+    like_count: i64,
+    overall_rating: f64,
+    description: &'a str,
+    created_at: String,
+    edited_at: String,
+    username: &'a str,
+    author_name: &'a str,
+    surname: &'a str,
+    bio: &'a str,
+    genre_name: &'a str,
+    genre_color: &'a str,
+    playback_position: Option<f64>,
+    is_liked: bool,
+}
+
+#[derive(Serialize)]
+struct DownloadMetadata {
+    id: Id,
+    downloaded_at: String,
+}
+
+fn format_timestamp(timestamp: &DateTime<Utc>) -> String {
+    timestamp.to_rfc3339_opts(SecondsFormat::Secs, true)
+}
+
+fn normal_play_time(position: f64) -> String {
+    let milliseconds = (position.max(0.0) * 1000.0).round() as u64;
+    format!(
+        "{:02}:{:02}:{:02}.{:03}",
+        milliseconds / 3_600_000,
+        milliseconds / 60_000 % 60,
+        milliseconds / 1000 % 60,
+        milliseconds % 1000
+    )
+}
+
+fn bundle_file_name(name: &str, stored_path: &str) -> String {
+    match Path::new(stored_path)
+        .extension()
+        .and_then(|extension| extension.to_str())
+    {
+        Some(extension) => format!("{name}.{extension}"),
+        None => name.to_string(),
+    }
+}
+
+fn chapters_json(chapters: &[Chapter]) -> serde_json::Result<Vec<u8>> {
+    serde_json::to_vec_pretty(&SimpleChapters {
+        version: SIMPLE_CHAPTERS_VERSION,
+        chapters: chapters
+            .iter()
+            .map(|chapter| SimpleChapter {
+                start: normal_play_time(chapter.position),
+                title: &chapter.name,
+            })
+            .collect(),
+    })
+}
+
+fn metadata_json(
+    book: &AudiobookDetail,
+    audio: &str,
+    cover: Option<&str>,
+    download: &AudiobookDownload,
+) -> serde_json::Result<Vec<u8>> {
+    serde_json::to_vec_pretty(&BundleMetadata {
+        audiobook: BookMetadata {
+            id: book.id,
+            name: &book.name,
+            author_id: book.author_id,
+            genre_id: book.genre_id,
+            audio,
+            cover,
+            length: book.length,
+            stream_count: book.stream_count,
+            like_count: book.like_count,
+            overall_rating: book.overall_rating,
+            description: &book.description,
+            created_at: format_timestamp(&book.created_at),
+            edited_at: format_timestamp(&book.edited_at),
+            username: &book.username,
+            author_name: &book.author_name,
+            surname: &book.surname,
+            bio: &book.bio,
+            genre_name: &book.genre_name,
+            genre_color: &book.genre_color,
+            playback_position: book.playback_position,
+            is_liked: book.is_liked,
+        },
+        download: DownloadMetadata {
+            id: download.id,
+            downloaded_at: format_timestamp(&download.downloaded_at),
+        },
+    })
+}
+
+/// Creates the offline download bundle of the book: a ZIP archive with the audio, the cover,
+/// `chapters.json` in the Podlove Simple Chapters format and `metadata.json` with the details
+/// of the book and the download. The archive is streamed from the storage as it is written.
+/// Returns the stream of the archive and its size.
+pub async fn download_bundle(
+    storage: &dyn Storage,
+    book: &AudiobookDetail,
+    chapters: &[Chapter],
+    download: &AudiobookDownload,
+) -> std::io::Result<(ByteStream, u64)> {
+    let audio_name = bundle_file_name("audio", &book.file_path);
+    let cover_name = book
+        .thumbnail
+        .as_deref()
+        .map(|thumbnail| bundle_file_name("cover", thumbnail));
+    let mut media = vec![(audio_name.as_str(), book.file_path.as_str())];
+    if let (Some(name), Some(thumbnail)) = (&cover_name, &book.thumbnail) {
+        media.push((name, thumbnail));
+    }
+
+    let mut archive = ZipStream::new(&download.downloaded_at);
+    for (name, path) in media {
+        let Some(meta) = storage.head(path).await? else {
+            return Err(not_found(path));
+        };
+        archive.add_stream(
+            name,
+            meta.size,
+            storage.get_range(path, 0..meta.size).await?,
+        );
+    }
+    archive.add_bytes("chapters.json", chapters_json(chapters)?);
+    archive.add_bytes(
+        "metadata.json",
+        metadata_json(book, &audio_name, cover_name.as_deref(), download)?,
+    );
+    Ok(archive.finish())
+}
@@ -0,0 +1,897 @@
+use lofty::{FileType, ItemKey, TagType, TaggedFile, TaggedFileExt};
+use std::collections::HashSet;
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+
+const ID3V2_HEADER_LEN: usize = 10;
+const MP4_BOX_HEADER_LEN: u64 = 8;
+/// Nero `chpl` timestamps are stored in 100 ns units
+const CHPL_TIMESCALE: f64 = 10_000_000.0;
+/// Upper bound of samples read from a chapter track, guards against corrupted sample tables
+const MAX_CHAPTER_SAMPLES: usize = 10_000;
+
+/// Chapter marker embedded in an uploaded audio file
+#[derive(Debug, Clone, PartialEq)]
+pub struct EmbeddedChapter {
+    pub name: String,
+    pub position: f64,
+}
+
+impl EmbeddedChapter {
+    pub fn new(name: &str, position: f64) -> Self {
+        Self {
+            name: name.trim().to_string(),
+            position,
+        }
+    }
+}
+
+/// Reads chapter markers stored in the file, supported are:
+/// - MP4/M4B Nero `chpl` atoms and QuickTime chapter tracks
+/// - ID3v2 `CHAP` frames (limited to those listed in a `CTOC` frame if there is any)
+/// - Vorbis comments in the `CHAPTERxx` and `CHAPTERxxNAME` form
+///
+/// The returned chapters are ordered by their position, unnamed chapters are numbered.
+pub fn read_embedded_chapters(
+    file: &mut File,
+    tagged_file: &TaggedFile,
+) -> std::io::Result<Vec<EmbeddedChapter>> {
+    file.rewind()?;
+    let mut chapters = match tagged_file.file_type() {
+        FileType::Mpeg => read_id3v2_chapters(file)?,
+        FileType::Mp4 => read_mp4_chapters(file)?,
+        FileType::Vorbis | FileType::Opus | FileType::Flac | FileType::Speex => {
+            read_vorbis_chapters(tagged_file)
+        }
+        _ => Vec::new(),
+    };
+    chapters.retain(|chapter| chapter.position.is_finite() && chapter.position >= 0.0);
+    chapters.sort_by(|a, b| a.position.total_cmp(&b.position));
+    chapters.dedup_by(|a, b| a.position == b.position);
+    for (index, chapter) in chapters.iter_mut().enumerate() {
+        if chapter.name.is_empty() {
+            chapter.name = format!("Chapter {}", index + 1);
+        }
+    }
+    Ok(chapters)
+}
+
+fn read_vorbis_chapters(tagged_file: &TaggedFile) -> Vec<EmbeddedChapter> {
+    let Some(tag) = tagged_file.tag(TagType::VorbisComments) else {
+        return Vec::new();
+    };
+    vorbis_chapters(|key| {
+        tag.items()
+            .find_map(|item| match (item.key(), item.value().text()) {
+                (ItemKey::Unknown(k), Some(value)) if k.eq_ignore_ascii_case(key) => Some(value),
+                _ => None,
+            })
+    })
+}
+
+/// Chapters in the `CHAPTERxx` and `CHAPTERxxNAME` comments, `comment` looks a comment up
+fn vorbis_chapters<'a>(comment: impl Fn(&str) -> Option<&'a str>) -> Vec<EmbeddedChapter> {
+    // chapters are numbered from either 0 or 1 and the numbering has no gaps
+    let first = if comment("CHAPTER000").is_some() {
+        0
+    } else {
+        1
+    };
+    (first..1000)
+        .map(|index| format!("CHAPTER{index:03}"))
+        .map_while(|key| {
+            let position = parse_timestamp(comment(&key)?)?;
+            let name = comment(&format!("{key}NAME")).unwrap_or_default();
+            Some(EmbeddedChapter::new(name, position))
+        })
+        .collect()
+}
+
+/// Parses `HH:MM:SS.sss` timestamps used by Vorbis chapter comments
+fn parse_timestamp(timestamp: &str) -> Option<f64> {
+    timestamp.trim().split(':').try_fold(0f64, |acc, part| {
+        Some(acc * 60.0 + part.parse::<f64>().ok()?)
+    })
+}
+
+fn read_id3v2_chapters(file: &mut File) -> std::io::Result<Vec<EmbeddedChapter>> {
+    let mut header = [0u8; ID3V2_HEADER_LEN];
+    if file.read_exact(&mut header).is_err() || &header[..3] != b"ID3" {
+        return Ok(Vec::new());
+    }
+    let version = header[3];
+    let flags = header[5];
+    // CHAP and CTOC frames were introduced in ID3v2.3
+    if !(3..=4).contains(&version) {
+        return Ok(Vec::new());
+    }
+    let size = syncsafe(&header[6..10]) as usize;
+    let mut data = vec![0u8; size];
+    file.read_exact(&mut data)?;
+    if version == 3 && flags & 0x80 != 0 {
+        data = remove_unsynchronisation(&data);
+    }
+
+    let mut offset = 0;
+    if flags & 0x40 != 0 {
+        offset = match version {
+            3 => be_u32(&data, 0).map_or(data.len(), |len| len as usize + 4),
+            _ => data
+                .get(0..4)
+                .map_or(data.len(), |len| syncsafe(len) as usize),
+        };
+    }
+
+    let mut chapters = Vec::new();
+    let mut listed = HashSet::new();
+    let mut has_toc = false;
+    for (id, content) in Id3v2Frames::new(data.get(offset..).unwrap_or_default(), version) {
+        match &id {
+            b"CHAP" => {
+                if let Some(chapter) = parse_chap_frame(&content, version) {
+                    chapters.push(chapter);
+                }
+            }
+            b"CTOC" => {
+                has_toc = true;
+                listed.extend(parse_ctoc_children(&content));
+            }
+            _ => {}
+        }
+    }
+    if has_toc {
+        chapters.retain(|(element_id, _)| listed.contains(element_id));
+    }
+    Ok(chapters.into_iter().map(|(_, chapter)| chapter).collect())
+}
+
+/// `CHAP` frame: element ID, start and end time in milliseconds, byte offsets and sub-frames
+fn parse_chap_frame(content: &[u8], version: u8) -> Option<(Vec<u8>, EmbeddedChapter)> {
+    let id_end = content.iter().position(|&b| b == 0)?;
+    let element_id = content[..id_end].to_vec();
+    let times = id_end + 1;
+    let start_ms = be_u32(content, times)?;
+    let name = Id3v2Frames::new(content.get(times + 16..).unwrap_or_default(), version)
+        .find(|(id, _)| id == b"TIT2")
+        .map(|(_, text)| decode_id3_text(&text))
+        .unwrap_or_default();
+    Some((
+        element_id,
+        EmbeddedChapter::new(&name, f64::from(start_ms) / 1000.0),
+    ))
+}
+
+/// `CTOC` frame: element ID, flags, entry count and the element IDs of the entries
+fn parse_ctoc_children(content: &[u8]) -> Vec<Vec<u8>> {
+    let Some(id_end) = content.iter().position(|&b| b == 0) else {
+        return Vec::new();
+    };
+    let Some(&count) = content.get(id_end + 2) else {
+        return Vec::new();
+    };
+    content
+        .get(id_end + 3..)
+        .unwrap_or_default()
+        .split(|&b| b == 0)
+        .take(count as usize)
+        .map(<[u8]>::to_vec)
+        .collect()
+}
+
+/// Iterates over ID3v2.3/ID3v2.4 frames, yielding their IDs and decoded contents.
+/// Compressed and encrypted frames are skipped.
+struct Id3v2Frames<'a> {
+    data: &'a [u8],
+    version: u8,
+}
+
+impl<'a> Id3v2Frames<'a> {
+    fn new(data: &'a [u8], version: u8) -> Self {
+        Self { data, version }
+    }
+}
+
+impl Iterator for Id3v2Frames<'_> {
+    type Item = ([u8; 4], Vec<u8>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let header = self.data.get(..ID3V2_HEADER_LEN)?;
+            // padding after the last frame
+            if header[0] == 0 {
+                return None;
+            }
+            let id: [u8; 4] = header[..4].try_into().ok()?;
+            let size = match self.version {
+                4 => syncsafe(&header[4..8]),
+                _ => be_u32(header, 4)?,
+            } as usize;
+            let format_flags = header[9];
+            let content = self.data.get(ID3V2_HEADER_LEN..ID3V2_HEADER_LEN + size)?;
+            self.data = &self.data[ID3V2_HEADER_LEN + size..];
+
+            let content = match self.version {
+                4 => {
+                    // compression or encryption
+                    if format_flags & 0x0C != 0 {
+                        continue;
+                    }
+                    let grouping = usize::from(format_flags & 0x40 != 0);
+                    let data_length = if format_flags & 0x01 != 0 { 4 } else { 0 };
+                    let content = content.get(grouping + data_length..)?;
+                    if format_flags & 0x02 != 0 {
+                        remove_unsynchronisation(content)
+                    } else {
+                        content.to_vec()
+                    }
+                }
+                _ => {
+                    // compression or encryption
+                    if format_flags & 0xC0 != 0 {
+                        continue;
+                    }
+                    let grouping = usize::from(format_flags & 0x20 != 0);
+                    content.get(grouping..)?.to_vec()
+                }
+            };
+            return Some((id, content));
+        }
+    }
+}
+
+fn decode_id3_text(content: &[u8]) -> String {
+    let Some((&encoding, text)) = content.split_first() else {
+        return String::new();
+    };
+    let text = match encoding {
+        0 => text.iter().map(|&b| char::from(b)).collect(),
+        1 | 2 => decode_utf16(text, encoding == 2),
+        _ => String::from_utf8_lossy(text).to_string(),
+    };
+    // text frames may hold several null separated values
+    text.split('\0').next().unwrap_or_default().to_string()
+}
+
+/// Decodes UTF-16 text, the byte order mark takes precedence over `big_endian`
+fn decode_utf16(text: &[u8], big_endian: bool) -> String {
+    let (big_endian, text) = match text {
+        [0xFE, 0xFF, rest @ ..] => (true, rest),
+        [0xFF, 0xFE, rest @ ..] => (false, rest),
+        _ => (big_endian, text),
+    };
+    let units = text.chunks_exact(2).map(|pair| {
+        if big_endian {
+            u16::from_be_bytes([pair[0], pair[1]])
+        } else {
+            u16::from_le_bytes([pair[0], pair[1]])
+        }
+    });
+    char::decode_utf16(units)
+        .map(|c| c.unwrap_or(char::REPLACEMENT_CHARACTER))
+        .collect()
+}
+
+fn remove_unsynchronisation(data: &[u8]) -> Vec<u8> {
+    let mut result = Vec::with_capacity(data.len());
+    let mut previous = 0u8;
+    for &byte in data {
+        if !(previous == 0xFF && byte == 0x00) {
+            result.push(byte);
+        }
+        previous = byte;
+    }
+    result
+}
+
+fn read_mp4_chapters(file: &mut File) -> std::io::Result<Vec<EmbeddedChapter>> {
+    let Some(moov) = read_top_level_box(file, b"moov")? else {
+        return Ok(Vec::new());
+    };
+    let chapters = find_box(&moov, &[b"udta", b"chpl"])
+        .map(parse_chpl)
+        .unwrap_or_default();
+    if !chapters.is_empty() {
+        return Ok(chapters);
+    }
+    read_quicktime_chapter_track(file, &moov)
+}
+
+/// Finds a top level box in the file and reads its contents into memory
+fn read_top_level_box(file: &mut File, name: &[u8; 4]) -> std::io::Result<Option<Vec<u8>>> {
+    let file_len = file.metadata()?.len();
+    let mut position = 0u64;
+    while position + MP4_BOX_HEADER_LEN <= file_len {
+        let mut header = [0u8; MP4_BOX_HEADER_LEN as usize];
+        file.seek(SeekFrom::Start(position))?;
+        file.read_exact(&mut header)?;
+        let (size, header_len) = match u32::from_be_bytes(header[..4].try_into().unwrap()) {
+            0 => (file_len - position, MP4_BOX_HEADER_LEN),
+            1 => {
+                let mut large_size = [0u8; 8];
+                file.read_exact(&mut large_size)?;
+                (u64::from_be_bytes(large_size), MP4_BOX_HEADER_LEN + 8)
+            }
+            size => (u64::from(size), MP4_BOX_HEADER_LEN),
+        };
+        if size < header_len || position + size > file_len {
+            break;
+        }
+        if &header[4..] == name {
+            let mut contents = vec![0u8; (size - header_len) as usize];
+            file.read_exact(&mut contents)?;
+            return Ok(Some(contents));
+        }
+        position += size;
+    }
+    Ok(None)
+}
+
+/// Iterates over the child boxes contained in `data`, yielding their names and contents
+struct Mp4Boxes<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Iterator for Mp4Boxes<'a> {
+    type Item = ([u8; 4], &'a [u8]);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let size = be_u32(self.data, 0)? as usize;
+        let name: [u8; 4] = self.data.get(4..8)?.try_into().ok()?;
+        let (size, header_len) = match size {
+            0 => (self.data.len(), 8),
+            1 => (be_u64(self.data, 8)? as usize, 16),
+            size => (size, 8),
+        };
+        let contents = self.data.get(header_len..size)?;
+        self.data = &self.data[size..];
+        Some((name, contents))
+    }
+}
+
+fn boxes(data: &[u8]) -> Mp4Boxes<'_> {
+    Mp4Boxes { data }
+}
+
+fn find_box<'a>(data: &'a [u8], path: &[&[u8; 4]]) -> Option<&'a [u8]> {
+    path.iter().try_fold(data, |data, name| {
+        boxes(data)
+            .find(|(box_name, _)| box_name == *name)
+            .map(|(_, contents)| contents)
+    })
+}
+
+/// Nero chapter list: version, flags, (reserved), chapter count
+/// and chapters consisting of a start time and a length-prefixed title
+fn parse_chpl(contents: &[u8]) -> Vec<EmbeddedChapter> {
+    let Some(&version) = contents.first() else {
+        return Vec::new();
+    };
+    let mut offset = if version == 0 { 4 } else { 8 };
+    let Some(&count) = contents.get(offset) else {
+        return Vec::new();
+    };
+    offset += 1;
+
+    let mut chapters = Vec::with_capacity(count as usize);
+    for _ in 0..count {
+        let (Some(start), Some(&title_len)) = (be_u64(contents, offset), contents.get(offset + 8))
+        else {
+            break;
+        };
+        let title_start = offset + 9;
+        let Some(title) = contents.get(title_start..title_start + title_len as usize) else {
+            break;
+        };
+        chapters.push(EmbeddedChapter::new(
+            &String::from_utf8_lossy(title),
+            start as f64 / CHPL_TIMESCALE,
+        ));
+        offset = title_start + title_len as usize;
+    }
+    chapters
+}
+
+/// QuickTime chapters are stored as samples of a text track referenced by the `chap` entry
+/// of the audio track's `tref` box. Every sample is a length-prefixed title and the sample
+/// durations determine the chapter positions.
+fn read_quicktime_chapter_track(
+    file: &mut File,
+    moov: &[u8],
+) -> std::io::Result<Vec<EmbeddedChapter>> {
+    let tracks: Vec<&[u8]> = boxes(moov)
+        .filter(|(name, _)| name == b"trak")
+        .map(|(_, contents)| contents)
+        .collect();
+    let chapter_track_ids: HashSet<u32> = tracks
+        .iter()
+        .filter_map(|trak| find_box(trak, &[b"tref", b"chap"]))
+        .flat_map(|chap| {
+            chap.chunks_exact(4)
+                .map(|id| u32::from_be_bytes(id.try_into().unwrap()))
+        })
+        .collect();
+    let Some(chapter_track) = tracks.into_iter().find(|trak| {
+        find_box(trak, &[b"tkhd"])
+            .and_then(track_id)
+            .is_some_and(|id| chapter_track_ids.contains(&id))
+    }) else {
+        return Ok(Vec::new());
+    };
+    let Some(samples) = ChapterSamples::parse(chapter_track) else {
+        return Ok(Vec::new());
+    };
+
+    let mut chapters = Vec::with_capacity(samples.sizes.len());
+    let mut time = 0u64;
+    for ((offset, size), duration) in samples
+        .offsets()
+        .into_iter()
+        .zip(samples.sizes.iter())
+        .zip(samples.durations())
+    {
+        let mut sample = vec![0u8; *size as usize];
+        file.seek(SeekFrom::Start(offset))?;
+        file.read_exact(&mut sample)?;
+        let title_len = be_u16(&sample, 0).unwrap_or_default() as usize;
+        let title = sample.get(2..2 + title_len).unwrap_or_default();
+        let title = match title {
+            [0xFE, 0xFF, ..] | [0xFF, 0xFE, ..] => decode_utf16(title, true),
+            _ => String::from_utf8_lossy(title).to_string(),
+        };
+        chapters.push(EmbeddedChapter::new(
+            &title,
+            time as f64 / f64::from(samples.timescale),
+        ));
+        time += u64::from(duration);
+    }
+    Ok(chapters)
+}
+
+fn track_id(tkhd: &[u8]) -> Option<u32> {
+    match tkhd.first()? {
+        0 => be_u32(tkhd, 12),
+        _ => be_u32(tkhd, 20),
+    }
+}
+
+/// Sample tables of the chapter track
+struct ChapterSamples {
+    timescale: u32,
+    /// (sample count, sample duration)
+    time_to_sample: Vec<(u32, u32)>,
+    /// (first chunk, samples per chunk)
+    sample_to_chunk: Vec<(u32, u32)>,
+    sizes: Vec<u32>,
+    chunk_offsets: Vec<u64>,
+}
+
+impl ChapterSamples {
+    fn parse(trak: &[u8]) -> Option<Self> {
+        let mdhd = find_box(trak, &[b"mdia", b"mdhd"])?;
+        let timescale = match mdhd.first()? {
+            0 => be_u32(mdhd, 12)?,
+            _ => be_u32(mdhd, 20)?,
+        };
+        if timescale == 0 {
+            return None;
+        }
+        let stbl = find_box(trak, &[b"mdia", b"minf", b"stbl"])?;
+        let table = |name: &[u8; 4], entry_len: usize| {
+            let contents = find_box(stbl, &[name])?;
+            let count = be_u32(contents, 4)? as usize;
+            Some((contents.get(8..)?, count.min(contents.len() / entry_len)))
+        };
+
+        let (stts, count) = table(b"stts", 8)?;
+        let time_to_sample = (0..count)
+            .map(|i| Some((be_u32(stts, i * 8)?, be_u32(stts, i * 8 + 4)?)))
+            .collect::<Option<_>>()?;
+        let (stsc, count) = table(b"stsc", 12)?;
+        let sample_to_chunk = (0..count)
+            .map(|i| Some((be_u32(stsc, i * 12)?, be_u32(stsc, i * 12 + 4)?)))
+            .collect::<Option<_>>()?;
+
+        let stsz = find_box(stbl, &[b"stsz"])?;
+        let uniform_size = be_u32(stsz, 4)?;
+        let sample_count = (be_u32(stsz, 8)? as usize).min(MAX_CHAPTER_SAMPLES);
+        let sizes = if uniform_size != 0 {
+            vec![uniform_size; sample_count]
+        } else {
+            (0..sample_count.min(stsz.len() / 4))
+                .map(|i| be_u32(stsz, 12 + i * 4))
+                .collect::<Option<_>>()?
+        };
+
+        let chunk_offsets = match table(b"stco", 4) {
+            Some((stco, count)) => (0..count)
+                .map(|i| be_u32(stco, i * 4).map(u64::from))
+                .collect::<Option<_>>()?,
+            None => {
+                let (co64, count) = table(b"co64", 8)?;
+                (0..count)
+                    .map(|i| be_u64(co64, i * 8))
+                    .collect::<Option<_>>()?
+            }
+        };
+
+        Some(Self {
+            timescale,
+            time_to_sample,
+            sample_to_chunk,
+            sizes,
+            chunk_offsets,
+        })
+    }
+
+    fn durations(&self) -> impl Iterator<Item = u32> + '_ {
+        self.time_to_sample
+            .iter()
+            .flat_map(|&(count, duration)| (0..count).map(move |_| duration))
+    }
+
+    /// File offsets of the samples, computed from the chunk offsets and the sample sizes
+    fn offsets(&self) -> Vec<u64> {
+        let mut sample = 0usize;
+        let mut offsets = Vec::with_capacity(self.sizes.len());
+        for (chunk_index, &chunk_offset) in self.chunk_offsets.iter().enumerate() {
+            let chunk = chunk_index as u32 + 1;
+            let samples_per_chunk = self
+                .sample_to_chunk
+                .iter()
+                .take_while(|(first_chunk, _)| *first_chunk <= chunk)
+                .last()
+                .map_or(1, |(_, samples)| *samples);
+            let mut offset = chunk_offset;
+            for size in self
+                .sizes
+                .iter()
+                .skip(sample)
+                .take(samples_per_chunk as usize)
+            {
+                offsets.push(offset);
+                offset += u64::from(*size);
+            }
+            sample += samples_per_chunk as usize;
+        }
+        offsets
+    }
+}
+
+fn syncsafe(bytes: &[u8]) -> u32 {
+    bytes
+        .iter()
+        .fold(0u32, |acc, b| (acc << 7) | u32::from(b & 0x7F))
+}
+
+fn be_u16(data: &[u8], offset: usize) -> Option<u16> {
+    Some(u16::from_be_bytes(
+        data.get(offset..offset + 2)?.try_into().ok()?,
+    ))
+}
+
+fn be_u32(data: &[u8], offset: usize) -> Option<u32> {
+    Some(u32::from_be_bytes(
+        data.get(offset..offset + 4)?.try_into().ok()?,
+    ))
+}
+
+fn be_u64(data: &[u8], offset: usize) -> Option<u64> {
+    Some(u64::from_be_bytes(
+        data.get(offset..offset + 8)?.try_into().ok()?,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+    use std::io::Write;
+
+    fn file_with(contents: &[u8]) -> File {
+        let mut file = tempfile::tempfile().expect("Create temporary file should succeed");
+        file.write_all(contents)
+            .expect("Write temporary file should succeed");
+        file.rewind().expect("Rewind temporary file should succeed");
+        file
+    }
+
+    fn to_syncsafe(size: usize) -> [u8; 4] {
+        [
+            (size >> 21 & 0x7F) as u8,
+            (size >> 14 & 0x7F) as u8,
+            (size >> 7 & 0x7F) as u8,
+            (size & 0x7F) as u8,
+        ]
+    }
+
+    fn id3_frame(id: &[u8; 4], content: &[u8], version: u8) -> Vec<u8> {
+        let mut frame = id.to_vec();
+        match version {
+            4 => frame.extend(to_syncsafe(content.len())),
+            _ => frame.extend((content.len() as u32).to_be_bytes()),
+        }
+        frame.extend([0, 0]);
+        frame.extend(content);
+        frame
+    }
+
+    fn id3_tag(version: u8, frames: &[Vec<u8>]) -> Vec<u8> {
+        let data = frames.concat();
+        let mut tag = b"ID3".to_vec();
+        tag.extend([version, 0, 0]);
+        tag.extend(to_syncsafe(data.len()));
+        tag.extend(data);
+        tag
+    }
+
+    fn chap_frame(element_id: &str, start_ms: u32, title: &[u8], version: u8) -> Vec<u8> {
+        let mut content = element_id.as_bytes().to_vec();
+        content.push(0);
+        content.extend(start_ms.to_be_bytes());
+        content.extend((start_ms + 1000).to_be_bytes());
+        content.extend([0xFF; 8]);
+        content.extend(id3_frame(b"TIT2", title, version));
+        id3_frame(b"CHAP", &content, version)
+    }
+
+    fn ctoc_frame(children: &[&str], version: u8) -> Vec<u8> {
+        let mut content = b"toc\0".to_vec();
+        content.extend([0x03, children.len() as u8]);
+        for child in children {
+            content.extend(child.as_bytes());
+            content.push(0);
+        }
+        id3_frame(b"CTOC", &content, version)
+    }
+
+    fn mp4_box(name: &[u8; 4], contents: &[u8]) -> Vec<u8> {
+        let mut mp4_box = ((contents.len() + 8) as u32).to_be_bytes().to_vec();
+        mp4_box.extend(name);
+        mp4_box.extend(contents);
+        mp4_box
+    }
+
+    fn chpl(chapters: &[(u64, &str)], count: u8) -> Vec<u8> {
+        let mut contents = vec![1, 0, 0, 0, 0, 0, 0, 0, count];
+        for (start, title) in chapters {
+            contents.extend(start.to_be_bytes());
+            contents.push(title.len() as u8);
+            contents.extend(title.as_bytes());
+        }
+        mp4_box(b"chpl", &contents)
+    }
+
+    fn mp4_file(moov: &[Vec<u8>]) -> Vec<u8> {
+        let mut file = mp4_box(b"ftyp", b"M4B \0\0\0\0");
+        file.extend(mp4_box(b"moov", &moov.concat()));
+        file
+    }
+
+    fn full_box(name: &[u8; 4], entries: &[u32]) -> Vec<u8> {
+        let mut contents = vec![0, 0, 0, 0];
+        for entry in entries {
+            contents.extend(entry.to_be_bytes());
+        }
+        mp4_box(name, &contents)
+    }
+
+    fn tkhd(track_id: u32) -> Vec<u8> {
+        full_box(b"tkhd", &[0, 0, track_id])
+    }
+
+    /// Audio track 1 referencing the text track 2 with the chapter titles stored at
+    /// `offset`, the first chapter lasts 5 s
+    fn quicktime_moov(titles: &[&str], offset: u32) -> Vec<u8> {
+        let audio = mp4_box(
+            b"trak",
+            &[
+                tkhd(1),
+                mp4_box(b"tref", &mp4_box(b"chap", &2u32.to_be_bytes())),
+            ]
+            .concat(),
+        );
+        let sizes: Vec<u32> = titles.iter().map(|title| title.len() as u32 + 2).collect();
+        let mut stsz = vec![0, titles.len() as u32];
+        stsz.extend(&sizes);
+        let stbl = [
+            full_box(b"stts", &[2, 1, 5000, 1, 3000]),
+            full_box(b"stsc", &[1, 1, titles.len() as u32, 1]),
+            full_box(b"stsz", &stsz),
+            full_box(b"stco", &[1, offset]),
+        ]
+        .concat();
+        let mdia = [
+            full_box(b"mdhd", &[0, 0, 1000, 8000]),
+            mp4_box(b"minf", &mp4_box(b"stbl", &stbl)),
+        ]
+        .concat();
+        let text = mp4_box(b"trak", &[tkhd(2), mp4_box(b"mdia", &mdia)].concat());
+        [audio, text].concat()
+    }
+
+    fn title_samples(titles: &[&str]) -> Vec<u8> {
+        titles
+            .iter()
+            .flat_map(|title| {
+                let mut sample = (title.len() as u16).to_be_bytes().to_vec();
+                sample.extend(title.as_bytes());
+                sample
+            })
+            .collect()
+    }
+
+    fn quicktime_file(titles: &[&str]) -> Vec<u8> {
+        // the length of the moov box does not depend on the offset of the samples
+        let header_len = mp4_file(&[quicktime_moov(titles, 0)]).len() + 8;
+        let mut file = mp4_file(&[quicktime_moov(titles, header_len as u32)]);
+        file.extend(mp4_box(b"mdat", &title_samples(titles)));
+        file
+    }
+
+    fn positions(chapters: &[EmbeddedChapter]) -> Vec<(&str, f64)> {
+        chapters
+            .iter()
+            .map(|chapter| (chapter.name.as_str(), chapter.position))
+            .collect()
+    }
+
+    #[test]
+    fn id3v23_chapters_listed_in_the_table_of_contents() {
+        let tag = id3_tag(
+            3,
+            &[
+                chap_frame("ch0", 0, b"\x03Intro", 3),
+                chap_frame("ch1", 61_500, b"\x00Second", 3),
+                chap_frame("hidden", 90_000, b"\x03Bonus", 3),
+                ctoc_frame(&["ch0", "ch1"], 3),
+            ],
+        );
+        let chapters =
+            read_id3v2_chapters(&mut file_with(&tag)).expect("Read ID3 chapters should succeed");
+        assert_eq!(positions(&chapters), vec![("Intro", 0.0), ("Second", 61.5)]);
+    }
+
+    #[test]
+    fn id3v24_chapters_with_utf16_titles() {
+        let mut title = vec![1, 0xFF, 0xFE];
+        title.extend("Kapitola č".encode_utf16().flat_map(u16::to_le_bytes));
+        let tag = id3_tag(4, &[chap_frame("ch0", 2000, &title, 4)]);
+        let chapters =
+            read_id3v2_chapters(&mut file_with(&tag)).expect("Read ID3 chapters should succeed");
+        assert_eq!(positions(&chapters), vec![("Kapitola č", 2.0)]);
+    }
+
+    #[test]
+    fn id3_chapters_of_malformed_tags() {
+        // no tag, an unsupported version
+        let chapters = read_id3v2_chapters(&mut file_with(b"\xFF\xFB\x90\x00"))
+            .expect("Read ID3 chapters should succeed");
+        assert!(chapters.is_empty());
+        let tag = id3_tag(2, &[chap_frame("ch0", 0, b"\x03Intro", 3)]);
+        let chapters =
+            read_id3v2_chapters(&mut file_with(&tag)).expect("Read ID3 chapters should succeed");
+        assert!(chapters.is_empty());
+
+        // the frame after the first chapter claims to be longer than the tag
+        let mut truncated = chap_frame("ch1", 1000, b"\x03Cut", 3);
+        truncated[7] = 0xFF;
+        let tag = id3_tag(3, &[chap_frame("ch0", 0, b"\x03Intro", 3), truncated]);
+        let chapters =
+            read_id3v2_chapters(&mut file_with(&tag)).expect("Read ID3 chapters should succeed");
+        assert_eq!(positions(&chapters), vec![("Intro", 0.0)]);
+
+        // a chapter without its times is skipped
+        let tag = id3_tag(3, &[id3_frame(b"CHAP", b"ch0\0\0\0", 3)]);
+        let chapters =
+            read_id3v2_chapters(&mut file_with(&tag)).expect("Read ID3 chapters should succeed");
+        assert!(chapters.is_empty());
+
+        // the tag claims to be longer than the file
+        let tag = id3_tag(3, &[chap_frame("ch0", 0, b"\x03Intro", 3)]);
+        assert!(read_id3v2_chapters(&mut file_with(&tag[..tag.len() - 5])).is_err());
+    }
+
+    #[test]
+    fn nero_chapters() {
+        let file = mp4_file(&[mp4_box(
+            b"udta",
+            &chpl(&[(0, "Intro"), (615_000_000, "Second")], 2),
+        )]);
+        let chapters =
+            read_mp4_chapters(&mut file_with(&file)).expect("Read MP4 chapters should succeed");
+        assert_eq!(positions(&chapters), vec![("Intro", 0.0), ("Second", 61.5)]);
+    }
+
+    #[test]
+    fn nero_chapters_of_malformed_files() {
+        // more chapters are announced than there are
+        let file = mp4_file(&[mp4_box(b"udta", &chpl(&[(0, "Intro")], 3))]);
+        let chapters =
+            read_mp4_chapters(&mut file_with(&file)).expect("Read MP4 chapters should succeed");
+        assert_eq!(positions(&chapters), vec![("Intro", 0.0)]);
+
+        // the title of the chapter is cut off
+        let mut file = mp4_file(&[mp4_box(b"udta", &chpl(&[(0, "Intro")], 1))]);
+        let len = file.len();
+        file[len - 6] = 200;
+        let chapters =
+            read_mp4_chapters(&mut file_with(&file)).expect("Read MP4 chapters should succeed");
+        assert!(chapters.is_empty());
+
+        // the moov box is longer than the file
+        let file = mp4_file(&[mp4_box(b"udta", &chpl(&[(0, "Intro")], 1))]);
+        let chapters = read_mp4_chapters(&mut file_with(&file[..file.len() - 4]))
+            .expect("Read MP4 chapters should succeed");
+        assert!(chapters.is_empty());
+
+        // not an MP4 file at all
+        let chapters =
+            read_mp4_chapters(&mut file_with(b"fLaC")).expect("Read MP4 chapters should succeed");
+        assert!(chapters.is_empty());
+    }
+
+    #[test]
+    fn quicktime_chapters() {
+        let file = quicktime_file(&["One", "Two"]);
+        let chapters =
+            read_mp4_chapters(&mut file_with(&file)).expect("Read MP4 chapters should succeed");
+        assert_eq!(positions(&chapters), vec![("One", 0.0), ("Two", 5.0)]);
+    }
+
+    #[test]
+    fn quicktime_chapters_of_malformed_files() {
+        // the samples with the titles are missing
+        let file = quicktime_file(&["One", "Two"]);
+        assert!(read_mp4_chapters(&mut file_with(&file[..file.len() - 4])).is_err());
+
+        // the chapter track has no sample tables
+        let audio = mp4_box(
+            b"trak",
+            &[
+                tkhd(1),
+                mp4_box(b"tref", &mp4_box(b"chap", &2u32.to_be_bytes())),
+            ]
+            .concat(),
+        );
+        let text = mp4_box(b"trak", &tkhd(2));
+        let file = mp4_file(&[audio, text]);
+        let chapters =
+            read_mp4_chapters(&mut file_with(&file)).expect("Read MP4 chapters should succeed");
+        assert!(chapters.is_empty());
+    }
+
+    #[test]
+    fn vorbis_comment_chapters() {
+        let comments = HashMap::from([
+            ("CHAPTER001", "00:00:00.000"),
+            ("CHAPTER001NAME", "Intro"),
+            ("CHAPTER002", "00:01:01.500"),
+            ("CHAPTER003", "01:00:00"),
+            ("CHAPTER003NAME", "Third"),
+            // the numbering has a gap, later chapters are not read
+            ("CHAPTER005", "02:00:00"),
+        ]);
+        let chapters = vorbis_chapters(|key| comments.get(key).copied());
+        assert_eq!(
+            positions(&chapters),
+            vec![("Intro", 0.0), ("", 61.5), ("Third", 3600.0)]
+        );
+
+        let comments = HashMap::from([("CHAPTER000", "00:00:10"), ("CHAPTER001", "00:00:20")]);
+        let chapters = vorbis_chapters(|key| comments.get(key).copied());
+        assert_eq!(positions(&chapters), vec![("", 10.0), ("", 20.0)]);
+    }
+
+    #[test]
+    fn vorbis_comment_chapters_with_malformed_timestamps() {
+        let comments = HashMap::from([
+            ("CHAPTER001", "00:00:05"),
+            ("CHAPTER002", "00:xx:10"),
+            ("CHAPTER003", "00:00:20"),
+        ]);
+        let chapters = vorbis_chapters(|key| comments.get(key).copied());
+        assert_eq!(positions(&chapters), vec![("", 5.0)]);
+        assert_eq!(parse_timestamp(""), None);
+        assert_eq!(parse_timestamp(" 1:02:03.5 "), Some(3723.5));
+    }
+}
@@ -0,0 +1,54 @@
+use crate::handlers::utilities::is_public_media;
+use crate::media::hls::hls_directory;
+use crate::media::images::image_files;
+use crate::media::storage::Storage;
+use crate::media::waveform::waveform_path;
+use sha2::{Digest, Sha256};
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+/// Media are stored under the SHA-256 of their contents, the same file uploaded
+/// twice ends up under the same path
+pub fn content_path(digest: &str, mime: &str, extension: &str) -> String {
+    format!("/media/{digest}_{mime}.{extension}")
+}
+
+pub fn data_digest(data: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(data))
+}
+
+pub async fn file_digest(file: &Path) -> std::io::Result<String> {
+    let file: PathBuf = file.to_path_buf();
+    tokio::task::spawn_blocking(move || {
+        let mut reader = std::fs::File::open(file)?;
+        let mut hasher = Sha256::new();
+        let mut buffer = vec![0; 64 * 1024];
+        loop {
+            let read = reader.read(&mut buffer)?;
+            if read == 0 {
+                break;
+            }
+            hasher.update(&buffer[..read]);
+        }
+        Ok(format!("{:x}", hasher.finalize()))
+    })
+    .await?
+}
+
+/// Removes the stored file together with everything derived from it (image sizes,
+/// HLS renditions and the waveform of audio files)
+pub async fn remove_media_files(storage: &dyn Storage, path: &str) -> std::io::Result<()> {
+    // the bundled examples are served from /static, not from the storage
+    if !path.starts_with("/media/") {
+        return Ok(());
+    }
+    if is_public_media(Path::new(path)) {
+        for file in image_files(path) {
+            storage.delete(&file).await?;
+        }
+        return Ok(());
+    }
+    storage.delete(path).await?;
+    storage.delete_directory(&hls_directory(path)).await?;
+    storage.delete(&waveform_path(path)).await
+}
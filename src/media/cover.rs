@@ -0,0 +1,24 @@
+use crate::media::formats::MediaFormat;
+use lofty::{PictureType, TaggedFile, TaggedFileExt};
+
+/// Picture embedded in the tags of an audio file (ID3v2 APIC, MP4 `covr`, FLAC/Vorbis pictures)
+#[derive(Debug, Clone)]
+pub struct EmbeddedCover {
+    pub data: Vec<u8>,
+}
+
+/// Returns the front cover of the file, or the first picture of a supported format if there
+/// is no picture marked as the front cover (MP4 files do not mark their pictures at all)
+pub fn read_embedded_cover(tagged_file: &TaggedFile) -> Option<EmbeddedCover> {
+    let pictures = || tagged_file.tags().iter().flat_map(|tag| tag.pictures());
+    pictures()
+        .filter(|picture| picture.pic_type() == PictureType::CoverFront)
+        .chain(pictures())
+        .find(|picture| {
+            // tags often carry a wrong or no MIME type, so the format is detected from the data
+            MediaFormat::sniff(picture.data()).is_some_and(|format| format.mime() == "image")
+        })
+        .map(|picture| EmbeddedCover {
+            data: picture.data().to_vec(),
+        })
+}
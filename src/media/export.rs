@@ -0,0 +1,289 @@
+use crate::database::common::{DbReadMany, DbReadOne};
+use crate::database::models::audiobook::{AudiobookDetail, AudiobookGetById, AudiobookGetByIdJoin};
+use crate::database::models::chapter::{Chapter, ChaptersGetByBookId};
+use crate::database::models::export::{
+    AudiobookExport, AudiobookExportFinish, AudiobookExportsTake,
+};
+use crate::database::models::Id;
+use crate::database::repositories::audiobook::repository::AudiobookRepository;
+use crate::database::repositories::chapter::repository::ChapterRepository;
+use crate::database::repositories::export::repository::AudiobookExportRepository;
+use crate::media::content::data_digest;
+use crate::media::storage::Storage;
+use crate::{BOOK_EXPORT_INTERVAL, BOOK_EXPORT_TIMEOUT};
+use log::{info, warn};
+use std::fmt::Write;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::process::Command;
+use tokio::sync::Notify;
+
+/// Exports are kept next to the media, but they are not media: they are not referenced
+/// by books and the garbage collection of the media leaves them alone
+const EXPORT_DIRECTORY: &str = "/exports";
+const EXPORT_AUDIO_BITRATE: &str = "128k";
+
+lazy_static::lazy_static! {
+    static ref EXPORT_REQUESTED: Notify = Notify::new();
+}
+
+/// Wakes the export up, so that requested exports do not wait for the next round
+pub fn notify_book_export() {
+    EXPORT_REQUESTED.notify_one();
+}
+
+/// Digest of everything the export of the book is made of, the export of an edited book
+/// has another fingerprint
+pub fn export_fingerprint(book: &AudiobookDetail, chapters: &[Chapter]) -> String {
+    let mut contents = [
+        book.file_path.as_str(),
+        book.thumbnail.as_deref().unwrap_or_default(),
+        &book.name,
+        &book.author_name,
+        &book.surname,
+        &book.genre_name,
+        &book.description,
+    ]
+    .join("\0");
+    for chapter in chapters {
+        let _ = write!(contents, "\0{}\0{}", chapter.name, chapter.position);
+    }
+    data_digest(contents.as_bytes())
+}
+
+fn export_path(audiobook_id: Id, fingerprint: &str) -> String {
+    let fingerprint = fingerprint.get(..16).unwrap_or(fingerprint);
+    format!("{EXPORT_DIRECTORY}/audiobook_{audiobook_id}_{fingerprint}.m4b")
+}
+
+/// Values of the ffmetadata format escape its special characters with a backslash
+fn escape_metadata(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        if matches!(c, '=' | ';' | '#' | '\\' | '\n') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+/// Tags and chapters of the export in the ffmetadata format, each chapter ends where
+/// the next one begins
+fn ffmetadata(book: &AudiobookDetail, chapters: &[Chapter]) -> String {
+    let author = format!("{} {}", book.author_name, book.surname);
+    let mut metadata = String::from(";FFMETADATA1\n");
+    for (key, value) in [
+        ("title", book.name.as_str()),
+        ("album", &book.name),
+        ("artist", &author),
+        ("album_artist", &author),
+        ("genre", &book.genre_name),
+        ("description", &book.description),
+        ("comment", &book.description),
+    ] {
+        let _ = writeln!(metadata, "{key}={}", escape_metadata(value));
+    }
+
+    let length = (book.length * 1000.0) as i64;
+    let starts: Vec<i64> = chapters
+        .iter()
+        .map(|chapter| (chapter.position * 1000.0) as i64)
+        .collect();
+    for (index, chapter) in chapters.iter().enumerate() {
+        let start = starts[index];
+        let end = starts.get(index + 1).copied().unwrap_or(length).min(length);
+        if end <= start {
+            continue;
+        }
+        let _ = write!(
+            metadata,
+            "\n[CHAPTER]\nTIMEBASE=1/1000\nSTART={start}\nEND={end}\ntitle={}\n",
+            escape_metadata(&chapter.name)
+        );
+    }
+    metadata
+}
+
+/// Muxes the audio of the book into an MP4 container with the chapters, the cover and
+/// the tags of the book using ffmpeg, and stores it at `path`. AAC audio is copied,
+/// other audio is encoded to AAC. Returns the size of the export.
+async fn export_m4b(
+    storage: &dyn Storage,
+    book: &AudiobookDetail,
+    chapters: &[Chapter],
+    path: &str,
+) -> anyhow::Result<u64> {
+    let audio = storage.local_copy(&book.file_path).await?;
+    let cover = match &book.thumbnail {
+        Some(thumbnail) => match storage.local_copy(thumbnail).await {
+            Ok(cover) => Some(cover),
+            Err(err) => {
+                warn!("exporting book {} without its cover: {err}", book.id);
+                None
+            }
+        },
+        None => None,
+    };
+    let staging = match storage.upload_directory() {
+        Some(directory) => tempfile::tempdir_in(directory)?,
+        None => tempfile::tempdir()?,
+    };
+    let metadata_path = staging.path().join("metadata.txt");
+    tokio::fs::write(&metadata_path, ffmetadata(book, chapters)).await?;
+    let output = staging.path().join("export.m4b");
+
+    let mut ffmpeg = Command::new("ffmpeg");
+    ffmpeg
+        .args(["-hide_banner", "-loglevel", "error", "-y", "-i"])
+        .arg(audio.path());
+    if let Some(cover) = &cover {
+        ffmpeg.arg("-i").arg(cover.path());
+    }
+    let metadata_input = if cover.is_some() { "2" } else { "1" };
+    ffmpeg
+        .args(["-f", "ffmetadata", "-i"])
+        .arg(&metadata_path)
+        .args(["-map", "0:a:0"]);
+    if cover.is_some() {
+        ffmpeg.args([
+            "-map",
+            "1:v:0",
+            "-c:v",
+            "mjpeg",
+            "-disposition:v:0",
+            "attached_pic",
+        ]);
+    }
+    ffmpeg
+        .args(["-map_metadata", metadata_input])
+        .args(["-map_chapters", metadata_input]);
+    let is_aac = Path::new(&book.file_path)
+        .extension()
+        .is_some_and(|extension| extension == "m4a" || extension == "m4b");
+    if is_aac {
+        ffmpeg.args(["-c:a", "copy"]);
+    } else {
+        ffmpeg.args(["-c:a", "aac", "-b:a", EXPORT_AUDIO_BITRATE]);
+    }
+    ffmpeg
+        .args(["-movflags", "+faststart", "-f", "mp4"])
+        .arg(&output);
+
+    let result = ffmpeg
+        .output()
+        .await
+        .map_err(|err| anyhow::anyhow!("could not run ffmpeg: {err}"))?;
+    if !result.status.success() {
+        anyhow::bail!(
+            "ffmpeg exited with {}: {}",
+            result.status,
+            String::from_utf8_lossy(&result.stderr).trim()
+        );
+    }
+    let size = tokio::fs::metadata(&output).await?.len();
+    storage.put_file(path, &output).await?;
+    Ok(size)
+}
+
+/// Runs the requested exports one by one. Exports that were interrupted (by a restart,
+/// for example) are run again once `BOOK_EXPORT_TIMEOUT` passes.
+pub fn spawn_book_export(
+    storage: Arc<dyn Storage>,
+    export_repo: AudiobookExportRepository,
+    audiobook_repo: AudiobookRepository,
+    chapter_repo: ChapterRepository,
+) {
+    actix_web::rt::spawn(async move {
+        loop {
+            let take = AudiobookExportsTake::new(1, BOOK_EXPORT_TIMEOUT);
+            match export_repo.take_pending(&take).await {
+                Ok(exports) if !exports.is_empty() => {
+                    for export in exports {
+                        run_export(
+                            storage.as_ref(),
+                            &export_repo,
+                            &audiobook_repo,
+                            &chapter_repo,
+                            export,
+                        )
+                        .await;
+                    }
+                    continue;
+                }
+                Ok(_) => {}
+                Err(err) => warn!("failed to read the requested exports: {err}"),
+            }
+            let _ = actix_web::rt::time::timeout(
+                Duration::from_secs(BOOK_EXPORT_INTERVAL),
+                EXPORT_REQUESTED.notified(),
+            )
+            .await;
+        }
+    });
+}
+
+async fn run_export(
+    storage: &dyn Storage,
+    export_repo: &AudiobookExportRepository,
+    audiobook_repo: &AudiobookRepository,
+    chapter_repo: &ChapterRepository,
+    export: AudiobookExport,
+) {
+    let timeout = Duration::from_secs(BOOK_EXPORT_TIMEOUT.unsigned_abs());
+    let exported = actix_web::rt::time::timeout(
+        timeout,
+        export_book(storage, audiobook_repo, chapter_repo, &export),
+    )
+    .await
+    .unwrap_or_else(|_| Err(anyhow::anyhow!("the export took too long")));
+    let finish = match &exported {
+        Ok((fingerprint, path, size)) => {
+            info!("book {} was exported to {path}", export.audiobook_id);
+            AudiobookExportFinish::ready(&export, fingerprint, path, *size as i64)
+        }
+        Err(err) => {
+            warn!("failed to export book {}: {err}", export.audiobook_id);
+            AudiobookExportFinish::failed(&export, &err.to_string())
+        }
+    };
+    let unused = match export_repo.finish(&finish).await {
+        Ok(unused) => unused,
+        Err(err) => {
+            warn!(
+                "failed to record the export of book {}: {err}",
+                export.audiobook_id
+            );
+            return;
+        }
+    };
+    for path in unused {
+        if let Err(err) = storage.delete(&path).await {
+            warn!("failed to remove the export {path}: {err}");
+        }
+    }
+}
+
+/// Exports the book in its current state, returns the fingerprint of the state,
+/// the path of the export and its size
+async fn export_book(
+    storage: &dyn Storage,
+    audiobook_repo: &AudiobookRepository,
+    chapter_repo: &ChapterRepository,
+    export: &AudiobookExport,
+) -> anyhow::Result<(String, String, u64)> {
+    let book = audiobook_repo
+        .read_one(&AudiobookGetById::new(&export.audiobook_id, true))
+        .await?;
+    let book = audiobook_repo
+        .read_one(&AudiobookGetByIdJoin::new(book.author_id, book.id, true))
+        .await?;
+    let chapters = chapter_repo
+        .read_many(&ChaptersGetByBookId::new(book.id))
+        .await?;
+    let fingerprint = export_fingerprint(&book, &chapters);
+    let path = export_path(book.id, &fingerprint);
+    let size = export_m4b(storage, &book, &chapters, &path).await?;
+    Ok((fingerprint, path, size))
+}
@@ -0,0 +1,441 @@
+use crate::media::tracks::skip_id3v2;
+use image::{ImageFormat, ImageReader};
+use lofty::{AudioFile, FileType, Probe};
+use std::fmt::{Display, Formatter};
+use std::fs::File;
+use std::io::{BufReader, Read, Seek, SeekFrom};
+use std::path::Path;
+use thiserror::Error;
+
+/// Enough to get past the segment table of the first Ogg page to the codec identification
+const SNIFF_LEN: u64 = 512;
+
+/// Formats that can be uploaded, detected from the contents of the file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MediaFormat {
+    Mp3,
+    M4a,
+    M4b,
+    Ogg,
+    Opus,
+    Flac,
+    Wav,
+    Jpeg,
+    Png,
+    WebP,
+}
+
+#[derive(Error, Debug)]
+pub enum FormatError {
+    #[error("the format of the file is not supported")]
+    Unsupported,
+    #[error("the file looks like {0}, but it is not a valid {0} file ({1})")]
+    Invalid(MediaFormat, String),
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+}
+
+impl Display for MediaFormat {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            Self::Mp3 => "MP3",
+            Self::M4a => "M4A",
+            Self::M4b => "M4B",
+            Self::Ogg => "Ogg Vorbis",
+            Self::Opus => "Opus",
+            Self::Flac => "FLAC",
+            Self::Wav => "WAV",
+            Self::Jpeg => "JPEG",
+            Self::Png => "PNG",
+            Self::WebP => "WebP",
+        };
+        f.write_str(name)
+    }
+}
+
+impl MediaFormat {
+    /// Extension of the stored file
+    #[must_use]
+    pub const fn extension(self) -> &'static str {
+        match self {
+            Self::Mp3 => "mp3",
+            Self::M4a => "m4a",
+            Self::M4b => "m4b",
+            Self::Ogg => "ogg",
+            Self::Opus => "opus",
+            Self::Flac => "flac",
+            Self::Wav => "wav",
+            Self::Jpeg => "jpg",
+            Self::Png => "png",
+            Self::WebP => "webp",
+        }
+    }
+
+    /// Top-level MIME type of the format, `audio` or `image`
+    #[must_use]
+    pub const fn mime(self) -> &'static str {
+        match self {
+            Self::Jpeg | Self::Png | Self::WebP => "image",
+            _ => "audio",
+        }
+    }
+
+    /// MIME types browsers and operating systems send for files of the format
+    #[must_use]
+    pub const fn content_types(self) -> &'static [&'static str] {
+        match self {
+            Self::Mp3 => &[
+                "audio/mpeg",
+                "audio/mp3",
+                "audio/mpeg3",
+                "audio/x-mpeg",
+                "audio/x-mp3",
+                "audio/mpa",
+            ],
+            Self::M4a | Self::M4b => &[
+                "audio/mp4",
+                "audio/m4a",
+                "audio/x-m4a",
+                "audio/m4b",
+                "audio/x-m4b",
+            ],
+            Self::Ogg => &["audio/ogg", "audio/vorbis", "audio/x-vorbis+ogg"],
+            Self::Opus => &["audio/ogg", "audio/opus"],
+            Self::Flac => &["audio/flac", "audio/x-flac"],
+            Self::Wav => &["audio/wav", "audio/x-wav", "audio/wave", "audio/vnd.wave"],
+            Self::Jpeg => &["image/jpeg", "image/jpg", "image/pjpeg"],
+            Self::Png => &["image/png", "image/x-png"],
+            Self::WebP => &["image/webp"],
+        }
+    }
+
+    /// Detects the format from the magic bytes at the beginning of the data
+    #[must_use]
+    pub fn sniff(data: &[u8]) -> Option<Self> {
+        match data {
+            [0xFF, 0xD8, 0xFF, ..] => Some(Self::Jpeg),
+            [0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1A, b'\n', ..] => Some(Self::Png),
+            [b'R', b'I', b'F', b'F', _, _, _, _, b'W', b'E', b'B', b'P', ..] => Some(Self::WebP),
+            [b'R', b'I', b'F', b'F', _, _, _, _, b'W', b'A', b'V', b'E', ..] => Some(Self::Wav),
+            [b'f', b'L', b'a', b'C', ..] => Some(Self::Flac),
+            [_, _, _, _, b'f', b't', b'y', b'p', brand @ ..] => match brand.get(..4)? {
+                b"M4B " => Some(Self::M4b),
+                b"M4A " | b"mp41" | b"mp42" | b"isom" | b"iso2" => Some(Self::M4a),
+                _ => None,
+            },
+            [b'O', b'g', b'g', b'S', ..] => {
+                // the first page carries only the identification header of the codec
+                let payload = data.get(27 + usize::from(*data.get(26)?)..)?;
+                if payload.starts_with(b"OpusHead") {
+                    Some(Self::Opus)
+                } else if payload.starts_with(b"\x01vorbis") {
+                    Some(Self::Ogg)
+                } else {
+                    None
+                }
+            }
+            // MPEG audio frame sync, Layer III
+            [0xFF, second, ..] if second & 0xE0 == 0xE0 && (second >> 1) & 0b11 == 1 => {
+                Some(Self::Mp3)
+            }
+            _ => None,
+        }
+    }
+
+    /// Parses the container of the file, so that a file with forged magic bytes is not accepted
+    pub fn verify(self, path: &Path) -> Result<(), FormatError> {
+        let reader = BufReader::new(File::open(path)?);
+        let invalid = |err: String| FormatError::Invalid(self, err);
+        let image_format = match self {
+            Self::Mp3 => return probe_audio(reader, FileType::Mpeg).map_err(invalid),
+            Self::M4a | Self::M4b => return probe_audio(reader, FileType::Mp4).map_err(invalid),
+            Self::Ogg => return probe_audio(reader, FileType::Vorbis).map_err(invalid),
+            Self::Opus => return probe_audio(reader, FileType::Opus).map_err(invalid),
+            Self::Flac => return probe_audio(reader, FileType::Flac).map_err(invalid),
+            Self::Wav => return probe_audio(reader, FileType::Wav).map_err(invalid),
+            Self::Jpeg => ImageFormat::Jpeg,
+            Self::Png => ImageFormat::Png,
+            Self::WebP => ImageFormat::WebP,
+        };
+        ImageReader::with_format(reader, image_format)
+            .into_dimensions()
+            .map(|_| ())
+            .map_err(|err| invalid(err.to_string()))
+    }
+}
+
+fn probe_audio(reader: BufReader<File>, file_type: FileType) -> Result<(), String> {
+    let tagged_file = Probe::with_file_type(reader, file_type)
+        .read()
+        .map_err(|err| err.to_string())?;
+    if tagged_file.properties().duration().is_zero() {
+        return Err("the file does not contain any audio".to_string());
+    }
+    Ok(())
+}
+
+/// Detects the format of the file from its magic bytes and checks that its container can be parsed.
+///
+/// ID3v2 tags in front of the data are skipped, as FLAC and WAV files are sometimes tagged
+/// with them too. Tagged files with unrecognized data are treated as MP3, because encoders
+/// may put padding between the tag and the first frame.
+pub fn detect_format(path: &Path) -> Result<MediaFormat, FormatError> {
+    let mut file = File::open(path)?;
+    let offset = skip_id3v2(&mut file)?;
+
+    let mut data = Vec::new();
+    file.seek(SeekFrom::Start(offset))?;
+    file.take(SNIFF_LEN).read_to_end(&mut data)?;
+    let format = MediaFormat::sniff(&data)
+        .or((offset > 0).then_some(MediaFormat::Mp3))
+        .ok_or(FormatError::Unsupported)?;
+    format.verify(path)?;
+    Ok(format)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{ImageBuffer, Rgb};
+    use std::io::{Cursor, Write};
+    use tempfile::NamedTempFile;
+
+    const SAMPLE_RATE: u32 = 8000;
+
+    fn file_with(contents: &[u8]) -> NamedTempFile {
+        let mut file = NamedTempFile::new().expect("Create temporary file should succeed");
+        file.write_all(contents)
+            .expect("Write temporary file should succeed");
+        file
+    }
+
+    /// One second of silence as 8-bit mono PCM
+    fn wav() -> Vec<u8> {
+        let mut data = b"RIFF".to_vec();
+        data.extend((36 + SAMPLE_RATE).to_le_bytes());
+        data.extend(b"WAVEfmt ");
+        data.extend(16u32.to_le_bytes());
+        data.extend(1u16.to_le_bytes());
+        data.extend(1u16.to_le_bytes());
+        data.extend(SAMPLE_RATE.to_le_bytes());
+        data.extend(SAMPLE_RATE.to_le_bytes());
+        data.extend(1u16.to_le_bytes());
+        data.extend(8u16.to_le_bytes());
+        data.extend(b"data");
+        data.extend(SAMPLE_RATE.to_le_bytes());
+        data.extend(vec![0x80; SAMPLE_RATE as usize]);
+        data
+    }
+
+    /// Ten frames of MPEG-1 Layer III at 128 kbps and 44.1 kHz
+    fn mp3() -> Vec<u8> {
+        let mut frame = vec![0xFF, 0xFB, 0x90, 0x00];
+        frame.resize(417, 0);
+        frame.repeat(10)
+    }
+
+    /// The stream info of one second of 8-bit mono audio
+    fn flac() -> Vec<u8> {
+        let mut data = b"fLaC".to_vec();
+        data.extend([0x80, 0, 0, 34]);
+        data.extend(4096u16.to_be_bytes());
+        data.extend(4096u16.to_be_bytes());
+        data.extend([0; 6]);
+        // sample rate (20 bits), channels - 1 (3 bits), bits per sample - 1 (5 bits)
+        // and the number of samples (36 bits)
+        let info = (u64::from(SAMPLE_RATE) << 44) | (7 << 36) | u64::from(SAMPLE_RATE);
+        data.extend(info.to_be_bytes());
+        data.extend([0; 16]);
+        data
+    }
+
+    fn image(format: ImageFormat) -> Vec<u8> {
+        let mut data = Cursor::new(Vec::new());
+        ImageBuffer::from_pixel(4, 4, Rgb([200u8, 100, 0]))
+            .write_to(&mut data, format)
+            .expect("Encode image should succeed");
+        data.into_inner()
+    }
+
+    fn mp4_box(name: &[u8; 4], contents: &[u8]) -> Vec<u8> {
+        let mut mp4_box = ((contents.len() + 8) as u32).to_be_bytes().to_vec();
+        mp4_box.extend(name);
+        mp4_box.extend(contents);
+        mp4_box
+    }
+
+    /// An audio track lasting one second, without any samples
+    fn mp4(brand: &[u8; 4]) -> Vec<u8> {
+        let mut hdlr = vec![0; 8];
+        hdlr.extend(b"soun");
+        hdlr.extend([0; 13]);
+        let mut mdhd = vec![0; 12];
+        mdhd.extend(1000u32.to_be_bytes());
+        mdhd.extend(1000u32.to_be_bytes());
+        mdhd.extend([0; 4]);
+        let mdia = [mp4_box(b"hdlr", &hdlr), mp4_box(b"mdhd", &mdhd)].concat();
+        let moov = mp4_box(b"trak", &mp4_box(b"mdia", &mdia));
+        let mut ftyp = brand.to_vec();
+        ftyp.extend([0; 4]);
+        [mp4_box(b"ftyp", &ftyp), mp4_box(b"moov", &moov)].concat()
+    }
+
+    fn ogg_page(header_type: u8, granule_position: u64, sequence: u32, payload: &[u8]) -> Vec<u8> {
+        let mut page = b"OggS\0".to_vec();
+        page.push(header_type);
+        page.extend(granule_position.to_le_bytes());
+        page.extend(1u32.to_le_bytes());
+        page.extend(sequence.to_le_bytes());
+        page.extend([0; 4]);
+        page.push(1);
+        page.push(payload.len() as u8);
+        page.extend(payload);
+        page
+    }
+
+    fn comment_header(magic: &[u8]) -> Vec<u8> {
+        let mut comment = magic.to_vec();
+        comment.extend(4u32.to_le_bytes());
+        comment.extend(b"test");
+        comment.extend(0u32.to_le_bytes());
+        comment.push(1);
+        comment
+    }
+
+    /// Pages with the headers of the codec followed by a page ending one second in
+    fn ogg(headers: &[Vec<u8>], sample_rate: u64) -> Vec<u8> {
+        let mut data = Vec::new();
+        for (sequence, header) in headers.iter().enumerate() {
+            let header_type = if sequence == 0 { 0x02 } else { 0 };
+            data.extend(ogg_page(header_type, 0, sequence as u32, header));
+        }
+        data.extend(ogg_page(0x04, sample_rate, headers.len() as u32, &[0; 16]));
+        data
+    }
+
+    fn vorbis() -> Vec<u8> {
+        let mut identification = b"\x01vorbis".to_vec();
+        identification.extend(0u32.to_le_bytes());
+        identification.push(1);
+        identification.extend(44_100u32.to_le_bytes());
+        identification.extend([0; 12]);
+        identification.extend([0xB8, 1]);
+        let setup = b"\x05vorbis\0".to_vec();
+        ogg(
+            &[identification, comment_header(b"\x03vorbis"), setup],
+            44_100,
+        )
+    }
+
+    fn opus() -> Vec<u8> {
+        let mut identification = b"OpusHead\x01\x01".to_vec();
+        identification.extend(0u16.to_le_bytes());
+        identification.extend(48_000u32.to_le_bytes());
+        identification.extend([0; 3]);
+        ogg(&[identification, comment_header(b"OpusTags")], 48_000)
+    }
+
+    fn id3_tag() -> Vec<u8> {
+        let mut tag = b"ID3\x03\0\0\0\0\0\x0A".to_vec();
+        tag.extend(b"TIT2\0\0\0\0\0\0");
+        tag
+    }
+
+    #[test]
+    fn sniff_allowed_formats() {
+        let cases = [
+            (mp3(), MediaFormat::Mp3),
+            (mp4(b"M4A "), MediaFormat::M4a),
+            (mp4(b"isom"), MediaFormat::M4a),
+            (mp4(b"M4B "), MediaFormat::M4b),
+            (vorbis(), MediaFormat::Ogg),
+            (opus(), MediaFormat::Opus),
+            (flac(), MediaFormat::Flac),
+            (wav(), MediaFormat::Wav),
+            (image(ImageFormat::Jpeg), MediaFormat::Jpeg),
+            (image(ImageFormat::Png), MediaFormat::Png),
+            (image(ImageFormat::WebP), MediaFormat::WebP),
+        ];
+        for (data, format) in cases {
+            assert_eq!(MediaFormat::sniff(&data), Some(format));
+        }
+    }
+
+    #[test]
+    fn sniff_unsupported_and_truncated_headers() {
+        let cases: [&[u8]; 9] = [
+            b"",
+            b"GIF89a",
+            b"\0\0\0\x20ftypqt  ",
+            // the brand is cut off
+            b"\0\0\0\x20ftypM4",
+            // the segment table of the Ogg page is cut off
+            b"OggS\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\x01",
+            b"OggS\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\x01\x10\x7FFLAC",
+            b"RIFF\0\0\0\0AVI ",
+            // MPEG Layer II
+            b"\xFF\xFD\x90\x00",
+            b"\xFF",
+        ];
+        for data in cases {
+            assert_eq!(MediaFormat::sniff(data), None);
+        }
+    }
+
+    #[test]
+    fn detect_allowed_formats() {
+        let cases = [
+            (mp3(), MediaFormat::Mp3),
+            ([id3_tag(), mp3()].concat(), MediaFormat::Mp3),
+            (mp4(b"M4A "), MediaFormat::M4a),
+            (mp4(b"M4B "), MediaFormat::M4b),
+            (vorbis(), MediaFormat::Ogg),
+            (opus(), MediaFormat::Opus),
+            (flac(), MediaFormat::Flac),
+            (wav(), MediaFormat::Wav),
+            ([id3_tag(), flac()].concat(), MediaFormat::Flac),
+            (image(ImageFormat::Jpeg), MediaFormat::Jpeg),
+            (image(ImageFormat::Png), MediaFormat::Png),
+            (image(ImageFormat::WebP), MediaFormat::WebP),
+        ];
+        for (data, format) in cases {
+            let file = file_with(&data);
+            assert_eq!(
+                detect_format(file.path()).expect("Detect format should succeed"),
+                format
+            );
+        }
+    }
+
+    #[test]
+    fn detect_rejects_forged_and_truncated_files() {
+        let png = image(ImageFormat::Png);
+        let cases = [
+            (b"GIF89a\x01\0\x01\0".to_vec(), false),
+            // the magic bytes are followed by garbage
+            (b"\x89PNG\r\n\x1A\nnot an image".to_vec(), true),
+            (png[..20].to_vec(), true),
+            (wav()[..40].to_vec(), true),
+            (flac()[..20].to_vec(), true),
+            (mp4(b"M4B ")[..30].to_vec(), true),
+            (opus()[..60].to_vec(), true),
+        ];
+        for (data, valid_magic) in cases {
+            let file = file_with(&data);
+            let result = detect_format(file.path());
+            if valid_magic {
+                assert!(matches!(result, Err(FormatError::Invalid(..))));
+            } else {
+                assert!(matches!(result, Err(FormatError::Unsupported)));
+            }
+        }
+    }
+
+    #[test]
+    fn verify_rejects_the_wrong_format() {
+        let file = file_with(&wav());
+        assert!(MediaFormat::Wav.verify(file.path()).is_ok());
+        assert!(MediaFormat::Flac.verify(file.path()).is_err());
+        assert!(MediaFormat::Png.verify(file.path()).is_err());
+    }
+}
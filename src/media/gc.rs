@@ -0,0 +1,220 @@
+use crate::database::models::media::MediaUnreferenced;
+use crate::database::repositories::media::repository::MediaRepository;
+use crate::media::content::remove_media_files;
+use crate::media::storage::Storage;
+use crate::MEDIA_GC_INTERVAL;
+use chrono::{Duration, Utc};
+use log::{info, warn};
+use std::collections::HashSet;
+use std::env;
+use std::fmt::{Display, Formatter};
+use std::sync::Arc;
+
+const MEDIA_DIRECTORY: &str = "/media";
+
+#[derive(Debug, Clone, Copy)]
+pub struct GcOptions {
+    /// Only report what would be removed
+    pub dry_run: bool,
+    /// Files and media younger than this many seconds are never removed
+    pub grace_period: i64,
+}
+
+impl GcOptions {
+    #[must_use]
+    #[inline]
+    pub const fn new(dry_run: bool, grace_period: i64) -> Self {
+        Self {
+            dry_run,
+            grace_period,
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct GcReport {
+    pub dry_run: bool,
+    /// Paths books and users point to, that are missing in the storage
+    pub dangling: Vec<String>,
+    /// Registered media without any references
+    pub unreferenced: Vec<String>,
+    /// Stored files that do not belong to any registered medium
+    pub orphaned: Vec<String>,
+    /// Size of the orphaned files in bytes
+    pub orphaned_size: u64,
+}
+
+impl GcReport {
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.dangling.is_empty() && self.unreferenced.is_empty() && self.orphaned.is_empty()
+    }
+}
+
+impl Display for GcReport {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let action = if self.dry_run {
+            "would be removed"
+        } else {
+            "removed"
+        };
+        writeln!(f, "dangling references: {}", self.dangling.len())?;
+        for path in &self.dangling {
+            writeln!(f, "  {path}")?;
+        }
+        writeln!(
+            f,
+            "unreferenced media {action}: {}",
+            self.unreferenced.len()
+        )?;
+        for path in &self.unreferenced {
+            writeln!(f, "  {path}")?;
+        }
+        writeln!(
+            f,
+            "orphaned files {action}: {} ({} bytes)",
+            self.orphaned.len(),
+            self.orphaned_size
+        )?;
+        for path in &self.orphaned {
+            writeln!(f, "  {path}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Medium a stored file belongs to, identified by the path of the medium without extension.
+/// Image sizes (`x_image.256.webp`), HLS renditions (`x_audio_hls/...`) and waveforms
+/// (`x_audio_waveform.dat`) belong to the medium they were derived from (`x_image.jpg`
+/// and `x_audio.mp3`). Leftovers of interrupted writes (`x_image.jpg.tmp`) belong to none.
+fn media_stem(path: &str) -> Option<&str> {
+    let relative = path.strip_prefix(MEDIA_DIRECTORY)?.strip_prefix('/')?;
+    if relative.ends_with(".tmp") {
+        return None;
+    }
+    let stem = match relative.split_once('/') {
+        Some((directory, _)) => directory.strip_suffix("_hls")?,
+        None => {
+            let stem = relative.split('.').next()?;
+            stem.strip_suffix("_waveform").unwrap_or(stem)
+        }
+    };
+    Some(stem)
+}
+
+/// Files next to the media that are never collected: parts of resumable uploads, which are
+/// removed when they expire, and hidden files (`.gitignore`), except for the `.tmp*` files
+/// multipart uploads are buffered in
+fn is_kept(path: &str) -> bool {
+    let Some(name) = path
+        .strip_prefix(MEDIA_DIRECTORY)
+        .and_then(|name| name.strip_prefix('/'))
+        .filter(|name| !name.contains('/'))
+    else {
+        return false;
+    };
+    (name.starts_with("upload_") && name.ends_with(".part"))
+        || (name.starts_with('.') && !name.starts_with(".tmp"))
+}
+
+/// Cross-checks the stored media against the database.
+///
+/// Reports the references of books and users to files missing in the storage, and removes
+/// the registered media nothing points to and the stored files that do not belong to any
+/// registered medium (left behind by failed uploads or removals), as long as they are older
+/// than the grace period. Nothing is removed in a dry run.
+pub async fn collect_garbage(
+    storage: &dyn Storage,
+    media_repo: &MediaRepository,
+    options: GcOptions,
+) -> anyhow::Result<GcReport> {
+    let mut report = GcReport {
+        dry_run: options.dry_run,
+        ..GcReport::default()
+    };
+    let objects = storage.list(MEDIA_DIRECTORY).await?;
+
+    let stored: HashSet<&str> = objects.iter().map(|(path, _)| path.as_str()).collect();
+    report.dangling = media_repo
+        .read_references()
+        .await?
+        .into_iter()
+        .filter(|path| path.starts_with("/media/") && !stored.contains(path.as_str()))
+        .collect();
+
+    let params = MediaUnreferenced::all(options.grace_period);
+    let unreferenced = if options.dry_run {
+        media_repo.read_unreferenced(&params).await?
+    } else {
+        media_repo
+            .remove_unreferenced(&params, |media| async move {
+                match remove_media_files(storage, &media.path).await {
+                    Ok(()) => true,
+                    Err(err) => {
+                        warn!("failed to remove {}: {err}", media.path);
+                        false
+                    }
+                }
+            })
+            .await?
+    };
+    report.unreferenced = unreferenced.into_iter().map(|media| media.path).collect();
+
+    // the files of unreferenced media are reported with them, not as orphans
+    let registered = media_repo.read_paths().await?;
+    let registered: HashSet<&str> = registered
+        .iter()
+        .chain(&report.unreferenced)
+        .filter_map(|path| media_stem(path))
+        .collect();
+    let cutoff = Utc::now() - Duration::seconds(options.grace_period);
+    for (path, meta) in &objects {
+        let belongs = media_stem(path).is_some_and(|stem| registered.contains(stem));
+        if belongs || is_kept(path) || meta.last_modified >= cutoff {
+            continue;
+        }
+        if !options.dry_run {
+            if let Err(err) = storage.delete(path).await {
+                warn!("failed to remove {path}: {err}");
+                continue;
+            }
+        }
+        report.orphaned.push(path.clone());
+        report.orphaned_size += meta.size;
+    }
+    report.orphaned.sort();
+    Ok(report)
+}
+
+/// Periodically collects the garbage in the media storage, as configured by `MEDIA_GC`:
+/// `report` (the default) only logs what would be removed, `delete` removes it and `off`
+/// disables the collection
+pub fn spawn_media_gc(
+    media_repo: MediaRepository,
+    storage: Arc<dyn Storage>,
+    grace_period: i64,
+) -> anyhow::Result<()> {
+    let dry_run = match env::var("MEDIA_GC").as_deref() {
+        Err(_) | Ok("report") => true,
+        Ok("delete") => false,
+        Ok("off") => return Ok(()),
+        Ok(other) => anyhow::bail!("unknown media gc mode {other}, use report, delete or off"),
+    };
+    let options = GcOptions::new(dry_run, grace_period);
+    actix_web::rt::spawn(async move {
+        let mut interval =
+            actix_web::rt::time::interval(std::time::Duration::from_secs(MEDIA_GC_INTERVAL));
+        loop {
+            interval.tick().await;
+            match collect_garbage(storage.as_ref(), &media_repo, options).await {
+                Ok(report) if report.is_empty() => {}
+                Ok(report) if !report.dangling.is_empty() => {
+                    warn!("media garbage collection:\n{report}");
+                }
+                Ok(report) => info!("media garbage collection:\n{report}"),
+                Err(err) => warn!("media garbage collection failed: {err}"),
+            }
+        }
+    });
+    Ok(())
+}
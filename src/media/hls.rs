@@ -0,0 +1,237 @@
+use crate::media::storage::Storage;
+use chrono::{DateTime, Duration, SecondsFormat, Utc};
+use std::io::Error;
+use std::path::Path;
+use tokio::process::Command;
+
+pub const HLS_MASTER_PLAYLIST: &str = "master.m3u8";
+/// Bitrates of the AAC renditions, the names are used as the rendition directories
+const HLS_BITRATES: [&str; 3] = ["48k", "96k", "160k"];
+const HLS_SEGMENT_SECONDS: u32 = 10;
+
+/// Chapter emitted into the media playlists as an `EXT-X-DATERANGE` tag
+pub struct PlaylistChapter<'a> {
+    pub id: i64,
+    pub name: &'a str,
+    pub position: f64,
+}
+
+/// Directory with the HLS renditions of an audio file, stored next to the original
+/// (`/media/audiobook_<uuid>_audio.mp3` -> `/media/audiobook_<uuid>_audio_hls`)
+pub fn hls_directory(file_path: &str) -> String {
+    let stem = Path::new(file_path).with_extension("");
+    format!("{}_hls", stem.display())
+}
+
+/// Whether the packaging of the audio file has finished
+pub async fn hls_available(storage: &dyn Storage, file_path: &str) -> std::io::Result<bool> {
+    storage
+        .exists(&format!(
+            "{}/{HLS_MASTER_PLAYLIST}",
+            hls_directory(file_path)
+        ))
+        .await
+}
+
+/// Segments the audio file into MPEG-TS renditions at `HLS_BITRATES` using ffmpeg and writes
+/// a master playlist referencing all of them. The renditions are created in a temporary
+/// directory and the master playlist is stored last, so it is only ever visible once
+/// all renditions are stored.
+pub async fn package_hls(storage: &dyn Storage, file_path: &str) -> std::io::Result<()> {
+    let audio = storage.local_copy(file_path).await?;
+    let staging = match storage.upload_directory() {
+        Some(directory) => tempfile::tempdir_in(directory)?,
+        None => tempfile::tempdir()?,
+    };
+    let staging_path = staging.path().display();
+
+    let mut ffmpeg = Command::new("ffmpeg");
+    ffmpeg
+        .args(["-hide_banner", "-loglevel", "error", "-y", "-i"])
+        .arg(audio.path())
+        .arg("-vn");
+    for _ in HLS_BITRATES {
+        ffmpeg.args(["-map", "0:a:0"]);
+    }
+    ffmpeg.args(["-c:a", "aac", "-ac", "2"]);
+    for (index, bitrate) in HLS_BITRATES.iter().enumerate() {
+        ffmpeg.arg(format!("-b:a:{index}")).arg(bitrate);
+    }
+    let stream_map = HLS_BITRATES
+        .iter()
+        .enumerate()
+        .map(|(index, bitrate)| format!("a:{index},name:{bitrate}"))
+        .collect::<Vec<_>>()
+        .join(" ");
+    ffmpeg
+        .args([
+            "-f",
+            "hls",
+            "-hls_playlist_type",
+            "vod",
+            "-hls_segment_type",
+            "mpegts",
+        ])
+        .args(["-hls_time", &HLS_SEGMENT_SECONDS.to_string()])
+        .args(["-hls_flags", "independent_segments"])
+        .args(["-master_pl_name", HLS_MASTER_PLAYLIST])
+        .args(["-var_stream_map", &stream_map])
+        .arg("-hls_segment_filename")
+        .arg(format!("{staging_path}/%v/segment_%05d.ts"))
+        .arg(format!("{staging_path}/%v/playlist.m3u8"));
+
+    let result = ffmpeg.output().await?;
+    if !result.status.success() {
+        return Err(Error::other(format!(
+            "ffmpeg exited with {}: {}",
+            result.status,
+            String::from_utf8_lossy(&result.stderr).trim()
+        )));
+    }
+
+    let output = hls_directory(file_path);
+    storage.delete_directory(&output).await?;
+    for bitrate in HLS_BITRATES {
+        let mut rendition = tokio::fs::read_dir(staging.path().join(bitrate)).await?;
+        while let Some(entry) = rendition.next_entry().await? {
+            let name = entry.file_name();
+            let name = name.to_string_lossy();
+            storage
+                .put_file(&format!("{output}/{bitrate}/{name}"), &entry.path())
+                .await?;
+        }
+    }
+    storage
+        .put_file(
+            &format!("{output}/{HLS_MASTER_PLAYLIST}"),
+            &staging.path().join(HLS_MASTER_PLAYLIST),
+        )
+        .await
+}
+
+/// Rewrites the URIs of a playlist with `rewrite_uri`, so that they can point to signed URLs.
+///
+/// Chapters are added to media playlists as `EXT-X-DATERANGE` tags. Date ranges have to be
+/// anchored by `EXT-X-PROGRAM-DATE-TIME`, so the first segment is dated to `anchor`
+/// and the chapters are dated relative to it.
+pub fn rewrite_playlist(
+    playlist: &str,
+    rewrite_uri: impl Fn(&str) -> String,
+    chapters: &[PlaylistChapter],
+    anchor: DateTime<Utc>,
+) -> String {
+    let mut result = String::with_capacity(playlist.len());
+    let mut chapters_written = chapters.is_empty();
+    for line in playlist.lines() {
+        let line = line.trim();
+        if line.starts_with("#EXTINF") && !chapters_written {
+            result.push_str(&date_ranges(chapters, anchor));
+            chapters_written = true;
+        }
+        if line.is_empty() {
+            continue;
+        } else if !line.starts_with('#') {
+            result.push_str(&rewrite_uri(line));
+        } else if let Some((start, rest)) = line.split_once("URI=\"") {
+            let (uri, end) = rest.split_once('"').unwrap_or((rest, ""));
+            result.push_str(&format!("{start}URI=\"{}\"{end}", rewrite_uri(uri)));
+        } else {
+            result.push_str(line);
+        }
+        result.push('\n');
+    }
+    result
+}
+
+fn date_ranges(chapters: &[PlaylistChapter], anchor: DateTime<Utc>) -> String {
+    let date = |position: f64| {
+        (anchor + Duration::milliseconds((position * 1000.0) as i64))
+            .to_rfc3339_opts(SecondsFormat::Millis, true)
+    };
+    let mut tags = format!("#EXT-X-PROGRAM-DATE-TIME:{}\n", date(0.0));
+    for chapter in chapters {
+        // quoted attribute values must not contain quotes or line breaks
+        let title: String = chapter
+            .name
+            .chars()
+            .map(|c| if c == '"' || c.is_control() { '\'' } else { c })
+            .collect();
+        tags.push_str(&format!(
+            "#EXT-X-DATERANGE:ID=\"chapter-{}\",CLASS=\"audiohub.chapter\",START-DATE=\"{}\",X-TITLE=\"{title}\"\n",
+            chapter.id,
+            date(chapter.position),
+        ));
+    }
+    tags
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn sign(uri: &str) -> String {
+        format!("{uri}?signature=abc")
+    }
+
+    #[test]
+    fn rendition_directory() {
+        assert_eq!(
+            hls_directory("/media/audiobook_1_audio.mp3"),
+            "/media/audiobook_1_audio_hls"
+        );
+    }
+
+    #[test]
+    fn rewrite_master_playlist() {
+        let playlist = "#EXTM3U\r\n#EXT-X-VERSION:3\r\n\r\n\
+            #EXT-X-MEDIA:TYPE=AUDIO,GROUP-ID=\"audio\",URI=\"48k/playlist.m3u8\",NAME=\"48k\"\r\n\
+            #EXT-X-STREAM-INF:BANDWIDTH=52800,CODECS=\"mp4a.40.2\"\r\n\
+            48k/playlist.m3u8\r\n";
+        let anchor = Utc::now();
+        assert_eq!(
+            rewrite_playlist(playlist, sign, &[], anchor),
+            "#EXTM3U\n#EXT-X-VERSION:3\n\
+            #EXT-X-MEDIA:TYPE=AUDIO,GROUP-ID=\"audio\",URI=\"48k/playlist.m3u8?signature=abc\",NAME=\"48k\"\n\
+            #EXT-X-STREAM-INF:BANDWIDTH=52800,CODECS=\"mp4a.40.2\"\n\
+            48k/playlist.m3u8?signature=abc\n"
+        );
+    }
+
+    #[test]
+    fn rewrite_media_playlist_with_chapters() {
+        let playlist = "#EXTM3U\n#EXT-X-VERSION:3\n#EXT-X-TARGETDURATION:10\n\
+            #EXT-X-PLAYLIST-TYPE:VOD\n\
+            #EXTINF:10.000000,\nsegment_00000.ts\n\
+            #EXTINF:4.500000,\nsegment_00001.ts\n\
+            #EXT-X-ENDLIST\n";
+        let chapters = [
+            PlaylistChapter {
+                id: 7,
+                name: "Prologue",
+                position: 0.0,
+            },
+            PlaylistChapter {
+                id: 8,
+                name: "The \"Twist\"\nbegins",
+                position: 12.3456,
+            },
+        ];
+        let anchor = Utc
+            .with_ymd_and_hms(2024, 1, 1, 0, 0, 0)
+            .single()
+            .expect("The time should be valid");
+        assert_eq!(
+            rewrite_playlist(playlist, sign, &chapters, anchor),
+            "#EXTM3U\n#EXT-X-VERSION:3\n#EXT-X-TARGETDURATION:10\n#EXT-X-PLAYLIST-TYPE:VOD\n\
+            #EXT-X-PROGRAM-DATE-TIME:2024-01-01T00:00:00.000Z\n\
+            #EXT-X-DATERANGE:ID=\"chapter-7\",CLASS=\"audiohub.chapter\",\
+            START-DATE=\"2024-01-01T00:00:00.000Z\",X-TITLE=\"Prologue\"\n\
+            #EXT-X-DATERANGE:ID=\"chapter-8\",CLASS=\"audiohub.chapter\",\
+            START-DATE=\"2024-01-01T00:00:12.345Z\",X-TITLE=\"The 'Twist''begins\"\n\
+            #EXTINF:10.000000,\nsegment_00000.ts?signature=abc\n\
+            #EXTINF:4.500000,\nsegment_00001.ts?signature=abc\n\
+            #EXT-X-ENDLIST\n"
+        );
+    }
+}
@@ -0,0 +1,149 @@
+use crate::media::signing::sign_media_url;
+use image::codecs::jpeg::JpegEncoder;
+use image::error::EncodingError;
+use image::imageops::FilterType;
+use image::{DynamicImage, ImageDecoder, ImageError, ImageFormat, ImageReader, ImageResult};
+use std::io::Cursor;
+use std::path::Path;
+
+/// Uploaded images are re-encoded, the stored file is always a JPEG
+pub const IMAGE_EXTENSION: &str = "jpg";
+/// Derivatives fit into squares of these sizes (in pixels)
+pub const IMAGE_SIZES: [u32; 3] = [96, 256, 768];
+const JPEG_QUALITY: u8 = 85;
+/// Quality of the lossy WebP derivatives, from 0 to 100
+const WEBP_QUALITY: f32 = 80.0;
+
+/// Path of a derivative of the image
+/// (`/media/user_<uuid>_image.jpg` -> `/media/user_<uuid>_image.256.webp`)
+pub fn image_derivative_path(path: &str, size: u32, extension: &str) -> String {
+    Path::new(path)
+        .with_extension(format!("{size}.{extension}"))
+        .to_string_lossy()
+        .to_string()
+}
+
+/// All files belonging to the image, the image itself and its derivatives
+pub fn image_files(path: &str) -> Vec<String> {
+    let mut files = vec![path.to_string()];
+    for size in IMAGE_SIZES {
+        files.push(image_derivative_path(path, size, "webp"));
+        files.push(image_derivative_path(path, size, IMAGE_EXTENSION));
+    }
+    files
+}
+
+/// Decodes the image and encodes its derivatives in all `IMAGE_SIZES` as WebP and JPEG.
+/// The largest JPEG is returned for `path` as well, for clients that do not pick a size.
+///
+/// Re-encoding drops all metadata of the upload, the EXIF orientation is applied
+/// to the pixels beforehand, so that photos from phones are not displayed rotated.
+pub fn encode_image_derivatives(data: &[u8], path: &str) -> ImageResult<Vec<(String, Vec<u8>)>> {
+    let mut decoder = ImageReader::new(Cursor::new(data))
+        .with_guessed_format()?
+        .into_decoder()?;
+    let orientation = decoder.orientation()?;
+    let mut image = DynamicImage::from_decoder(decoder)?;
+    image.apply_orientation(orientation);
+    // neither JPEG nor our derivatives keep transparency
+    let image = DynamicImage::ImageRgb8(image.to_rgb8());
+
+    let mut files = Vec::with_capacity(IMAGE_SIZES.len() * 2 + 1);
+    let mut largest = None;
+    for size in IMAGE_SIZES {
+        let derivative = if image.width() > size || image.height() > size {
+            image.resize(size, size, FilterType::Lanczos3)
+        } else {
+            image.clone()
+        };
+        let jpeg = encode_jpeg(&derivative)?;
+        files.push((
+            image_derivative_path(path, size, IMAGE_EXTENSION),
+            jpeg.clone(),
+        ));
+        files.push((
+            image_derivative_path(path, size, "webp"),
+            encode_webp(&derivative)?,
+        ));
+        largest = Some(jpeg);
+    }
+    if let Some(largest) = largest {
+        files.push((path.to_string(), largest));
+    }
+    Ok(files)
+}
+
+fn encode_jpeg(image: &DynamicImage) -> ImageResult<Vec<u8>> {
+    let mut jpeg = Vec::new();
+    image.write_with_encoder(JpegEncoder::new_with_quality(&mut jpeg, JPEG_QUALITY))?;
+    Ok(jpeg)
+}
+
+/// The encoder of the `image` crate only writes lossless WebP, which is larger than JPEG
+fn encode_webp(image: &DynamicImage) -> ImageResult<Vec<u8>> {
+    let rgb = image.to_rgb8();
+    let webp = webp::Encoder::from_rgb(rgb.as_raw(), rgb.width(), rgb.height())
+        .encode_simple(false, WEBP_QUALITY)
+        .map_err(|err| {
+            ImageError::Encoding(EncodingError::new(
+                ImageFormat::WebP.into(),
+                format!("{err:?}"),
+            ))
+        })?;
+    Ok(webp.to_vec())
+}
+
+/// Image the derivative was created from. Images uploaded before derivatives were created
+/// are served in place of their derivatives, only JPEG images get derivative links
+/// (see `DisplayImage`), so the original is always a JPEG.
+pub fn derivative_source(path: &str) -> Option<String> {
+    let (source, size_and_extension) = path.split_once('.')?;
+    let (size, extension) = size_and_extension.split_once('.')?;
+    let is_derivative = (extension == "webp" || extension == IMAGE_EXTENSION)
+        && IMAGE_SIZES.iter().any(|s| s.to_string() == size);
+    is_derivative.then(|| format!("{source}.{IMAGE_EXTENSION}"))
+}
+
+/// Image prepared for templates, with signed links to all of its derivatives.
+///
+/// Default images and images stored in other formats than `IMAGE_EXTENSION` (uploaded
+/// before derivatives were created) have empty `srcset`s, templates then only use `src`.
+#[derive(Debug, Clone)]
+pub struct DisplayImage {
+    pub src: String,
+    pub webp_srcset: String,
+    pub jpeg_srcset: String,
+}
+
+impl DisplayImage {
+    /// `default` is used when there is no image, it may be empty
+    #[must_use]
+    pub fn new(path: Option<&str>, default: &str) -> Self {
+        let Some(path) = path else {
+            return Self {
+                src: default.to_string(),
+                webp_srcset: String::new(),
+                jpeg_srcset: String::new(),
+            };
+        };
+        let has_derivatives = path.ends_with(&format!(".{IMAGE_EXTENSION}"));
+        let srcset = |extension: &str| {
+            if !has_derivatives {
+                return String::new();
+            }
+            IMAGE_SIZES
+                .iter()
+                .map(|size| {
+                    let url = sign_media_url(&image_derivative_path(path, *size, extension));
+                    format!("{url} {size}w")
+                })
+                .collect::<Vec<_>>()
+                .join(", ")
+        };
+        Self {
+            src: sign_media_url(path),
+            webp_srcset: srcset("webp"),
+            jpeg_srcset: srcset(IMAGE_EXTENSION),
+        }
+    }
+}
@@ -0,0 +1,19 @@
+pub mod analysis;
+pub mod archive;
+pub mod bundle;
+pub mod chapters;
+pub mod content;
+pub mod cover;
+pub mod export;
+pub mod formats;
+pub mod gc;
+pub mod hls;
+pub mod images;
+pub mod processing;
+pub mod signing;
+pub mod silence;
+pub mod storage;
+pub mod stream;
+pub mod tracks;
+pub mod uploads;
+pub mod waveform;
@@ -0,0 +1,99 @@
+use crate::database::models::audiobook::{Audiobook, AudiobookProcessed, AudiobookProcessingTake};
+use crate::database::repositories::audiobook::repository::AudiobookRepository;
+use crate::database::repositories::chapter::repository::ChapterRepository;
+use crate::media::analysis::analyze_audio;
+use crate::media::hls::package_hls;
+use crate::media::storage::Storage;
+use crate::{BOOK_PROCESSING_INTERVAL, BOOK_PROCESSING_TIMEOUT};
+use log::{info, warn};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Notify;
+
+lazy_static::lazy_static! {
+    static ref BOOKS_UPLOADED: Notify = Notify::new();
+}
+
+/// Wakes the processing up, so that new books do not wait for the next round
+pub fn notify_book_processing() {
+    BOOKS_UPLOADED.notify_one();
+}
+
+/// Processes the uploaded books one by one: checks the stored audio, creates its waveform,
+/// chapter suggestions and HLS renditions, and marks the book as ready, or as failed with
+/// the error shown to its author. Books whose processing was interrupted (by a restart,
+/// for example) are processed again once `BOOK_PROCESSING_TIMEOUT` passes.
+pub fn spawn_book_processing(
+    storage: Arc<dyn Storage>,
+    audiobook_repo: AudiobookRepository,
+    chapter_repo: ChapterRepository,
+) {
+    actix_web::rt::spawn(async move {
+        loop {
+            let take = AudiobookProcessingTake::new(1, BOOK_PROCESSING_TIMEOUT);
+            match audiobook_repo.take_unprocessed(&take).await {
+                Ok(books) if !books.is_empty() => {
+                    for book in books {
+                        process_book(storage.as_ref(), &audiobook_repo, &chapter_repo, book).await;
+                    }
+                    continue;
+                }
+                Ok(_) => {}
+                Err(err) => warn!("failed to read the books waiting for processing: {err}"),
+            }
+            let _ = actix_web::rt::time::timeout(
+                Duration::from_secs(BOOK_PROCESSING_INTERVAL),
+                BOOKS_UPLOADED.notified(),
+            )
+            .await;
+        }
+    });
+}
+
+async fn process_book(
+    storage: &dyn Storage,
+    audiobook_repo: &AudiobookRepository,
+    chapter_repo: &ChapterRepository,
+    book: Audiobook,
+) {
+    let timeout = Duration::from_secs(BOOK_PROCESSING_TIMEOUT.unsigned_abs());
+    let error =
+        match actix_web::rt::time::timeout(timeout, process(storage, chapter_repo, &book)).await {
+            Ok(Ok(())) => {
+                info!("book {} was processed", book.id);
+                None
+            }
+            Ok(Err(err)) => Some(err.to_string()),
+            Err(_) => Some("processing took too long".to_string()),
+        };
+    if let Some(error) = &error {
+        warn!("failed to process book {}: {error}", book.id);
+    }
+    if let Err(err) = audiobook_repo
+        .finish_processing(&AudiobookProcessed::new(&book.id, error))
+        .await
+    {
+        warn!("failed to record the processing of book {}: {err}", book.id);
+    }
+}
+
+async fn process(
+    storage: &dyn Storage,
+    chapter_repo: &ChapterRepository,
+    book: &Audiobook,
+) -> anyhow::Result<()> {
+    match storage.head(&book.file_path).await? {
+        Some(meta) if meta.size > 0 => {}
+        Some(_) => anyhow::bail!("the uploaded audio is empty"),
+        None => anyhow::bail!("the uploaded audio is missing"),
+    }
+    let suggestions = analyze_audio(storage, chapter_repo, book.id, &book.file_path).await?;
+    info!(
+        "found {suggestions} chapter suggestions in {}",
+        book.file_path
+    );
+    package_hls(storage, &book.file_path)
+        .await
+        .map_err(|err| anyhow::anyhow!("could not create the HLS renditions: {err}"))?;
+    Ok(())
+}
@@ -0,0 +1,201 @@
+use crate::error::{AppError, AppErrorKind};
+use crate::MEDIA_URL_VALIDITY;
+use actix_web::body::MessageBody;
+use actix_web::dev::{ServiceRequest, ServiceResponse};
+use actix_web::middleware::Next;
+use actix_web::web;
+use hmac::{Hmac, Mac};
+use lazy_static::lazy_static;
+use log::warn;
+use rand_core::{OsRng, RngCore};
+use serde::Deserialize;
+use sha2::Sha256;
+use std::env;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+type HmacSha256 = Hmac<Sha256>;
+
+lazy_static! {
+    pub static ref MEDIA_SIGNING_KEY: Vec<u8> = load_signing_key();
+}
+
+/// All instances serving the same media have to share the key, a random key is used
+/// only as a fallback, links signed by it stop working after a restart.
+fn load_signing_key() -> Vec<u8> {
+    match env::var("MEDIA_SIGNING_KEY") {
+        Ok(key) if !key.is_empty() => key.into_bytes(),
+        _ => {
+            warn!("MEDIA_SIGNING_KEY is not set, using a random key for signing media links");
+            let mut key = vec![0u8; 64];
+            OsRng.fill_bytes(&mut key);
+            key
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct SignedUrlQuery {
+    expires: u64,
+    signature: String,
+}
+
+/// Appends an expiry timestamp and its signature to the path.
+///
+/// The expiry is rounded up to whole validity periods, so the same URL is generated for the
+/// whole period and browsers can cache the media. Every link is valid for at least
+/// `MEDIA_URL_VALIDITY` seconds.
+pub fn sign_url(path: &str) -> String {
+    let period = now() / MEDIA_URL_VALIDITY;
+    let expires = (period + 2) * MEDIA_URL_VALIDITY;
+    format!(
+        "{path}?expires={expires}&signature={}",
+        to_hex(&mac(path, expires).finalize().into_bytes())
+    )
+}
+
+/// Signs media stored by the application, other URLs (e.g. default images) are returned as is
+pub fn sign_media_url(url: &str) -> String {
+    match url.starts_with("/media/") {
+        true => sign_url(url),
+        false => url.to_string(),
+    }
+}
+
+pub fn verify_url(path: &str, query: &str) -> Result<(), AppError> {
+    let Ok(query) = web::Query::<SignedUrlQuery>::from_query(query) else {
+        return Err(AppError::new(
+            AppErrorKind::Forbidden,
+            "The link is not signed",
+        ));
+    };
+    let signature_matches = from_hex(&query.signature)
+        .is_some_and(|signature| mac(path, query.expires).verify_slice(&signature).is_ok());
+    if !signature_matches {
+        return Err(AppError::new(
+            AppErrorKind::Forbidden,
+            "The link signature is invalid",
+        ));
+    }
+    if query.expires < now() {
+        return Err(AppError::new(
+            AppErrorKind::Forbidden,
+            "The link has expired",
+        ));
+    }
+    Ok(())
+}
+
+/// Middleware rejecting requests whose URL was not signed by `sign_url` or has expired
+pub async fn require_signed_url(
+    request: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+    verify_url(request.path(), request.query_string())?;
+    next.call(request).await
+}
+
+fn mac(path: &str, expires: u64) -> HmacSha256 {
+    let mut mac =
+        HmacSha256::new_from_slice(&MEDIA_SIGNING_KEY).expect("HMAC accepts keys of any size");
+    mac.update(path.as_bytes());
+    mac.update(b"\n");
+    mac.update(expires.to_string().as_bytes());
+    mac
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or_default()
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+fn from_hex(hex: &str) -> Option<Vec<u8>> {
+    hex.as_bytes()
+        .chunks(2)
+        .map(|pair| match pair.len() {
+            2 => u8::from_str_radix(std::str::from_utf8(pair).ok()?, 16).ok(),
+            _ => None,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn split(url: &str) -> (&str, &str) {
+        url.split_once('?').expect("Signed URL should have a query")
+    }
+
+    fn assert_rejected(path: &str, query: &str, message: &str) {
+        let error = verify_url(path, query).expect_err("Verify URL should fail");
+        assert!(matches!(error.app_error_kind, AppErrorKind::Forbidden));
+        assert_eq!(error.message, message);
+    }
+
+    #[test]
+    fn signed_url_round_trip() {
+        let url = sign_url("/media/audio/book.mp3");
+        let (path, query) = split(&url);
+        assert_eq!(path, "/media/audio/book.mp3");
+        assert!(verify_url(path, query).is_ok());
+        assert_eq!(sign_media_url("/static/default.png"), "/static/default.png");
+    }
+
+    #[test]
+    fn verify_rejects_unsigned_and_tampered_urls() {
+        let url = sign_url("/media/audio/book.mp3");
+        let (path, query) = split(&url);
+        assert_rejected(path, "", "The link is not signed");
+
+        let (expiry, signature) = query
+            .split_once("&signature=")
+            .expect("Signed URL should have a signature");
+        let flipped = match signature.chars().last() {
+            Some('0') => '1',
+            _ => '0',
+        };
+        let tampered = format!(
+            "{expiry}&signature={}{flipped}",
+            &signature[..signature.len() - 1]
+        );
+        assert_rejected(path, &tampered, "The link signature is invalid");
+
+        // the signature is bound to the path and to the expiry
+        assert_rejected(
+            "/media/audio/other.mp3",
+            query,
+            "The link signature is invalid",
+        );
+        let extended = format!("expires={}&signature={signature}", u64::MAX);
+        assert_rejected(path, &extended, "The link signature is invalid");
+    }
+
+    #[test]
+    fn verify_rejects_malformed_signatures() {
+        let path = "/media/audio/book.mp3";
+        let expires = now() + MEDIA_URL_VALIDITY;
+        for signature in ["", "abc", "zz", "ééé", &"g".repeat(64)] {
+            let query = format!("expires={expires}&signature={signature}");
+            assert_rejected(path, &query, "The link signature is invalid");
+        }
+        assert_rejected(path, "expires=soon&signature=00", "The link is not signed");
+    }
+
+    #[test]
+    fn verify_rejects_expired_urls() {
+        let path = "/media/audio/book.mp3";
+        let expires = now() - 1;
+        let signature = to_hex(&mac(path, expires).finalize().into_bytes());
+        assert_rejected(
+            path,
+            &format!("expires={expires}&signature={signature}"),
+            "The link has expired",
+        );
+    }
+}
@@ -0,0 +1,170 @@
+use crate::media::waveform::Waveform;
+use crate::{DEFAULT_SILENCE_MIN_DURATION, DEFAULT_SILENCE_THRESHOLD_DB};
+use lazy_static::lazy_static;
+use log::warn;
+use std::env;
+
+lazy_static! {
+    pub static ref SILENCE_DETECTION: SilenceDetection = SilenceDetection::from_env();
+}
+
+/// Settings of the silence detection, see `DEFAULT_SILENCE_THRESHOLD_DB`
+#[derive(Debug, Clone)]
+pub struct SilenceDetection {
+    pub threshold_db: f64,
+    pub min_duration: f64,
+}
+
+/// A silence in the audio, in seconds from the beginning
+#[derive(Debug, Clone, PartialEq)]
+pub struct Silence {
+    pub start: f64,
+    pub end: f64,
+}
+
+impl Silence {
+    #[must_use]
+    pub fn length(&self) -> f64 {
+        self.end - self.start
+    }
+
+    #[must_use]
+    pub fn middle(&self) -> f64 {
+        (self.start + self.end) / 2.0
+    }
+}
+
+impl SilenceDetection {
+    fn from_env() -> Self {
+        Self {
+            threshold_db: env_or("SILENCE_THRESHOLD_DB", DEFAULT_SILENCE_THRESHOLD_DB),
+            min_duration: env_or("SILENCE_MIN_DURATION", DEFAULT_SILENCE_MIN_DURATION),
+        }
+    }
+
+    /// Finds the silences in the waveform which are at least `min_duration` long.
+    /// Silences at the very beginning and end of the audio are not reported,
+    /// as they cannot separate two chapters.
+    #[must_use]
+    pub fn detect(&self, waveform: &Waveform) -> Vec<Silence> {
+        let level = 32768.0 * 10f64.powf(self.threshold_db / 20.0);
+        let peak_duration = waveform.peak_duration();
+        let is_silent = |(min, max): &(i16, i16)| {
+            f64::from(-i32::from(*min)) < level && f64::from(*max) < level
+        };
+
+        let mut silences = Vec::new();
+        let mut start = None;
+        for (index, peak) in waveform.peaks.iter().enumerate() {
+            match (is_silent(peak), start) {
+                (true, None) => start = Some(index),
+                (false, Some(first)) => {
+                    start = None;
+                    if first == 0 {
+                        continue;
+                    }
+                    let silence = Silence {
+                        start: first as f64 * peak_duration,
+                        end: index as f64 * peak_duration,
+                    };
+                    if silence.length() >= self.min_duration {
+                        silences.push(silence);
+                    }
+                }
+                _ => {}
+            }
+        }
+        silences
+    }
+}
+
+fn env_or(name: &str, default: f64) -> f64 {
+    match env::var(name) {
+        Err(_) => default,
+        Ok(value) => value.parse().unwrap_or_else(|_| {
+            warn!("{name} is not a number, using the default {default}");
+            default
+        }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::media::waveform::{WAVEFORM_SAMPLES_PER_PIXEL, WAVEFORM_SAMPLE_RATE};
+
+    const SILENT: (i16, i16) = (-100, 100);
+    const LOUD: (i16, i16) = (-10000, 10000);
+
+    /// Waveform of 10 ms peaks made of runs of the same peak
+    fn waveform(runs: &[((i16, i16), usize)]) -> Waveform {
+        let mut waveform = Waveform::new(WAVEFORM_SAMPLE_RATE, WAVEFORM_SAMPLES_PER_PIXEL);
+        for (peak, count) in runs {
+            waveform.peaks.extend(std::iter::repeat_n(*peak, *count));
+        }
+        waveform
+    }
+
+    fn bounds(silences: &[Silence]) -> Vec<(f64, f64)> {
+        silences
+            .iter()
+            .map(|silence| {
+                (
+                    (silence.start * 100.0).round() / 100.0,
+                    (silence.end * 100.0).round() / 100.0,
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn detect_silences_between_chapters() {
+        let detection = SilenceDetection {
+            threshold_db: -40.0,
+            min_duration: 2.0,
+        };
+        let waveform = waveform(&[
+            // the audio starts and ends in silence, which does not separate chapters
+            (SILENT, 100),
+            (LOUD, 300),
+            (SILENT, 250),
+            (LOUD, 100),
+            // too short
+            (SILENT, 150),
+            (LOUD, 100),
+            (SILENT, 201),
+            (LOUD, 50),
+            (SILENT, 300),
+        ]);
+        let silences = detection.detect(&waveform);
+        assert_eq!(bounds(&silences), vec![(4.0, 6.5), (10.0, 12.01)]);
+        assert!((silences[0].middle() - 5.25).abs() < 1e-9);
+        assert!((silences[0].length() - 2.5).abs() < 1e-9);
+    }
+
+    #[test]
+    fn detect_silences_below_the_threshold() {
+        // -40 dB is 1 % of the full scale, 327 of 32768
+        let detection = SilenceDetection {
+            threshold_db: -40.0,
+            min_duration: 1.0,
+        };
+        for (quiet, silent) in [
+            ((-327, 327), true),
+            ((-400, 100), false),
+            ((-100, 400), false),
+            ((i16::MIN, 0), false),
+        ] {
+            let waveform = waveform(&[(LOUD, 10), (quiet, 150), (LOUD, 10)]);
+            assert_eq!(detection.detect(&waveform).len(), usize::from(silent));
+        }
+
+        // nothing is silent at a lower threshold
+        let detection = SilenceDetection {
+            threshold_db: -60.0,
+            min_duration: 1.0,
+        };
+        let waveform = waveform(&[(LOUD, 10), (SILENT, 150), (LOUD, 10)]);
+        assert!(detection.detect(&waveform).is_empty());
+    }
+}
@@ -0,0 +1,140 @@
+use crate::media::storage::{not_found, ByteStream, LocalCopy, ObjectMeta, Storage};
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use futures_util::StreamExt;
+use std::fs::Metadata;
+use std::io::{ErrorKind, SeekFrom};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, UNIX_EPOCH};
+use tokio::io::{AsyncReadExt, AsyncSeekExt};
+use tokio_util::io::ReaderStream;
+
+/// Media stored in the local filesystem, media paths are relative to `root`
+pub struct LocalStorage {
+    root: PathBuf,
+}
+
+impl LocalStorage {
+    #[must_use]
+    #[inline]
+    pub const fn new(root: PathBuf) -> Self {
+        Self { root }
+    }
+
+    fn file_path(&self, path: &str) -> PathBuf {
+        self.root.join(path.trim_start_matches('/'))
+    }
+
+    fn object_meta(metadata: &Metadata) -> std::io::Result<ObjectMeta> {
+        let modified = metadata
+            .modified()?
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        Ok(ObjectMeta {
+            size: metadata.len(),
+            last_modified: DateTime::<Utc>::from(UNIX_EPOCH + modified),
+            etag: format!("{:x}-{:x}", metadata.len(), modified.as_nanos()),
+        })
+    }
+
+    async fn create_parent(file: &Path) -> std::io::Result<()> {
+        if let Some(parent) = file.parent() {
+            tokio::fs::create_dir_all(parent).await?;
+        }
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl Storage for LocalStorage {
+    async fn put_file(&self, path: &str, file: &Path) -> std::io::Result<()> {
+        let destination = self.file_path(path);
+        Self::create_parent(&destination).await?;
+        // uploads can be buffered on another filesystem (tmpfs or a node-local disk
+        // in Kubernetes), where rename(2) fails, the file has to be copied then
+        if tokio::fs::rename(file, &destination).await.is_err() {
+            tokio::fs::copy(file, &destination).await?;
+            tokio::fs::remove_file(file).await?;
+        }
+        Ok(())
+    }
+
+    async fn put(&self, path: &str, data: Vec<u8>) -> std::io::Result<()> {
+        let destination = self.file_path(path);
+        Self::create_parent(&destination).await?;
+        // readers never see a partially written file
+        let staging = PathBuf::from(format!("{}.tmp", destination.display()));
+        tokio::fs::write(&staging, data).await?;
+        tokio::fs::rename(&staging, &destination).await
+    }
+
+    async fn get_range(&self, path: &str, range: Range<u64>) -> std::io::Result<ByteStream> {
+        let mut file = tokio::fs::File::open(self.file_path(path)).await?;
+        file.seek(SeekFrom::Start(range.start)).await?;
+        Ok(ReaderStream::new(file.take(range.end - range.start)).boxed())
+    }
+
+    async fn head(&self, path: &str) -> std::io::Result<Option<ObjectMeta>> {
+        let metadata = match tokio::fs::metadata(self.file_path(path)).await {
+            Ok(metadata) if metadata.is_file() => metadata,
+            Ok(_) => return Ok(None),
+            Err(err) if err.kind() == ErrorKind::NotFound => return Ok(None),
+            Err(err) => return Err(err),
+        };
+        Ok(Some(Self::object_meta(&metadata)?))
+    }
+
+    async fn delete(&self, path: &str) -> std::io::Result<()> {
+        match tokio::fs::remove_file(self.file_path(path)).await {
+            Err(err) if err.kind() == ErrorKind::NotFound => Ok(()),
+            result => result,
+        }
+    }
+
+    async fn delete_directory(&self, path: &str) -> std::io::Result<()> {
+        match tokio::fs::remove_dir_all(self.file_path(path)).await {
+            Err(err) if err.kind() == ErrorKind::NotFound => Ok(()),
+            result => result,
+        }
+    }
+
+    async fn list(&self, path: &str) -> std::io::Result<Vec<(String, ObjectMeta)>> {
+        let mut objects = Vec::new();
+        let mut directories = vec![path.trim_end_matches('/').to_string()];
+        while let Some(directory) = directories.pop() {
+            let mut entries = match tokio::fs::read_dir(self.file_path(&directory)).await {
+                Ok(entries) => entries,
+                Err(err) if err.kind() == ErrorKind::NotFound => continue,
+                Err(err) => return Err(err),
+            };
+            while let Some(entry) = entries.next_entry().await? {
+                let path = format!("{directory}/{}", entry.file_name().to_string_lossy());
+                let metadata = entry.metadata().await?;
+                if metadata.is_dir() {
+                    directories.push(path);
+                } else if metadata.is_file() {
+                    objects.push((path, Self::object_meta(&metadata)?));
+                }
+            }
+        }
+        Ok(objects)
+    }
+
+    /// The files are only served by the application
+    async fn presign(&self, _path: &str, _expires_in: Duration) -> std::io::Result<Option<String>> {
+        Ok(None)
+    }
+
+    fn upload_directory(&self) -> Option<PathBuf> {
+        Some(self.root.join("media"))
+    }
+
+    async fn local_copy(&self, path: &str) -> std::io::Result<LocalCopy> {
+        let file = self.file_path(path);
+        if !tokio::fs::try_exists(&file).await? {
+            return Err(not_found(path));
+        }
+        Ok(LocalCopy::Stored(file))
+    }
+}
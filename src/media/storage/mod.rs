@@ -0,0 +1,139 @@
+pub mod local;
+pub mod s3;
+
+use crate::media::storage::local::LocalStorage;
+use crate::media::storage::s3::S3Storage;
+use async_trait::async_trait;
+use bytes::Bytes;
+use chrono::{DateTime, Utc};
+use futures_util::stream::BoxStream;
+use futures_util::TryStreamExt;
+use std::env;
+use std::io::{Error, ErrorKind};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+use tempfile::TempPath;
+use tokio::io::AsyncWriteExt;
+
+pub type ByteStream = BoxStream<'static, std::io::Result<Bytes>>;
+
+/// Metadata of a stored object, used for conditional and range requests
+#[derive(Debug, Clone)]
+pub struct ObjectMeta {
+    pub size: u64,
+    pub last_modified: DateTime<Utc>,
+    /// Entity tag without the surrounding quotes
+    pub etag: String,
+}
+
+/// Local file with the contents of a stored object, for tools like ffmpeg that need a file.
+/// Downloaded copies are removed when dropped.
+pub enum LocalCopy {
+    Stored(PathBuf),
+    Downloaded(TempPath),
+}
+
+impl LocalCopy {
+    #[must_use]
+    pub fn path(&self) -> &Path {
+        match self {
+            LocalCopy::Stored(path) => path,
+            LocalCopy::Downloaded(path) => path,
+        }
+    }
+}
+
+/// Storage of the media files (audio, images and everything derived from them).
+///
+/// Objects are addressed by the paths stored in the database (`/media/<file>`),
+/// directories are only a prefix of the paths and do not have to exist on their own.
+#[async_trait]
+pub trait Storage: Send + Sync {
+    /// Stores the local file under `path`, the file may be moved instead of copied
+    async fn put_file(&self, path: &str, file: &Path) -> std::io::Result<()>;
+
+    async fn put(&self, path: &str, data: Vec<u8>) -> std::io::Result<()>;
+
+    /// Streams the bytes of the object in `range`, which has to lie within the object
+    async fn get_range(&self, path: &str, range: Range<u64>) -> std::io::Result<ByteStream>;
+
+    /// Returns `None` if the object does not exist
+    async fn head(&self, path: &str) -> std::io::Result<Option<ObjectMeta>>;
+
+    /// Deleting an object that does not exist is not an error
+    async fn delete(&self, path: &str) -> std::io::Result<()>;
+
+    /// Deletes all objects under the directory
+    async fn delete_directory(&self, path: &str) -> std::io::Result<()>;
+
+    /// Lists the paths of all objects under the directory (recursively) with their metadata
+    async fn list(&self, path: &str) -> std::io::Result<Vec<(String, ObjectMeta)>>;
+
+    /// URL the object can be downloaded from without going through the application,
+    /// `None` if the storage can not be accessed by clients directly
+    async fn presign(&self, path: &str, expires_in: Duration) -> std::io::Result<Option<String>>;
+
+    /// Directory uploads should be buffered in, so that `put_file` can move them
+    fn upload_directory(&self) -> Option<PathBuf> {
+        None
+    }
+
+    async fn exists(&self, path: &str) -> std::io::Result<bool> {
+        Ok(self.head(path).await?.is_some())
+    }
+
+    /// Reads the whole object, only meant for small objects like playlists
+    async fn get(&self, path: &str) -> std::io::Result<Vec<u8>> {
+        let Some(meta) = self.head(path).await? else {
+            return Err(not_found(path));
+        };
+        let chunks: Vec<Bytes> = self
+            .get_range(path, 0..meta.size)
+            .await?
+            .try_collect()
+            .await?;
+        Ok(chunks.concat())
+    }
+
+    /// Provides the object as a local file
+    async fn local_copy(&self, path: &str) -> std::io::Result<LocalCopy> {
+        let Some(meta) = self.head(path).await? else {
+            return Err(not_found(path));
+        };
+        let extension = Path::new(path)
+            .extension()
+            .map(|extension| format!(".{}", extension.to_string_lossy()))
+            .unwrap_or_default();
+        let copy = tempfile::Builder::new()
+            .suffix(&extension)
+            .tempfile()?
+            .into_temp_path();
+        let mut file = tokio::fs::File::create(&copy).await?;
+        let mut stream = self.get_range(path, 0..meta.size).await?;
+        while let Some(chunk) = stream.try_next().await? {
+            file.write_all(&chunk).await?;
+        }
+        file.flush().await?;
+        Ok(LocalCopy::Downloaded(copy))
+    }
+}
+
+pub(super) fn not_found(path: &str) -> Error {
+    Error::new(ErrorKind::NotFound, format!("{path} does not exist"))
+}
+
+/// Creates the storage configured by `MEDIA_STORAGE`, `local` (the default) or `s3`.
+///
+/// The local storage keeps the media in `./media`. The S3 storage uses the bucket
+/// in `S3_BUCKET` and the standard `AWS_*` variables (`AWS_ENDPOINT`, `AWS_REGION`,
+/// `AWS_ACCESS_KEY_ID`, `AWS_SECRET_ACCESS_KEY`, `AWS_ALLOW_HTTP`, ...).
+pub fn storage_from_env() -> anyhow::Result<Arc<dyn Storage>> {
+    let storage: Arc<dyn Storage> = match env::var("MEDIA_STORAGE").as_deref() {
+        Err(_) | Ok("local") => Arc::new(LocalStorage::new(PathBuf::from("."))),
+        Ok("s3") => Arc::new(S3Storage::from_env(&env::var("S3_BUCKET")?)?),
+        Ok(other) => anyhow::bail!("unknown media storage {other}, use local or s3"),
+    };
+    Ok(storage)
+}
@@ -0,0 +1,135 @@
+use crate::media::storage::{ByteStream, ObjectMeta, Storage};
+use async_trait::async_trait;
+use futures_util::{StreamExt, TryStreamExt};
+use http::Method;
+use object_store::aws::{AmazonS3, AmazonS3Builder};
+use object_store::buffered::BufWriter;
+use object_store::path::Path as ObjectPath;
+use object_store::signer::Signer;
+use object_store::{GetOptions, GetRange, ObjectStore, PutPayload};
+use std::io::Error;
+use std::ops::Range;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::AsyncWriteExt;
+
+/// Media stored in an S3-compatible bucket (AWS S3, MinIO, ...)
+pub struct S3Storage {
+    store: Arc<AmazonS3>,
+}
+
+impl S3Storage {
+    pub fn from_env(bucket: &str) -> object_store::Result<Self> {
+        let store = AmazonS3Builder::from_env()
+            .with_bucket_name(bucket)
+            .build()?;
+        Ok(Self {
+            store: Arc::new(store),
+        })
+    }
+
+    fn object_path(path: &str) -> ObjectPath {
+        ObjectPath::from(path.trim_start_matches('/'))
+    }
+}
+
+fn object_meta(meta: object_store::ObjectMeta) -> ObjectMeta {
+    ObjectMeta {
+        size: meta.size as u64,
+        last_modified: meta.last_modified,
+        etag: meta.e_tag.unwrap_or_default().trim_matches('"').to_string(),
+    }
+}
+
+fn to_io_error(err: object_store::Error) -> Error {
+    match err {
+        object_store::Error::NotFound { .. } => Error::new(std::io::ErrorKind::NotFound, err),
+        err => Error::other(err),
+    }
+}
+
+#[async_trait]
+impl Storage for S3Storage {
+    /// Large files are uploaded in parts
+    async fn put_file(&self, path: &str, file: &Path) -> std::io::Result<()> {
+        let mut source = tokio::fs::File::open(file).await?;
+        let mut writer = BufWriter::new(self.store.clone(), Self::object_path(path));
+        tokio::io::copy(&mut source, &mut writer).await?;
+        writer.shutdown().await
+    }
+
+    async fn put(&self, path: &str, data: Vec<u8>) -> std::io::Result<()> {
+        self.store
+            .put(&Self::object_path(path), PutPayload::from(data))
+            .await
+            .map_err(to_io_error)?;
+        Ok(())
+    }
+
+    async fn get_range(&self, path: &str, range: Range<u64>) -> std::io::Result<ByteStream> {
+        // S3 rejects empty ranges
+        if range.is_empty() {
+            return Ok(futures_util::stream::empty().boxed());
+        }
+        let options = GetOptions {
+            range: Some(GetRange::Bounded(range.start as usize..range.end as usize)),
+            ..GetOptions::default()
+        };
+        let result = self
+            .store
+            .get_opts(&Self::object_path(path), options)
+            .await
+            .map_err(to_io_error)?;
+        Ok(result.into_stream().map_err(to_io_error).boxed())
+    }
+
+    async fn head(&self, path: &str) -> std::io::Result<Option<ObjectMeta>> {
+        match self.store.head(&Self::object_path(path)).await {
+            Ok(meta) => Ok(Some(object_meta(meta))),
+            Err(object_store::Error::NotFound { .. }) => Ok(None),
+            Err(err) => Err(to_io_error(err)),
+        }
+    }
+
+    async fn delete(&self, path: &str) -> std::io::Result<()> {
+        match self.store.delete(&Self::object_path(path)).await {
+            Ok(()) | Err(object_store::Error::NotFound { .. }) => Ok(()),
+            Err(err) => Err(to_io_error(err)),
+        }
+    }
+
+    async fn delete_directory(&self, path: &str) -> std::io::Result<()> {
+        let prefix = Self::object_path(path);
+        let locations = self
+            .store
+            .list(Some(&prefix))
+            .map_ok(|meta| meta.location)
+            .boxed();
+        self.store
+            .delete_stream(locations)
+            .try_collect::<Vec<_>>()
+            .await
+            .map_err(to_io_error)?;
+        Ok(())
+    }
+
+    async fn list(&self, path: &str) -> std::io::Result<Vec<(String, ObjectMeta)>> {
+        let prefix = Self::object_path(path);
+        self.store
+            .list(Some(&prefix))
+            .map_ok(|meta| (format!("/{}", meta.location), object_meta(meta)))
+            .try_collect()
+            .await
+            .map_err(to_io_error)
+    }
+
+    async fn presign(&self, path: &str, expires_in: Duration) -> std::io::Result<Option<String>> {
+        let url = self
+            .store
+            .signed_url(Method::GET, &Self::object_path(path), expires_in)
+            .await
+            .map_err(to_io_error)?;
+        Ok(Some(url.to_string()))
+    }
+}
@@ -0,0 +1,213 @@
+use crate::error::{AppError, AppErrorKind};
+use crate::media::storage::Storage;
+use actix_files::{file_extension_to_mime, HttpRange};
+use actix_web::body::SizedStream;
+use actix_web::http::header::{
+    EntityTag, HttpDate, ACCEPT_RANGES, CONTENT_RANGE, CONTENT_TYPE, ETAG, IF_NONE_MATCH, IF_RANGE,
+    LAST_MODIFIED, RANGE,
+};
+use actix_web::http::StatusCode;
+use actix_web::{HttpRequest, HttpResponse};
+use std::path::Path;
+use std::time::SystemTime;
+
+/// Serves the stored object, range requests are answered with partial content so that
+/// players can seek.
+///
+/// A range of an object that has changed since the client fetched the other parts of it
+/// (the validator in `If-Range` does not match) is replaced with the whole object.
+pub async fn stream_object(
+    request: &HttpRequest,
+    storage: &dyn Storage,
+    path: &str,
+) -> Result<HttpResponse, AppError> {
+    let Some(meta) = storage.head(path).await? else {
+        return Err(AppError::new(
+            AppErrorKind::NotFound,
+            "The file does not exist",
+        ));
+    };
+    let etag = EntityTag::new_strong(meta.etag).to_string();
+    let last_modified = HttpDate::from(SystemTime::from(meta.last_modified)).to_string();
+    let extension = Path::new(path)
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .unwrap_or_default();
+
+    let mut response = HttpResponse::Ok();
+    response
+        .insert_header((CONTENT_TYPE, file_extension_to_mime(extension).to_string()))
+        .insert_header((ETAG, etag.as_str()))
+        .insert_header((LAST_MODIFIED, last_modified.as_str()))
+        .insert_header((ACCEPT_RANGES, "bytes"));
+    if none_match_fails(request, &etag) {
+        return Ok(response.status(StatusCode::NOT_MODIFIED).finish());
+    }
+
+    let mut range = 0..meta.size;
+    let requested_range = request
+        .headers()
+        .get(RANGE)
+        .and_then(|range| range.to_str().ok());
+    if let Some(requested_range) = requested_range {
+        if if_range_matches(request, &etag, &last_modified) {
+            let Ok(ranges) = HttpRange::parse(requested_range, meta.size) else {
+                return Ok(HttpResponse::RangeNotSatisfiable()
+                    .insert_header((CONTENT_RANGE, format!("bytes */{}", meta.size)))
+                    .finish());
+            };
+            if let Some(first) = ranges.first() {
+                range = first.start..first.start + first.length;
+                response.status(StatusCode::PARTIAL_CONTENT).insert_header((
+                    CONTENT_RANGE,
+                    format!("bytes {}-{}/{}", range.start, range.end - 1, meta.size),
+                ));
+            }
+        }
+    }
+
+    let stream = storage.get_range(path, range.clone()).await?;
+    Ok(response.body(SizedStream::new(range.end - range.start, stream)))
+}
+
+/// Whether the client already has the current version of the object
+fn none_match_fails(request: &HttpRequest, etag: &str) -> bool {
+    let Some(if_none_match) = request
+        .headers()
+        .get(IF_NONE_MATCH)
+        .and_then(|value| value.to_str().ok())
+    else {
+        return false;
+    };
+    if_none_match.trim() == "*"
+        || if_none_match
+            .split(',')
+            .any(|tag| tag.trim().trim_start_matches("W/") == etag)
+}
+
+/// The validator in `If-Range` has to be either a strong entity tag or the exact
+/// modification date of the object
+fn if_range_matches(request: &HttpRequest, etag: &str, last_modified: &str) -> bool {
+    let Some(if_range) = request.headers().get(IF_RANGE) else {
+        return true;
+    };
+    match if_range.as_bytes() {
+        [b'W', b'/', ..] => false,
+        [b'"', ..] => if_range == etag,
+        _ => if_range == last_modified,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::media::storage::local::LocalStorage;
+    use actix_web::body::to_bytes;
+    use actix_web::test::TestRequest;
+    use tempfile::TempDir;
+
+    const PATH: &str = "/media/book_audio.mp3";
+    const CONTENTS: &[u8] = b"0123456789abcdefghij";
+
+    async fn storage() -> (TempDir, LocalStorage) {
+        let root = tempfile::tempdir().expect("Create temporary directory should succeed");
+        let storage = LocalStorage::new(root.path().to_path_buf());
+        storage
+            .put(PATH, CONTENTS.to_vec())
+            .await
+            .expect("Store object should succeed");
+        (root, storage)
+    }
+
+    async fn stream(storage: &LocalStorage, headers: &[(&str, &str)]) -> HttpResponse {
+        let mut request = TestRequest::get().uri(PATH);
+        for header in headers {
+            request = request.insert_header(*header);
+        }
+        stream_object(&request.to_http_request(), storage, PATH)
+            .await
+            .expect("Stream object should succeed")
+    }
+
+    fn header<'a>(response: &'a HttpResponse, name: &str) -> Option<&'a str> {
+        response
+            .headers()
+            .get(name)
+            .and_then(|value| value.to_str().ok())
+    }
+
+    async fn body(response: HttpResponse) -> Vec<u8> {
+        to_bytes(response.into_body())
+            .await
+            .expect("Read body should succeed")
+            .to_vec()
+    }
+
+    #[actix_web::test]
+    async fn stream_ranges() {
+        let (_root, storage) = storage().await;
+        let response = stream(&storage, &[]).await;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(header(&response, "accept-ranges"), Some("bytes"));
+        assert_eq!(header(&response, "content-type"), Some("audio/mpeg"));
+        assert_eq!(body(response).await, CONTENTS);
+
+        let response = stream(&storage, &[("range", "bytes=5-9")]).await;
+        assert_eq!(response.status(), StatusCode::PARTIAL_CONTENT);
+        assert_eq!(header(&response, "content-range"), Some("bytes 5-9/20"));
+        assert_eq!(body(response).await, b"56789");
+
+        let response = stream(&storage, &[("range", "bytes=-4")]).await;
+        assert_eq!(header(&response, "content-range"), Some("bytes 16-19/20"));
+        assert_eq!(body(response).await, b"ghij");
+
+        // only the first of multiple ranges is served, clients request the rest later
+        let response = stream(&storage, &[("range", "bytes=2-3,10-12")]).await;
+        assert_eq!(response.status(), StatusCode::PARTIAL_CONTENT);
+        assert_eq!(header(&response, "content-range"), Some("bytes 2-3/20"));
+        assert_eq!(body(response).await, b"23");
+    }
+
+    #[actix_web::test]
+    async fn stream_unsatisfiable_range() {
+        let (_root, storage) = storage().await;
+        for range in ["bytes=20-30", "bytes=x-y"] {
+            let response = stream(&storage, &[("range", range)]).await;
+            assert_eq!(response.status(), StatusCode::RANGE_NOT_SATISFIABLE);
+            assert_eq!(header(&response, "content-range"), Some("bytes */20"));
+        }
+    }
+
+    #[actix_web::test]
+    async fn stream_conditional_requests() {
+        let (_root, storage) = storage().await;
+        let response = stream(&storage, &[]).await;
+        let etag = header(&response, "etag")
+            .expect("The response should have an entity tag")
+            .to_string();
+        let last_modified = header(&response, "last-modified")
+            .expect("The response should have a modification date")
+            .to_string();
+
+        let response = stream(&storage, &[("if-none-match", &etag)]).await;
+        assert_eq!(response.status(), StatusCode::NOT_MODIFIED);
+
+        // the range is served while the object has not changed
+        for validator in [etag.as_str(), last_modified.as_str()] {
+            let response =
+                stream(&storage, &[("range", "bytes=0-1"), ("if-range", validator)]).await;
+            assert_eq!(response.status(), StatusCode::PARTIAL_CONTENT);
+            assert_eq!(body(response).await, b"01");
+        }
+
+        // a stale or weak validator gets the whole object
+        let weak = format!("W/{etag}");
+        for validator in ["\"stale\"", weak.as_str(), "Thu, 01 Jan 1970 00:00:00 GMT"] {
+            let response =
+                stream(&storage, &[("range", "bytes=0-1"), ("if-range", validator)]).await;
+            assert_eq!(response.status(), StatusCode::OK);
+            assert!(header(&response, "content-range").is_none());
+            assert_eq!(body(response).await, CONTENTS);
+        }
+    }
+}
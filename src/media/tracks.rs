@@ -0,0 +1,320 @@
+use crate::media::chapters::{read_embedded_chapters, EmbeddedChapter};
+use crate::media::cover::{read_embedded_cover, EmbeddedCover};
+use actix_multipart::form::tempfile::TempFile;
+use lofty::{Accessor, AudioFile, FileType, TaggedFileExt};
+use log::warn;
+use std::cmp::Ordering;
+use std::fs::File;
+use std::io::{BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+const ID3V2_HEADER_LEN: u64 = 10;
+const ID3V1_TAG_LEN: u64 = 128;
+const APE_FOOTER_LEN: u64 = 32;
+/// How far into the audio data we look for the first MPEG frame
+const FRAME_SEARCH_LIMIT: usize = 64 * 1024;
+
+const MPEG1_LAYER3_BITRATES: [u32; 15] = [
+    0, 32, 40, 48, 56, 64, 80, 96, 112, 128, 160, 192, 224, 256, 320,
+];
+const MPEG2_LAYER3_BITRATES: [u32; 15] =
+    [0, 8, 16, 24, 32, 40, 48, 56, 64, 80, 96, 112, 128, 144, 160];
+const MPEG1_SAMPLE_RATES: [u32; 3] = [44100, 48000, 32000];
+const MPEG2_SAMPLE_RATES: [u32; 3] = [22050, 24000, 16000];
+const MPEG25_SAMPLE_RATES: [u32; 3] = [11025, 12000, 8000];
+
+/// Metadata of a single uploaded track as reported by `lofty`
+#[derive(Debug, Clone)]
+pub struct TrackInfo {
+    pub title: String,
+    pub length: f64,
+    pub file_type: FileType,
+    pub sample_rate: Option<u32>,
+    pub channels: Option<u8>,
+    pub chapters: Vec<EmbeddedChapter>,
+    pub cover: Option<EmbeddedCover>,
+}
+
+impl TrackInfo {
+    pub fn read(track: &mut TempFile) -> lofty::Result<Self> {
+        let file = track.file.as_file_mut();
+        file.rewind()?;
+        let tagged_file = lofty::read_from(file)?;
+        let title = tagged_file
+            .primary_tag()
+            .or_else(|| tagged_file.first_tag())
+            .and_then(|tag| tag.title().map(|title| title.trim().to_string()))
+            .filter(|title| !title.is_empty())
+            .unwrap_or_else(|| file_stem(&track.file_name));
+        // broken chapter markers should not prevent the book from being uploaded
+        let chapters = read_embedded_chapters(file, &tagged_file).unwrap_or_else(|err| {
+            warn!("could not read the chapters embedded in {title}: {err}");
+            Vec::new()
+        });
+        Ok(Self {
+            title,
+            length: tagged_file.properties().duration().as_secs_f64(),
+            file_type: tagged_file.file_type(),
+            sample_rate: tagged_file.properties().sample_rate(),
+            channels: tagged_file.properties().channels(),
+            chapters,
+            cover: read_embedded_cover(&tagged_file),
+        })
+    }
+}
+
+fn file_stem(file_name: &Option<String>) -> String {
+    file_name
+        .as_deref()
+        .and_then(|name| Path::new(name).file_stem())
+        .map(|stem| stem.to_string_lossy().to_string())
+        .unwrap_or_default()
+}
+
+/// Orders uploaded tracks by their file names, comparing numbers by value,
+/// so that `2 - Chapter.mp3` comes before `10 - Chapter.mp3`.
+pub fn sort_tracks(tracks: &mut [TempFile]) {
+    tracks.sort_by(|a, b| {
+        natural_cmp(
+            a.file_name.as_deref().unwrap_or_default(),
+            b.file_name.as_deref().unwrap_or_default(),
+        )
+    });
+}
+
+fn natural_cmp(a: &str, b: &str) -> Ordering {
+    let mut a = a.chars().peekable();
+    let mut b = b.chars().peekable();
+    loop {
+        match (a.peek().copied(), b.peek().copied()) {
+            (None, None) => return Ordering::Equal,
+            (None, Some(_)) => return Ordering::Less,
+            (Some(_), None) => return Ordering::Greater,
+            (Some(x), Some(y)) if x.is_ascii_digit() && y.is_ascii_digit() => {
+                let mut left = String::new();
+                while let Some(c) = a.next_if(|c| c.is_ascii_digit()) {
+                    left.push(c);
+                }
+                let mut right = String::new();
+                while let Some(c) = b.next_if(|c| c.is_ascii_digit()) {
+                    right.push(c);
+                }
+                let left = left.trim_start_matches('0');
+                let right = right.trim_start_matches('0');
+                let ordering = left.len().cmp(&right.len()).then(left.cmp(right));
+                if ordering != Ordering::Equal {
+                    return ordering;
+                }
+            }
+            (Some(x), Some(y)) => {
+                let ordering = x.to_lowercase().cmp(y.to_lowercase());
+                if ordering != Ordering::Equal {
+                    return ordering;
+                }
+                a.next();
+                b.next();
+            }
+        }
+    }
+}
+
+/// Only MPEG audio frames can be joined back to back without re-encoding, and only when
+/// all tracks have the same sample rate and number of channels, players would otherwise
+/// play the joined file at the wrong speed or glitch at the track boundaries
+pub fn can_concatenate(tracks: &[TrackInfo]) -> bool {
+    let Some(first) = tracks.first() else {
+        return true;
+    };
+    tracks.len() == 1
+        || tracks.iter().all(|t| {
+            t.file_type == FileType::Mpeg
+                && t.sample_rate == first.sample_rate
+                && t.channels == first.channels
+        })
+}
+
+/// Positions (in seconds) at which the individual tracks start in the joined file
+fn track_starts(tracks: &[TrackInfo]) -> Vec<f64> {
+    tracks
+        .iter()
+        .scan(0f64, |start, track| {
+            let current = *start;
+            *start += track.length;
+            Some(current)
+        })
+        .collect()
+}
+
+/// Chapters of the whole book. Chapters embedded in a track are shifted by the track's
+/// position in the joined file, markers past the end of the track are dropped.
+/// Tracks of multi-file uploads without any embedded chapters become a chapter of their own.
+pub fn book_chapters(tracks: &[TrackInfo]) -> Vec<EmbeddedChapter> {
+    let mut chapters = Vec::new();
+    for (track, start) in tracks.iter().zip(track_starts(tracks)) {
+        if !track.chapters.is_empty() {
+            chapters.extend(
+                track
+                    .chapters
+                    .iter()
+                    .filter(|chapter| chapter.position < track.length)
+                    .map(|chapter| EmbeddedChapter::new(&chapter.name, start + chapter.position)),
+            );
+        } else if tracks.len() > 1 {
+            chapters.push(EmbeddedChapter::new(&track.title, start));
+        }
+    }
+    chapters
+}
+
+/// Joins MP3 tracks into a single file so that they can be played back without gaps.
+/// Tags and the Xing/Info/VBRI header frames are dropped from every track, as the header
+/// frame of the first track would otherwise make players think the whole book is only
+/// as long as the first track.
+pub fn concatenate_mp3(sources: &[&Path], destination: &Path) -> std::io::Result<()> {
+    let mut output = BufWriter::new(File::create(destination)?);
+    for source in sources {
+        let mut input = File::open(source)?;
+        let (start, end) = mpeg_audio_range(&mut input)?;
+        input.seek(SeekFrom::Start(start))?;
+        std::io::copy(&mut (&mut input).take(end - start), &mut output)?;
+    }
+    output.flush()
+}
+
+/// Skips the ID3v2 tags (there may be more than one) at the beginning of the file,
+/// returns the offset of the data that follows them
+pub fn skip_id3v2(file: &mut File) -> std::io::Result<u64> {
+    let mut offset = 0u64;
+    loop {
+        let mut header = [0u8; ID3V2_HEADER_LEN as usize];
+        file.seek(SeekFrom::Start(offset))?;
+        if file.read_exact(&mut header).is_err() || &header[..3] != b"ID3" {
+            return Ok(offset);
+        }
+        let size = header[6..10]
+            .iter()
+            .fold(0u64, |acc, b| (acc << 7) | u64::from(b & 0x7F));
+        let footer = if header[5] & 0x10 != 0 {
+            ID3V2_HEADER_LEN
+        } else {
+            0
+        };
+        offset += ID3V2_HEADER_LEN + size + footer;
+    }
+}
+
+/// Returns the byte range of the MPEG frames in the file, skipping all tags and the VBR header
+fn mpeg_audio_range(file: &mut File) -> std::io::Result<(u64, u64)> {
+    let file_len = file.metadata()?.len();
+    let mut start = skip_id3v2(file)?;
+
+    let mut end = file_len;
+    // ID3v1 tag at the very end of the file
+    if end >= start + ID3V1_TAG_LEN {
+        let mut tag = [0u8; 3];
+        file.seek(SeekFrom::Start(end - ID3V1_TAG_LEN))?;
+        file.read_exact(&mut tag)?;
+        if &tag == b"TAG" {
+            end -= ID3V1_TAG_LEN;
+        }
+    }
+    // APEv2 tag in front of the ID3v1 tag
+    if end >= start + APE_FOOTER_LEN {
+        let mut footer = [0u8; APE_FOOTER_LEN as usize];
+        file.seek(SeekFrom::Start(end - APE_FOOTER_LEN))?;
+        file.read_exact(&mut footer)?;
+        if &footer[..8] == b"APETAGEX" {
+            let size = u64::from(u32::from_le_bytes([
+                footer[12], footer[13], footer[14], footer[15],
+            ]));
+            let flags = u32::from_le_bytes([footer[20], footer[21], footer[22], footer[23]]);
+            let header = if flags & (1 << 31) != 0 {
+                APE_FOOTER_LEN
+            } else {
+                0
+            };
+            end = end.saturating_sub(size + header).max(start);
+        }
+    }
+
+    // tags claiming to be larger than the file leave no audio to join
+    if start >= end {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            "the file contains no MPEG audio frames",
+        ));
+    }
+    let mut buffer = vec![0u8; FRAME_SEARCH_LIMIT.min((end - start) as usize)];
+    file.seek(SeekFrom::Start(start))?;
+    file.read_exact(&mut buffer)?;
+    if let Some(offset) =
+        (0..buffer.len().saturating_sub(4)).find(|&i| FrameHeader::parse(&buffer[i..]).is_some())
+    {
+        let frame = FrameHeader::parse(&buffer[offset..]).expect("frame header was found");
+        start += offset as u64;
+        if frame.is_vbr_header(&buffer[offset..]) {
+            start += frame.length as u64;
+        }
+    }
+
+    Ok((start, end.max(start)))
+}
+
+/// MPEG-1/2/2.5 Layer III frame header
+struct FrameHeader {
+    version_1: bool,
+    mono: bool,
+    length: usize,
+}
+
+impl FrameHeader {
+    fn parse(bytes: &[u8]) -> Option<Self> {
+        if bytes.len() < 4 || bytes[0] != 0xFF || bytes[1] & 0xE0 != 0xE0 {
+            return None;
+        }
+        let version = (bytes[1] >> 3) & 0b11;
+        let layer = (bytes[1] >> 1) & 0b11;
+        let bitrate_index = (bytes[2] >> 4) as usize;
+        let sample_rate_index = ((bytes[2] >> 2) & 0b11) as usize;
+        let padding = ((bytes[2] >> 1) & 1) as usize;
+        // only Layer III, valid bitrate and sample rate
+        if version == 1 || layer != 1 || bitrate_index == 0 || bitrate_index == 15 {
+            return None;
+        }
+        if sample_rate_index == 3 {
+            return None;
+        }
+        let version_1 = version == 3;
+        let (bitrate, sample_rate) = match version {
+            3 => (
+                MPEG1_LAYER3_BITRATES[bitrate_index],
+                MPEG1_SAMPLE_RATES[sample_rate_index],
+            ),
+            2 => (
+                MPEG2_LAYER3_BITRATES[bitrate_index],
+                MPEG2_SAMPLE_RATES[sample_rate_index],
+            ),
+            _ => (
+                MPEG2_LAYER3_BITRATES[bitrate_index],
+                MPEG25_SAMPLE_RATES[sample_rate_index],
+            ),
+        };
+        let coefficient = if version_1 { 144 } else { 72 };
+        Some(Self {
+            version_1,
+            mono: bytes[3] >> 6 == 3,
+            length: (coefficient * bitrate * 1000 / sample_rate) as usize + padding,
+        })
+    }
+
+    fn is_vbr_header(&self, frame: &[u8]) -> bool {
+        let xing_offset = match (self.version_1, self.mono) {
+            (true, false) => 36,
+            (true, true) | (false, false) => 21,
+            (false, true) => 13,
+        };
+        let tag_at = |offset: usize| frame.get(offset..offset + 4);
+        matches!(tag_at(xing_offset), Some(b"Xing") | Some(b"Info"))
+            || matches!(tag_at(36), Some(b"VBRI"))
+    }
+}
@@ -0,0 +1,82 @@
+use crate::database::repositories::upload::repository::UploadRepository;
+use crate::media::storage::Storage;
+use crate::UPLOAD_CLEANUP_INTERVAL;
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use log::{info, warn};
+use std::collections::HashMap;
+use std::io::ErrorKind;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+use uuid::Uuid;
+
+/// File the received part of a resumable upload is kept in until it is complete.
+///
+/// The file is kept next to the media with the local storage, so that the finished upload
+/// can be moved in place, and in the temporary directory otherwise.
+pub fn partial_upload_path(storage: &dyn Storage, id: Uuid) -> PathBuf {
+    storage
+        .upload_directory()
+        .unwrap_or_else(std::env::temp_dir)
+        .join(format!("upload_{id}.part"))
+}
+
+pub async fn remove_partial_upload(storage: &dyn Storage, id: Uuid) -> std::io::Result<()> {
+    match tokio::fs::remove_file(partial_upload_path(storage, id)).await {
+        Err(err) if err.kind() == ErrorKind::NotFound => Ok(()),
+        result => result,
+    }
+}
+
+/// Parses the `Upload-Metadata` header of the tus protocol, a comma separated list
+/// of keys with optional base64 encoded values. Returns `None` if the header is malformed.
+pub fn parse_upload_metadata(header: &str) -> Option<HashMap<String, String>> {
+    let mut metadata = HashMap::new();
+    for pair in header
+        .split(',')
+        .map(str::trim)
+        .filter(|pair| !pair.is_empty())
+    {
+        let mut parts = pair.split(' ');
+        let key = parts.next()?;
+        let value = match parts.next() {
+            Some(value) => String::from_utf8(STANDARD.decode(value).ok()?).ok()?,
+            None => String::new(),
+        };
+        if parts.next().is_some() {
+            return None;
+        }
+        metadata.insert(key.to_string(), value);
+    }
+    Some(metadata)
+}
+
+/// Periodically removes the resumable uploads that were not finished before they expired
+pub fn spawn_upload_cleanup(upload_repo: UploadRepository, storage: Arc<dyn Storage>) {
+    actix_web::rt::spawn(async move {
+        let mut interval =
+            actix_web::rt::time::interval(Duration::from_secs(UPLOAD_CLEANUP_INTERVAL));
+        loop {
+            interval.tick().await;
+            let uploads = match upload_repo.delete_expired().await {
+                Ok(uploads) => uploads,
+                Err(err) => {
+                    warn!("failed to remove expired uploads: {err}");
+                    continue;
+                }
+            };
+            for upload in &uploads {
+                if let Err(err) = remove_partial_upload(storage.as_ref(), upload.id).await {
+                    warn!(
+                        "failed to remove the file of expired upload {}: {err}",
+                        upload.id
+                    );
+                }
+            }
+            if !uploads.is_empty() {
+                info!("removed {} expired uploads", uploads.len());
+            }
+        }
+    });
+}
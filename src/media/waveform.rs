@@ -0,0 +1,132 @@
+use std::io::Error;
+use std::path::Path;
+use std::process::Stdio;
+use tokio::io::AsyncReadExt;
+use tokio::process::{ChildStderr, ChildStdout, Command};
+
+/// The audio is decoded to mono at this rate, which is plenty for drawing and silence detection
+pub const WAVEFORM_SAMPLE_RATE: u32 = 8000;
+/// One min/max pair per 10 ms of audio
+pub const WAVEFORM_SAMPLES_PER_PIXEL: u32 = 80;
+/// Version of the audiowaveform `.dat` format we write
+const DAT_VERSION: i32 = 1;
+const DAT_HEADER_LEN: usize = 20;
+
+/// Minimum and maximum sample of every `samples_per_pixel` samples of the audio,
+/// stored in the binary `.dat` format of audiowaveform (version 1, 16-bit samples)
+#[derive(Debug, Clone, PartialEq)]
+pub struct Waveform {
+    pub sample_rate: u32,
+    pub samples_per_pixel: u32,
+    pub peaks: Vec<(i16, i16)>,
+}
+
+impl Waveform {
+    #[must_use]
+    pub const fn new(sample_rate: u32, samples_per_pixel: u32) -> Self {
+        Self {
+            sample_rate,
+            samples_per_pixel,
+            peaks: Vec::new(),
+        }
+    }
+
+    /// Duration of a single peak in seconds
+    #[must_use]
+    pub fn peak_duration(&self) -> f64 {
+        f64::from(self.samples_per_pixel) / f64::from(self.sample_rate)
+    }
+
+    #[must_use]
+    pub fn to_dat(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(DAT_HEADER_LEN + self.peaks.len() * 4);
+        bytes.extend_from_slice(&DAT_VERSION.to_le_bytes());
+        // flags, 0 means 16-bit samples
+        bytes.extend_from_slice(&0u32.to_le_bytes());
+        bytes.extend_from_slice(&self.sample_rate.to_le_bytes());
+        bytes.extend_from_slice(&self.samples_per_pixel.to_le_bytes());
+        bytes.extend_from_slice(&(self.peaks.len() as u32).to_le_bytes());
+        for (min, max) in &self.peaks {
+            bytes.extend_from_slice(&min.to_le_bytes());
+            bytes.extend_from_slice(&max.to_le_bytes());
+        }
+        bytes
+    }
+}
+
+/// Peaks file of an audio file, stored next to the original
+/// (`/media/audiobook_<uuid>_audio.mp3` -> `/media/audiobook_<uuid>_audio_waveform.dat`)
+pub fn waveform_path(file_path: &str) -> String {
+    let stem = Path::new(file_path).with_extension("");
+    format!("{}_waveform.dat", stem.display())
+}
+
+/// Decodes the audio file with ffmpeg into its peaks. Books can be many hours long,
+/// so the decoded samples are folded into peaks as they arrive instead of being kept.
+pub async fn generate_waveform(file: &Path) -> std::io::Result<Waveform> {
+    let mut ffmpeg = Command::new("ffmpeg")
+        .args(["-hide_banner", "-loglevel", "error", "-i"])
+        .arg(file)
+        .args(["-vn", "-ac", "1", "-ar", &WAVEFORM_SAMPLE_RATE.to_string()])
+        .args(["-f", "s16le", "-acodec", "pcm_s16le", "-"])
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+
+    // stderr is read alongside the samples, ffmpeg would block once its pipe is full
+    let (waveform, errors) = tokio::join!(
+        read_peaks(ffmpeg.stdout.take()),
+        read_errors(ffmpeg.stderr.take())
+    );
+    let status = ffmpeg.wait().await?;
+    if !status.success() {
+        return Err(Error::other(format!(
+            "ffmpeg exited with {status}: {}",
+            String::from_utf8_lossy(&errors?).trim()
+        )));
+    }
+    waveform
+}
+
+async fn read_peaks(stdout: Option<ChildStdout>) -> std::io::Result<Waveform> {
+    let mut waveform = Waveform::new(WAVEFORM_SAMPLE_RATE, WAVEFORM_SAMPLES_PER_PIXEL);
+    let Some(mut stdout) = stdout else {
+        return Ok(waveform);
+    };
+    let mut buffer = vec![0u8; 64 * 1024];
+    let mut filled = 0;
+    let mut peak = (i16::MAX, i16::MIN);
+    let mut peak_samples = 0;
+    loop {
+        let read = stdout.read(&mut buffer[filled..]).await?;
+        if read == 0 {
+            break;
+        }
+        filled += read;
+        let whole_samples = filled - filled % 2;
+        for sample in buffer[..whole_samples].chunks_exact(2) {
+            let sample = i16::from_le_bytes([sample[0], sample[1]]);
+            peak = (peak.0.min(sample), peak.1.max(sample));
+            peak_samples += 1;
+            if peak_samples == WAVEFORM_SAMPLES_PER_PIXEL {
+                waveform.peaks.push(peak);
+                peak = (i16::MAX, i16::MIN);
+                peak_samples = 0;
+            }
+        }
+        buffer.copy_within(whole_samples..filled, 0);
+        filled -= whole_samples;
+    }
+    if peak_samples > 0 {
+        waveform.peaks.push(peak);
+    }
+    Ok(waveform)
+}
+
+async fn read_errors(stderr: Option<ChildStderr>) -> std::io::Result<Vec<u8>> {
+    let mut errors = Vec::new();
+    if let Some(mut stderr) = stderr {
+        stderr.read_to_end(&mut errors).await?;
+    }
+    Ok(errors)
+}
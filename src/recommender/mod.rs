@@ -1,3 +1,4 @@
 pub mod recommender;
 
 pub mod recommandation_system;
+pub mod registration;
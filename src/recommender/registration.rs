@@ -0,0 +1,117 @@
+use crate::database::common::DbReadOne;
+use crate::database::models::audiobook::AudiobookGetById;
+use crate::database::models::genre::GenreGetById;
+use crate::database::models::recommender_queue::{
+    RecommenderTask, RecommenderTaskPostpone, RecommenderTasksTake,
+};
+use crate::database::repositories::audiobook::repository::AudiobookRepository;
+use crate::database::repositories::genre::repository::GenreRepository;
+use crate::database::repositories::recommender_queue::repository::RecommenderQueueRepository;
+use crate::recommender::recommender::add_book_recommender;
+use crate::{RECOMMENDER_QUEUE_INTERVAL, RECOMMENDER_RETRY_MAX_DELAY};
+use log::{info, warn};
+use std::time::Duration;
+use tokio::sync::Notify;
+
+/// Tasks taken at once, and the seconds after which they are handed out again
+/// if they were neither finished nor postponed
+const TASKS_PER_BATCH: i64 = 32;
+const TASK_LEASE: i64 = 5 * 60;
+
+lazy_static::lazy_static! {
+    static ref QUEUE_CHANGED: Notify = Notify::new();
+}
+
+/// Wakes the registration up, so that new books do not wait for the next round
+pub fn notify_recommender_queue() {
+    QUEUE_CHANGED.notify_one();
+}
+
+/// Registers the books queued when they were created in the recommender. Failed registrations
+/// (the recommender is not running, for example) are retried with an exponential backoff.
+pub fn spawn_recommender_registration(
+    queue_repo: RecommenderQueueRepository,
+    audiobook_repo: AudiobookRepository,
+    genre_repo: GenreRepository,
+) {
+    actix_web::rt::spawn(async move {
+        loop {
+            let take = RecommenderTasksTake::new(TASKS_PER_BATCH, TASK_LEASE);
+            match queue_repo.take_due(&take).await {
+                Ok(tasks) => {
+                    for task in tasks {
+                        process_task(&queue_repo, &audiobook_repo, &genre_repo, task).await;
+                    }
+                }
+                Err(err) => warn!("failed to read the recommender queue: {err}"),
+            }
+            let _ = actix_web::rt::time::timeout(
+                Duration::from_secs(RECOMMENDER_QUEUE_INTERVAL),
+                QUEUE_CHANGED.notified(),
+            )
+            .await;
+        }
+    });
+}
+
+async fn process_task(
+    queue_repo: &RecommenderQueueRepository,
+    audiobook_repo: &AudiobookRepository,
+    genre_repo: &GenreRepository,
+    task: RecommenderTask,
+) {
+    let result = match register(audiobook_repo, genre_repo, &task).await {
+        Ok(()) => queue_repo.finish(&task.audiobook_id).await,
+        Err(err) => {
+            let delay = retry_delay(task.attempts);
+            warn!(
+                "failed to add book {} to the recommender, retrying in {delay} s: {err}",
+                task.audiobook_id
+            );
+            queue_repo
+                .postpone(&RecommenderTaskPostpone::new(
+                    &task.audiobook_id,
+                    delay,
+                    &err,
+                ))
+                .await
+        }
+    };
+    if let Err(err) = result {
+        warn!(
+            "failed to update the recommender queue for book {}: {err}",
+            task.audiobook_id
+        );
+    }
+}
+
+async fn register(
+    audiobook_repo: &AudiobookRepository,
+    genre_repo: &GenreRepository,
+    task: &RecommenderTask,
+) -> Result<(), String> {
+    let book = audiobook_repo
+        .read_one(&AudiobookGetById::new(&task.audiobook_id, true))
+        .await
+        .map_err(|err| err.to_string())?;
+    // deleted books are removed from the recommender, they must not be added back
+    if book.deleted_at.is_some() {
+        return Ok(());
+    }
+    let genre = genre_repo
+        .read_one(&GenreGetById::new(&book.genre_id))
+        .await
+        .map_err(|err| err.to_string())?;
+    add_book_recommender(&book, &genre.name)
+        .await
+        .map_err(|err| err.to_string())?;
+    info!("book {} added to the recommender", book.id);
+    Ok(())
+}
+
+/// Doubles with every failed attempt, starting at a minute
+fn retry_delay(attempts: i32) -> i64 {
+    60_i64
+        .saturating_mul(1 << attempts.clamp(0, 20))
+        .min(RECOMMENDER_RETRY_MAX_DELAY)
+}
@@ -1,9 +1,13 @@
 use crate::database::models::active_audiobook::PlayedAudiobook;
 use crate::database::models::audiobook::{
-    AudiobookDisplay, AudiobookRecommenderDisplay, QuickSearch,
+    AudiobookAudioReplaced, AudiobookDisplay, AudiobookRecommenderDisplay, AudiobookVersion,
+    QuickSearch,
 };
 use crate::database::models::chapter::ChapterDisplay;
 use crate::database::models::genre::Genre;
+use crate::database::models::listening_session::ListeningHistoryEntry;
+use crate::database::models::play::DailyPlays;
+use crate::database::models::Id;
 use askama::Template;
 
 #[derive(Template)]
@@ -125,10 +129,53 @@ pub struct AudiobookDetailAuthorContentTemplate {
     pub is_liked: bool,
 }
 
+/// State of the M4B export of the book, `ready` when the stored export is made of
+/// the current state of the book
+#[derive(Template)]
+#[template(path = "audiobook/export.html")]
+pub struct AudiobookExportTemplate {
+    pub audiobook_id: Id,
+    pub ready: bool,
+    pub running: bool,
+    pub size: Option<i64>,
+    pub error: Option<String>,
+}
+
+/// Whether the listener finished the book or is listening to it, with the buttons
+/// to change that
+#[derive(Template)]
+#[template(path = "audiobook/listening_state.html")]
+pub struct ListeningStateTemplate {
+    pub audiobook_id: Id,
+    pub finished: bool,
+    pub started: bool,
+}
+
+/// Plays of a book per day, `most` is the number of plays of the busiest day, the bars
+/// are relative to it
+#[derive(Template)]
+#[template(path = "audiobook/plays.html")]
+pub struct DailyPlaysTemplate {
+    pub total: i64,
+    pub days: Vec<DailyPlays>,
+    pub most: i64,
+    pub period: i32,
+}
+
+/// Page of the listening history, `next_offset` is the offset of the next page if there is one
+#[derive(Template)]
+#[template(path = "audiobook/history.html")]
+pub struct ListeningHistoryTemplate {
+    pub history: Vec<ListeningHistoryEntry>,
+    pub next_offset: Option<i64>,
+}
+
 #[derive(Template)]
 #[template(path = "components/player.html")]
 pub struct PlayerTemplate {
     pub played_book: PlayedAudiobook,
+    pub stream_url: String,
+    pub hls_url: Option<String>,
 }
 
 #[derive(Template)]
@@ -201,3 +248,52 @@ impl From<AudiobookEditBase> for AudiobookEditPageTemplate {
         }
     }
 }
+
+#[derive(Template)]
+#[template(path = "studio_replace_audio.html")]
+pub struct AudiobookAudioReplacePageTemplate {
+    pub message: String,
+    pub audiobook: AudiobookDisplay,
+    pub versions: Vec<AudiobookVersion>,
+}
+
+#[derive(Template)]
+#[template(path = "audiobook/audio_replace.html")]
+pub struct AudiobookAudioReplaceContentTemplate {
+    pub message: String,
+    pub audiobook: AudiobookDisplay,
+    pub versions: Vec<AudiobookVersion>,
+}
+
+pub struct AudiobookAudioReplaceBase {
+    pub message: String,
+    pub audiobook: AudiobookDisplay,
+    pub versions: Vec<AudiobookVersion>,
+}
+
+impl From<AudiobookAudioReplaceBase> for AudiobookAudioReplaceContentTemplate {
+    fn from(value: AudiobookAudioReplaceBase) -> Self {
+        Self {
+            message: value.message,
+            audiobook: value.audiobook,
+            versions: value.versions,
+        }
+    }
+}
+
+impl From<AudiobookAudioReplaceBase> for AudiobookAudioReplacePageTemplate {
+    fn from(value: AudiobookAudioReplaceBase) -> Self {
+        Self {
+            message: value.message,
+            audiobook: value.audiobook,
+            versions: value.versions,
+        }
+    }
+}
+
+#[derive(Template)]
+#[template(path = "audiobook/audio_replaced.html")]
+pub struct AudiobookAudioReplacedTemplate {
+    pub audiobook: AudiobookDisplay,
+    pub replaced: AudiobookAudioReplaced,
+}
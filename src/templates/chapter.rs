@@ -1,4 +1,4 @@
-use crate::database::models::chapter::ChapterDisplay;
+use crate::database::models::chapter::{ChapterDisplay, ChapterRetention, ChapterSuggestion};
 use crate::database::models::Id;
 use askama::Template;
 
@@ -6,6 +6,7 @@ use askama::Template;
 #[template(path = "components/chapter-create-player.html")]
 pub struct ChapterCreatorPlayerTemplate {
     pub source: String,
+    pub audiobook_id: Id,
 }
 
 #[derive(Template)]
@@ -21,5 +22,15 @@ pub struct ChapterTimelineTemplate {
 pub struct ChapterListTemplate {
     pub audiobook_id: Id,
     pub chapters: Vec<ChapterDisplay>,
+    pub suggestions: Vec<ChapterSuggestion>,
     pub show_delete: bool,
 }
+
+/// Where the listeners of a book stop, `listeners` is the number of listeners of the most
+/// listened chapter, the bars are relative to it
+#[derive(Template)]
+#[template(path = "chapter/chapter-retention.html")]
+pub struct ChapterRetentionTemplate {
+    pub chapters: Vec<ChapterRetention>,
+    pub listeners: i64,
+}
@@ -1,14 +1,40 @@
 use crate::database::models::audiobook::AudiobookDisplay;
+use crate::database::models::user::StorageUsage;
 use askama::Template;
 
 #[derive(Template)]
 #[template(path = "studio.html")]
 pub struct StudioPageTemplate {
     pub audiobooks: Vec<AudiobookDisplay>,
+    pub storage: StorageUsage,
 }
 
 #[derive(Template)]
 #[template(path = "audiobook/studio-content.html")]
 pub struct StudioContentTemplate {
     pub audiobooks: Vec<AudiobookDisplay>,
+    pub storage: StorageUsage,
+}
+
+pub struct StudioBase {
+    pub audiobooks: Vec<AudiobookDisplay>,
+    pub storage: StorageUsage,
+}
+
+impl From<StudioBase> for StudioPageTemplate {
+    fn from(value: StudioBase) -> Self {
+        Self {
+            audiobooks: value.audiobooks,
+            storage: value.storage,
+        }
+    }
+}
+
+impl From<StudioBase> for StudioContentTemplate {
+    fn from(value: StudioBase) -> Self {
+        Self {
+            audiobooks: value.audiobooks,
+            storage: value.storage,
+        }
+    }
 }
@@ -1,4 +1,5 @@
 #![allow(dead_code)]
+use crate::media::images::DisplayImage;
 use chrono::{DateTime, Utc};
 use std::cmp::min;
 
@@ -6,6 +7,10 @@ pub fn format_date(timestamp: &DateTime<Utc>) -> String {
     timestamp.format("%d.%m.%Y").to_string()
 }
 
+pub fn format_datetime(timestamp: &DateTime<Utc>) -> String {
+    timestamp.format("%d.%m.%Y %H:%M").to_string()
+}
+
 pub fn format_position(position: &f64) -> String {
     let seconds = (position % 60f64).round();
     let minutes = ((position / 60f64) % 60f64).floor();
@@ -17,6 +22,10 @@ pub fn display_optional(value: &Option<String>) -> String {
     value.to_owned().unwrap_or(String::from(""))
 }
 
+pub fn display_optional_image(value: &Option<String>) -> DisplayImage {
+    DisplayImage::new(value.as_deref(), "")
+}
+
 pub fn as_integer(number: &i16) -> i16 {
     number.to_owned()
 }
@@ -37,3 +46,41 @@ pub fn format_overall_rating(rating: &f64) -> String {
 pub fn get_max_init_page(max_page: &i64) -> i64 {
     min(*max_page, 2)
 }
+
+pub fn format_size(bytes: &i64) -> String {
+    const UNITS: [&str; 4] = ["B", "KB", "MB", "GB"];
+    let mut size = *bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    match unit {
+        0 => format!("{bytes} B"),
+        _ => format!("{size:.1} {}", UNITS[unit]),
+    }
+}
+
+/// Browser and system of the player from its user agent, e.g. `Firefox on Linux`
+pub fn client_name(user_agent: &str) -> String {
+    let browser = ["Edg", "OPR", "Firefox", "Chrome", "Safari"]
+        .into_iter()
+        .find(|browser| user_agent.contains(&format!("{browser}/")))
+        .map(|browser| match browser {
+            "Edg" => "Edge",
+            "OPR" => "Opera",
+            browser => browser,
+        });
+    let system = ["Android", "iPhone", "iPad", "Windows", "Mac OS", "Linux"]
+        .into_iter()
+        .find(|system| user_agent.contains(system))
+        .map(|system| match system {
+            "Mac OS" => "macOS",
+            system => system,
+        });
+    match (browser, system) {
+        (Some(browser), Some(system)) => format!("{browser} on {system}"),
+        (Some(name), None) | (None, Some(name)) => name.to_string(),
+        (None, None) => "unknown player".to_string(),
+    }
+}